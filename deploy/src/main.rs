@@ -1,7 +1,27 @@
 use std::env;
 use std::error::Error;
+use std::fs;
 use std::process::{Command, Stdio};
 
+const ROM_PATH: &str = "target/mips-nintendo64-none/release/game.n64";
+const ASSET_PACK_PATH: &str = "game/assets.pak";
+
+// Must match `PACK_ALIGN` in `n64/src/rom.rs`
+const PACK_ALIGN: usize = 0x1000;
+
+fn append_asset_pack() -> Result<(), Box<dyn Error>> {
+    let mut rom = fs::read(ROM_PATH)?;
+    let pack = fs::read(ASSET_PACK_PATH)
+        .map_err(|e| format!("Unable to read {}: {}", ASSET_PACK_PATH, e))?;
+
+    rom.resize((rom.len() + PACK_ALIGN - 1) & !(PACK_ALIGN - 1), 0);
+    rom.extend_from_slice(&pack);
+
+    fs::write(ROM_PATH, rom)?;
+
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     if !env::current_dir()?.ends_with("loka-n64") {
         env::set_current_dir("../")?;
@@ -22,11 +42,11 @@ fn main() -> Result<(), Box<dyn Error>> {
         .status()?
         .success());
 
+    append_asset_pack()?;
+
     assert!(Command::new("tools/usb64.exe")
-        .args(&[
-            "-rom=target/mips-nintendo64-none/release/game.n64",
-            "-start"
-        ])
+        .arg(format!("-rom={}", ROM_PATH))
+        .arg("-start")
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
         .status()?
@@ -1,4 +1,11 @@
+use n64_audio::{
+    adpcm, midi,
+    sfxr::{self, Params},
+    tracker::Module,
+    wav::{self, Wav},
+};
 use n64_math::Color;
+use n64_types::DEFAULT_SAMPLE_RATE;
 use png;
 use std::convert::TryInto;
 use std::env;
@@ -6,12 +13,76 @@ use std::error::Error;
 use std::ffi::OsStr;
 use std::fs::{self, File};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     io::BufReader,
     path::{Path, PathBuf},
 };
 use tiled::{Map, Tileset};
 
+// Must match the pack layout in `n64/src/fs.rs`
+const PACK_MAGIC: &[u8; 4] = b"LPAK";
+const PACK_VERSION: u32 = 1;
+const PACK_HEADER_SIZE: usize = 16;
+const PACK_ENTRY_SIZE: usize = 64;
+const PACK_NAME_SIZE: usize = 56;
+const PACK_DATA_ALIGN: usize = 8;
+
+const SFXR_SEED: u32 = 1;
+
+struct AssetPack {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl AssetPack {
+    fn new() -> Self {
+        Self {
+            files: BTreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, name: impl Into<String>, data: impl Into<Vec<u8>>) {
+        self.files.insert(name.into(), data.into());
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut res = Vec::new();
+
+        res.extend_from_slice(PACK_MAGIC);
+        res.extend_from_slice(&PACK_VERSION.to_be_bytes());
+        res.extend_from_slice(&(self.files.len() as u32).to_be_bytes());
+        res.extend_from_slice(&0u32.to_be_bytes());
+
+        let mut offset = PACK_HEADER_SIZE + self.files.len() * PACK_ENTRY_SIZE;
+
+        for (name, data) in self.files.iter() {
+            if name.len() >= PACK_NAME_SIZE {
+                return Err(format!("Asset name too long: {}", name).into());
+            }
+
+            offset = (offset + PACK_DATA_ALIGN - 1) & !(PACK_DATA_ALIGN - 1);
+
+            let mut raw_name = [0; PACK_NAME_SIZE];
+            raw_name[..name.len()].copy_from_slice(name.as_bytes());
+
+            res.extend_from_slice(&raw_name);
+            res.extend_from_slice(&(offset as u32).to_be_bytes());
+            res.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+            offset += data.len();
+        }
+
+        for data in self.files.values() {
+            res.resize(
+                (res.len() + PACK_DATA_ALIGN - 1) & !(PACK_DATA_ALIGN - 1),
+                0,
+            );
+            res.extend_from_slice(data);
+        }
+
+        Ok(res)
+    }
+}
+
 struct Image {
     width: i32,
     height: i32,
@@ -118,18 +189,6 @@ fn parse_textures(out_dir: &str) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-#[rustfmt::skip]
-macro_rules! TILE_TEMPLATE { () => {
-r##"static {tile_ident}: StaticTexture = StaticTexture::from_static({width}, {height}, include_bytes!({tile_path:?}));
-"##
-}; }
-
-#[rustfmt::skip]
-macro_rules! TILE_IDENT_TEMPLATE { () => {
-r##"    &{tile_ident},
-"##
-}; }
-
 fn find_tileset_with_gid(gid: u32, tilesets: &[Tileset]) -> Result<&Tileset, Box<dyn Error>> {
     for tileset in tilesets {
         let effective_gid = gid as i32 - tileset.first_gid as i32;
@@ -207,66 +266,39 @@ fn load_tile_image(
     Err(format!("GID {} Not Found In Tileset Images", gid).into())
 }
 
+// All tiles the map can use back to back, in the order of `used_tile_ids`
+// without the empty tile. Layers index into it with 1 added.
 fn parse_map_tiles(
-    out_dir: &str,
     map_path: &Path,
-    name: &str,
-    uppercase_name: &str,
     map: &Map,
     used_tile_ids: &[u32],
     tileset_image_cache: &mut HashMap<PathBuf, Image>,
-) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
-    let mut map_tiles = Vec::new();
-    let mut map_tile_refs = Vec::new();
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut tiles = Vec::new();
 
     for id in used_tile_ids.iter() {
         if *id == 0 {
             continue;
         }
 
-        let width: i32 = map.tile_width.try_into().unwrap();
-        let height: i32 = map.tile_height.try_into().unwrap();
-
-        let tile_path = Path::new(out_dir)
-            .join(format!("{}_tile_{}", name, *id))
-            .with_extension("ntex");
-        let tile_path = tile_path.to_str().ok_or("Bad Path")?;
-
         let tileset = find_tileset_with_gid(*id, &map.tilesets)?;
         let tile_image = load_tile_image(*id, map_path, tileset, tileset_image_cache)?;
 
-        write_binary_file_if_changed(&tile_path, &tile_image)?;
-
-        let tile_ident = format!("{}_TILE_{}", uppercase_name, id);
-
-        let tile = format!(
-            TILE_TEMPLATE!(),
-            tile_ident = tile_ident,
-            width = width,
-            height = height,
-            tile_path = tile_path,
-        );
-
-        let tile_ref = format!(TILE_IDENT_TEMPLATE!(), tile_ident = tile_ident);
-
-        map_tiles.push(tile);
-        map_tile_refs.push(tile_ref);
+        tiles.extend_from_slice(&tile_image);
     }
 
-    Ok((map_tiles, map_tile_refs))
+    Ok(tiles)
 }
 
 #[rustfmt::skip]
 macro_rules! MAP_TEMPLATE { () => {
-r##"pub static {tiles_name_ident}: &'static [&'static StaticTexture] = &[
-{map_tile_refs}];
-
-pub static {map_name_ident}: &'static StaticMapData = &StaticMapData {{
+r##"pub static {map_name_ident}: &'static StaticMapData = &StaticMapData {{
     width_in_tiles: {map_width},
     height_in_tiles: {map_height},
     tile_width: {tile_width},
     tile_height: {tile_height},
-    layers: include_bytes!({map_data_path:?}),
+    layers_path: {map_pack_path:?},
+    tiles_path: {tiles_pack_path:?},
 }};"##
 }; }
 
@@ -275,17 +307,14 @@ macro_rules! MAPS_TEMPLATE { () => {
 r##"// This file is generated
 
 use crate::map::StaticMapData;
-use n64::gfx::StaticTexture;
 
-{tiles}
 {maps}
 
 "##
 }; }
 
-fn parse_maps(out_dir: &str) -> Result<(), Box<dyn Error>> {
+fn parse_maps(pack: &mut AssetPack) -> Result<(), Box<dyn Error>> {
     let mut maps = Vec::new();
-    let mut tiles = Vec::new();
 
     let mut used_tile_ids_map = HashMap::new();
     let mut used_tile_ids = Vec::new();
@@ -316,7 +345,7 @@ fn parse_maps(out_dir: &str) -> Result<(), Box<dyn Error>> {
             tiled::parse_with_path(reader, &path)?
         };
 
-        let mut layers = Vec::new();
+        let mut layers: Vec<u8> = Vec::new();
 
         for layer in map.layers.iter() {
             for row in layer.tiles.iter() {
@@ -333,25 +362,15 @@ fn parse_maps(out_dir: &str) -> Result<(), Box<dyn Error>> {
             }
         }
 
-        let (map_tiles, map_tile_refs) = parse_map_tiles(
-            out_dir,
-            &path,
-            &name,
-            &uppercase_name,
-            &map,
-            &used_tile_ids,
-            &mut tileset_image_cache,
-        )?;
-
-        tiles.extend_from_slice(&map_tiles);
+        let tiles = parse_map_tiles(&path, &map, &used_tile_ids, &mut tileset_image_cache)?;
 
-        let map_data_path = Path::new(out_dir).join(name).with_extension("nmap");
-        let map_data_path = map_data_path.to_str().ok_or("Bad Path")?;
+        let map_pack_path = format!("maps/{}.nmap", name);
+        pack.add(map_pack_path.clone(), layers);
 
-        write_binary_file_if_changed(map_data_path, &layers)?;
+        let tiles_pack_path = format!("maps/{}.ntiles", name);
+        pack.add(tiles_pack_path.clone(), tiles);
 
         let map_name_ident = format!("{}", &uppercase_name);
-        let tiles_name_ident = format!("{}_TILES", &uppercase_name);
         let map_width = map.width as i32;
         let map_height = map.height as i32;
         let tile_width = map.tile_width as i32;
@@ -360,34 +379,198 @@ fn parse_maps(out_dir: &str) -> Result<(), Box<dyn Error>> {
         let map = format!(
             MAP_TEMPLATE!(),
             map_name_ident = map_name_ident,
-            tiles_name_ident = tiles_name_ident,
-            map_tile_refs = map_tile_refs.join(""),
             map_width = map_width,
             map_height = map_height,
             tile_width = tile_width,
             tile_height = tile_height,
-            map_data_path = map_data_path,
+            map_pack_path = map_pack_path,
+            tiles_pack_path = tiles_pack_path,
         );
 
         maps.push(map);
     }
 
-    let maps = format!(
-        MAPS_TEMPLATE!(),
-        tiles = tiles.join(""),
-        maps = maps.join(""),
-    );
+    let maps = format!(MAPS_TEMPLATE!(), maps = maps.join(""));
 
     write_file_if_changed(env::current_dir()?.join("src").join("maps.rs"), maps)?;
 
     Ok(())
 }
 
+#[rustfmt::skip]
+macro_rules! SOUNDS_TEMPLATE { () => {
+r##"pub const SAMPLE_RATE: u32 = {sample_rate};
+
+{sounds}"##
+}; }
+
+fn parse_sounds(out_dir: &str, pack: &mut AssetPack) -> Result<(), Box<dyn Error>> {
+    let mut sounds = String::new();
+
+    for path in fs::read_dir("sound")?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| {
+            path.extension() == Some(OsStr::new("sfs"))
+                || path.extension() == Some(OsStr::new("wav"))
+        })
+    {
+        println!("rerun-if-changed={}", path.to_string_lossy());
+
+        let name = path
+            .file_name()
+            .ok_or("No File Name")?
+            .to_str()
+            .ok_or("Bad Os String")?;
+        let stem = path
+            .file_stem()
+            .ok_or("No File Stem")?
+            .to_str()
+            .ok_or("Bad Os String")?;
+
+        let data = fs::read(&path)?;
+
+        let (samples, sample_rate) = if path.extension() == Some(OsStr::new("sfs")) {
+            let params = Params::from_bytes(&data)
+                .map_err(|e| format!("Unable to parse {}: {:?}", path.to_string_lossy(), e))?;
+
+            // The parameters are kept for variations made at runtime.
+            pack.add(format!("sound/{}", name), data);
+
+            (
+                sfxr::render(&params, DEFAULT_SAMPLE_RATE, SFXR_SEED),
+                DEFAULT_SAMPLE_RATE,
+            )
+        } else {
+            let wav = Wav::parse(&data)
+                .map_err(|e| format!("Unable to parse {}: {:?}", path.to_string_lossy(), e))?;
+
+            (wav.to_mono(), wav.sample_rate)
+        };
+
+        let encoded = adpcm::encode(&samples, sample_rate);
+        let out_path = Path::new(out_dir).join(format!("{}.adpcm", stem));
+        write_binary_file_if_changed(&out_path, &encoded)?;
+
+        sounds.push_str(&format!(
+            "pub static {name}: &[u8] = include_bytes!({path:?});\n",
+            name = stem.to_uppercase(),
+            path = out_path,
+        ));
+    }
+
+    let sounds = format!(
+        SOUNDS_TEMPLATE!(),
+        sample_rate = DEFAULT_SAMPLE_RATE,
+        sounds = sounds,
+    );
+
+    write_file_if_changed(Path::new(out_dir).join("sound_includes.rs"), sounds)?;
+
+    Ok(())
+}
+
+fn parse_songs(pack: &mut AssetPack) -> Result<(), Box<dyn Error>> {
+    for path in fs::read_dir("songs")?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| {
+            path.extension() == Some(OsStr::new("mod"))
+                || path.extension() == Some(OsStr::new("xm"))
+                || path.extension() == Some(OsStr::new("mid"))
+        })
+    {
+        println!("rerun-if-changed={}", path.to_string_lossy());
+
+        let name = path
+            .file_name()
+            .ok_or("No File Name")?
+            .to_str()
+            .ok_or("Bad Os String")?;
+        let stem = path
+            .file_stem()
+            .ok_or("No File Stem")?
+            .to_str()
+            .ok_or("Bad Os String")?;
+
+        let data = fs::read(&path)?;
+
+        if path.extension() == Some(OsStr::new("mid")) {
+            let sequence = midi::import(&data)
+                .map_err(|e| format!("Unable to parse {}: {:?}", path.to_string_lossy(), e))?;
+
+            pack.add(format!("songs/{}.chip", stem), sequence);
+        } else {
+            // Checked here so a broken song fails the build instead of the game.
+            Module::parse(&data)
+                .map_err(|e| format!("Unable to parse {}: {:?}", path.to_string_lossy(), e))?;
+
+            pack.add(format!("songs/{}", name), data);
+        }
+    }
+
+    Ok(())
+}
+
+// Long music is streamed from the pack while it plays instead of loaded.
+// Mono is ADPCM compressed, stereo stays 16 bit PCM.
+fn parse_music(pack: &mut AssetPack) -> Result<(), Box<dyn Error>> {
+    if !Path::new("music").exists() {
+        return Ok(());
+    }
+
+    for path in fs::read_dir("music")?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.extension() == Some(OsStr::new("wav")))
+    {
+        println!("rerun-if-changed={}", path.to_string_lossy());
+
+        let stem = path
+            .file_stem()
+            .ok_or("No File Stem")?
+            .to_str()
+            .ok_or("Bad Os String")?;
+
+        let data = fs::read(&path)?;
+        let wav = Wav::parse(&data)
+            .map_err(|e| format!("Unable to parse {}: {:?}", path.to_string_lossy(), e))?;
+
+        match wav.channels {
+            1 => pack.add(
+                format!("music/{}.adpcm", stem),
+                adpcm::encode(&wav.samples, wav.sample_rate),
+            ),
+            2 => pack.add(
+                format!("music/{}.wav", stem),
+                wav::write(&wav.samples, 2, wav.sample_rate),
+            ),
+            _ => {
+                return Err(format!(
+                    "Unable to stream {}: only mono and stereo is supported",
+                    path.to_string_lossy()
+                )
+                .into())
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let out_dir = env::var("OUT_DIR")?;
+    let mut pack = AssetPack::new();
 
     parse_textures(&out_dir)?;
-    parse_maps(&out_dir)?;
+    parse_maps(&mut pack)?;
+    parse_sounds(&out_dir, &mut pack)?;
+    parse_songs(&mut pack)?;
+    parse_music(&mut pack)?;
+
+    let pack_path = env::current_dir()?.join("assets.pak");
+    write_binary_file_if_changed(&pack_path, pack.to_bytes()?)?;
+    println!("cargo:rustc-env=ASSET_PACK={}", pack_path.to_string_lossy());
 
     Ok(())
 }
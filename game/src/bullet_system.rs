@@ -4,10 +4,11 @@ use crate::components::movable::{self, MovableComponent};
 use crate::enemy_system::{EnemySystem, ENEMY_SIZE};
 use crate::entity::{self, OwnedEntity};
 use crate::{Player, SHIP_SIZE};
-use alloc::vec::Vec;
+use n64_alloc::{Arena, ArenaVec, Pool};
 use n64_math::{self, Aabb2, Color, Vec2};
 
 const BULLET_SIZE: Vec2 = Vec2::new(0.00625, 0.00625);
+const MAX_BULLETS: usize = 512;
 
 struct Bullet {
     entity: OwnedEntity,
@@ -16,14 +17,14 @@ struct Bullet {
 }
 
 pub struct BulletSystem {
-    bullets: Vec<Bullet>,
+    bullets: Pool<Bullet>,
     screen_bb: Aabb2,
 }
 
 impl BulletSystem {
     pub fn new() -> Self {
         Self {
-            bullets: Vec::new(),
+            bullets: Pool::new(MAX_BULLETS),
             screen_bb: Aabb2::new(Vec2::zero(), Vec2::new(1.0, 1.0)),
         }
     }
@@ -51,7 +52,9 @@ impl BulletSystem {
             },
         );
 
-        self.bullets.push(Bullet {
+        // When the pool is full the bullet is dropped, which destroys its
+        // entity.
+        let _ = self.bullets.insert(Bullet {
             entity,
             can_hit_player: false,
             can_hit_enemy: true,
@@ -73,22 +76,27 @@ impl BulletSystem {
             },
         );
 
-        self.bullets.push(Bullet {
+        let _ = self.bullets.insert(Bullet {
             entity: entity,
             can_hit_player: true,
             can_hit_enemy: false,
         });
     }
 
-    pub fn update(&mut self, enemy_system: &mut EnemySystem, player: &mut Player) {
-        let mut delete_list = Vec::new();
+    pub fn update(
+        &mut self,
+        frame_arena: &Arena,
+        enemy_system: &mut EnemySystem,
+        player: &mut Player,
+    ) {
+        let mut delete_list = ArenaVec::new(frame_arena);
 
-        for (i, bullet) in self.bullets.iter_mut().enumerate() {
+        for (handle, bullet) in self.bullets.iter_mut() {
             if let Some(movable) = movable::get_component(&bullet.entity) {
                 let bullet_bb = Aabb2::from_center_size(movable.pos, BULLET_SIZE);
 
                 if !bullet_bb.collides(&self.screen_bb) {
-                    delete_list.push(i);
+                    delete_list.push(handle);
                 }
 
                 if bullet.can_hit_enemy {
@@ -103,7 +111,7 @@ impl BulletSystem {
                                 enemy.entity(),
                                 50 + (n64_math::random_f32() * 20.0) as i32,
                             );
-                            delete_list.push(i);
+                            delete_list.push(handle);
                         }
                     }
                 }
@@ -119,20 +127,16 @@ impl BulletSystem {
                             player.entity(),
                             50 + (n64_math::random_f32() * 20.0) as i32,
                         );
-                        delete_list.push(i);
+                        delete_list.push(handle);
                     }
                 }
             }
         }
 
-        {
-            let len = self.bullets.len();
-
-            for (i, delete_index) in delete_list.iter().enumerate() {
-                self.bullets.swap(*delete_index, len - 1 - i);
-            }
-
-            self.bullets.drain((len - delete_list.len())..);
+        // A bullet can be on the list more than once, removing a stale handle
+        // does nothing.
+        for handle in delete_list.iter() {
+            self.bullets.remove(*handle);
         }
     }
 }
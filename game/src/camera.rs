@@ -1,4 +1,4 @@
-use n64::Controllers;
+use n64::{Buttons, ControllerState};
 use n64_math::Vec2;
 
 pub struct Camera {
@@ -10,20 +10,20 @@ impl Camera {
         Self { pos: Vec2::zero() }
     }
 
-    pub fn update(&mut self, controllers: &Controllers) {
-        if controllers.c_up() {
+    pub fn update(&mut self, controller: &ControllerState) {
+        if controller.pressed(Buttons::C_UP) {
             self.pos.set_y(self.pos.y() - 10.0);
         }
 
-        if controllers.c_down() {
+        if controller.pressed(Buttons::C_DOWN) {
             self.pos.set_y(self.pos.y() + 10.0);
         }
 
-        if controllers.c_left() {
+        if controller.pressed(Buttons::C_LEFT) {
             self.pos.set_x(self.pos.x() - 10.0);
         }
 
-        if controllers.c_right() {
+        if controller.pressed(Buttons::C_RIGHT) {
             self.pos.set_x(self.pos.x() + 10.0);
         }
     }
@@ -1,15 +1,22 @@
 use crate::components::systems;
 use crate::entity::Entity;
 use crate::impl_system;
+use crate::rumble;
 
 #[derive(Copy, Clone)]
 pub struct HealthComponent {
     pub health: i32,
+    /// Controller that rumbles when the entity takes damage.
+    pub rumble_port: Option<usize>,
 }
 
 pub fn damage(entity: &Entity, damage: i32) {
     if let Some(component) = lock_mut().lookup_mut(entity) {
         component.health = i32::max(0, component.health - damage);
+
+        if let Some(port) = component.rumble_port {
+            rumble::hit(port);
+        }
     }
 }
 
@@ -3,9 +3,10 @@ use crate::components::box_drawable::{self, BoxDrawableComponent};
 use crate::components::health::{self, HealthComponent};
 use crate::components::movable::{self, MovableComponent};
 use crate::entity::{self, Entity, OwnedEntity};
+use crate::sound;
 use crate::Player;
 use alloc::vec::Vec;
-use n64::current_time_us;
+use n64_alloc::{Arena, ArenaVec};
 use n64_math::{self, Color, Vec2};
 
 pub const ENEMY_SIZE: Vec2 = Vec2::new(0.05, 0.05);
@@ -53,7 +54,13 @@ impl EnemySystem {
                 ),
             },
         );
-        health::add(&entity, HealthComponent { health: 100 });
+        health::add(
+            &entity,
+            HealthComponent {
+                health: 100,
+                rumble_port: None,
+            },
+        );
 
         self.enemies.push(Enemy {
             entity,
@@ -62,14 +69,19 @@ impl EnemySystem {
         });
     }
 
-    pub fn update(&mut self, bullet_system: &mut BulletSystem, player: &mut Player) {
-        let mut delete_list = Vec::new();
-
-        let now = current_time_us();
+    pub fn update(
+        &mut self,
+        frame_arena: &Arena,
+        bullet_system: &mut BulletSystem,
+        player: &mut Player,
+        now: i64,
+    ) {
+        let mut delete_list = ArenaVec::new(frame_arena);
 
         for (i, enemy) in self.enemies_mut().iter_mut().enumerate() {
             if !health::is_alive(&enemy.entity) {
                 player.add_score(1000);
+                sound::explosion();
                 delete_list.push(i);
             }
 
@@ -84,14 +96,10 @@ impl EnemySystem {
             }
         }
 
-        {
-            let len = self.enemies.len();
-
-            for (i, delete_index) in delete_list.iter().enumerate() {
-                self.enemies.swap(*delete_index, len - 1 - i);
-            }
-
-            self.enemies.drain((len - delete_list.len())..);
+        // Indices are in ascending order, removing from the back keeps the
+        // remaining ones valid.
+        while let Some(index) = delete_list.pop() {
+            self.enemies.swap_remove(index);
         }
     }
 
@@ -18,11 +18,16 @@ use map::Map;
 use maps::MAP_1;
 use n64::{
     self, current_time_us,
+    fs::Fs,
     gfx::{CommandBuffer, CommandBufferCache},
-    ipl3font, slow_cpu_clear, VideoMode, N64,
+    ipl3font, slow_cpu_clear, AudioConfig, Buttons, Mixer, Sample, VideoMode,
+    DEFAULT_BUFFER_FRAMES, DEFAULT_SAMPLE_RATE, N64,
 };
+use n64_alloc::Arena;
 use n64_math::Color;
 use player::{Player, SHIP_SIZE};
+use save::Save;
+use sound::Sound;
 
 mod bullet_system;
 mod camera;
@@ -32,159 +37,278 @@ mod entity;
 mod map;
 mod maps;
 mod player;
+#[cfg(not(target_vendor = "nintendo64"))]
+mod replay_file;
+mod rumble;
+mod save;
+mod sound;
+mod sounds;
 mod textures;
 
 const RED: Color = Color::new(0b10000_00011_00011_1);
 const GREEN: Color = Color::new(0b00011_10000_00011_1);
 const BLUE: Color = Color::new(0b00011_00011_10000_1);
 
+const FRAME_ARENA_SIZE: usize = 16 * 1024;
+
+// Walking the heap for the overlay takes a while, it is done once a second.
+#[cfg(target_vendor = "nintendo64")]
+const HEAP_STATS_INTERVAL: u32 = 50;
+
+const VOICE_COUNT: usize = 16;
+
+const AUDIO_CONFIG: AudioConfig = AudioConfig {
+    sample_rate: DEFAULT_SAMPLE_RATE,
+    buffer_frames: DEFAULT_BUFFER_FRAMES,
+};
+
 const VIDEO_MODE: VideoMode = VideoMode::Pal {
     width: 320,
     height: 240,
 };
 
 fn main() {
-    let mut n64 = N64::new(VIDEO_MODE);
+    let mut n64 = N64::new(VIDEO_MODE, AUDIO_CONFIG);
+    let mut fs = Fs::new(env!("ASSET_PACK")).expect("Asset pack not found");
 
-    let mut camera = Camera::new();
-    let mut player = Player::new();
-    let mut bullet_system = BulletSystem::new();
-    let mut enemy_system = EnemySystem::new();
     let mut command_buffer_cache = CommandBufferCache::new();
-    let map = Map::load(MAP_1);
+    let mut frame_arena = Arena::new(FRAME_ARENA_SIZE);
+    let map = {
+        #[cfg(all(target_vendor = "nintendo64", feature = "alloc-debug"))]
+        let _tag = n64_alloc::debug::tag("map");
+        Map::load(&mut fs, MAP_1)
+    };
 
     let mut frame_begin_time;
     let mut last_frame_begin_time = current_time_us();
     let mut frame_used_time = 0;
     let mut dt;
+    // Sum of frame times, game logic uses it instead of the clock so that
+    // replays run the same.
+    let mut game_time_us = 0;
+
+    #[cfg(target_vendor = "nintendo64")]
+    let mut heap_stats = ALLOC.stats();
+    #[cfg(target_vendor = "nintendo64")]
+    let mut heap_stats_countdown = HEAP_STATS_INTERVAL;
+
+    #[cfg(not(target_vendor = "nintendo64"))]
+    let mut replay_file = replay_file::ReplayFile::from_args(&mut n64.controllers, &mut n64.audio);
+
+    let sample_rate = n64.audio.real_frequency();
+    let mut sound = Sound::new(&mut fs, sample_rate, AUDIO_CONFIG.buffer_frames);
+    let mut mixer = Mixer::new(VOICE_COUNT, sample_rate);
+
+    // Without a song there is at least a tone.
+    if !sound.has_music() {
+        let tone = Sample::from_vec(
+            (0..128)
+                .map(|i| if i < 64 { 5000 } else { -5000 })
+                .collect(),
+            sample_rate,
+        )
+        .looping();
+        mixer.play(&tone);
+    }
 
-    enemy_system.spawn_enemy();
-    enemy_system.spawn_enemy();
-    enemy_system.spawn_enemy();
-    enemy_system.spawn_enemy();
-    enemy_system.spawn_enemy();
-    enemy_system.spawn_enemy();
+    let mut save = Save::load(&mut n64.saves);
+    rumble::set_enabled(save.rumble);
 
     loop {
-        frame_begin_time = current_time_us();
+        let mut camera = Camera::new();
+        let mut player = Player::new();
+        let mut bullet_system = BulletSystem::new();
+        let mut enemy_system = EnemySystem::new();
+
+        enemy_system.spawn_enemy();
+        enemy_system.spawn_enemy();
+        enemy_system.spawn_enemy();
+        enemy_system.spawn_enemy();
+        enemy_system.spawn_enemy();
+        enemy_system.spawn_enemy();
+
+        loop {
+            frame_begin_time = current_time_us();
+            frame_arena.reset();
 
-        {
-            dt = (frame_begin_time - last_frame_begin_time) as f32 / 1e6;
-            last_frame_begin_time = frame_begin_time;
-        }
+            {
+                let frame_time_us = n64
+                    .controllers
+                    .update(&n64.graphics, frame_begin_time - last_frame_begin_time);
+                dt = frame_time_us as f32 / 1e6;
+                game_time_us += frame_time_us;
+                last_frame_begin_time = frame_begin_time;
+
+                rumble::update(&mut n64.controllers, frame_time_us);
+
+                #[cfg(not(target_vendor = "nintendo64"))]
+                replay_file.update(&n64.controllers, player.score());
+            }
 
-        {
-            // Update
+            {
+                // Update
+
+                camera.update(n64.controllers.port(0));
+
+                {
+                    #[cfg(all(target_vendor = "nintendo64", feature = "alloc-debug"))]
+                    let _tag = n64_alloc::debug::tag("enemies");
+                    enemy_system.update(
+                        &frame_arena,
+                        &mut bullet_system,
+                        &mut player,
+                        game_time_us,
+                    );
+                }
 
-            n64.controllers.update(&n64.graphics);
+                player.update(n64.controllers.port(0), &mut bullet_system, game_time_us);
 
-            camera.update(&n64.controllers);
+                {
+                    #[cfg(all(target_vendor = "nintendo64", feature = "alloc-debug"))]
+                    let _tag = n64_alloc::debug::tag("bullets");
+                    bullet_system.update(&frame_arena, &mut enemy_system, &mut player);
+                }
 
-            enemy_system.update(&mut bullet_system, &mut player);
+                movable::simulate(dt);
 
-            player.update(&n64.controllers, &mut bullet_system);
+                if !health::is_alive(player.entity()) {
+                    #[cfg(not(target_vendor = "nintendo64"))]
+                    replay_file.finish(player.score());
 
-            bullet_system.update(&mut enemy_system, &mut player);
+                    break;
+                }
+            }
 
-            movable::simulate(dt);
+            {
+                // Audio
 
-            if !health::is_alive(player.entity()) {
-                break;
+                sound.update(&mut mixer, game_time_us);
+                n64.audio.update(|buffer| sound.mix(&mut mixer, buffer));
             }
-        }
 
-        {
-            // Audio
+            {
+                // Graphics
+
+                let (colored_rect_count, textured_rect_count) = {
+                    let mut fb = n64.framebuffer.next_buffer();
+                    let mut cb = CommandBuffer::new(&mut fb, &mut command_buffer_cache);
+
+                    cb.clear();
+
+                    map.render(&mut cb, VIDEO_MODE, &camera);
+                    box_drawable::draw(&mut cb, VIDEO_MODE, &camera);
+                    sprite_drawable::draw(&mut cb, VIDEO_MODE, &camera);
 
-            n64.audio.update(|buffer| {
-                for (i, chunk) in buffer.chunks_mut(128).enumerate() {
-                    for sample in chunk {
-                        if i % 2 == 0 {
-                            *sample = 5000;
-                        } else {
-                            *sample = -5000;
+                    cb.run(&mut n64.graphics)
+                };
+
+                {
+                    let mut fb = n64.framebuffer.next_buffer();
+
+                    ipl3font::draw_number(&mut fb, 300, 10, BLUE, player.score());
+                    ipl3font::draw_number(
+                        &mut fb,
+                        300,
+                        215,
+                        BLUE,
+                        health::get_component(player.entity())
+                            .map(|hc| hc.health)
+                            .unwrap_or(0),
+                    );
+
+                    #[cfg(target_vendor = "nintendo64")]
+                    {
+                        use core::fmt::Write;
+
+                        heap_stats_countdown -= 1;
+                        if heap_stats_countdown == 0 {
+                            heap_stats = ALLOC.stats();
+                            heap_stats_countdown = HEAP_STATS_INTERVAL;
                         }
+
+                        let mut buffer = [0; 256];
+                        let mut stats = ipl3font::TextBuffer::new(&mut buffer);
+                        let _ = write!(stats, "{}", heap_stats.text(1));
+                        ipl3font::draw_str(&mut fb, 15, 120, RED, stats.as_bytes());
+
+                        #[cfg(feature = "alloc-debug")]
+                        {
+                            let mut buffer = [0; 256];
+                            let mut report = ipl3font::TextBuffer::new(&mut buffer);
+                            let _ = writeln!(report, "LIVE {}", n64_alloc::debug::live_count());
+                            let _ = n64_alloc::debug::write_report(&mut report, 3);
+                            ipl3font::draw_str(&mut fb, 15, 50, RED, report.as_bytes());
+                        }
+                    }
+
+                    {
+                        ipl3font::draw_number(&mut fb, 100, 10, RED, (dt * 1000.0 * 1000.0) as i32);
+                        ipl3font::draw_number(&mut fb, 200, 10, RED, frame_used_time as i32);
+                        ipl3font::draw_number(&mut fb, 100, 30, GREEN, colored_rect_count);
+                        ipl3font::draw_number(&mut fb, 200, 30, GREEN, textured_rect_count);
                     }
                 }
-            });
+
+                let frame_end_time = n64.graphics.swap_buffers(&mut n64.framebuffer);
+                frame_used_time = frame_end_time - frame_begin_time;
+            }
         }
 
-        {
-            // Graphics
+        let score = player.score();
+        let place = save.add_score(score);
+        let mut saved = place.is_none() || n64.saves.store(&save).is_ok();
+
+        loop {
+            frame_begin_time = current_time_us();
+
+            let frame_time_us = n64
+                .controllers
+                .update(&n64.graphics, frame_begin_time - last_frame_begin_time);
+            last_frame_begin_time = frame_begin_time;
 
-            let (colored_rect_count, textured_rect_count) = {
-                let mut fb = n64.framebuffer.next_buffer();
-                let mut cb = CommandBuffer::new(&mut fb, &mut command_buffer_cache);
+            rumble::update(&mut n64.controllers, frame_time_us);
 
-                cb.clear();
+            let controller = n64.controllers.port(0);
 
-                map.render(&mut cb, VIDEO_MODE, &camera);
-                box_drawable::draw(&mut cb, VIDEO_MODE, &camera);
-                sprite_drawable::draw(&mut cb, VIDEO_MODE, &camera);
+            if controller.just_pressed(Buttons::START) {
+                break;
+            }
 
-                cb.run(&mut n64.graphics)
-            };
+            if controller.just_pressed(Buttons::L) {
+                save.rumble = !save.rumble;
+                rumble::set_enabled(save.rumble);
+                saved = n64.saves.store(&save).is_ok();
+            }
 
             {
-                let mut fb = n64.framebuffer.next_buffer();
-
-                ipl3font::draw_number(&mut fb, 300, 10, BLUE, player.score());
-                ipl3font::draw_number(
-                    &mut fb,
-                    300,
-                    215,
-                    BLUE,
-                    health::get_component(player.entity())
-                        .map(|hc| hc.health)
-                        .unwrap_or(0),
-                );
-
-                #[cfg(target_vendor = "nintendo64")]
-                {
-                    ipl3font::draw_number(
-                        &mut fb,
-                        100,
-                        160,
-                        RED,
-                        n64_alloc::BYTES_USED.load(core::sync::atomic::Ordering::SeqCst),
-                    );
-                    ipl3font::draw_number(
-                        &mut fb,
-                        100,
-                        180,
-                        RED,
-                        n64_alloc::BYTES_LEFT.load(core::sync::atomic::Ordering::SeqCst),
-                    );
-                    ipl3font::draw_number(
-                        &mut fb,
-                        100,
-                        200,
-                        RED,
-                        *n64_alloc::PAGE_OFFSET.lock() as i32,
-                    );
+                let mut out_tex = n64.framebuffer.next_buffer();
+                slow_cpu_clear(out_tex.data);
+                ipl3font::draw_str(&mut out_tex, 50, 10, RED, b"GAME OVER");
+                ipl3font::draw_number(&mut out_tex, 250, 10, BLUE, score);
+
+                ipl3font::draw_str(&mut out_tex, 50, 50, BLUE, b"HIGH SCORES");
+                for (i, &high_score) in save.high_scores.iter().enumerate() {
+                    let color = if place == Some(i) { GREEN } else { BLUE };
+                    let y = 70 + 16 * i as i32;
+                    ipl3font::draw_number(&mut out_tex, 50, y, color, i as i32 + 1);
+                    ipl3font::draw_number(&mut out_tex, 250, y, color, high_score);
                 }
 
-                {
-                    ipl3font::draw_number(&mut fb, 100, 10, RED, (dt * 1000.0 * 1000.0) as i32);
-                    ipl3font::draw_number(&mut fb, 200, 10, RED, frame_used_time as i32);
-                    ipl3font::draw_number(&mut fb, 100, 30, GREEN, colored_rect_count);
-                    ipl3font::draw_number(&mut fb, 200, 30, GREEN, textured_rect_count);
+                let rumble_text: &[u8] = if save.rumble {
+                    b"L RUMBLE ON"
+                } else {
+                    b"L RUMBLE OFF"
+                };
+                ipl3font::draw_str(&mut out_tex, 50, 170, BLUE, rumble_text);
+                ipl3font::draw_str(&mut out_tex, 50, 190, BLUE, b"START PLAY AGAIN");
+
+                if !saved {
+                    ipl3font::draw_str(&mut out_tex, 50, 210, RED, b"SAVE FAILED");
                 }
             }
 
-            let frame_end_time = n64.graphics.swap_buffers(&mut n64.framebuffer);
-            frame_used_time = frame_end_time - frame_begin_time;
-        }
-    }
-
-    loop {
-        {
-            let mut out_tex = n64.framebuffer.next_buffer();
-            slow_cpu_clear(out_tex.data);
-            ipl3font::draw_str(&mut out_tex, 50, 10, RED, b"GAME OVER");
+            n64.graphics.swap_buffers(&mut n64.framebuffer);
         }
-
-        n64.graphics.swap_buffers(&mut n64.framebuffer);
     }
 }
 
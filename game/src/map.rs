@@ -1,5 +1,10 @@
-use crate::{camera::Camera, maps::MAP_1_TILES};
-use n64::{gfx::CommandBuffer, VideoMode};
+use crate::camera::Camera;
+use alloc::{boxed::Box, vec::Vec};
+use n64::{
+    fs::Fs,
+    gfx::{CommandBuffer, StaticTexture},
+    VideoMode,
+};
 use n64_math::Vec2;
 
 pub struct StaticMapData {
@@ -7,16 +12,46 @@ pub struct StaticMapData {
     pub height_in_tiles: i32,
     pub tile_width: i32,
     pub tile_height: i32,
-    pub layers: &'static [u8],
+    pub layers_path: &'static str,
+    pub tiles_path: &'static str,
 }
 
 pub struct Map {
     data: &'static StaticMapData,
+    layers: Vec<u8>,
+    tiles: &'static [u8],
 }
 
 impl Map {
-    pub fn load(data: &'static StaticMapData) -> Self {
-        Self { data }
+    pub fn load(fs: &mut Fs, data: &'static StaticMapData) -> Self {
+        let layers = fs
+            .read_to_vec(data.layers_path)
+            .expect("Map layers missing from asset pack");
+
+        // The RDP draws from the tiles after the frame is built, they stay
+        // loaded for as long as the game runs.
+        let tiles = Box::leak(
+            fs.read_to_vec(data.tiles_path)
+                .expect("Map tiles missing from asset pack")
+                .into_boxed_slice(),
+        );
+
+        Self {
+            data,
+            layers,
+            tiles,
+        }
+    }
+
+    fn tile(&self, index: usize) -> StaticTexture {
+        let tiles: &'static [u8] = self.tiles;
+        let size = 2 * (self.data.tile_width * self.data.tile_height) as usize;
+
+        StaticTexture::from_static(
+            self.data.tile_width,
+            self.data.tile_height,
+            &tiles[index * size..][..size],
+        )
     }
 
     pub fn render(&self, cb: &mut CommandBuffer, video_mode: VideoMode, camera: &Camera) {
@@ -33,7 +68,7 @@ impl Map {
         let first_tile_x = camera_tile.x() as i32;
         let first_tile_y = camera_tile.y() as i32;
 
-        for layer in self.data.layers.chunks_exact(tiles_in_layer) {
+        for layer in self.layers.chunks_exact(tiles_in_layer) {
             for y in first_tile_y..(first_tile_y + tiles_on_screen_y) {
                 if y < 0 || y >= self.data.height_in_tiles {
                     continue;
@@ -64,7 +99,7 @@ impl Map {
                     cb.add_textured_rect(
                         upper_left - camera.pos,
                         lower_right - camera.pos,
-                        MAP_1_TILES[(tile - 1) as usize].as_texture(),
+                        self.tile((tile - 1) as usize).as_texture(),
                     );
                 }
             }
@@ -4,12 +4,13 @@ use crate::components::movable::{self, MovableComponent};
 use crate::components::sprite_drawable::{self, SpriteDrawableComponent};
 use crate::entity::{self, Entity, OwnedEntity};
 use crate::textures::SHIP_2_SMALL;
-use n64::{current_time_us, Controllers};
+use n64::{Buttons, ControllerState};
 use n64_math::Vec2;
 
 const START_POS: Vec2 = Vec2::new(0.5, 0.8);
 const SHIP_SPEED: f32 = 0.35;
 const SHIP_SHOOT_DELAY_MS: i32 = 150;
+const STICK_DEADZONE: f32 = 0.25;
 pub const SHIP_SIZE: Vec2 = Vec2::new(32.0 / 320.0 as f32, 32.0 / 240.0 as f32);
 
 pub struct Player {
@@ -40,7 +41,13 @@ impl Player {
                 texture: SHIP_2_SMALL.as_texture(),
             },
         );
-        health::add(&player.entity, HealthComponent { health: 5000 });
+        health::add(
+            &player.entity,
+            HealthComponent {
+                health: 5000,
+                rumble_port: Some(0),
+            },
+        );
 
         player
     }
@@ -57,35 +64,31 @@ impl Player {
         self.score
     }
 
-    pub fn update(&mut self, controllers: &Controllers, bullet_system: &mut BulletSystem) {
-        let controller_x = controllers.x();
-        let controller_y = controllers.y();
-
-        let mut controller_dir = Vec2::new(0.0, 0.0);
-
-        if controller_x.abs() > 32 {
-            controller_dir.set_x(if controller_x > 0 { 1.0 } else { -1.0 });
-        }
-
-        if controller_y.abs() > 32 {
-            controller_dir.set_y(if controller_y > 0 { -1.0 } else { 1.0 });
-        }
+    pub fn update(
+        &mut self,
+        controller: &ControllerState,
+        bullet_system: &mut BulletSystem,
+        now: i64,
+    ) {
+        let stick = controller.stick(STICK_DEADZONE);
 
         if let Some(movable) = movable::lock_mut().lookup_mut(&self.entity) {
-            movable.speed = SHIP_SPEED * controller_dir;
+            // Stick y is up, screen y is down.
+            movable.speed = SHIP_SPEED * Vec2::new(stick.x(), -stick.y());
         }
 
         if let Some(movable) = movable::get_component(&self.entity) {
-            let now = current_time_us();
+            // Fire on press, then repeat while held.
+            let shoot = controller.just_pressed(Buttons::Z)
+                || (controller.pressed(Buttons::Z)
+                    && now - self.last_shoot_time > SHIP_SHOOT_DELAY_MS as i64 * 1000);
 
-            if now - self.last_shoot_time > SHIP_SHOOT_DELAY_MS as i64 * 1000 {
-                if controllers.z() {
-                    bullet_system.shoot_bullet(
-                        movable.pos + Vec2::new(0.0, -SHIP_SIZE.y() / 2.0),
-                        Vec2::new(0.0, movable.speed.y() - 0.65),
-                    );
-                    self.last_shoot_time = now;
-                }
+            if shoot {
+                bullet_system.shoot_bullet(
+                    movable.pos + Vec2::new(0.0, -SHIP_SIZE.y() / 2.0),
+                    Vec2::new(0.0, movable.speed.y() - 0.65),
+                );
+                self.last_shoot_time = now;
             }
         }
     }
@@ -0,0 +1,136 @@
+// Input recording on the PC build.
+//
+// `--record <file>` writes the session to a file as it is played.
+// `--replay <file>` plays a recording back and exits when it ends, printing the
+// final score. With `--expect-score <score>` it exits with an error if the
+// score differs, for replay based regression tests.
+// `--audio-wav <file>` writes the audio to a WAV file instead of playing it,
+// and `--audio null` or `--audio null-fast` throws it away, for machines
+// without a sound card.
+
+use n64::{
+    audio_emu::{Audio, AudioSink},
+    Controllers, Recording,
+};
+use std::fs::{self, File};
+use std::io::Write;
+use std::process::exit;
+
+pub struct ReplayFile {
+    record: Option<File>,
+    written: usize,
+    expect_score: Option<i32>,
+    playing_back: bool,
+}
+
+impl ReplayFile {
+    /// Seeds the random generator, starts recording or playback and picks
+    /// the audio sink.
+    pub fn from_args(controllers: &mut Controllers, audio: &mut Audio) -> ReplayFile {
+        let mut replay_file = ReplayFile {
+            record: None,
+            written: 0,
+            expect_score: None,
+            playing_back: false,
+        };
+
+        let mut seed = n64_math::rand::DEFAULT_SEED;
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.next()) {
+                ("--record", Some(path)) => {
+                    let file = File::create(&path).unwrap_or_else(|e| {
+                        println!("Unable to create recording {}: {}", path, e);
+                        exit(1);
+                    });
+
+                    controllers.start_recording(seed);
+                    replay_file.record = Some(file);
+                }
+                ("--replay", Some(path)) => {
+                    let recording = fs::read(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|data| {
+                            Recording::from_bytes(data).map_err(|e| format!("{:?}", e))
+                        })
+                        .unwrap_or_else(|e| {
+                            println!("Unable to load recording {}: {}", path, e);
+                            exit(1);
+                        });
+
+                    seed = recording.seed();
+                    controllers.start_playback(recording);
+                    replay_file.playing_back = true;
+                }
+                ("--expect-score", Some(score)) => {
+                    replay_file.expect_score = Some(score.parse().unwrap_or_else(|_| {
+                        println!("Bad score {}", score);
+                        exit(1);
+                    }));
+                }
+                ("--audio", Some(sink)) => {
+                    audio.set_sink(match sink.as_str() {
+                        "device" => AudioSink::Device,
+                        "null" => AudioSink::Null { real_time: true },
+                        "null-fast" => AudioSink::Null { real_time: false },
+                        _ => {
+                            println!("Unknown audio sink {}", sink);
+                            exit(1);
+                        }
+                    });
+                }
+                ("--audio-wav", Some(path)) => {
+                    audio.set_sink(AudioSink::Wav {
+                        path: path.into(),
+                        real_time: true,
+                    });
+                }
+                (arg, _) => {
+                    println!("Unknown argument {}", arg);
+                    exit(1);
+                }
+            }
+        }
+
+        n64_math::seed_random(seed);
+
+        replay_file
+    }
+
+    /// Call after `Controllers::update`. Writes the newly recorded frames and
+    /// ends the session when playback has ended.
+    pub fn update(&mut self, controllers: &Controllers, score: i32) {
+        if let (Some(file), Some(recording)) = (&mut self.record, controllers.recording()) {
+            let data = recording.as_bytes();
+
+            if let Err(e) = file.write_all(&data[self.written..]) {
+                println!("Unable to write recording: {}", e);
+                self.record = None;
+            }
+
+            self.written = data.len();
+        }
+
+        if self.playing_back && !controllers.is_playing_back() {
+            self.finish(score);
+        }
+    }
+
+    /// Ends a playback session, call on game over.
+    pub fn finish(&mut self, score: i32) {
+        if !self.playing_back {
+            return;
+        }
+
+        println!("Replay ended with score {}", score);
+
+        match self.expect_score {
+            Some(expected) if expected != score => {
+                println!("Expected score {}", expected);
+                exit(1);
+            }
+            _ => exit(0),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use n64::{Accessory, Controllers, MAX_CONTROLLERS};
+use spin::Mutex;
+
+const HIT_RUMBLE_US: i64 = 150_000;
+
+struct Rumble {
+    enabled: bool,
+    remaining_us: [i64; MAX_CONTROLLERS],
+    on: [bool; MAX_CONTROLLERS],
+}
+
+static RUMBLE: Mutex<Rumble> = Mutex::new(Rumble {
+    enabled: true,
+    remaining_us: [0; MAX_CONTROLLERS],
+    on: [false; MAX_CONTROLLERS],
+});
+
+/// Short rumble on the controller in `port`, if it has a Rumble Pak.
+pub fn hit(port: usize) {
+    let mut rumble = RUMBLE.lock();
+
+    if rumble.enabled {
+        rumble.remaining_us[port] = HIT_RUMBLE_US;
+    }
+}
+
+/// Disabling stops the motors on the next `update`.
+pub fn set_enabled(enabled: bool) {
+    let mut rumble = RUMBLE.lock();
+    rumble.enabled = enabled;
+
+    if !enabled {
+        rumble.remaining_us = [0; MAX_CONTROLLERS];
+    }
+}
+
+/// Turns motors on and off, call once per frame.
+pub fn update(controllers: &mut Controllers, frame_time_us: i64) {
+    let mut rumble = RUMBLE.lock();
+
+    for port in 0..MAX_CONTROLLERS {
+        let on = rumble.remaining_us[port] > 0;
+        rumble.remaining_us[port] = i64::max(0, rumble.remaining_us[port] - frame_time_us);
+
+        if on != rumble.on[port] && controllers.port(port).accessory() == Accessory::RumblePak {
+            // Tried again next frame if it fails.
+            if controllers.set_rumble(port, on).is_ok() {
+                rumble.on[port] = on;
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use alloc::vec::Vec;
+use n64::{SaveData, Saves};
+
+pub const HIGH_SCORE_COUNT: usize = 5;
+
+/// High scores and settings, kept in the cartridge's save chip.
+pub struct Save {
+    /// Best first.
+    pub high_scores: [i32; HIGH_SCORE_COUNT],
+    pub rumble: bool,
+}
+
+impl Default for Save {
+    fn default() -> Save {
+        Save {
+            high_scores: [0; HIGH_SCORE_COUNT],
+            rumble: true,
+        }
+    }
+}
+
+impl Save {
+    /// Falls back to defaults when nothing is saved or the cartridge has no
+    /// save chip.
+    pub fn load(saves: &mut Saves) -> Save {
+        saves.load().unwrap_or_default()
+    }
+
+    /// Returns the place in the table, if the score made it in.
+    pub fn add_score(&mut self, score: i32) -> Option<usize> {
+        let place = self
+            .high_scores
+            .iter()
+            .position(|&high_score| score > high_score)?;
+
+        for i in (place + 1..HIGH_SCORE_COUNT).rev() {
+            self.high_scores[i] = self.high_scores[i - 1];
+        }
+        self.high_scores[place] = score;
+
+        Some(place)
+    }
+}
+
+impl SaveData for Save {
+    const VERSION: u16 = 1;
+
+    fn write(&self, out: &mut Vec<u8>) {
+        for score in self.high_scores.iter() {
+            out.extend_from_slice(&score.to_be_bytes());
+        }
+        out.push(self.rumble as u8);
+    }
+
+    fn read(version: u16, data: &[u8]) -> Option<Save> {
+        if version != Self::VERSION || data.len() != HIGH_SCORE_COUNT * 4 + 1 {
+            return None;
+        }
+
+        let mut save = Save::default();
+        for (score, bytes) in save.high_scores.iter_mut().zip(data.chunks_exact(4)) {
+            *score = i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        save.rumble = data[HIGH_SCORE_COUNT * 4] != 0;
+
+        Some(save)
+    }
+}
@@ -0,0 +1,150 @@
+use crate::sounds::{self, EXPLOSION_0};
+use alloc::{string::String, vec::Vec};
+use n64::{fs::Fs, Mixer, Sample, Scheduler, Stream};
+use n64_audio::{
+    chiptune::Synth,
+    effects::{self, Bus, Ducker, OnePole, Reverb},
+    sfxr::{self, Params},
+    tracker::{Module, Player},
+};
+use n64_math::rand::Rng;
+use spin::Mutex;
+
+const EXPLOSION_VARIATIONS: usize = 3;
+
+// Its own generator so sounds do not change the game's random sequence.
+const SOUND_SEED: u32 = 0x5f3d;
+
+static PENDING_EXPLOSIONS: Mutex<u32> = Mutex::new(0);
+
+/// Played on the next `Sound::update`.
+pub fn explosion() {
+    *PENDING_EXPLOSIONS.lock() += 1;
+}
+
+enum Music {
+    // With its own mixer so the music and sound effects go on separate buses.
+    Tracker(Player, Mixer),
+    Chiptune(Synth),
+    Stream(Stream),
+}
+
+pub struct Sound {
+    explosions: Vec<Sample>,
+    rng: Rng,
+    scheduler: Scheduler,
+    music: Option<Music>,
+    music_buffer: Vec<i16>,
+    effects_bus: Bus,
+    music_bus: Bus,
+    ducker: Ducker,
+}
+
+impl Sound {
+    /// Renders variations of the explosion from its sfxr parameters, so
+    /// repeated explosions do not all sound the same. The first streamed
+    /// track in `music/`, or else the first song in `songs/`, plays as
+    /// music at `sample_rate`, ducked under the sound effects.
+    /// `buffer_frames` is the size of the audio buffers, for scheduling.
+    pub fn new(fs: &mut Fs, sample_rate: u32, buffer_frames: usize) -> Sound {
+        let mut rng = Rng::new(SOUND_SEED);
+        let mut explosions = Vec::new();
+        explosions.push(Sample::from_adpcm(EXPLOSION_0).expect("Bad sound data"));
+
+        if let Some(params) = fs
+            .read_to_vec("sound/explosion_0.sfs")
+            .ok()
+            .and_then(|data| Params::from_bytes(&data).ok())
+        {
+            for _ in 1..EXPLOSION_VARIATIONS {
+                let mut params = params.clone();
+                params.mutate(&mut rng);
+
+                let samples = sfxr::render(&params, sounds::SAMPLE_RATE, rng.next_u32());
+                explosions.push(Sample::from_vec(samples, sounds::SAMPLE_RATE));
+            }
+        }
+
+        let song = fs
+            .names()
+            .find(|name| name.starts_with("music/"))
+            .or_else(|| fs.names().find(|name| name.starts_with("songs/")))
+            .map(String::from);
+        let music = song.map(|song| {
+            if song.starts_with("music/") {
+                let reader = fs.reader(&song).expect("Song not found");
+                return Music::Stream(
+                    Stream::new(reader, sample_rate)
+                        .expect("Bad song data")
+                        .looping(),
+                );
+            }
+
+            let data = fs.read_to_vec(&song).expect("Song not found");
+
+            if song.ends_with(".chip") {
+                Music::Chiptune(Synth::new(data, sample_rate).expect("Bad song data"))
+            } else {
+                let module = Module::parse(&data).expect("Bad song data");
+                let mixer = Mixer::new(module.channel_count(), sample_rate);
+                Music::Tracker(Player::new(module), mixer)
+            }
+        });
+
+        Sound {
+            explosions,
+            rng,
+            scheduler: Scheduler::new(buffer_frames),
+            music,
+            music_buffer: Vec::new(),
+            effects_bus: Bus::new().with(Reverb::new(0.3, 0.5, 0.2, sample_rate)),
+            music_bus: Bus::new().with(OnePole::high_pass(30.0, sample_rate)),
+            ducker: Ducker::new(0.05, 0.4, 5.0, 250.0, sample_rate),
+        }
+    }
+
+    #[inline]
+    pub fn has_music(&self) -> bool {
+        self.music.is_some()
+    }
+
+    /// Starts the sounds requested since the last update, call once per frame.
+    /// They play `time_us` apart from one update to the next, whatever the
+    /// audio buffers.
+    pub fn update(&mut self, mixer: &mut Mixer, time_us: i64) {
+        let explosions = core::mem::replace(&mut *PENDING_EXPLOSIONS.lock(), 0);
+
+        for _ in 0..explosions {
+            let index = self.rng.next_u32() as usize % self.explosions.len();
+            self.scheduler
+                .play_at(mixer, &self.explosions[index], time_us);
+        }
+    }
+
+    /// Fills an audio buffer with the sound effects through their bus, and
+    /// the music through its own, ducked under them.
+    pub fn mix(&mut self, mixer: &mut Mixer, buffer: &mut [i16]) {
+        mixer.mix(buffer);
+        self.effects_bus.process(buffer);
+
+        let music = match &mut self.music {
+            Some(music) => music,
+            None => return,
+        };
+
+        self.music_buffer.clear();
+        self.music_buffer.resize(buffer.len(), 0);
+
+        match music {
+            Music::Tracker(player, music_mixer) => {
+                player.render(music_mixer, &mut self.music_buffer)
+            }
+            Music::Chiptune(synth) => synth.render(&mut self.music_buffer),
+            Music::Stream(stream) => stream.render(&mut self.music_buffer),
+        }
+
+        self.music_bus.process(&mut self.music_buffer);
+        self.ducker.process(&mut self.music_buffer, buffer);
+        effects::add(buffer, &self.music_buffer);
+    }
+}
@@ -0,0 +1,299 @@
+use alloc::alloc::{alloc, dealloc, handle_alloc_error};
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::slice;
+
+const ARENA_ALIGN: usize = 16;
+
+/// A bump allocator over a single block taken from the global allocator.
+///
+/// Allocations are only freed all at once by `reset`, which needs `&mut self`
+/// so nothing allocated from the arena can outlive it. Destructors of values
+/// in the arena are never run.
+pub struct Arena {
+    data: NonNull<u8>,
+    capacity: usize,
+    used: Cell<usize>,
+    peak: Cell<usize>,
+}
+
+impl Arena {
+    pub fn new(capacity: usize) -> Arena {
+        let layout = Layout::from_size_align(capacity.max(1), ARENA_ALIGN).unwrap();
+        let data =
+            NonNull::new(unsafe { alloc(layout) }).unwrap_or_else(|| handle_alloc_error(layout));
+
+        Arena {
+            data,
+            capacity,
+            used: Cell::new(0),
+            peak: Cell::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// The most bytes that have been in use since the arena was created.
+    #[inline]
+    pub fn peak(&self) -> usize {
+        self.peak.get()
+    }
+
+    /// Frees everything allocated from the arena.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.used.set(0);
+    }
+
+    fn alloc_layout(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let base = self.data.as_ptr() as usize;
+        let start = (base + self.used.get() + layout.align() - 1) & !(layout.align() - 1);
+        let end = start.checked_add(layout.size())?;
+
+        if end - base > self.capacity {
+            return None;
+        }
+
+        self.used.set(end - base);
+        self.peak.set(self.peak.get().max(end - base));
+
+        NonNull::new(start as *mut u8)
+    }
+
+    // Grows the allocation at `ptr` in place if it is the last one made.
+    fn try_grow_in_place(&self, ptr: NonNull<u8>, old_size: usize, new_size: usize) -> bool {
+        let base = self.data.as_ptr() as usize;
+        let offset = ptr.as_ptr() as usize - base;
+
+        if offset + old_size != self.used.get() || offset + new_size > self.capacity {
+            return false;
+        }
+
+        self.used.set(offset + new_size);
+        self.peak.set(self.peak.get().max(offset + new_size));
+
+        true
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_alloc<T>(&self, value: T) -> Result<&mut T, T> {
+        match self.alloc_layout(Layout::new::<T>()) {
+            Some(ptr) => unsafe {
+                let ptr = ptr.as_ptr() as *mut T;
+                ptr::write(ptr, value);
+                Ok(&mut *ptr)
+            },
+            None => Err(value),
+        }
+    }
+
+    /// Panics if the arena is full.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        match self.try_alloc(value) {
+            Ok(value) => value,
+            Err(_) => arena_full(),
+        }
+    }
+
+    /// Panics if the arena is full.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> &mut [T] {
+        let layout = Layout::for_value(src);
+        let ptr = self.alloc_layout(layout).unwrap_or_else(|| arena_full());
+
+        unsafe {
+            let ptr = ptr.as_ptr() as *mut T;
+            ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len());
+            slice::from_raw_parts_mut(ptr, src.len())
+        }
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.capacity.max(1), ARENA_ALIGN).unwrap();
+        unsafe { dealloc(self.data.as_ptr(), layout) };
+    }
+}
+
+#[cold]
+fn arena_full() -> ! {
+    panic!("Arena full");
+}
+
+/// A growable vector that lives in an `Arena`.
+///
+/// Growing moves the contents to a new block unless the vector is the last
+/// allocation in the arena, the old block is reclaimed on `Arena::reset`.
+pub struct ArenaVec<'a, T: Copy> {
+    arena: &'a Arena,
+    ptr: NonNull<T>,
+    len: usize,
+    capacity: usize,
+    _phantom: PhantomData<&'a mut [T]>,
+}
+
+impl<'a, T: Copy> ArenaVec<'a, T> {
+    #[inline]
+    pub fn new(arena: &'a Arena) -> ArenaVec<'a, T> {
+        ArenaVec {
+            arena,
+            ptr: NonNull::dangling(),
+            len: 0,
+            capacity: 0,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn with_capacity(arena: &'a Arena, capacity: usize) -> ArenaVec<'a, T> {
+        let mut vec = ArenaVec::new(arena);
+        vec.reserve(capacity);
+        vec
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Panics if the arena is full.
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        if self.len == self.capacity {
+            self.reserve(1);
+        }
+
+        unsafe { ptr::write(self.ptr.as_ptr().add(self.len), value) };
+        self.len += 1;
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            self.len -= 1;
+            Some(unsafe { ptr::read(self.ptr.as_ptr().add(self.len)) })
+        }
+    }
+
+    /// Panics if the arena is full.
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self
+            .len
+            .checked_add(additional)
+            .unwrap_or_else(|| arena_full());
+        if required <= self.capacity {
+            return;
+        }
+
+        let new_capacity = required.max(self.capacity * 2).max(4);
+        let size = mem::size_of::<T>();
+
+        if self.capacity > 0
+            && self.arena.try_grow_in_place(
+                self.ptr.cast(),
+                self.capacity * size,
+                new_capacity * size,
+            )
+        {
+            self.capacity = new_capacity;
+            return;
+        }
+
+        let layout = Layout::array::<T>(new_capacity).unwrap_or_else(|_| arena_full());
+        let ptr = self
+            .arena
+            .alloc_layout(layout)
+            .unwrap_or_else(|| arena_full())
+            .cast::<T>();
+
+        unsafe { ptr::copy_nonoverlapping(self.ptr.as_ptr(), ptr.as_ptr(), self.len) };
+
+        self.ptr = ptr;
+        self.capacity = new_capacity;
+    }
+}
+
+impl<'a, T: Copy> Deref for ArenaVec<'a, T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<'a, T: Copy> DerefMut for ArenaVec<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<'a, T: Copy> Extend<T> for ArenaVec<'a, T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+#[test]
+fn arena_reuses_memory_after_reset() {
+    let mut arena = Arena::new(1024);
+
+    for _ in 0..4 {
+        let mut vec = ArenaVec::new(&arena);
+        vec.extend(0..100u32);
+
+        let value = arena.alloc(7u64);
+        assert_eq!(value as *mut u64 as usize % mem::align_of::<u64>(), 0);
+        assert_eq!(vec.iter().sum::<u32>(), 4950);
+        assert!(arena.used() <= arena.capacity());
+
+        arena.reset();
+        assert_eq!(arena.used(), 0);
+    }
+
+    assert!(arena.peak() >= 400);
+}
+
+#[test]
+fn arena_vec_grows_in_place_at_the_top() {
+    let arena = Arena::new(4096);
+
+    let mut vec = ArenaVec::with_capacity(&arena, 4);
+    let start = vec.as_ptr();
+    vec.extend(0..64u16);
+
+    assert_eq!(vec.as_ptr(), start);
+    assert_eq!(arena.used(), vec.capacity() * 2);
+    assert_eq!(vec.pop(), Some(63));
+}
+
+#[test]
+fn arena_try_alloc_when_full() {
+    let arena = Arena::new(16);
+
+    assert!(arena.try_alloc([0u8; 16]).is_ok());
+    assert_eq!(arena.try_alloc(1u8), Err(1));
+}
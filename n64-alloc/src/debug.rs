@@ -0,0 +1,272 @@
+//! Allocation debugging, enabled with the `debug` feature.
+//!
+//! Every allocation is wrapped in guard words and a table of live allocations
+//! is kept. Freed memory is poisoned. Double frees, frees of unknown pointers
+//! and overruns of either end of an allocation panic in `dealloc`.
+//!
+//! The global allocator has no way of knowing who called it, so call sites are
+//! named with `tag`. Allocations made while a tag is active are reported under
+//! that tag.
+
+use super::{AllocErr, CellHeader};
+use core::alloc::Layout;
+use core::cmp;
+use core::fmt::{self, Write};
+use core::mem;
+use core::ptr::{self, NonNull};
+use spin::Mutex;
+
+const WORD: usize = mem::size_of::<usize>();
+
+const GUARD_LIVE: usize = 0xCAFE_D00D;
+const GUARD_FREED: usize = 0xDEAD_BEEF;
+const GUARD_TAIL: u8 = 0xFD;
+
+const POISON_ALLOC: u8 = 0xCD;
+const POISON_FREE: u8 = 0xDD;
+
+// Layout of a debug allocation:
+//
+// | unused | size | GUARD_LIVE | data | GUARD_TAIL * WORD |
+//
+// The first word is left unused because the free list overwrites the first
+// word of a cell when it is freed. The front is padded to the alignment of the
+// allocation.
+const FRONT_WORDS: usize = 3;
+
+const MAX_LIVE: usize = 4096;
+const MAX_GROUPS: usize = 64;
+
+pub const UNTAGGED: &str = "untagged";
+
+#[derive(Copy, Clone)]
+struct LiveEntry {
+    ptr: usize,
+    size: usize,
+    tag: &'static str,
+}
+
+impl LiveEntry {
+    const EMPTY: LiveEntry = LiveEntry {
+        ptr: 0,
+        size: 0,
+        tag: UNTAGGED,
+    };
+}
+
+struct LiveTable {
+    entries: [LiveEntry; MAX_LIVE],
+    len: usize,
+    overflowed: bool,
+}
+
+static LIVE: Mutex<LiveTable> = Mutex::new(LiveTable {
+    entries: [LiveEntry::EMPTY; MAX_LIVE],
+    len: 0,
+    overflowed: false,
+});
+
+static TAG: Mutex<&'static str> = Mutex::new(UNTAGGED);
+
+/// Restores the previous tag when dropped.
+pub struct TagScope {
+    previous: &'static str,
+}
+
+impl Drop for TagScope {
+    fn drop(&mut self) {
+        *TAG.lock() = self.previous;
+    }
+}
+
+/// Tags all allocations until the returned scope is dropped.
+pub fn tag(tag: &'static str) -> TagScope {
+    let previous = mem::replace(&mut *TAG.lock(), tag);
+    TagScope { previous }
+}
+
+/// Outstanding allocations with the same tag and size.
+#[derive(Copy, Clone, Debug)]
+pub struct LiveGroup {
+    pub tag: &'static str,
+    pub size: usize,
+    pub count: usize,
+}
+
+impl LiveGroup {
+    #[inline]
+    pub fn bytes(&self) -> usize {
+        self.size * self.count
+    }
+}
+
+/// Number of outstanding allocations.
+pub fn live_count() -> usize {
+    LIVE.lock().len
+}
+
+/// Fills `out` with outstanding allocations grouped by tag and size, largest
+/// total first, and returns the number of groups written.
+pub fn live_groups(out: &mut [LiveGroup]) -> usize {
+    let mut groups = [LiveGroup {
+        tag: UNTAGGED,
+        size: 0,
+        count: 0,
+    }; MAX_GROUPS];
+    let mut len = 0;
+
+    {
+        let live = LIVE.lock();
+
+        for entry in &live.entries[..live.len] {
+            if let Some(group) = groups[..len]
+                .iter_mut()
+                .find(|g| g.size == entry.size && g.tag == entry.tag)
+            {
+                group.count += 1;
+            } else if len < MAX_GROUPS {
+                groups[len] = LiveGroup {
+                    tag: entry.tag,
+                    size: entry.size,
+                    count: 1,
+                };
+                len += 1;
+            }
+        }
+    }
+
+    let groups = &mut groups[..len];
+    groups.sort_unstable_by_key(|g| cmp::Reverse(g.bytes()));
+
+    let len = cmp::min(len, out.len());
+    out[..len].copy_from_slice(&groups[..len]);
+    len
+}
+
+/// Writes up to `max_lines` of `live_groups` as `TAG SIZExCOUNT` lines.
+pub fn write_report<W: Write>(w: &mut W, max_lines: usize) -> fmt::Result {
+    let mut groups = [LiveGroup {
+        tag: UNTAGGED,
+        size: 0,
+        count: 0,
+    }; MAX_GROUPS];
+
+    // The table lock is released before writing, `w` may allocate.
+    let len = live_groups(&mut groups[..cmp::min(max_lines, MAX_GROUPS)]);
+
+    for group in &groups[..len] {
+        writeln!(w, "{} {}x{}", group.tag, group.size, group.count)?;
+    }
+
+    Ok(())
+}
+
+#[inline]
+fn front_len(align: usize) -> usize {
+    (FRONT_WORDS * WORD + align - 1) & !(align - 1)
+}
+
+// The layout actually requested from the free lists for `layout`.
+pub(crate) fn outer_layout(layout: Layout) -> Result<Layout, AllocErr> {
+    let align = cmp::max(layout.align(), WORD);
+    let size = front_len(align)
+        .checked_add(layout.size())
+        .and_then(|s| s.checked_add(WORD))
+        .ok_or(AllocErr)?;
+
+    Layout::from_size_align(size, align).map_err(|_| AllocErr)
+}
+
+pub(crate) unsafe fn on_alloc(outer: NonNull<u8>, layout: Layout) -> NonNull<u8> {
+    let align = cmp::max(layout.align(), WORD);
+    let data = outer.as_ptr().add(front_len(align));
+    let words = data as *mut usize;
+
+    ptr::write(words.offset(-2), layout.size());
+    ptr::write(words.offset(-1), GUARD_LIVE);
+    ptr::write_bytes(data, POISON_ALLOC, layout.size());
+    ptr::write_bytes(data.add(layout.size()), GUARD_TAIL, WORD);
+
+    let tag = *TAG.lock();
+    let mut live = LIVE.lock();
+
+    if live.len < MAX_LIVE {
+        let index = live.len;
+        live.entries[index] = LiveEntry {
+            ptr: data as usize,
+            size: layout.size(),
+            tag,
+        };
+        live.len += 1;
+    } else {
+        live.overflowed = true;
+    }
+
+    NonNull::new_unchecked(data)
+}
+
+// Checks a pointer that is about to be freed and returns the pointer and
+// layout of the underlying cell.
+pub(crate) unsafe fn on_dealloc(data: NonNull<u8>, layout: Layout) -> (NonNull<u8>, Layout) {
+    let data = data.as_ptr();
+    let words = data as *mut usize;
+
+    let tracked = {
+        let mut live = LIVE.lock();
+        let len = live.len;
+
+        match live.entries[..len]
+            .iter()
+            .position(|e| e.ptr == data as usize)
+        {
+            Some(index) => {
+                live.entries.swap(index, len - 1);
+                live.len -= 1;
+                true
+            }
+            None => live.overflowed,
+        }
+    };
+
+    let guard = ptr::read(words.offset(-1));
+
+    if guard == GUARD_FREED {
+        panic!("n64-alloc: double free of {:p}", data);
+    }
+
+    if !tracked {
+        panic!("n64-alloc: free of unknown pointer {:p}", data);
+    }
+
+    if guard != GUARD_LIVE {
+        panic!("n64-alloc: underrun before {:p}", data);
+    }
+
+    let size = ptr::read(words.offset(-2));
+    if size != layout.size() {
+        panic!(
+            "n64-alloc: {:p} freed with size {}, allocated with {}",
+            data,
+            layout.size(),
+            size
+        );
+    }
+
+    let tail = data.add(size);
+    if (0..WORD).any(|i| *tail.add(i) != GUARD_TAIL) {
+        panic!("n64-alloc: overrun after {:p} ({} bytes)", data, size);
+    }
+
+    let outer_layout = outer_layout(layout).unwrap_or(layout);
+    let outer = data.sub(front_len(outer_layout.align()));
+
+    let cell = &*(outer as *const CellHeader).offset(-1);
+    if !cell.is_allocated() {
+        panic!("n64-alloc: double free of {:p}", data);
+    }
+
+    ptr::write_bytes(data, POISON_FREE, size + WORD);
+    ptr::write(words.offset(-1), GUARD_FREED);
+
+    (NonNull::new_unchecked(outer), outer_layout)
+}
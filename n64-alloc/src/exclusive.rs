@@ -0,0 +1,33 @@
+use crate::const_init::ConstInit;
+use spin::Mutex;
+
+pub(crate) struct Exclusive<T> {
+    inner: Mutex<T>,
+}
+
+impl<T: ConstInit> ConstInit for Exclusive<T> {
+    const INIT: Self = Exclusive {
+        inner: Mutex::new(T::INIT),
+    };
+}
+
+impl<T> Exclusive<T> {
+    /// Get exclusive, mutable access to the inner value.
+    ///
+    /// # Safety
+    ///
+    /// It is the callers' responsibility to ensure that `f` does not re-enter
+    /// this method for this `Exclusive` instance.
+    //
+    // XXX: If we don't mark this function inline, then it won't be, and the
+    // code size also blows up by about 200 bytes.
+    #[inline]
+    pub(crate) unsafe fn with_exclusive_access<'a, F, U>(&'a self, f: F) -> U
+    where
+        for<'x> F: FnOnce(&'x mut T) -> U,
+    {
+        let mut guard = self.inner.lock();
+        let result = f(&mut guard);
+        result
+    }
+}
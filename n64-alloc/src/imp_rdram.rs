@@ -0,0 +1,78 @@
+pub(crate) use crate::exclusive::Exclusive;
+use crate::{AllocErr, BYTES_LEFT};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use memory_units::{Bytes, Pages};
+use spin::{Mutex, Once};
+
+extern "C" {
+    // End of the program image, provided by the linker script.
+    static __bss_end: u8;
+}
+
+// Written by the IPL3, 4 MB without and 8 MB with the Expansion Pak.
+const OS_MEM_SIZE_LOC: usize = 0x8000_0318;
+const RDRAM_BASE: usize = 0x8000_0000;
+
+const HEAP_ALIGN: usize = 16;
+
+pub const DEFAULT_RESERVE_BYTES: usize = 256 * 1024;
+
+/// Bytes at the top of RDRAM that are kept out of the heap, the stack lives
+/// there. Only read when the heap is first used.
+pub static HEAP_RESERVE_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_RESERVE_BYTES);
+
+pub static OFFSET: Mutex<usize> = Mutex::new(0);
+
+struct Heap {
+    start: usize,
+    len: usize,
+}
+
+static HEAP: Once<Heap> = Once::new();
+
+/// Installed RDRAM in bytes.
+#[inline]
+pub fn rdram_size() -> usize {
+    unsafe { ptr::read_volatile(OS_MEM_SIZE_LOC as *const usize) }
+}
+
+#[inline]
+pub fn has_expansion_pak() -> bool {
+    rdram_size() > 4 * 1024 * 1024
+}
+
+fn heap() -> &'static Heap {
+    HEAP.call_once(|| {
+        let image_end = unsafe { &__bss_end as *const u8 as usize };
+        let start = (image_end + HEAP_ALIGN - 1) & !(HEAP_ALIGN - 1);
+        let end = (RDRAM_BASE + rdram_size())
+            .saturating_sub(HEAP_RESERVE_BYTES.load(Ordering::SeqCst))
+            & !(HEAP_ALIGN - 1);
+        let len = end.saturating_sub(start);
+
+        BYTES_LEFT.fetch_add(len as i32, Ordering::SeqCst);
+
+        Heap { start, len }
+    })
+}
+
+/// Total number of bytes managed by the allocator.
+#[inline]
+pub fn heap_size() -> usize {
+    heap().len
+}
+
+pub(crate) unsafe fn alloc_pages(pages: Pages) -> Result<NonNull<u8>, AllocErr> {
+    let heap = heap();
+    let bytes: Bytes = pages.into();
+    let mut offset = OFFSET.lock();
+    let end = bytes.0.checked_add(*offset).ok_or(AllocErr)?;
+    if end <= heap.len {
+        let ptr = (heap.start + *offset) as *mut u8;
+        *offset = end;
+        NonNull::new(ptr).ok_or(AllocErr)
+    } else {
+        Err(AllocErr)
+    }
+}
@@ -2,10 +2,31 @@
 
 extern crate alloc;
 
+mod arena;
 mod const_init;
-mod imp_static_array;
+#[cfg(feature = "debug")]
+pub mod debug;
+mod exclusive;
 mod neighbors;
+mod pool;
+mod region;
 mod size_classes;
+mod stats;
+
+#[cfg(test)]
+mod tests;
+
+#[cfg(target_vendor = "nintendo64")]
+mod imp_rdram;
+#[cfg(target_vendor = "nintendo64")]
+use imp_rdram as imp;
+#[cfg(target_vendor = "nintendo64")]
+pub use imp_rdram::{has_expansion_pak, rdram_size, DEFAULT_RESERVE_BYTES, HEAP_RESERVE_BYTES};
+
+#[cfg(not(target_vendor = "nintendo64"))]
+mod imp_static_array;
+#[cfg(not(target_vendor = "nintendo64"))]
+use imp_static_array as imp;
 
 use const_init::ConstInit;
 use core::alloc::{GlobalAlloc, Layout};
@@ -15,9 +36,13 @@ use core::marker::Sync;
 use core::mem;
 use core::ptr::{self, NonNull};
 use core::sync::atomic::{AtomicI32, Ordering};
-use imp_static_array as imp;
 use memory_units::{size_of, ByteSize, Bytes, Pages, RoundUpTo, Words};
 use neighbors::Neighbors;
+use region::{Backing, Region};
+
+pub use arena::{Arena, ArenaVec};
+pub use pool::{Handle, Pool};
+pub use stats::{ClassStats, HeapStats, HeapStatsText};
 
 pub(crate) struct AllocErr;
 
@@ -346,14 +371,13 @@ trait AllocPolicy<'a> {
     fn should_merge_adjacent_free_cells(&self) -> bool;
 }
 
-struct LargeAllocPolicy;
-static LARGE_ALLOC_POLICY: LargeAllocPolicy = LargeAllocPolicy;
+struct LargeAllocPolicy<'r>(&'r Backing);
 
-impl LargeAllocPolicy {
+impl<'r> LargeAllocPolicy<'r> {
     const MIN_CELL_SIZE: Words = Words(size_classes::SizeClasses::NUM_SIZE_CLASSES * 2);
 }
 
-impl<'a> AllocPolicy<'a> for LargeAllocPolicy {
+impl<'a, 'r> AllocPolicy<'a> for LargeAllocPolicy<'r> {
     unsafe fn new_cell_for_free_list(
         &self,
         size: Words,
@@ -366,7 +390,7 @@ impl<'a> AllocPolicy<'a> for LargeAllocPolicy {
         let size: Bytes = cmp::max(size.into(), (align + Self::MIN_CELL_SIZE) * Words(2));
 
         let pages: Pages = (size + size_of::<CellHeader>()).round_up_to();
-        let new_pages = imp::alloc_pages(pages)?;
+        let new_pages = self.0.alloc_pages(pages)?;
         let allocated_size: Bytes = pages.into();
 
         let free_cell = &*FreeCell::from_uninitialized(
@@ -412,7 +436,7 @@ where
 {
     // The previous cell in the free list (not to be confused with the current
     // cell's previously _adjacent_ cell).
-    let previous_free = head;
+    let mut previous_free = head;
 
     loop {
         let current_free = previous_free.get();
@@ -454,7 +478,22 @@ where
             return Ok(result);
         }
 
-        previous_free.set(&*(*current_free.get()).next_free_raw.get());
+        // Step to the next cell by following this cell's link, so that `f`
+        // can unlink the next cell by setting it.
+        previous_free = &(*current_free.get()).next_free_raw;
+    }
+}
+
+// Visits the cells of a free list without merging any of them.
+unsafe fn for_each_free_cell<'a, F>(head: *const FreeCell<'a>, mut f: F)
+where
+    F: FnMut(&FreeCell<'a>),
+{
+    let mut current = head;
+
+    while !current.is_null() {
+        f(&*current);
+        current = (*current).next_free();
     }
 }
 
@@ -500,6 +539,8 @@ unsafe fn alloc_with_refill<'a, 'b>(
 pub struct N64Alloc<'a> {
     head: imp::Exclusive<*const FreeCell<'a>>,
     size_classes: size_classes::SizeClasses<'a>,
+    backing: Backing,
+    usage: imp::Exclusive<stats::Usage>,
 }
 
 unsafe impl<'a> Sync for N64Alloc<'a> {}
@@ -508,6 +549,8 @@ impl<'a> ConstInit for N64Alloc<'a> {
     const INIT: N64Alloc<'a> = N64Alloc {
         head: imp::Exclusive::INIT,
         size_classes: size_classes::SizeClasses::INIT,
+        backing: Backing::Heap,
+        usage: imp::Exclusive::INIT,
     };
 }
 
@@ -518,13 +561,68 @@ impl<'a> N64Alloc<'a> {
     /// allocator.
     pub const INIT: Self = <Self as ConstInit>::INIT;
 
+    /// An allocator that takes its pages from the `len` bytes at `start`
+    /// instead of the platform heap.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid for reads and writes, not be used by anything
+    /// else and outlive the allocator and every allocation made from it.
+    pub unsafe fn with_region(start: *mut u8, len: usize) -> Self {
+        N64Alloc {
+            backing: Backing::Region(Region::new(start, len)),
+            ..Self::INIT
+        }
+    }
+
+    /// Walks the free lists and returns a snapshot of the allocator.
+    pub fn stats(&self) -> HeapStats {
+        unsafe {
+            let usage = self.usage.with_exclusive_access(|usage| *usage);
+            let mut stats = HeapStats::new(self.backing.size(), self.backing.pages_used(), &usage);
+
+            self.head.with_exclusive_access(|head| {
+                for_each_free_cell(*head, |cell| {
+                    let size = cell.header.size().0;
+                    stats.large_free += size;
+                    stats.large_free_cells += 1;
+                    stats.largest_free_cell = cmp::max(stats.largest_free_cell, size);
+                });
+            });
+
+            for (class, head) in stats
+                .size_classes
+                .iter_mut()
+                .zip(self.size_classes.0.iter())
+            {
+                head.with_exclusive_access(|head| {
+                    for_each_free_cell(*head, |cell| {
+                        class.free += cell.header.size().0;
+                        class.free_cells += 1;
+                    });
+                });
+            }
+
+            stats
+        }
+    }
+
+    #[inline]
+    fn size_class_index(&self, size: Words, align: Bytes) -> Option<usize> {
+        if align <= size_of::<usize>() && size.0 <= size_classes::SizeClasses::NUM_SIZE_CLASSES {
+            Some(size.0 - 1)
+        } else {
+            None
+        }
+    }
+
     unsafe fn with_free_list_and_policy_for_size<F, T>(&self, size: Words, align: Bytes, f: F) -> T
     where
         F: for<'b> FnOnce(&'b Cell<*const FreeCell<'a>>, &'b dyn AllocPolicy<'a>) -> T,
     {
         if align <= size_of::<usize>() {
             if let Some(head) = self.size_classes.get(size) {
-                let policy = size_classes::SizeClassAllocPolicy(&self.head);
+                let policy = size_classes::SizeClassAllocPolicy(&self.head, &self.backing);
                 let policy = &policy as &dyn AllocPolicy<'a>;
                 return head.with_exclusive_access(|head| {
                     let head_cell = Cell::new(*head);
@@ -535,7 +633,8 @@ impl<'a> N64Alloc<'a> {
             }
         }
 
-        let policy = &LARGE_ALLOC_POLICY as &dyn AllocPolicy<'a>;
+        let policy = LargeAllocPolicy(&self.backing);
+        let policy = &policy as &dyn AllocPolicy<'a>;
         self.head.with_exclusive_access(|head| {
             let head_cell = Cell::new(*head);
             let result = f(&head_cell, policy);
@@ -545,6 +644,9 @@ impl<'a> N64Alloc<'a> {
     }
 
     unsafe fn alloc_impl(&self, layout: Layout) -> Result<NonNull<u8>, AllocErr> {
+        #[cfg(feature = "debug")]
+        let (layout, requested) = (debug::outer_layout(layout)?, layout);
+
         let size = Bytes(layout.size());
         let align = if layout.align() == 0 {
             Bytes(1)
@@ -560,12 +662,24 @@ impl<'a> N64Alloc<'a> {
 
         let word_size: Words = checked_round_up_to(size).ok_or(AllocErr)?;
 
-        self.with_free_list_and_policy_for_size(word_size, align, |head, policy| {
+        let ptr = self.with_free_list_and_policy_for_size(word_size, align, |head, policy| {
             alloc_with_refill(word_size, align, head, policy)
-        })
+        })?;
+
+        let class = self.size_class_index(word_size, align);
+        self.usage
+            .with_exclusive_access(|usage| usage.record_alloc(class, word_size.into()));
+
+        #[cfg(feature = "debug")]
+        let ptr = debug::on_alloc(ptr, requested);
+
+        Ok(ptr)
     }
 
     unsafe fn dealloc_impl(&self, ptr: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "debug")]
+        let (ptr, layout) = debug::on_dealloc(ptr, layout);
+
         let size = Bytes(layout.size());
         if size.0 == 0 {
             return;
@@ -574,6 +688,10 @@ impl<'a> N64Alloc<'a> {
         let size: Words = size.round_up_to();
         let align = Bytes(layout.align());
 
+        let class = self.size_class_index(size, align);
+        self.usage
+            .with_exclusive_access(|usage| usage.record_dealloc(class, size.into()));
+
         self.with_free_list_and_policy_for_size(size, align, |head, policy| {
             let cell = (ptr.as_ptr() as *mut CellHeader<'a> as *const CellHeader<'a>).offset(-1);
             let cell = &*cell;
@@ -650,14 +768,26 @@ impl<'a> N64Alloc<'a> {
     }
 }
 
-pub static BYTES_LEFT: AtomicI32 = AtomicI32::new(imp::SCRATCH_LEN_BYTES as i32);
+pub static BYTES_LEFT: AtomicI32 = AtomicI32::new(0);
 pub static BYTES_USED: AtomicI32 = AtomicI32::new(0);
+pub use imp::heap_size;
 pub use imp::OFFSET as PAGE_OFFSET;
 
+impl<'a> N64Alloc<'a> {
+    // Only the platform heap is counted, allocators over a region of their
+    // own have `stats`.
+    #[inline]
+    fn count_bytes(&self, bytes: i32) {
+        if let Backing::Heap = self.backing {
+            BYTES_LEFT.fetch_sub(bytes, Ordering::SeqCst);
+            BYTES_USED.fetch_add(bytes, Ordering::SeqCst);
+        }
+    }
+}
+
 unsafe impl GlobalAlloc for N64Alloc<'static> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        BYTES_LEFT.fetch_sub(layout.size() as i32, Ordering::SeqCst);
-        BYTES_USED.fetch_add(layout.size() as i32, Ordering::SeqCst);
+        self.count_bytes(layout.size() as i32);
 
         match self.alloc_impl(layout) {
             Ok(ptr) => ptr.as_ptr(),
@@ -666,8 +796,7 @@ unsafe impl GlobalAlloc for N64Alloc<'static> {
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        BYTES_LEFT.fetch_add(layout.size() as i32, Ordering::SeqCst);
-        BYTES_USED.fetch_sub(layout.size() as i32, Ordering::SeqCst);
+        self.count_bytes(-(layout.size() as i32));
 
         if let Some(ptr) = NonNull::new(ptr) {
             self.dealloc_impl(ptr, layout);
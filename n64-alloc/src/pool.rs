@@ -0,0 +1,201 @@
+use alloc::vec::Vec;
+use core::mem;
+
+/// Refers to a value in a `Pool`. Handles to removed values are detected by a
+/// generation count, so a stale handle never finds a newer value in the same
+/// slot.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+enum Slot<T> {
+    Occupied { generation: u32, value: T },
+    Free { generation: u32, next_free: u32 },
+}
+
+const NO_FREE_SLOT: u32 = u32::MAX;
+
+/// A fixed capacity object pool. All memory is allocated up front, inserting
+/// and removing values never touches the global allocator.
+pub struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    capacity: usize,
+    free_head: u32,
+    len: usize,
+}
+
+impl<T> Pool<T> {
+    pub fn new(capacity: usize) -> Pool<T> {
+        assert!(capacity < NO_FREE_SLOT as usize);
+
+        Pool {
+            slots: Vec::with_capacity(capacity),
+            capacity,
+            free_head: NO_FREE_SLOT,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity
+    }
+
+    /// Gives the value back if the pool is full.
+    pub fn insert(&mut self, value: T) -> Result<Handle, T> {
+        if self.free_head != NO_FREE_SLOT {
+            let index = self.free_head;
+            let slot = &mut self.slots[index as usize];
+
+            let generation = match *slot {
+                Slot::Free {
+                    generation,
+                    next_free,
+                } => {
+                    self.free_head = next_free;
+                    generation
+                }
+                Slot::Occupied { .. } => unreachable!(),
+            };
+
+            *slot = Slot::Occupied { generation, value };
+            self.len += 1;
+
+            return Ok(Handle { index, generation });
+        }
+
+        if self.slots.len() < self.capacity {
+            let index = self.slots.len() as u32;
+            self.slots.push(Slot::Occupied {
+                generation: 0,
+                value,
+            });
+            self.len += 1;
+
+            return Ok(Handle {
+                index,
+                generation: 0,
+            });
+        }
+
+        Err(value)
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+
+        match slot {
+            Slot::Occupied { generation, .. } if *generation == handle.generation => {
+                let free = Slot::Free {
+                    generation: generation.wrapping_add(1),
+                    next_free: self.free_head,
+                };
+
+                self.free_head = handle.index;
+                self.len -= 1;
+
+                match mem::replace(slot, free) {
+                    Slot::Occupied { value, .. } => Some(value),
+                    Slot::Free { .. } => unreachable!(),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.get(handle).is_some()
+    }
+
+    #[inline]
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        match self.slots.get(handle.index as usize) {
+            Some(Slot::Occupied { generation, value }) if *generation == handle.generation => {
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        match self.slots.get_mut(handle.index as usize) {
+            Some(Slot::Occupied { generation, value }) if *generation == handle.generation => {
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, &T)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot {
+                Slot::Occupied { generation, value } => Some((
+                    Handle {
+                        index: index as u32,
+                        generation: *generation,
+                    },
+                    value,
+                )),
+                Slot::Free { .. } => None,
+            })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle, &mut T)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| match slot {
+                Slot::Occupied { generation, value } => Some((
+                    Handle {
+                        index: index as u32,
+                        generation: *generation,
+                    },
+                    value,
+                )),
+                Slot::Free { .. } => None,
+            })
+    }
+}
+
+#[test]
+fn pool_reuses_slots_and_rejects_stale_handles() {
+    let mut pool = Pool::new(2);
+
+    let a = pool.insert(1).unwrap();
+    let b = pool.insert(2).unwrap();
+    assert_eq!(pool.insert(3), Err(3));
+
+    assert_eq!(pool.remove(a), Some(1));
+    assert_eq!(pool.remove(a), None);
+
+    let c = pool.insert(4).unwrap();
+    assert_eq!(pool.get(a), None);
+    assert_eq!(pool.get(c), Some(&4));
+    assert_eq!(pool.len(), 2);
+
+    *pool.get_mut(b).unwrap() += 10;
+    let mut values = pool.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+    values.sort_unstable();
+    assert_eq!(values, [4, 12]);
+}
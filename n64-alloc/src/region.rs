@@ -0,0 +1,67 @@
+use crate::{imp, AllocErr};
+use core::ptr::NonNull;
+use memory_units::{Bytes, Pages};
+use spin::Mutex;
+
+const REGION_ALIGN: usize = 16;
+
+// Where an allocator gets its pages from. The global allocator uses the
+// platform heap, allocators created with `N64Alloc::with_region` bump allocate
+// out of their own block of memory.
+pub(crate) enum Backing {
+    Heap,
+    Region(Region),
+}
+
+pub(crate) struct Region {
+    pub(crate) start: usize,
+    pub(crate) len: usize,
+    pub(crate) offset: Mutex<usize>,
+}
+
+impl Region {
+    pub(crate) fn new(start: *mut u8, len: usize) -> Region {
+        let start = start as usize;
+        let aligned_start = (start + REGION_ALIGN - 1) & !(REGION_ALIGN - 1);
+
+        Region {
+            start: aligned_start,
+            len: len.saturating_sub(aligned_start - start),
+            offset: Mutex::new(0),
+        }
+    }
+}
+
+impl Backing {
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            Backing::Heap => imp::heap_size(),
+            Backing::Region(region) => region.len,
+        }
+    }
+
+    pub(crate) fn pages_used(&self) -> usize {
+        match self {
+            Backing::Heap => *imp::OFFSET.lock(),
+            Backing::Region(region) => *region.offset.lock(),
+        }
+    }
+
+    pub(crate) unsafe fn alloc_pages(&self, pages: Pages) -> Result<NonNull<u8>, AllocErr> {
+        match self {
+            Backing::Heap => imp::alloc_pages(pages),
+            Backing::Region(region) => {
+                let bytes: Bytes = pages.into();
+                let mut offset = region.offset.lock();
+                let end = bytes.0.checked_add(*offset).ok_or(AllocErr)?;
+                if end <= region.len {
+                    let ptr = (region.start + *offset) as *mut u8;
+                    *offset = end;
+                    NonNull::new(ptr).ok_or(AllocErr)
+                } else {
+                    Err(AllocErr)
+                }
+            }
+        }
+    }
+}
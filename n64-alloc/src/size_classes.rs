@@ -1,6 +1,7 @@
 use super::{alloc_with_refill, AllocErr, AllocPolicy, CellHeader, FreeCell, LargeAllocPolicy};
 use crate::const_init::ConstInit;
-use crate::imp_static_array as imp;
+use crate::imp;
+use crate::region::Backing;
 use core::cell::Cell;
 use core::cmp;
 use memory_units::{size_of, Bytes, RoundUpTo, Words};
@@ -27,7 +28,10 @@ impl<'a> SizeClasses<'a> {
 // `LargeAllocPolicy`.
 const MIN_NEW_CELL_SIZE: Bytes = Bytes(8192);
 
-pub(crate) struct SizeClassAllocPolicy<'a, 'b>(pub(crate) &'b imp::Exclusive<*const FreeCell<'a>>)
+pub(crate) struct SizeClassAllocPolicy<'a, 'b>(
+    pub(crate) &'b imp::Exclusive<*const FreeCell<'a>>,
+    pub(crate) &'b Backing,
+)
 where
     'a: 'b;
 
@@ -54,7 +58,7 @@ where
                 new_cell_size,
                 size_of::<usize>(),
                 &head_cell,
-                &LargeAllocPolicy,
+                &LargeAllocPolicy(self.1),
             );
             *head = head_cell.get();
             result
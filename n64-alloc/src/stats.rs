@@ -0,0 +1,205 @@
+use crate::const_init::ConstInit;
+use crate::size_classes::SizeClasses;
+use core::fmt;
+use memory_units::{Bytes, Words};
+
+// Live bytes per size class and in the large free list, kept up to date by
+// `N64Alloc::alloc_impl` and `N64Alloc::dealloc_impl`.
+#[derive(Copy, Clone)]
+pub(crate) struct Usage {
+    classes: [usize; SizeClasses::NUM_SIZE_CLASSES],
+    large: usize,
+    total: usize,
+    peak: usize,
+}
+
+impl ConstInit for Usage {
+    const INIT: Usage = Usage {
+        classes: [0; SizeClasses::NUM_SIZE_CLASSES],
+        large: 0,
+        total: 0,
+        peak: 0,
+    };
+}
+
+impl Usage {
+    pub(crate) fn record_alloc(&mut self, class: Option<usize>, bytes: Bytes) {
+        match class {
+            Some(class) => self.classes[class] += bytes.0,
+            None => self.large += bytes.0,
+        }
+
+        self.total += bytes.0;
+        self.peak = self.peak.max(self.total);
+    }
+
+    pub(crate) fn record_dealloc(&mut self, class: Option<usize>, bytes: Bytes) {
+        match class {
+            Some(class) => self.classes[class] -= bytes.0,
+            None => self.large -= bytes.0,
+        }
+
+        self.total -= bytes.0;
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ClassStats {
+    /// Allocation size served by this class.
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    pub free_cells: usize,
+}
+
+/// A snapshot of an allocator, see `N64Alloc::stats`.
+///
+/// Byte counts are of cell data and rounded up to whole words, cell headers
+/// are not counted.
+#[derive(Copy, Clone)]
+pub struct HeapStats {
+    /// Bytes the allocator can take pages from.
+    pub heap_size: usize,
+    /// Bytes taken from the heap with `alloc_pages` so far.
+    pub pages: usize,
+    /// Bytes in live allocations.
+    pub used: usize,
+    /// The most bytes that have been in live allocations at once.
+    pub peak: usize,
+    pub large_used: usize,
+    pub large_free: usize,
+    pub large_free_cells: usize,
+    /// Largest cell in the large free list. Neighbouring free cells waiting to
+    /// be merged are counted separately.
+    pub largest_free_cell: usize,
+    pub size_classes: [ClassStats; SizeClasses::NUM_SIZE_CLASSES],
+}
+
+impl HeapStats {
+    pub(crate) fn new(heap_size: usize, pages: usize, usage: &Usage) -> HeapStats {
+        let mut size_classes = [ClassStats::default(); SizeClasses::NUM_SIZE_CLASSES];
+
+        for (i, class) in size_classes.iter_mut().enumerate() {
+            let size: Bytes = Words(i + 1).into();
+            class.size = size.0;
+            class.used = usage.classes[i];
+        }
+
+        HeapStats {
+            heap_size,
+            pages,
+            used: usage.total,
+            peak: usage.peak,
+            large_used: usage.large,
+            large_free: 0,
+            large_free_cells: 0,
+            largest_free_cell: 0,
+            size_classes,
+        }
+    }
+
+    /// Heap that has not been handed out as pages yet, always one block.
+    #[inline]
+    pub fn untouched(&self) -> usize {
+        self.heap_size.saturating_sub(self.pages)
+    }
+
+    /// Free bytes in the large free list and in untouched pages. Size class
+    /// free lists only serve their own size and are not counted.
+    #[inline]
+    pub fn free(&self) -> usize {
+        self.large_free + self.untouched()
+    }
+
+    #[inline]
+    pub fn largest_free_block(&self) -> usize {
+        self.largest_free_cell.max(self.untouched())
+    }
+
+    /// 0.0 when all free memory is one block, approaching 1.0 as it is split
+    /// into more and smaller blocks.
+    pub fn fragmentation(&self) -> f32 {
+        let free = self.free();
+
+        if free == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free_block().min(free) as f32 / free as f32
+        }
+    }
+
+    pub fn size_class_free(&self) -> usize {
+        self.size_classes.iter().map(|c| c.free).sum()
+    }
+
+    /// Compact text for the debug overlay, with the `max_classes` size classes
+    /// holding the most memory.
+    #[inline]
+    pub fn text(&self, max_classes: usize) -> HeapStatsText<'_> {
+        HeapStatsText {
+            stats: self,
+            max_classes,
+        }
+    }
+}
+
+pub struct HeapStatsText<'a> {
+    stats: &'a HeapStats,
+    max_classes: usize,
+}
+
+struct Kib(usize);
+
+impl fmt::Display for Kib {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 < 10 * 1024 {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "{}K", self.0 / 1024)
+        }
+    }
+}
+
+// Only uses characters that exist in the IPL3 font.
+impl<'a> fmt::Display for HeapStatsText<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = self.stats;
+
+        writeln!(f, "HEAP {} PAGES {}", Kib(s.heap_size), Kib(s.pages))?;
+        writeln!(f, "USED {} PEAK {}", Kib(s.used), Kib(s.peak))?;
+        writeln!(
+            f,
+            "FREE {} MAX {}",
+            Kib(s.free()),
+            Kib(s.largest_free_block())
+        )?;
+
+        let fragmentation = (s.fragmentation() * 100.0) as u32;
+        writeln!(
+            f,
+            "FRAG {}.{:02} SMALL {}",
+            fragmentation / 100,
+            fragmentation % 100,
+            Kib(s.size_class_free())
+        )?;
+
+        let mut shown = [usize::MAX; 8];
+        let max_classes = self.max_classes.min(shown.len());
+
+        for slot in 0..max_classes {
+            let biggest = s
+                .size_classes
+                .iter()
+                .enumerate()
+                .filter(|(i, c)| c.used + c.free > 0 && !shown.contains(i))
+                .max_by_key(|(_, c)| c.used + c.free);
+
+            if let Some((i, class)) = biggest {
+                shown[slot] = i;
+                writeln!(f, "S{} {}/{}", class.size, Kib(class.used), Kib(class.free))?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,545 @@
+extern crate std;
+
+use super::{size_classes::SizeClasses, Backing, CellHeader, FreeCell, N64Alloc};
+use core::alloc::{GlobalAlloc, Layout};
+use memory_units::{size_of, Bytes, RoundUpTo, Words};
+use n64_math::rand::Rng;
+use std::{collections::BTreeSet, vec, vec::Vec};
+
+struct TestHeap {
+    alloc: N64Alloc<'static>,
+    _memory: Vec<u64>,
+}
+
+impl TestHeap {
+    fn new(len: usize) -> TestHeap {
+        let mut memory = vec![0u64; len / 8];
+        let alloc = unsafe { N64Alloc::with_region(memory.as_mut_ptr() as *mut u8, len) };
+
+        TestHeap {
+            alloc,
+            _memory: memory,
+        }
+    }
+
+    fn used(&self) -> (usize, usize) {
+        match &self.alloc.backing {
+            Backing::Region(region) => (region.start, region.start + *region.offset.lock()),
+            Backing::Heap => unreachable!(),
+        }
+    }
+
+    // Walks every cell handed out by the backing region and every free list and
+    // checks that they agree with each other.
+    unsafe fn check_invariants(&self) {
+        let (start, end) = self.used();
+
+        let mut free_cells = BTreeSet::new();
+        let mut allocated_cells = Vec::new();
+
+        let mut cell = start as *const CellHeader;
+        let mut prev: *const CellHeader = core::ptr::null();
+
+        while (cell as usize) < end {
+            let header = &*cell;
+            let next = header.neighbors.next_unchecked();
+
+            assert_eq!(cell as usize % 8, 0, "misaligned cell {:p}", cell);
+            assert_eq!(
+                header.neighbors.prev_unchecked(),
+                prev,
+                "bad prev {:p}",
+                cell
+            );
+            assert!(next > cell && (next as usize) <= end, "bad next {:p}", cell);
+
+            if header.is_free() {
+                free_cells.insert(cell as usize);
+            } else {
+                allocated_cells.push((header.unchecked_data() as usize, next as usize));
+            }
+
+            if CellHeader::next_cell_is_invalid(&header.neighbors) {
+                prev = core::ptr::null();
+            } else {
+                prev = cell;
+            }
+
+            cell = next;
+        }
+
+        assert_eq!(cell as usize, end, "cells do not cover the used region");
+
+        let mut seen = BTreeSet::new();
+
+        let head = self.alloc.head.with_exclusive_access(|head| *head);
+        let mut free = head;
+
+        while !free.is_null() {
+            let cell = &*free;
+
+            assert!(seen.insert(free as usize), "free list cycle at {:p}", free);
+            assert!(
+                free_cells.remove(&(free as usize)),
+                "large free list entry {:p} is not a free cell",
+                free
+            );
+
+            if cell.next_free_can_merge() {
+                assert_eq!(
+                    cell.next_free() as *const CellHeader,
+                    cell.header.neighbors.prev_unchecked(),
+                    "merge bit set on {:p} without a free previous neighbor",
+                    free
+                );
+            }
+
+            free = cell.next_free();
+        }
+
+        assert!(
+            free_cells.is_empty(),
+            "free cells missing from the large free list: {:x?}",
+            free_cells
+        );
+
+        for (index, class) in self.alloc.size_classes.0.iter().enumerate() {
+            let class_size: Bytes = Words(index + 1).into();
+            let mut free = class.with_exclusive_access(|head| *head);
+
+            while !free.is_null() {
+                let cell: &FreeCell = &*free;
+                let addr = free as usize;
+
+                assert!(
+                    seen.insert(addr),
+                    "free list cycle or overlap at {:p}",
+                    free
+                );
+                assert!(
+                    cell.header.is_free(),
+                    "allocated cell {:p} in size class",
+                    free
+                );
+                assert!(!cell.next_free_can_merge(), "merge bit in size class");
+                assert!(
+                    cell.header.size() >= class_size,
+                    "cell {:p} too small",
+                    free
+                );
+
+                let cell_end = cell.header.neighbors.next_unchecked() as usize;
+                assert!(
+                    allocated_cells
+                        .iter()
+                        .any(|&(data, next)| addr >= data && cell_end <= next),
+                    "size class cell {:p} outside of any allocated cell",
+                    free
+                );
+
+                free = cell.next_free();
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Live {
+    ptr: *mut u8,
+    layout: Layout,
+    fill: u8,
+}
+
+fn random_layout(rng: &mut Rng) -> Layout {
+    // Size classes reserve room for size^2 words each, keep the small sizes
+    // to a few classes so the region isn't used up by empty classes.
+    let largest_class = SizeClasses::NUM_SIZE_CLASSES * size_of::<usize>().0;
+    let size = match rng.next_u32() % 8 {
+        0 => largest_class + 1 + rng.next_u32() as usize % (32 * 1024),
+        _ => 1 + rng.next_u32() as usize % 512,
+    };
+
+    let align = match rng.next_u32() % 8 {
+        0 => 16,
+        1 => 64,
+        _ => 8,
+    };
+
+    Layout::from_size_align(size, align).unwrap()
+}
+
+unsafe fn fill(live: &Live) {
+    core::ptr::write_bytes(live.ptr, live.fill, live.layout.size());
+}
+
+unsafe fn check_fill(live: &Live, len: usize) {
+    let data = core::slice::from_raw_parts(live.ptr, len);
+    assert!(
+        data.iter().all(|b| *b == live.fill),
+        "allocation at {:p} was overwritten",
+        live.ptr
+    );
+}
+
+unsafe fn check_live(heap: &TestHeap, live: &[Live]) {
+    let (start, end) = heap.used();
+
+    let mut ranges = Vec::new();
+
+    for l in live {
+        let addr = l.ptr as usize;
+
+        assert_eq!(addr % l.layout.align(), 0, "misaligned allocation");
+        assert!(addr >= start && addr + l.layout.size() <= end);
+
+        // In debug builds the cell starts before the guard words.
+        #[cfg(not(feature = "debug"))]
+        {
+            let cell = &*(l.ptr as *const CellHeader).offset(-1);
+            assert!(
+                cell.is_allocated(),
+                "live allocation {:p} marked free",
+                l.ptr
+            );
+            assert!(cell.size().0 >= l.layout.size());
+        }
+
+        ranges.push((addr, addr + l.layout.size()));
+    }
+
+    ranges.sort_unstable();
+
+    for pair in ranges.windows(2) {
+        assert!(
+            pair[0].1 <= pair[1].0,
+            "overlapping allocations {:x?}",
+            pair
+        );
+    }
+}
+
+fn run_random(ops: usize, check_every: usize) {
+    let heap = TestHeap::new(16 * 1024 * 1024);
+    let mut rng = Rng::new_unseeded();
+    let mut live: Vec<Live> = Vec::new();
+
+    for i in 0..ops {
+        unsafe {
+            match rng.next_u32() % 4 {
+                0 | 1 if live.len() < 512 => {
+                    let layout = random_layout(&mut rng);
+                    let ptr = heap.alloc.alloc(layout);
+                    assert!(!ptr.is_null(), "out of memory after {} ops", i);
+
+                    let l = Live {
+                        ptr,
+                        layout,
+                        fill: rng.next_u32() as u8,
+                    };
+                    fill(&l);
+                    live.push(l);
+                }
+                2 if !live.is_empty() => {
+                    let index = rng.next_u32() as usize % live.len();
+                    let old = live[index];
+                    let new_size = random_layout(&mut rng).size();
+
+                    let ptr = heap.alloc.realloc(old.ptr, old.layout, new_size);
+                    assert!(!ptr.is_null(), "out of memory after {} ops", i);
+
+                    let moved = Live { ptr, ..old };
+                    check_fill(&moved, usize::min(old.layout.size(), new_size));
+
+                    let l = Live {
+                        ptr,
+                        layout: Layout::from_size_align(new_size, old.layout.align()).unwrap(),
+                        fill: rng.next_u32() as u8,
+                    };
+                    fill(&l);
+                    live[index] = l;
+                }
+                _ if !live.is_empty() => {
+                    let l = live.swap_remove(rng.next_u32() as usize % live.len());
+                    check_fill(&l, l.layout.size());
+                    heap.alloc.dealloc(l.ptr, l.layout);
+                }
+                _ => {}
+            }
+
+            if i % check_every == 0 {
+                heap.check_invariants();
+                check_live(&heap, &live);
+            }
+        }
+    }
+
+    unsafe {
+        for l in live.drain(..) {
+            check_fill(&l, l.layout.size());
+            heap.alloc.dealloc(l.ptr, l.layout);
+        }
+
+        heap.check_invariants();
+    }
+}
+
+#[test]
+fn random_alloc_free_realloc() {
+    run_random(20_000, 64);
+}
+
+#[test]
+fn random_alloc_free_realloc_checked_every_op() {
+    run_random(2_000, 1);
+}
+
+#[test]
+fn size_class_boundaries() {
+    let heap = TestHeap::new(4 * 1024 * 1024);
+    let word = size_of::<usize>().0;
+
+    unsafe {
+        let mut live = Vec::new();
+
+        for words in &[
+            1,
+            2,
+            SizeClasses::NUM_SIZE_CLASSES,
+            SizeClasses::NUM_SIZE_CLASSES + 1,
+        ] {
+            for _ in 0..16 {
+                let layout = Layout::from_size_align(words * word, word).unwrap();
+                let ptr = heap.alloc.alloc(layout);
+                assert!(!ptr.is_null());
+                live.push((ptr, layout));
+            }
+        }
+
+        heap.check_invariants();
+
+        for (ptr, layout) in live {
+            heap.alloc.dealloc(ptr, layout);
+        }
+
+        heap.check_invariants();
+    }
+}
+
+#[test]
+fn large_frees_merge_back() {
+    let heap = TestHeap::new(1024 * 1024);
+    let layout = Layout::from_size_align(64 * 1024, 8).unwrap();
+
+    unsafe {
+        let mut live = Vec::new();
+
+        loop {
+            let ptr = heap.alloc.alloc(layout);
+            if ptr.is_null() {
+                break;
+            }
+            live.push(ptr);
+        }
+
+        assert!(live.len() >= 8, "only {} allocations fit", live.len());
+        heap.check_invariants();
+
+        let count = live.len();
+
+        for ptr in live.drain(..) {
+            heap.alloc.dealloc(ptr, layout);
+        }
+
+        heap.check_invariants();
+
+        // With everything merged back the same allocations must fit again
+        // without taking any more pages.
+        let (_, end) = heap.used();
+
+        for _ in 0..count {
+            let ptr = heap.alloc.alloc(layout);
+            assert!(!ptr.is_null());
+            live.push(ptr);
+        }
+
+        assert_eq!(heap.used().1, end);
+        heap.check_invariants();
+    }
+}
+
+#[test]
+fn out_of_memory_returns_null() {
+    let heap = TestHeap::new(128 * 1024);
+
+    unsafe {
+        let too_big = Layout::from_size_align(256 * 1024, 8).unwrap();
+        assert!(heap.alloc.alloc(too_big).is_null());
+
+        let small = Layout::from_size_align(100, 8).unwrap();
+        let mut count = 0;
+
+        while !heap.alloc.alloc(small).is_null() {
+            count += 1;
+        }
+
+        assert!(count > 0);
+        heap.check_invariants();
+    }
+}
+
+#[test]
+fn region_heaps_leave_global_counters_alone() {
+    use super::{BYTES_LEFT, BYTES_USED};
+    use core::sync::atomic::Ordering;
+
+    let heap = TestHeap::new(1024 * 1024);
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    let before = (
+        BYTES_LEFT.load(Ordering::SeqCst),
+        BYTES_USED.load(Ordering::SeqCst),
+    );
+
+    unsafe {
+        let ptr = heap.alloc.alloc(layout);
+        assert_eq!(
+            (
+                BYTES_LEFT.load(Ordering::SeqCst),
+                BYTES_USED.load(Ordering::SeqCst)
+            ),
+            before
+        );
+        heap.alloc.dealloc(ptr, layout);
+    }
+}
+
+#[cfg(not(feature = "debug"))]
+#[test]
+fn allocated_cell_header_round_trip() {
+    use super::AllocatedCell;
+
+    let heap = TestHeap::new(1024 * 1024);
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+
+    unsafe {
+        let ptr = heap.alloc.alloc(layout);
+        let cell = &*(ptr as *const AllocatedCell).offset(-1);
+
+        assert!(cell.header.is_allocated());
+        assert_eq!(cell.data(), ptr as *const u8);
+        assert!(cell.header.size().0 >= layout.size());
+
+        heap.alloc.dealloc(ptr, layout);
+        assert!(cell.header.is_free());
+    }
+}
+
+#[cfg(feature = "debug")]
+#[test]
+#[should_panic(expected = "double free")]
+fn debug_detects_double_free() {
+    let heap = TestHeap::new(1024 * 1024);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+
+    unsafe {
+        let ptr = heap.alloc.alloc(layout);
+        heap.alloc.dealloc(ptr, layout);
+        heap.alloc.dealloc(ptr, layout);
+    }
+}
+
+#[cfg(feature = "debug")]
+#[test]
+#[should_panic(expected = "overrun")]
+fn debug_detects_overrun() {
+    let heap = TestHeap::new(1024 * 1024);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+
+    unsafe {
+        let ptr = heap.alloc.alloc(layout);
+        *ptr.add(24) = 0;
+        heap.alloc.dealloc(ptr, layout);
+    }
+}
+
+#[cfg(feature = "debug")]
+#[test]
+fn debug_reports_live_allocations_by_tag() {
+    use super::debug::{self, LiveGroup};
+
+    let heap = TestHeap::new(1024 * 1024);
+    let layout = Layout::from_size_align(40, 8).unwrap();
+
+    unsafe {
+        let ptrs = {
+            let _tag = debug::tag("leak test");
+            (0..3).map(|_| heap.alloc.alloc(layout)).collect::<Vec<_>>()
+        };
+
+        assert!(core::slice::from_raw_parts(ptrs[0], 40)
+            .iter()
+            .all(|b| *b == 0xCD));
+
+        let mut groups = [LiveGroup {
+            tag: debug::UNTAGGED,
+            size: 0,
+            count: 0,
+        }; 64];
+        let len = debug::live_groups(&mut groups);
+
+        assert!(groups[..len]
+            .iter()
+            .any(|g| g.tag == "leak test" && g.size == 40 && g.count >= 3));
+
+        for ptr in ptrs {
+            heap.alloc.dealloc(ptr, layout);
+            assert!(core::slice::from_raw_parts(ptr, 40)
+                .iter()
+                .all(|b| *b == 0xDD));
+        }
+
+        heap.check_invariants();
+    }
+}
+
+// Debug builds count the guard words too.
+#[cfg(not(feature = "debug"))]
+#[test]
+fn stats_track_usage_and_free_space() {
+    use std::string::ToString;
+
+    let heap = TestHeap::new(1024 * 1024);
+    let small = Layout::from_size_align(20, 4).unwrap();
+    let large = Layout::from_size_align(10_000, 8).unwrap();
+
+    unsafe {
+        let a = heap.alloc.alloc(small);
+        let b = heap.alloc.alloc(large);
+
+        let stats = heap.alloc.stats();
+        let small_words: Words = Bytes(20).round_up_to();
+        let small_bytes = Bytes::from(small_words).0;
+
+        assert_eq!(stats.heap_size, 1024 * 1024);
+        assert_eq!(stats.pages, heap.used().1 - heap.used().0);
+        assert_eq!(stats.used, small_bytes + 10_000);
+        assert_eq!(stats.large_used, 10_000);
+        assert_eq!(
+            stats.size_classes[small_bytes / size_of::<usize>().0 - 1].used,
+            small_bytes
+        );
+        assert!(stats.largest_free_block() <= stats.free());
+
+        heap.alloc.dealloc(b, large);
+        heap.alloc.dealloc(a, small);
+
+        let stats = heap.alloc.stats();
+        assert_eq!(stats.used, 0);
+        assert_eq!(stats.peak, small_bytes + 10_000);
+        assert!(stats.fragmentation() >= 0.0 && stats.fragmentation() < 1.0);
+
+        let text = stats.text(4).to_string();
+        assert!(text
+            .bytes()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || b" ./K\n".contains(&c)));
+    }
+}
@@ -0,0 +1,426 @@
+//! 4 bit ADPCM in the style of the console's VADPCM: every sample gets its
+//! own codebook of second order predictors, and each frame of 16 samples
+//! picks the predictor and scale that fit it best.
+//!
+//! The format, all big endian:
+//!
+//! | "ADPC" | sample rate: u32 | length: u32 | predictor count: u32 |
+//! | predictors: count * [i16; 2] | frames |
+//!
+//! Predictor coefficients are 4.11 fixed point. A frame is a header byte,
+//! scale << 4 | predictor, followed by 16 residuals of 4 bits, high nibble
+//! first. 16 samples take 9 bytes instead of 32.
+
+use alloc::{vec, vec::Vec};
+use core::convert::TryInto;
+use libm::sqrt;
+
+pub const FRAME_SAMPLES: usize = 16;
+pub const FRAME_BYTES: usize = 1 + FRAME_SAMPLES / 2;
+pub const MAX_PREDICTORS: usize = 8;
+
+const MAGIC: &[u8; 4] = b"ADPC";
+const HEADER_SIZE: usize = 16;
+const COEFFICIENT_BITS: u32 = 11;
+const MAX_SCALE: u32 = 12;
+
+// Keeps the prediction of two full scale samples within an i32.
+const MAX_COEFFICIENT: f64 = 1.99;
+
+const CLUSTER_ITERATIONS: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AdpcmError {
+    BadMagic,
+    UnexpectedEnd,
+    TooManyPredictors,
+    /// A frame refers to a predictor the codebook does not have.
+    BadPredictor,
+}
+
+type Predictor = [i32; 2];
+
+#[inline]
+fn predict(predictor: &Predictor, history: &[i32; 2]) -> i32 {
+    (predictor[0] * history[0] + predictor[1] * history[1]) >> COEFFICIENT_BITS
+}
+
+#[inline]
+fn clamp<T: PartialOrd>(value: T, min: T, max: T) -> T {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}
+
+#[inline]
+fn to_i16(value: i32) -> i32 {
+    clamp(value, i16::MIN as i32, i16::MAX as i32)
+}
+
+/// Everything before the frames. Enough to decode frames that are read
+/// separately, like when streaming.
+#[derive(Copy, Clone, Debug)]
+pub struct Header {
+    pub sample_rate: u32,
+    /// In samples.
+    pub len: usize,
+    /// Where the frames start.
+    pub size: usize,
+    predictor_count: usize,
+    predictors: [Predictor; MAX_PREDICTORS],
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Header, AdpcmError> {
+        if data.len() < HEADER_SIZE {
+            return Err(AdpcmError::UnexpectedEnd);
+        }
+
+        if &data[..4] != MAGIC {
+            return Err(AdpcmError::BadMagic);
+        }
+
+        let word = |offset: usize| u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap());
+        let sample_rate = word(4);
+        let len = word(8) as usize;
+        let predictor_count = word(12) as usize;
+
+        if predictor_count > MAX_PREDICTORS {
+            return Err(AdpcmError::TooManyPredictors);
+        }
+
+        let size = HEADER_SIZE + predictor_count * 4;
+        let codebook = data
+            .get(HEADER_SIZE..size)
+            .ok_or(AdpcmError::UnexpectedEnd)?;
+
+        let mut predictors = [[0; 2]; MAX_PREDICTORS];
+        for (predictor, bytes) in predictors.iter_mut().zip(codebook.chunks_exact(4)) {
+            predictor[0] = i16::from_be_bytes([bytes[0], bytes[1]]) as i32;
+            predictor[1] = i16::from_be_bytes([bytes[2], bytes[3]]) as i32;
+        }
+
+        Ok(Header {
+            sample_rate,
+            len,
+            size,
+            predictor_count,
+            predictors,
+        })
+    }
+
+    #[inline]
+    pub fn frame_count(&self) -> usize {
+        self.len / FRAME_SAMPLES + (self.len % FRAME_SAMPLES).min(1)
+    }
+
+    /// Starts decoding at the first frame.
+    #[inline]
+    pub fn decoder(&self) -> Decoder {
+        Decoder {
+            predictor_count: self.predictor_count,
+            predictors: self.predictors,
+            history: [0; 2],
+        }
+    }
+}
+
+/// Compressed sample data, see the module documentation for the layout.
+#[derive(Copy, Clone, Debug)]
+pub struct Adpcm<'a> {
+    header: Header,
+    frames: &'a [u8],
+}
+
+impl<'a> Adpcm<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Adpcm<'a>, AdpcmError> {
+        let header = Header::parse(data)?;
+        let frames = data
+            .get(header.size..header.size + header.frame_count() * FRAME_BYTES)
+            .ok_or(AdpcmError::UnexpectedEnd)?;
+
+        Ok(Adpcm { header, frames })
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.header.sample_rate
+    }
+
+    /// In samples.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.header.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.header.len == 0
+    }
+
+    #[inline]
+    pub fn frame_count(&self) -> usize {
+        self.frames.len() / FRAME_BYTES
+    }
+
+    #[inline]
+    pub fn frame(&self, index: usize) -> &'a [u8] {
+        &self.frames[index * FRAME_BYTES..][..FRAME_BYTES]
+    }
+
+    /// Starts decoding at the first frame.
+    #[inline]
+    pub fn decoder(&self) -> Decoder {
+        self.header.decoder()
+    }
+
+    /// Decodes all of the data at once.
+    pub fn decode(&self) -> Result<Vec<i16>, AdpcmError> {
+        let mut decoder = self.decoder();
+        let mut res = Vec::with_capacity(self.frame_count() * FRAME_SAMPLES);
+        let mut out = [0; FRAME_SAMPLES];
+
+        for frame in self.frames.chunks_exact(FRAME_BYTES) {
+            decoder.decode_frame(frame, &mut out)?;
+            res.extend_from_slice(&out);
+        }
+
+        res.truncate(self.header.len);
+        Ok(res)
+    }
+}
+
+/// Decodes frames in order, each frame predicts from the end of the last.
+#[derive(Clone)]
+pub struct Decoder {
+    predictor_count: usize,
+    predictors: [Predictor; MAX_PREDICTORS],
+    // The previous sample first.
+    history: [i32; 2],
+}
+
+impl Decoder {
+    pub fn decode_frame(
+        &mut self,
+        frame: &[u8],
+        out: &mut [i16; FRAME_SAMPLES],
+    ) -> Result<(), AdpcmError> {
+        let scale = (frame[0] >> 4) as u32;
+        let index = (frame[0] & 0x0f) as usize;
+
+        if index >= self.predictor_count {
+            return Err(AdpcmError::BadPredictor);
+        }
+
+        let predictor = self.predictors[index];
+
+        for (i, out) in out.iter_mut().enumerate() {
+            let byte = frame[1 + i / 2];
+            let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0x0f };
+
+            // Sign extends the nibble.
+            let residual = ((nibble << 4) as i8 >> 4) as i32;
+            let sample = to_i16(predict(&predictor, &self.history) + (residual << scale));
+
+            self.history = [sample, self.history[0]];
+            *out = sample as i16;
+        }
+
+        Ok(())
+    }
+
+    /// Where the next frame continues from, for resuming at a loop point.
+    #[inline]
+    pub fn history(&self) -> [i16; 2] {
+        [self.history[0] as i16, self.history[1] as i16]
+    }
+
+    #[inline]
+    pub fn set_history(&mut self, history: [i16; 2]) {
+        self.history = [history[0] as i32, history[1] as i32];
+    }
+}
+
+// The least squares second order predictor of one frame, and the energy of
+// the frame to weigh it with.
+fn frame_predictor(samples: &[i16], start: usize) -> ([f64; 2], f64) {
+    let at = |n: usize| samples.get(n).map_or(0.0, |&sample| sample as f64);
+
+    let mut r11 = 0.0;
+    let mut r12 = 0.0;
+    let mut r22 = 0.0;
+    let mut r01 = 0.0;
+    let mut r02 = 0.0;
+    let mut energy = 0.0;
+
+    for n in start..start + FRAME_SAMPLES {
+        let x0 = at(n);
+        let x1 = if n >= 1 { at(n - 1) } else { 0.0 };
+        let x2 = if n >= 2 { at(n - 2) } else { 0.0 };
+
+        r11 += x1 * x1;
+        r12 += x1 * x2;
+        r22 += x2 * x2;
+        r01 += x0 * x1;
+        r02 += x0 * x2;
+        energy += x0 * x0;
+    }
+
+    let det = r11 * r22 - r12 * r12;
+
+    // Nearly singular when the frame is silent or a single tone.
+    let coefficients = if det > 1e-9 * r11 * r22 {
+        [(r01 * r22 - r02 * r12) / det, (r02 * r11 - r01 * r12) / det]
+    } else if r11 > 0.0 {
+        [r01 / r11, 0.0]
+    } else {
+        [0.0, 0.0]
+    };
+
+    let limit = |c: f64| clamp(c, -MAX_COEFFICIENT, MAX_COEFFICIENT);
+    ([limit(coefficients[0]), limit(coefficients[1])], energy)
+}
+
+fn distance(a: &[f64; 2], b: &[f64; 2]) -> f64 {
+    (a[0] - b[0]) * (a[0] - b[0]) + (a[1] - b[1]) * (a[1] - b[1])
+}
+
+// Groups the frame predictors into a codebook with k-means. The first entry
+// is always "no prediction", which suits noise and sudden changes.
+fn design_codebook(samples: &[i16]) -> Vec<Predictor> {
+    let mut frames = (0..samples.len())
+        .step_by(FRAME_SAMPLES)
+        .map(|start| frame_predictor(samples, start))
+        .filter(|(_, energy)| *energy > 0.0)
+        .collect::<Vec<_>>();
+
+    frames.sort_by(|a, b| a.0[0].partial_cmp(&b.0[0]).unwrap());
+
+    let clusters = (MAX_PREDICTORS - 1).min(frames.len());
+    let mut centroids = (0..clusters)
+        .map(|i| frames[(2 * i + 1) * frames.len() / (2 * clusters)].0)
+        .collect::<Vec<_>>();
+
+    for _ in 0..CLUSTER_ITERATIONS {
+        let mut sums = vec![([0.0; 2], 0.0); clusters];
+
+        for (coefficients, energy) in frames.iter() {
+            let nearest = (0..clusters)
+                .min_by(|&a, &b| {
+                    distance(coefficients, &centroids[a])
+                        .partial_cmp(&distance(coefficients, &centroids[b]))
+                        .unwrap()
+                })
+                .unwrap();
+
+            // Loud frames matter more, but not so much that quiet ones are
+            // left without a fitting predictor.
+            let weight = sqrt(*energy);
+            sums[nearest].0[0] += coefficients[0] * weight;
+            sums[nearest].0[1] += coefficients[1] * weight;
+            sums[nearest].1 += weight;
+        }
+
+        for (centroid, (sum, weight)) in centroids.iter_mut().zip(sums.iter()) {
+            if *weight > 0.0 {
+                *centroid = [sum[0] / weight, sum[1] / weight];
+            }
+        }
+    }
+
+    let mut codebook = Vec::new();
+    codebook.push([0, 0]);
+
+    for centroid in centroids {
+        let predictor = [
+            (centroid[0] * (1 << COEFFICIENT_BITS) as f64) as i32,
+            (centroid[1] * (1 << COEFFICIENT_BITS) as f64) as i32,
+        ];
+
+        if !codebook.contains(&predictor) {
+            codebook.push(predictor);
+        }
+    }
+
+    codebook
+}
+
+// Encodes one frame with the given predictor and scale, returning the
+// squared error and the history the decoder ends up with.
+fn encode_frame(
+    samples: &[i32; FRAME_SAMPLES],
+    predictor: &Predictor,
+    scale: u32,
+    mut history: [i32; 2],
+    residuals: &mut [i32; FRAME_SAMPLES],
+) -> (i64, [i32; 2]) {
+    let mut error = 0;
+
+    for (&sample, residual) in samples.iter().zip(residuals.iter_mut()) {
+        let prediction = predict(predictor, &history);
+        let rounding = (1 << scale) >> 1;
+
+        *residual = clamp((sample - prediction + rounding) >> scale, -8, 7);
+
+        let decoded = to_i16(prediction + (*residual << scale));
+        error += ((sample - decoded) as i64).pow(2);
+        history = [decoded, history[0]];
+    }
+
+    (error, history)
+}
+
+/// Encodes 16 bit mono samples, with a codebook made for them.
+pub fn encode(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let codebook = design_codebook(samples);
+
+    let mut res = Vec::new();
+    res.extend_from_slice(MAGIC);
+    res.extend_from_slice(&sample_rate.to_be_bytes());
+    res.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    res.extend_from_slice(&(codebook.len() as u32).to_be_bytes());
+
+    for predictor in codebook.iter() {
+        res.extend_from_slice(&(predictor[0] as i16).to_be_bytes());
+        res.extend_from_slice(&(predictor[1] as i16).to_be_bytes());
+    }
+
+    let mut history = [0; 2];
+    let mut residuals = [0; FRAME_SAMPLES];
+    let mut best_residuals = [0; FRAME_SAMPLES];
+
+    for chunk in samples.chunks(FRAME_SAMPLES) {
+        let mut frame = [0; FRAME_SAMPLES];
+        for (frame, &sample) in frame.iter_mut().zip(chunk.iter()) {
+            *frame = sample as i32;
+        }
+
+        let mut best = (i64::MAX, 0, 0, history);
+
+        for (index, predictor) in codebook.iter().enumerate() {
+            for scale in 0..=MAX_SCALE {
+                let (error, end_history) =
+                    encode_frame(&frame, predictor, scale, history, &mut residuals);
+
+                if error < best.0 {
+                    best = (error, index, scale, end_history);
+                    best_residuals = residuals;
+                }
+            }
+        }
+
+        let (_, index, scale, end_history) = best;
+        history = end_history;
+
+        res.push((scale << 4) as u8 | index as u8);
+        for pair in best_residuals.chunks_exact(2) {
+            res.push(((pair[0] as u8 & 0x0f) << 4) | (pair[1] as u8 & 0x0f));
+        }
+    }
+
+    res
+}
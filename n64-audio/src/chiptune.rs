@@ -0,0 +1,685 @@
+//! A small synthesizer for music made of note events instead of samples,
+//! usually imported from MIDI with `midi::import`.
+//!
+//! A sequence is "CHIP" followed by events until the end event. Each event
+//! is the milliseconds since the one before as a MIDI style variable length
+//! number, a type << 4 | channel byte and the event's data bytes:
+//!
+//! | note off: note | note on: note, velocity | volume: value | pan: value |
+//!
+//! Every one of the 16 channels has its own `Instrument`, an oscillator with
+//! an ADSR envelope. Everything but setting up an instrument is integer math.
+
+use alloc::vec::Vec;
+use libm::exp2f;
+
+pub const CHANNEL_COUNT: usize = 16;
+pub const MAX_VOICES: usize = 16;
+
+/// The channel General MIDI uses for drums.
+pub const DRUM_CHANNEL: usize = 9;
+
+const MAGIC: &[u8; 4] = b"CHIP";
+
+const NOTE_OFF: u8 = 0x0;
+const NOTE_ON: u8 = 0x1;
+const VOLUME: u8 = 0x2;
+const PAN: u8 = 0x3;
+const END: u8 = 0xff;
+
+// Envelope levels are 8.24 fixed point and gains 16.16.
+const ENVELOPE_ONE: i32 = 1 << 24;
+const GAIN_ONE: i32 = 1 << 16;
+const MAX_LEVEL: i32 = i16::MAX as i32;
+const CENTER_PAN: i32 = 64;
+
+// The noise generator is clocked this many times per cycle of the note.
+const NOISE_CLOCKS: f32 = 8.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SequenceError {
+    BadMagic,
+    UnexpectedEnd,
+    UnknownEvent(u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    /// 0 to 127, like MIDI controller 7.
+    Volume {
+        channel: u8,
+        value: u8,
+    },
+    /// 0 is left, 64 center and 127 right, like MIDI controller 10.
+    Pan {
+        channel: u8,
+        value: u8,
+    },
+}
+
+fn write_number(res: &mut Vec<u8>, value: u32) {
+    let mut shift = 28;
+    while shift > 0 && value >> shift == 0 {
+        shift -= 7;
+    }
+
+    while shift > 0 {
+        res.push(0x80 | ((value >> shift) as u8 & 0x7f));
+        shift -= 7;
+    }
+
+    res.push(value as u8 & 0x7f);
+}
+
+fn read_number(data: &[u8], offset: &mut usize) -> Result<u32, SequenceError> {
+    let mut value = 0u32;
+
+    for _ in 0..5 {
+        let byte = *data.get(*offset).ok_or(SequenceError::UnexpectedEnd)?;
+        *offset += 1;
+        value = (value << 7) | (byte & 0x7f) as u32;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(SequenceError::UnexpectedEnd)
+}
+
+/// Makes a sequence from events at absolute times in milliseconds, sorted by
+/// time. `length` is when the song ends, which is where it loops.
+pub fn encode(events: &[(u32, Event)], length: u32) -> Vec<u8> {
+    let mut res = MAGIC.to_vec();
+    let mut time = 0;
+
+    for &(event_time, event) in events {
+        write_number(&mut res, event_time.saturating_sub(time));
+        time = time.max(event_time);
+
+        match event {
+            Event::NoteOff { channel, note } => {
+                res.extend_from_slice(&[NOTE_OFF << 4 | channel, note]);
+            }
+            Event::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => res.extend_from_slice(&[NOTE_ON << 4 | channel, note, velocity]),
+            Event::Volume { channel, value } => {
+                res.extend_from_slice(&[VOLUME << 4 | channel, value])
+            }
+            Event::Pan { channel, value } => res.extend_from_slice(&[PAN << 4 | channel, value]),
+        }
+    }
+
+    write_number(&mut res, length.saturating_sub(time));
+    res.push(END);
+    res
+}
+
+// The delay and event at `offset`, no event at the end.
+fn read_event(data: &[u8], offset: &mut usize) -> Result<(u32, Option<Event>), SequenceError> {
+    let delay = read_number(data, offset)?;
+    let status = *data.get(*offset).ok_or(SequenceError::UnexpectedEnd)?;
+    *offset += 1;
+
+    if status == END {
+        return Ok((delay, None));
+    }
+
+    let channel = status & 0xf;
+    let size = match status >> 4 {
+        NOTE_ON => 2,
+        NOTE_OFF | VOLUME | PAN => 1,
+        _ => return Err(SequenceError::UnknownEvent(status)),
+    };
+
+    let bytes = data
+        .get(*offset..*offset + size)
+        .ok_or(SequenceError::UnexpectedEnd)?;
+    *offset += size;
+
+    let event = match status >> 4 {
+        NOTE_OFF => Event::NoteOff {
+            channel,
+            note: bytes[0],
+        },
+        NOTE_ON => Event::NoteOn {
+            channel,
+            note: bytes[0],
+            velocity: bytes[1],
+        },
+        VOLUME => Event::Volume {
+            channel,
+            value: bytes[0],
+        },
+        _ => Event::Pan {
+            channel,
+            value: bytes[0],
+        },
+    };
+
+    Ok((delay, Some(event)))
+}
+
+/// Reads back all the events of a sequence with their absolute times, and
+/// the length.
+pub fn decode(data: &[u8]) -> Result<(Vec<(u32, Event)>, u32), SequenceError> {
+    if !data.starts_with(MAGIC) {
+        return Err(SequenceError::BadMagic);
+    }
+
+    let mut offset = MAGIC.len();
+    let mut time = 0u32;
+    let mut events = Vec::new();
+
+    loop {
+        let (delay, event) = read_event(data, &mut offset)?;
+        time = time.saturating_add(delay);
+
+        match event {
+            Some(event) => events.push((time, event)),
+            None => return Ok((events, time)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    /// High for `duty` / 256 of every cycle.
+    Pulse(u8),
+    Triangle,
+    Sawtooth,
+    Noise,
+}
+
+/// The sound of a channel. Times are in seconds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Instrument {
+    pub waveform: Waveform,
+    pub attack: f32,
+    pub decay: f32,
+    /// Level from 0.0 to 1.0 held while the note is down.
+    pub sustain: f32,
+    pub release: f32,
+    pub volume: f32,
+}
+
+impl Default for Instrument {
+    fn default() -> Instrument {
+        Instrument {
+            waveform: Waveform::Square,
+            attack: 0.005,
+            decay: 0.1,
+            sustain: 0.6,
+            release: 0.1,
+            volume: 0.25,
+        }
+    }
+}
+
+impl Instrument {
+    /// Short noise bursts, pitched by the note.
+    pub fn drums() -> Instrument {
+        Instrument {
+            waveform: Waveform::Noise,
+            attack: 0.0,
+            decay: 0.15,
+            sustain: 0.0,
+            release: 0.05,
+            volume: 0.25,
+        }
+    }
+}
+
+// An instrument in steps per output frame.
+#[derive(Copy, Clone)]
+struct Envelope {
+    attack: i32,
+    decay: i32,
+    sustain: i32,
+    release_frames: i32,
+}
+
+impl Envelope {
+    fn new(instrument: &Instrument, sample_rate: u32) -> Envelope {
+        let frames = |seconds: f32| ((seconds * sample_rate as f32) as i32).max(1);
+        let sustain = clamp(instrument.sustain, 0.0, 1.0);
+
+        Envelope {
+            attack: ENVELOPE_ONE / frames(instrument.attack),
+            decay: ENVELOPE_ONE / frames(instrument.decay),
+            sustain: (sustain * ENVELOPE_ONE as f32) as i32,
+            release_frames: frames(instrument.release),
+        }
+    }
+}
+
+#[inline]
+fn clamp<T: PartialOrd>(value: T, min: T, max: T) -> T {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}
+
+struct Channel {
+    instrument: Instrument,
+    envelope: Envelope,
+    // The instrument volume, 16.16.
+    gain: i32,
+    volume: i32,
+    pan: i32,
+}
+
+impl Channel {
+    fn new(instrument: Instrument, sample_rate: u32) -> Channel {
+        Channel {
+            instrument,
+            envelope: Envelope::new(&instrument, sample_rate),
+            gain: (clamp(instrument.volume, 0.0, 2.0) * GAIN_ONE as f32) as i32,
+            volume: 100,
+            pan: CENTER_PAN,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+struct Voice {
+    channel: u8,
+    note: u8,
+    velocity: i32,
+    stage: Stage,
+    level: i32,
+    release_step: i32,
+    phase: u32,
+    step: u32,
+    noise: u16,
+    // When the note started, the oldest note is cut first.
+    started: u32,
+    left: i32,
+    right: i32,
+}
+
+impl Voice {
+    fn new() -> Voice {
+        Voice {
+            channel: 0,
+            note: 0,
+            velocity: 0,
+            stage: Stage::Off,
+            level: 0,
+            release_step: 0,
+            phase: 0,
+            step: 0,
+            noise: 1,
+            started: 0,
+            left: 0,
+            right: 0,
+        }
+    }
+
+    // Velocity, channel volume and pan, recalculated when they change.
+    fn update_gains(&mut self, channel: &Channel) {
+        let gain = (channel.gain as i64 * self.velocity as i64 * channel.volume as i64
+            / (127 * 127)) as i32;
+        let pan = channel.pan - CENTER_PAN;
+        self.left = gain * (CENTER_PAN - pan).min(CENTER_PAN) / CENTER_PAN;
+        self.right = gain * (CENTER_PAN + pan).min(CENTER_PAN) / CENTER_PAN;
+    }
+
+    fn release(&mut self, envelope: &Envelope) {
+        if self.stage != Stage::Off {
+            self.stage = Stage::Release;
+            self.release_step = (self.level / envelope.release_frames).max(1);
+        }
+    }
+
+    fn next_level(&mut self, envelope: &Envelope) -> i32 {
+        match self.stage {
+            Stage::Attack => {
+                self.level += envelope.attack;
+                if self.level >= ENVELOPE_ONE {
+                    self.level = ENVELOPE_ONE;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= envelope.decay;
+                if self.level <= envelope.sustain {
+                    self.level = envelope.sustain;
+                    self.stage = if envelope.sustain > 0 {
+                        Stage::Sustain
+                    } else {
+                        Stage::Off
+                    };
+                }
+            }
+            Stage::Sustain => (),
+            Stage::Release => {
+                self.level -= self.release_step;
+                if self.level <= 0 {
+                    self.level = 0;
+                    self.stage = Stage::Off;
+                }
+            }
+            Stage::Off => self.level = 0,
+        }
+
+        self.level
+    }
+
+    fn next_sample(&mut self, waveform: Waveform) -> i32 {
+        let phase = self.phase;
+        let (next, wrapped) = match waveform {
+            // The noise advances at several times the note's rate.
+            Waveform::Noise => phase.overflowing_add(self.step.saturating_mul(NOISE_CLOCKS as u32)),
+            _ => phase.overflowing_add(self.step),
+        };
+        self.phase = next;
+
+        match waveform {
+            Waveform::Square => square(phase < 1 << 31),
+            Waveform::Pulse(duty) => square(((phase >> 24) as u8) < duty),
+            Waveform::Triangle => {
+                let position = (phase >> 16) as i32;
+                let rising = if position < 32768 {
+                    position
+                } else {
+                    65535 - position
+                };
+                2 * rising - MAX_LEVEL
+            }
+            Waveform::Sawtooth => (phase >> 16) as i32 - 32768,
+            Waveform::Noise => {
+                // 15 bit LFSR like on the NES.
+                if wrapped {
+                    let bit = (self.noise ^ (self.noise >> 1)) & 1;
+                    self.noise = (self.noise >> 1) | (bit << 14);
+                }
+                square(self.noise & 1 == 0)
+            }
+        }
+    }
+}
+
+#[inline]
+fn square(high: bool) -> i32 {
+    if high {
+        MAX_LEVEL
+    } else {
+        -MAX_LEVEL
+    }
+}
+
+// Phase advance per output frame for a MIDI note, A4 is note 69 at 440 Hz.
+fn phase_step(note: u8, sample_rate: u32) -> u32 {
+    let frequency = 440.0 * exp2f((note as f32 - 69.0) / 12.0);
+    (frequency / sample_rate as f32 * 4_294_967_296.0) as u32
+}
+
+/// Plays a sequence, adding its sound to an interleaved stereo buffer. It
+/// loops by default.
+pub struct Synth {
+    sequence: Vec<u8>,
+    sample_rate: u32,
+    channels: Vec<Channel>,
+    voices: Vec<Voice>,
+    started: u32,
+    offset: usize,
+    // The output frame now, and the next event and its time in milliseconds.
+    // No event is the end of the sequence.
+    frame: u64,
+    event_time: u64,
+    event: Option<Event>,
+    looping: bool,
+    finished: bool,
+}
+
+impl Synth {
+    /// Checks the whole sequence up front so playing it can not fail.
+    /// Channel 10 gets `Instrument::drums`, the others the default.
+    pub fn new(sequence: Vec<u8>, sample_rate: u32) -> Result<Synth, SequenceError> {
+        decode(&sequence)?;
+
+        let channels = (0..CHANNEL_COUNT)
+            .map(|channel| {
+                let instrument = if channel == DRUM_CHANNEL {
+                    Instrument::drums()
+                } else {
+                    Instrument::default()
+                };
+                Channel::new(instrument, sample_rate)
+            })
+            .collect();
+
+        let mut synth = Synth {
+            sequence,
+            sample_rate,
+            channels,
+            voices: (0..MAX_VOICES).map(|_| Voice::new()).collect(),
+            started: 0,
+            offset: 0,
+            frame: 0,
+            event_time: 0,
+            event: None,
+            looping: true,
+            finished: false,
+        };
+        synth.restart();
+
+        Ok(synth)
+    }
+
+    #[inline]
+    pub fn instrument(&self, channel: usize) -> &Instrument {
+        &self.channels[channel].instrument
+    }
+
+    /// Changes the sound of `channel`, notes already playing keep theirs
+    /// until they end.
+    pub fn set_instrument(&mut self, channel: usize, instrument: Instrument) {
+        let volume = self.channels[channel].volume;
+        let pan = self.channels[channel].pan;
+
+        self.channels[channel] = Channel {
+            volume,
+            pan,
+            ..Channel::new(instrument, self.sample_rate)
+        };
+    }
+
+    #[inline]
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// True when a sequence that does not loop has reached its end and all
+    /// notes are done.
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.finished && self.voices.iter().all(|voice| voice.stage == Stage::Off)
+    }
+
+    /// Starts the sequence over, notes playing are released.
+    pub fn restart(&mut self) {
+        for voice in self.voices.iter_mut() {
+            let envelope = self.channels[voice.channel as usize].envelope;
+            voice.release(&envelope);
+        }
+
+        self.frame = 0;
+        self.finished = false;
+        self.rewind();
+    }
+
+    fn rewind(&mut self) {
+        self.offset = MAGIC.len();
+        self.event_time = 0;
+        self.read_event();
+    }
+
+    fn read_event(&mut self) {
+        // Checked in `new`.
+        let (delay, event) = read_event(&self.sequence, &mut self.offset).unwrap_or((0, None));
+        self.event_time += delay as u64;
+        self.event = event;
+    }
+
+    // The output frame the next event happens on.
+    fn event_frame(&self) -> u64 {
+        self.event_time * self.sample_rate as u64 / 1000
+    }
+
+    fn handle_events(&mut self) {
+        while !self.finished && self.frame >= self.event_frame() {
+            match self.event {
+                Some(Event::NoteOff { channel, note }) => self.note_off(channel as usize, note),
+                Some(Event::NoteOn {
+                    channel,
+                    note,
+                    velocity,
+                }) => self.note_on(channel as usize, note, velocity),
+                Some(Event::Volume { channel, value }) => {
+                    self.channels[channel as usize].volume = value.min(127) as i32;
+                    self.update_gains(channel as usize);
+                }
+                Some(Event::Pan { channel, value }) => {
+                    self.channels[channel as usize].pan = value.min(127) as i32;
+                    self.update_gains(channel as usize);
+                }
+                // A song without length can not loop.
+                None if self.looping && self.event_frame() > 0 => {
+                    self.frame -= self.event_frame();
+                    self.rewind();
+                    continue;
+                }
+                None => {
+                    self.finished = true;
+                    continue;
+                }
+            }
+
+            self.read_event();
+        }
+    }
+
+    fn update_gains(&mut self, channel: usize) {
+        let state = &self.channels[channel];
+        for voice in self.voices.iter_mut() {
+            if voice.channel as usize == channel && voice.stage != Stage::Off {
+                voice.update_gains(state);
+            }
+        }
+    }
+
+    /// Starts a note on `channel`, also used by the sequence.
+    pub fn note_on(&mut self, channel: usize, note: u8, velocity: u8) {
+        self.started = self.started.wrapping_add(1);
+        let started = self.started;
+
+        // The same note again restarts it, otherwise a free voice is used or
+        // the quietest releasing one, or the oldest.
+        let index = self
+            .voices
+            .iter()
+            .position(|voice| {
+                voice.stage != Stage::Off && voice.channel as usize == channel && voice.note == note
+            })
+            .or_else(|| {
+                self.voices
+                    .iter()
+                    .position(|voice| voice.stage == Stage::Off)
+            })
+            .or_else(|| {
+                self.voices
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, voice)| voice.stage == Stage::Release)
+                    .min_by_key(|(_, voice)| voice.level)
+                    .map(|(index, _)| index)
+            })
+            .unwrap_or_else(|| {
+                self.voices
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(_, voice)| started.wrapping_sub(voice.started))
+                    .map_or(0, |(index, _)| index)
+            });
+
+        let state = &self.channels[channel];
+        let voice = &mut self.voices[index];
+        voice.channel = channel as u8;
+        voice.note = note;
+        voice.velocity = velocity.min(127) as i32;
+        voice.stage = Stage::Attack;
+        voice.level = 0;
+        voice.step = phase_step(note, self.sample_rate);
+        voice.started = started;
+        voice.update_gains(state);
+    }
+
+    pub fn note_off(&mut self, channel: usize, note: u8) {
+        let envelope = self.channels[channel].envelope;
+
+        for voice in self.voices.iter_mut() {
+            if voice.channel as usize == channel
+                && voice.note == note
+                && voice.stage != Stage::Release
+            {
+                voice.release(&envelope);
+            }
+        }
+    }
+
+    /// Adds the next frames to `out`, on top of what is there, so it can go
+    /// after `Mixer::mix`.
+    pub fn render(&mut self, out: &mut [i16]) {
+        for frame in out.chunks_exact_mut(2) {
+            self.handle_events();
+
+            let mut left = 0;
+            let mut right = 0;
+
+            for voice in self.voices.iter_mut() {
+                if voice.stage == Stage::Off {
+                    continue;
+                }
+
+                let channel = &self.channels[voice.channel as usize];
+                let level = voice.next_level(&channel.envelope) >> 9;
+                let value = (voice.next_sample(channel.instrument.waveform) * level) >> 15;
+
+                left += ((value as i64 * voice.left as i64) >> 16) as i32;
+                right += ((value as i64 * voice.right as i64) >> 16) as i32;
+            }
+
+            frame[0] = clamp(frame[0] as i32 + left, i16::MIN as i32, MAX_LEVEL) as i16;
+            frame[1] = clamp(frame[1] as i32 + right, i16::MIN as i32, MAX_LEVEL) as i16;
+
+            self.frame += 1;
+        }
+    }
+}
@@ -0,0 +1,583 @@
+//! Effects for a bus of interleaved stereo, like all the sound effects or the
+//! music: filters, echo, reverb and ducking one bus under another.
+//!
+//! Parameters are worked out in floating point when an effect is made, the
+//! processing itself is integer math like the `mixer`.
+
+use alloc::{vec, vec::Vec};
+use core::f32::consts::PI;
+use libm::{cosf, expf, sinf};
+
+// Filter coefficients and time constants are 8.24 fixed point.
+const FIXED_BITS: u32 = 24;
+const FIXED_ONE: i64 = 1 << FIXED_BITS;
+
+// Filters keep this many bits below the 16 bit samples, so quiet signals are
+// not lost at low cutoffs.
+const STATE_BITS: u32 = 8;
+
+// Gains are 1.15 fixed point.
+const GAIN_BITS: u32 = 15;
+const GAIN_ONE: i32 = 1 << GAIN_BITS;
+
+/// The longest `Delay`, 500 ms of stereo at 22050 Hz takes 43 KiB.
+pub const MAX_DELAY_MS: u32 = 500;
+
+// Freeverb's tunings at 44100 Hz, scaled to the sample rate. The right
+// channel is a little longer to make it wide.
+const COMB_LENGTHS: [usize; 4] = [1116, 1188, 1277, 1356];
+const ALLPASS_LENGTHS: [usize; 2] = [556, 441];
+const STEREO_SPREAD: usize = 23;
+const TUNING_RATE: usize = 44100;
+const REVERB_INPUT_SHIFT: u32 = 3;
+
+#[inline]
+fn clamp<T: PartialOrd>(value: T, min: T, max: T) -> T {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}
+
+#[inline]
+fn saturate(value: i32) -> i16 {
+    clamp(value, i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+#[inline]
+fn to_fixed(value: f32) -> i64 {
+    (value as f64 * FIXED_ONE as f64) as i64
+}
+
+#[inline]
+fn to_gain(value: f32, max: f32) -> i32 {
+    (clamp(value, 0.0, max) * GAIN_ONE as f32) as i32
+}
+
+// How much of the way to a new value to go each frame, to get most of the
+// way there in `ms`.
+fn time_coefficient(ms: f32, sample_rate: u32) -> i64 {
+    let frames = ms * sample_rate as f32 / 1000.0;
+
+    if frames < 1.0 {
+        FIXED_ONE
+    } else {
+        to_fixed(1.0 - expf(-1.0 / frames))
+    }
+}
+
+/// A gentle 6 dB per octave filter.
+#[derive(Clone, Debug)]
+pub struct OnePole {
+    high_pass: bool,
+    coefficient: i64,
+    state: [i64; 2],
+}
+
+impl OnePole {
+    pub fn low_pass(cutoff: f32, sample_rate: u32) -> OnePole {
+        OnePole::new(false, cutoff, sample_rate)
+    }
+
+    pub fn high_pass(cutoff: f32, sample_rate: u32) -> OnePole {
+        OnePole::new(true, cutoff, sample_rate)
+    }
+
+    fn new(high_pass: bool, cutoff: f32, sample_rate: u32) -> OnePole {
+        let mut filter = OnePole {
+            high_pass,
+            coefficient: 0,
+            state: [0; 2],
+        };
+
+        filter.set_cutoff(cutoff, sample_rate);
+        filter
+    }
+
+    /// Can be changed while playing, for sweeps.
+    pub fn set_cutoff(&mut self, cutoff: f32, sample_rate: u32) {
+        let coefficient = 1.0 - expf(-2.0 * PI * cutoff / sample_rate as f32);
+        self.coefficient = to_fixed(clamp(coefficient, 0.0, 1.0));
+    }
+
+    fn process(&mut self, frames: &mut [i32]) {
+        for frame in frames.chunks_exact_mut(2) {
+            for (value, state) in frame.iter_mut().zip(self.state.iter_mut()) {
+                let input = (*value as i64) << STATE_BITS;
+                *state += ((input - *state) * self.coefficient) >> FIXED_BITS;
+
+                let low = (*state >> STATE_BITS) as i32;
+                *value = if self.high_pass { *value - low } else { low };
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = [0; 2];
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BiquadKind {
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+/// A 12 dB per octave filter with a resonance, from the Audio EQ Cookbook.
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    b: [i64; 3],
+    a: [i64; 2],
+    // The last two inputs, and the last two outputs with `STATE_BITS`.
+    inputs: [[i64; 2]; 2],
+    outputs: [[i64; 2]; 2],
+}
+
+impl Biquad {
+    /// `q` of 0.707 is flat, higher rings at `frequency`.
+    pub fn new(kind: BiquadKind, frequency: f32, q: f32, sample_rate: u32) -> Biquad {
+        let mut filter = Biquad {
+            b: [0; 3],
+            a: [0; 2],
+            inputs: [[0; 2]; 2],
+            outputs: [[0; 2]; 2],
+        };
+
+        filter.set(kind, frequency, q, sample_rate);
+        filter
+    }
+
+    /// Can be changed while playing, for sweeps.
+    pub fn set(&mut self, kind: BiquadKind, frequency: f32, q: f32, sample_rate: u32) {
+        let w0 = 2.0 * PI * clamp(frequency, 1.0, 0.49 * sample_rate as f32) / sample_rate as f32;
+        let cos = cosf(w0);
+        let alpha = sinf(w0) / (2.0 * q.max(0.1));
+
+        let b = match kind {
+            BiquadKind::LowPass => [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            BiquadKind::HighPass => [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            BiquadKind::BandPass => [alpha, 0.0, -alpha],
+            BiquadKind::Notch => [1.0, -2.0 * cos, 1.0],
+        };
+
+        let a0 = 1.0 + alpha;
+        self.b = [
+            to_fixed(b[0] / a0),
+            to_fixed(b[1] / a0),
+            to_fixed(b[2] / a0),
+        ];
+        self.a = [to_fixed(-2.0 * cos / a0), to_fixed((1.0 - alpha) / a0)];
+    }
+
+    fn process(&mut self, frames: &mut [i32]) {
+        for frame in frames.chunks_exact_mut(2) {
+            for (channel, value) in frame.iter_mut().enumerate() {
+                let [x1, x2] = self.inputs[channel];
+                let [y1, y2] = self.outputs[channel];
+                let x0 = *value as i64;
+
+                let sum = ((self.b[0] * x0 + self.b[1] * x1 + self.b[2] * x2) << STATE_BITS)
+                    - self.a[0] * y1
+                    - self.a[1] * y2;
+                let y0 = sum >> FIXED_BITS;
+
+                self.inputs[channel] = [x0, x1];
+                self.outputs[channel] = [y0, y1];
+                *value = (y0 >> STATE_BITS) as i32;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.inputs = [[0; 2]; 2];
+        self.outputs = [[0; 2]; 2];
+    }
+}
+
+/// An echo that repeats every `time_ms`, quieter each time.
+#[derive(Clone, Debug)]
+pub struct Delay {
+    // Stereo frames, kept as 16 bit to save RAM.
+    buffer: Vec<i16>,
+    position: usize,
+    feedback: i32,
+    wet: i32,
+}
+
+impl Delay {
+    /// Each echo is `feedback` times the last, up to 0.95. The echoes are
+    /// added to the input at `wet`.
+    pub fn new(time_ms: u32, feedback: f32, wet: f32, sample_rate: u32) -> Delay {
+        assert!(
+            time_ms > 0 && time_ms <= MAX_DELAY_MS,
+            "Delay time out of range"
+        );
+
+        let frames = (time_ms as usize * sample_rate as usize / 1000).max(1);
+
+        Delay {
+            buffer: vec![0; 2 * frames],
+            position: 0,
+            feedback: to_gain(feedback, 0.95),
+            wet: to_gain(wet, 1.0),
+        }
+    }
+
+    fn process(&mut self, frames: &mut [i32]) {
+        for frame in frames.chunks_exact_mut(2) {
+            let delayed = &mut self.buffer[self.position..self.position + 2];
+
+            for (value, delayed) in frame.iter_mut().zip(delayed.iter_mut()) {
+                let echo = *delayed as i32;
+                *delayed = saturate(*value + ((echo * self.feedback) >> GAIN_BITS));
+                *value += (echo * self.wet) >> GAIN_BITS;
+            }
+
+            self.position += 2;
+            if self.position >= self.buffer.len() {
+                self.position = 0;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for sample in self.buffer.iter_mut() {
+            *sample = 0;
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Comb {
+    buffer: Vec<i16>,
+    position: usize,
+    // The damping low-pass in the feedback.
+    filtered: i32,
+}
+
+impl Comb {
+    #[inline]
+    fn process(&mut self, input: i32, feedback: i32, damping: i32) -> i32 {
+        let output = self.buffer[self.position] as i32;
+
+        self.filtered = output + (((self.filtered - output) * damping) >> GAIN_BITS);
+        self.buffer[self.position] = saturate(input + ((self.filtered * feedback) >> GAIN_BITS));
+
+        self.position += 1;
+        if self.position >= self.buffer.len() {
+            self.position = 0;
+        }
+
+        output
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Allpass {
+    buffer: Vec<i16>,
+    position: usize,
+}
+
+impl Allpass {
+    #[inline]
+    fn process(&mut self, input: i32) -> i32 {
+        let delayed = self.buffer[self.position] as i32;
+        self.buffer[self.position] = saturate(input + (delayed >> 1));
+
+        self.position += 1;
+        if self.position >= self.buffer.len() {
+            self.position = 0;
+        }
+
+        delayed - input
+    }
+}
+
+/// A small room: Schroeder style, four damped combs into two allpasses for
+/// each side, fed from the middle.
+#[derive(Clone, Debug)]
+pub struct Reverb {
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    feedback: i32,
+    damping: i32,
+    wet: i32,
+}
+
+impl Reverb {
+    /// `room_size` and `damping` are 0.0 to 1.0, a bigger room rings longer
+    /// and more damping takes the highs out of the tail. The reverb is added
+    /// to the input at `wet`.
+    pub fn new(room_size: f32, damping: f32, wet: f32, sample_rate: u32) -> Reverb {
+        let scale = |length: usize| (length * sample_rate as usize / TUNING_RATE).max(1);
+
+        let combs = |spread: usize| {
+            COMB_LENGTHS
+                .iter()
+                .map(|&length| Comb {
+                    buffer: vec![0; scale(length + spread)],
+                    position: 0,
+                    filtered: 0,
+                })
+                .collect()
+        };
+        let allpasses = |spread: usize| {
+            ALLPASS_LENGTHS
+                .iter()
+                .map(|&length| Allpass {
+                    buffer: vec![0; scale(length + spread)],
+                    position: 0,
+                })
+                .collect()
+        };
+
+        Reverb {
+            combs: [combs(0), combs(STEREO_SPREAD)],
+            allpasses: [allpasses(0), allpasses(STEREO_SPREAD)],
+            feedback: to_gain(0.7 + 0.28 * clamp(room_size, 0.0, 1.0), 0.98),
+            damping: to_gain(0.4 * clamp(damping, 0.0, 1.0), 0.4),
+            wet: to_gain(wet, 1.0),
+        }
+    }
+
+    fn process(&mut self, frames: &mut [i32]) {
+        for frame in frames.chunks_exact_mut(2) {
+            let input = clamp(
+                (frame[0] + frame[1]) >> (REVERB_INPUT_SHIFT + 1),
+                i16::MIN as i32,
+                i16::MAX as i32,
+            );
+
+            for (channel, value) in frame.iter_mut().enumerate() {
+                let mut output = 0;
+
+                for comb in self.combs[channel].iter_mut() {
+                    output += comb.process(input, self.feedback, self.damping);
+                }
+
+                for allpass in self.allpasses[channel].iter_mut() {
+                    output = allpass.process(output);
+                }
+
+                *value += (output * self.wet) >> GAIN_BITS;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for comb in self.combs.iter_mut().flat_map(|combs| combs.iter_mut()) {
+            comb.filtered = 0;
+            for sample in comb.buffer.iter_mut() {
+                *sample = 0;
+            }
+        }
+
+        for allpass in self.allpasses.iter_mut().flat_map(|all| all.iter_mut()) {
+            for sample in allpass.buffer.iter_mut() {
+                *sample = 0;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Effect {
+    OnePole(OnePole),
+    Biquad(Biquad),
+    Delay(Delay),
+    Reverb(Reverb),
+}
+
+impl Effect {
+    fn process(&mut self, frames: &mut [i32]) {
+        match self {
+            Effect::OnePole(filter) => filter.process(frames),
+            Effect::Biquad(filter) => filter.process(frames),
+            Effect::Delay(delay) => delay.process(frames),
+            Effect::Reverb(reverb) => reverb.process(frames),
+        }
+    }
+
+    /// Forgets what has played, like filter state and echo tails.
+    pub fn reset(&mut self) {
+        match self {
+            Effect::OnePole(filter) => filter.reset(),
+            Effect::Biquad(filter) => filter.reset(),
+            Effect::Delay(delay) => delay.reset(),
+            Effect::Reverb(reverb) => reverb.reset(),
+        }
+    }
+}
+
+impl From<OnePole> for Effect {
+    fn from(filter: OnePole) -> Effect {
+        Effect::OnePole(filter)
+    }
+}
+
+impl From<Biquad> for Effect {
+    fn from(filter: Biquad) -> Effect {
+        Effect::Biquad(filter)
+    }
+}
+
+impl From<Delay> for Effect {
+    fn from(delay: Delay) -> Effect {
+        Effect::Delay(delay)
+    }
+}
+
+impl From<Reverb> for Effect {
+    fn from(reverb: Reverb) -> Effect {
+        Effect::Reverb(reverb)
+    }
+}
+
+/// Effects run in order on one group of sounds, then its volume.
+#[derive(Clone, Debug)]
+pub struct Bus {
+    effects: Vec<Effect>,
+    volume: i32,
+    frames: Vec<i32>,
+}
+
+impl Default for Bus {
+    fn default() -> Bus {
+        Bus::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        Bus {
+            effects: Vec::new(),
+            volume: GAIN_ONE,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Adds an effect after the ones already there.
+    pub fn with(mut self, effect: impl Into<Effect>) -> Bus {
+        self.push(effect);
+        self
+    }
+
+    pub fn push(&mut self, effect: impl Into<Effect>) {
+        self.effects.push(effect.into());
+    }
+
+    /// For changing effect parameters while playing.
+    #[inline]
+    pub fn effects_mut(&mut self) -> &mut [Effect] {
+        &mut self.effects
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    pub fn reset(&mut self) {
+        for effect in self.effects.iter_mut() {
+            effect.reset();
+        }
+    }
+
+    /// 0.0 to 2.0.
+    #[inline]
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = to_gain(volume, 2.0);
+    }
+
+    /// Runs the effects on interleaved stereo `frames` in place.
+    pub fn process(&mut self, frames: &mut [i16]) {
+        self.frames.clear();
+        self.frames
+            .extend(frames.iter().map(|&sample| sample as i32));
+
+        for effect in self.effects.iter_mut() {
+            effect.process(&mut self.frames);
+        }
+
+        for (out, &value) in frames.iter_mut().zip(self.frames.iter()) {
+            *out = saturate(((value as i64 * self.volume as i64) >> GAIN_BITS) as i32);
+        }
+    }
+}
+
+/// Turns a bus down while another one, the sidechain, is loud. Like music
+/// making room for sound effects.
+#[derive(Clone, Debug)]
+pub struct Ducker {
+    threshold: i32,
+    gain: i64,
+    target: i64,
+    attack: i64,
+    release: i64,
+    envelope: i64,
+}
+
+impl Ducker {
+    /// Above `threshold`, 0.0 to 1.0 of full scale, the bus goes down to
+    /// `gain` in about `attack_ms` and comes back in about `release_ms` once
+    /// the sidechain is quiet again.
+    pub fn new(
+        threshold: f32,
+        gain: f32,
+        attack_ms: f32,
+        release_ms: f32,
+        sample_rate: u32,
+    ) -> Ducker {
+        Ducker {
+            threshold: (clamp(threshold, 0.0, 1.0) * i16::MAX as f32) as i32,
+            gain: FIXED_ONE,
+            target: to_fixed(clamp(gain, 0.0, 1.0)),
+            attack: time_coefficient(attack_ms, sample_rate),
+            release: time_coefficient(release_ms, sample_rate),
+            envelope: 0,
+        }
+    }
+
+    /// How far down the bus is now, 1.0 is not at all.
+    #[inline]
+    pub fn gain(&self) -> f32 {
+        self.gain as f32 / FIXED_ONE as f32
+    }
+
+    /// Ducks interleaved stereo `frames` under `sidechain`, as many frames
+    /// as both have.
+    pub fn process(&mut self, frames: &mut [i16], sidechain: &[i16]) {
+        for (frame, side) in frames.chunks_exact_mut(2).zip(sidechain.chunks_exact(2)) {
+            // A peak follower that falls at the release rate, so the bus
+            // does not pump along with every wave of the sidechain.
+            let level = ((side[0] as i32).abs().max((side[1] as i32).abs()) as i64) << STATE_BITS;
+            if level > self.envelope {
+                self.envelope = level;
+            } else {
+                self.envelope += ((level - self.envelope) * self.release) >> FIXED_BITS;
+            }
+
+            let (target, coefficient) = if self.envelope >> STATE_BITS > self.threshold as i64 {
+                (self.target, self.attack)
+            } else {
+                (FIXED_ONE, self.release)
+            };
+            self.gain += ((target - self.gain) * coefficient) >> FIXED_BITS;
+
+            for value in frame.iter_mut() {
+                *value = ((*value as i64 * self.gain) >> FIXED_BITS) as i16;
+            }
+        }
+    }
+}
+
+/// Adds interleaved stereo `input` to `out`, as when summing buses.
+pub fn add(out: &mut [i16], input: &[i16]) {
+    for (out, &input) in out.iter_mut().zip(input.iter()) {
+        *out = saturate(*out as i32 + input as i32);
+    }
+}
@@ -0,0 +1,19 @@
+#![no_std]
+
+//! Sound synthesis that runs the same in build scripts and on the console,
+//! so assets can be made ahead of time or varied at runtime.
+
+extern crate alloc;
+
+pub mod adpcm;
+pub mod chiptune;
+pub mod effects;
+pub mod midi;
+pub mod mixer;
+pub mod sfxr;
+pub mod stream;
+pub mod tracker;
+pub mod wav;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,228 @@
+//! Standard MIDI files to `chiptune` sequences, done by the build script.
+//!
+//! All tracks are merged and tempo changes applied so only notes, channel
+//! volume and pan are left, timed in milliseconds. Programs, pitch bends and
+//! other controllers are dropped, channels get their sound from the
+//! `chiptune::Instrument` the game gives them.
+
+use crate::chiptune::{self, Event};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+const HEADER_SIZE: usize = 14;
+const DEFAULT_TEMPO: u64 = 500_000;
+
+const CONTROLLER_VOLUME: u8 = 7;
+const CONTROLLER_PAN: u8 = 10;
+
+const META: u8 = 0xff;
+const META_END_OF_TRACK: u8 = 0x2f;
+const META_TEMPO: u8 = 0x51;
+const SYSEX: u8 = 0xf0;
+const SYSEX_CONTINUE: u8 = 0xf7;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MidiError {
+    NotMidi,
+    UnexpectedEnd,
+    /// Only ticks per quarter note timing is read, not SMPTE frames.
+    UnsupportedTiming,
+    BadEvent,
+}
+
+#[derive(Copy, Clone)]
+enum TrackEvent {
+    // Microseconds per quarter note.
+    Tempo(u64),
+    Channel(Event),
+    End,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], MidiError> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset.saturating_add(len))
+            .ok_or(MidiError::UnexpectedEnd)?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, MidiError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn peek(&self) -> Result<u8, MidiError> {
+        self.data
+            .get(self.offset)
+            .copied()
+            .ok_or(MidiError::UnexpectedEnd)
+    }
+
+    fn u32(&mut self) -> Result<u32, MidiError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn number(&mut self) -> Result<u32, MidiError> {
+        let mut value = 0u32;
+
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7f) as u32;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(MidiError::BadEvent)
+    }
+}
+
+// The events of one track with their times in ticks.
+fn parse_track(data: &[u8], events: &mut Vec<(u64, TrackEvent)>) -> Result<(), MidiError> {
+    let mut reader = Reader { data, offset: 0 };
+    let mut tick = 0u64;
+    let mut running_status = None;
+
+    while !reader.is_empty() {
+        tick += reader.number()? as u64;
+
+        // Without a status byte the last one is used again.
+        let status = if reader.peek()? & 0x80 != 0 {
+            reader.u8()?
+        } else {
+            running_status.ok_or(MidiError::BadEvent)?
+        };
+
+        match status {
+            META => {
+                running_status = None;
+
+                let kind = reader.u8()?;
+                let len = reader.number()? as usize;
+                let bytes = reader.bytes(len)?;
+
+                match kind {
+                    META_TEMPO if len == 3 => {
+                        let tempo =
+                            ((bytes[0] as u64) << 16) | ((bytes[1] as u64) << 8) | bytes[2] as u64;
+                        events.push((tick, TrackEvent::Tempo(tempo)));
+                    }
+                    META_END_OF_TRACK => break,
+                    _ => (),
+                }
+            }
+            SYSEX | SYSEX_CONTINUE => {
+                running_status = None;
+
+                let len = reader.number()? as usize;
+                reader.bytes(len)?;
+            }
+            0x80..=0xef => {
+                running_status = Some(status);
+
+                let channel = status & 0xf;
+                let size = match status >> 4 {
+                    0xc | 0xd => 1,
+                    _ => 2,
+                };
+                let bytes = reader.bytes(size)?;
+
+                let event = match (status >> 4, bytes[0]) {
+                    (0x8, note) => Some(Event::NoteOff { channel, note }),
+                    // A note on at velocity 0 is a note off.
+                    (0x9, note) if bytes[1] == 0 => Some(Event::NoteOff { channel, note }),
+                    (0x9, note) => Some(Event::NoteOn {
+                        channel,
+                        note,
+                        velocity: bytes[1],
+                    }),
+                    (0xb, CONTROLLER_VOLUME) => Some(Event::Volume {
+                        channel,
+                        value: bytes[1],
+                    }),
+                    (0xb, CONTROLLER_PAN) => Some(Event::Pan {
+                        channel,
+                        value: bytes[1],
+                    }),
+                    _ => None,
+                };
+
+                if let Some(event) = event {
+                    events.push((tick, TrackEvent::Channel(event)));
+                }
+            }
+            _ => return Err(MidiError::BadEvent),
+        }
+    }
+
+    events.push((tick, TrackEvent::End));
+    Ok(())
+}
+
+/// Converts a format 0 or 1 MIDI file to a `chiptune` sequence. The song
+/// ends, and loops, at the end of its longest track.
+pub fn import(data: &[u8]) -> Result<Vec<u8>, MidiError> {
+    let mut reader = Reader { data, offset: 0 };
+
+    if data.len() < HEADER_SIZE || reader.bytes(4)? != b"MThd" {
+        return Err(MidiError::NotMidi);
+    }
+
+    let header_size = reader.u32()? as usize;
+    let header = reader.bytes(header_size)?;
+    if header.len() < 6 {
+        return Err(MidiError::UnexpectedEnd);
+    }
+
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if division & 0x8000 != 0 || division == 0 {
+        return Err(MidiError::UnsupportedTiming);
+    }
+
+    let mut events = Vec::new();
+
+    while !reader.is_empty() {
+        let id = reader.bytes(4)?;
+        let size = reader.u32()? as usize;
+        let chunk = reader.bytes(size)?;
+
+        // Other chunks are skipped like the standard says.
+        if id == b"MTrk" {
+            parse_track(chunk, &mut events)?;
+        }
+    }
+
+    // Stable, so events at the same tick keep their order within a track.
+    events.sort_by_key(|&(tick, _)| tick);
+
+    let mut tempo = DEFAULT_TEMPO;
+    let mut last_tick = 0;
+    let mut time_us = 0u64;
+    let mut length = 0;
+    let mut sequence = Vec::new();
+
+    for (tick, event) in events {
+        time_us += (tick - last_tick) * tempo / division as u64;
+        last_tick = tick;
+        let time = (time_us / 1000) as u32;
+
+        match event {
+            TrackEvent::Tempo(new_tempo) => tempo = new_tempo,
+            TrackEvent::Channel(event) => sequence.push((time, event)),
+            TrackEvent::End => length = length.max(time),
+        }
+    }
+
+    Ok(chiptune::encode(&sequence, length))
+}
@@ -0,0 +1,508 @@
+use crate::adpcm::{Adpcm, AdpcmError};
+use alloc::{rc::Rc, vec::Vec};
+use core::convert::TryInto;
+
+// Everything past the public API is integer math so the console and the PC
+// produce the same output bit for bit.
+const FRACTION_BITS: u32 = 16;
+const FRACTION_MASK: u32 = (1 << FRACTION_BITS) - 1;
+
+// Volumes and pan are 8.8 fixed point.
+const VOLUME_ONE: i32 = 256;
+const MAX_VOLUME: f32 = 2.0;
+
+// The limiter gain is 16.16 fixed point. It drops at once when the mix would
+// clip and recovers by 1/2^RELEASE_SHIFT of the distance every frame.
+const LIMITER_ONE: i64 = 1 << 16;
+const LIMITER_RELEASE_SHIFT: u32 = 11;
+
+// How many buffers past the next mixed frame a `Scheduler` plays sounds,
+// later than that it starts over.
+const MAX_SCHEDULE_BUFFERS: u64 = 3;
+
+#[derive(Clone)]
+enum SampleData {
+    I8(&'static [i8]),
+    I16(&'static [i16]),
+    Shared(Rc<[i16]>),
+}
+
+impl SampleData {
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            SampleData::I8(data) => data.len(),
+            SampleData::I16(data) => data.len(),
+            SampleData::Shared(data) => data.len(),
+        }
+    }
+
+    // As 16 bit.
+    #[inline]
+    fn get(&self, index: usize) -> i32 {
+        match self {
+            SampleData::I8(data) => (data[index] as i32) << 8,
+            SampleData::I16(data) => data[index] as i32,
+            SampleData::Shared(data) => data[index] as i32,
+        }
+    }
+}
+
+/// Mono sample data for the `Mixer`. Cloning is cheap, the data is shared.
+#[derive(Clone)]
+pub struct Sample {
+    data: SampleData,
+    sample_rate: u32,
+    loop_range: Option<(usize, usize)>,
+}
+
+impl Sample {
+    #[inline]
+    pub fn from_i8(data: &'static [i8], sample_rate: u32) -> Sample {
+        Sample::new(SampleData::I8(data), sample_rate)
+    }
+
+    #[inline]
+    pub fn from_i16(data: &'static [i16], sample_rate: u32) -> Sample {
+        Sample::new(SampleData::I16(data), sample_rate)
+    }
+
+    /// For sample data made at runtime.
+    #[inline]
+    pub fn from_vec(data: Vec<i16>, sample_rate: u32) -> Sample {
+        Sample::new(SampleData::Shared(data.into()), sample_rate)
+    }
+
+    /// Decodes ADPCM data made by `adpcm::encode`, the sample
+    /// rate comes from the data.
+    pub fn from_adpcm(data: &[u8]) -> Result<Sample, AdpcmError> {
+        let adpcm = Adpcm::parse(data)?;
+        Ok(Sample::from_vec(adpcm.decode()?, adpcm.sample_rate()))
+    }
+
+    #[inline]
+    fn new(data: SampleData, sample_rate: u32) -> Sample {
+        Sample {
+            data,
+            sample_rate,
+            loop_range: None,
+        }
+    }
+
+    /// Plays `start..end` over and over once the voice reaches `end`, instead
+    /// of stopping at the end of the data.
+    pub fn with_loop(mut self, start: usize, end: usize) -> Sample {
+        assert!(
+            start < end && end <= self.len(),
+            "Loop out of sample bounds"
+        );
+
+        self.loop_range = Some((start, end));
+        self
+    }
+
+    /// Loops all of the data.
+    #[inline]
+    pub fn looping(self) -> Sample {
+        let len = self.len();
+        self.with_loop(0, len)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.len() == 0
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[inline]
+    pub fn is_looping(&self) -> bool {
+        self.loop_range.is_some()
+    }
+}
+
+/// A playing sound, see `Mixer::play`. Stays valid after the sound ends, it
+/// just no longer does anything.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Voice {
+    index: u16,
+    id: u16,
+}
+
+struct VoiceState {
+    sample: Option<Sample>,
+    id: u16,
+    // When the voice was started, the oldest one-shot is stolen first.
+    started: u32,
+    // Output frames to wait before starting, see `Mixer::play_at`.
+    delay: usize,
+    position: usize,
+    fraction: u32,
+    pitch: f32,
+    // Position advance per output frame, 16.16 fixed point.
+    step: u32,
+    volume: i32,
+    pan: i32,
+    left: i32,
+    right: i32,
+}
+
+impl VoiceState {
+    fn new() -> VoiceState {
+        VoiceState {
+            sample: None,
+            id: 0,
+            started: 0,
+            delay: 0,
+            position: 0,
+            fraction: 0,
+            pitch: 1.0,
+            step: 0,
+            volume: VOLUME_ONE,
+            pan: 0,
+            left: VOLUME_ONE,
+            right: VOLUME_ONE,
+        }
+    }
+
+    fn update_gains(&mut self) {
+        // Linear pan that keeps the center at full volume on both sides.
+        self.left = self.volume * (VOLUME_ONE - self.pan).min(VOLUME_ONE) / VOLUME_ONE;
+        self.right = self.volume * (VOLUME_ONE + self.pan).min(VOLUME_ONE) / VOLUME_ONE;
+    }
+
+    fn update_step(&mut self, output_rate: u32) {
+        if let Some(sample) = &self.sample {
+            let pitch = (self.pitch * (1 << FRACTION_BITS) as f32) as u64;
+            let step = pitch * sample.sample_rate as u64 / output_rate as u64;
+            self.step = step.try_into().unwrap_or(u32::MAX);
+        }
+    }
+
+    fn mix(&mut self, accumulator: &mut [i32]) {
+        let sample = match &self.sample {
+            Some(sample) => sample,
+            None => return,
+        };
+
+        let delay = self.delay.min(accumulator.len() / 2);
+        self.delay -= delay;
+        let accumulator = &mut accumulator[2 * delay..];
+
+        let data = &sample.data;
+        let end = sample.loop_range.map_or(data.len(), |(_, end)| end);
+
+        for frame in accumulator.chunks_exact_mut(2) {
+            if self.position >= end {
+                match sample.loop_range {
+                    Some((start, end)) => {
+                        self.position = start + (self.position - end) % (end - start);
+                    }
+                    None => {
+                        self.sample = None;
+                        return;
+                    }
+                }
+            }
+
+            let next = if self.position + 1 < end {
+                self.position + 1
+            } else {
+                sample.loop_range.map_or(self.position, |(start, _)| start)
+            };
+
+            let a = data.get(self.position);
+            let b = data.get(next);
+            let value = a + (((b - a) * (self.fraction >> 1) as i32) >> (FRACTION_BITS - 1));
+
+            frame[0] += value * self.left / VOLUME_ONE;
+            frame[1] += value * self.right / VOLUME_ONE;
+
+            self.fraction += self.step;
+            self.position += (self.fraction >> FRACTION_BITS) as usize;
+            self.fraction &= FRACTION_MASK;
+        }
+    }
+}
+
+fn to_fixed_volume(volume: f32) -> i32 {
+    let volume = if volume > MAX_VOLUME {
+        MAX_VOLUME
+    } else if volume > 0.0 {
+        volume
+    } else {
+        0.0
+    };
+
+    (volume * VOLUME_ONE as f32) as i32
+}
+
+/// Mixes up to a fixed number of voices into interleaved stereo, the format
+/// `n64::Audio::update` hands out.
+pub struct Mixer {
+    voices: Vec<VoiceState>,
+    sample_rate: u32,
+    master_volume: i32,
+    limiter_gain: i64,
+    started: u32,
+    frame: u64,
+    accumulator: Vec<i32>,
+}
+
+impl Mixer {
+    /// `sample_rate` is the output rate, samples of other rates are resampled.
+    pub fn new(voice_count: usize, sample_rate: u32) -> Mixer {
+        assert!(voice_count <= u16::MAX as usize, "Too many voices");
+
+        Mixer {
+            voices: (0..voice_count).map(|_| VoiceState::new()).collect(),
+            sample_rate,
+            master_volume: VOLUME_ONE,
+            limiter_gain: LIMITER_ONE,
+            started: 0,
+            frame: 0,
+            accumulator: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn voice_count(&self) -> usize {
+        self.voices.len()
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Output frames mixed so far, the next `mix` starts at this frame.
+    #[inline]
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Starts `sample` at full volume, centered and at its own pitch. When
+    /// every voice is busy the oldest one-shot is cut off, returns None if
+    /// all of them are looping.
+    pub fn play(&mut self, sample: &Sample) -> Option<Voice> {
+        let index = match self.voices.iter().position(|voice| voice.sample.is_none()) {
+            Some(index) => index,
+            None => self
+                .voices
+                .iter()
+                .enumerate()
+                .filter(|(_, voice)| !matches!(&voice.sample, Some(sample) if sample.is_looping()))
+                .max_by_key(|(_, voice)| self.started.wrapping_sub(voice.started))
+                .map(|(index, _)| index)?,
+        };
+
+        self.started = self.started.wrapping_add(1);
+
+        let voice = &mut self.voices[index];
+        *voice = VoiceState {
+            sample: Some(sample.clone()),
+            id: voice.id.wrapping_add(1),
+            started: self.started,
+            ..VoiceState::new()
+        };
+        voice.update_step(self.sample_rate);
+
+        Some(Voice {
+            index: index as u16,
+            id: voice.id,
+        })
+    }
+
+    /// Like `play` but starts at output `frame`, which can be in the middle
+    /// of a later `mix`. Frames already mixed start at the next one.
+    pub fn play_at(&mut self, sample: &Sample, frame: u64) -> Option<Voice> {
+        let voice = self.play(sample)?;
+        let delay = frame.saturating_sub(self.frame);
+        self.voices[voice.index as usize].delay = delay.try_into().unwrap_or(usize::MAX);
+        Some(voice)
+    }
+
+    #[inline]
+    fn voice_mut(&mut self, voice: Voice) -> Option<&mut VoiceState> {
+        self.voices
+            .get_mut(voice.index as usize)
+            .filter(|state| state.id == voice.id && state.sample.is_some())
+    }
+
+    #[inline]
+    pub fn is_playing(&self, voice: Voice) -> bool {
+        matches!(
+            self.voices.get(voice.index as usize),
+            Some(state) if state.id == voice.id && state.sample.is_some()
+        )
+    }
+
+    #[inline]
+    pub fn stop(&mut self, voice: Voice) {
+        if let Some(state) = self.voice_mut(voice) {
+            state.sample = None;
+        }
+    }
+
+    pub fn stop_all(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.sample = None;
+        }
+    }
+
+    /// 0.0 is silent, 1.0 plays the sample as is, up to 2.0.
+    pub fn set_volume(&mut self, voice: Voice, volume: f32) {
+        if let Some(state) = self.voice_mut(voice) {
+            state.volume = to_fixed_volume(volume);
+            state.update_gains();
+        }
+    }
+
+    /// -1.0 is left only, 0.0 is both sides and 1.0 right only.
+    pub fn set_pan(&mut self, voice: Voice, pan: f32) {
+        if let Some(state) = self.voice_mut(voice) {
+            let pan = if pan > 1.0 {
+                1.0
+            } else if pan > -1.0 {
+                pan
+            } else {
+                -1.0
+            };
+
+            state.pan = (pan * VOLUME_ONE as f32) as i32;
+            state.update_gains();
+        }
+    }
+
+    /// Playback speed, 2.0 is an octave up.
+    pub fn set_pitch(&mut self, voice: Voice, pitch: f32) {
+        let sample_rate = self.sample_rate;
+
+        if let Some(state) = self.voice_mut(voice) {
+            state.pitch = if pitch > 0.0 { pitch } else { 0.0 };
+            state.update_step(sample_rate);
+        }
+    }
+
+    /// Jumps to sample `position`. Past the end a one-shot stops and a loop
+    /// wraps around.
+    pub fn set_position(&mut self, voice: Voice, position: usize) {
+        if let Some(state) = self.voice_mut(voice) {
+            state.position = position;
+            state.fraction = 0;
+        }
+    }
+
+    /// Applied to the sum of all voices, 0.0 to 2.0.
+    #[inline]
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = to_fixed_volume(volume);
+    }
+
+    #[inline]
+    pub fn master_volume(&self) -> f32 {
+        self.master_volume as f32 / VOLUME_ONE as f32
+    }
+
+    /// Fills `out` with the next interleaved stereo frames. A mix that would
+    /// clip is turned down and then slowly back up again.
+    pub fn mix(&mut self, out: &mut [i16]) {
+        self.accumulator.clear();
+        self.accumulator.resize(out.len(), 0);
+
+        for voice in self.voices.iter_mut() {
+            voice.mix(&mut self.accumulator);
+        }
+
+        self.frame += (out.len() / 2) as u64;
+
+        let max = i16::MAX as i64;
+
+        for (out, frame) in out
+            .chunks_exact_mut(2)
+            .zip(self.accumulator.chunks_exact(2))
+        {
+            let left = frame[0] as i64 * self.master_volume as i64 / VOLUME_ONE as i64;
+            let right = frame[1] as i64 * self.master_volume as i64 / VOLUME_ONE as i64;
+
+            let peak = left.abs().max(right.abs());
+            if peak * self.limiter_gain / LIMITER_ONE > max {
+                self.limiter_gain = max * LIMITER_ONE / peak;
+            }
+
+            for (out, value) in out.iter_mut().zip([left, right].iter()) {
+                let value = value * self.limiter_gain / LIMITER_ONE;
+                *out = value.max(-max).min(max) as i16;
+            }
+
+            if self.limiter_gain < LIMITER_ONE {
+                self.limiter_gain +=
+                    ((LIMITER_ONE - self.limiter_gain) >> LIMITER_RELEASE_SHIFT).max(1);
+            }
+        }
+    }
+}
+
+/// Turns game timestamps into output frames for `Mixer::play_at`. The mixer
+/// runs a whole audio buffer at a time, so sounds started as they are
+/// triggered land on buffer boundaries, scheduled ones keep their spacing.
+pub struct Scheduler {
+    // A time and the output frame it plays at.
+    anchor: Option<(i64, u64)>,
+    buffer_frames: u64,
+}
+
+impl Scheduler {
+    /// `buffer_frames` is the size of the buffers the mixer fills. Sounds
+    /// play about one buffer later than they would right away, which leaves
+    /// room for the buffer each video frame may or may not mix.
+    pub fn new(buffer_frames: usize) -> Scheduler {
+        Scheduler {
+            anchor: None,
+            buffer_frames: buffer_frames as u64,
+        }
+    }
+
+    /// The output frame to play something at `time_us`, in microseconds
+    /// from `current_time_us` or summed frame times. Times keep their
+    /// distance from the first one. When a time would be already mixed or
+    /// too far ahead, because the clocks drifted or the game stalled, it
+    /// starts over from there.
+    pub fn frame_at(&mut self, mixer: &Mixer, time_us: i64) -> u64 {
+        let next = mixer.frame();
+
+        if let Some((anchor_time, anchor_frame)) = self.anchor {
+            let offset = (time_us - anchor_time) * mixer.sample_rate() as i64 / 1_000_000;
+            let frame = anchor_frame as i64 + offset;
+            let max = next + MAX_SCHEDULE_BUFFERS * self.buffer_frames;
+
+            if frame >= next as i64 && frame <= max as i64 {
+                return frame as u64;
+            }
+        }
+
+        let frame = next + self.buffer_frames;
+        self.anchor = Some((time_us, frame));
+        frame
+    }
+
+    /// Starts `sample` at `time_us`, see `frame_at`.
+    pub fn play_at(&mut self, mixer: &mut Mixer, sample: &Sample, time_us: i64) -> Option<Voice> {
+        let frame = self.frame_at(mixer, time_us);
+        mixer.play_at(sample, frame)
+    }
+
+    /// Forgets the timing, for when the game's clock jumps.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.anchor = None;
+    }
+}
@@ -0,0 +1,655 @@
+//! Sound effects from sfxr parameters, the `.sfs` files saved by sfxr.
+//!
+//! The synthesis follows sfxr 1.2 so sounds come out as they did in the
+//! tool. sfxr runs at 44100 Hz with 8x supersampling, `render` averages that
+//! down to the requested rate.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use libm::{pow, powf, sinf};
+use n64_math::rand::Rng;
+
+const SYNTH_RATE: u32 = 44100;
+const SUPERSAMPLING: usize = 8;
+const PHASER_SIZE: usize = 1024;
+const NOISE_SIZE: usize = 32;
+
+// sfxr scales its output by a master volume and by 4 again when exporting.
+const MASTER_VOLUME: f32 = 0.05;
+const EXPORT_GAIN: f32 = 4.0;
+const EXPORT_SCALE: f32 = 32000.0;
+
+const FILE_VERSION: u32 = 102;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaveType {
+    Square,
+    Sawtooth,
+    Sine,
+    Noise,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedEnd,
+    UnknownVersion(u32),
+    UnknownWaveType(u32),
+}
+
+/// The sfxr sliders. Most are 0.0 to 1.0, the ramps, `pha_offset` and
+/// `arp_mod` are -1.0 to 1.0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Params {
+    pub wave_type: WaveType,
+    pub volume: f32,
+
+    pub base_freq: f32,
+    pub freq_limit: f32,
+    pub freq_ramp: f32,
+    pub freq_dramp: f32,
+    pub duty: f32,
+    pub duty_ramp: f32,
+
+    pub vib_strength: f32,
+    pub vib_speed: f32,
+    pub vib_delay: f32,
+
+    pub env_attack: f32,
+    pub env_sustain: f32,
+    pub env_decay: f32,
+    pub env_punch: f32,
+
+    pub filter_on: bool,
+    pub lpf_resonance: f32,
+    pub lpf_freq: f32,
+    pub lpf_ramp: f32,
+    pub hpf_freq: f32,
+    pub hpf_ramp: f32,
+
+    pub pha_offset: f32,
+    pub pha_ramp: f32,
+
+    pub repeat_speed: f32,
+
+    pub arp_speed: f32,
+    pub arp_mod: f32,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            wave_type: WaveType::Square,
+            volume: 0.5,
+
+            base_freq: 0.3,
+            freq_limit: 0.0,
+            freq_ramp: 0.0,
+            freq_dramp: 0.0,
+            duty: 0.0,
+            duty_ramp: 0.0,
+
+            vib_strength: 0.0,
+            vib_speed: 0.0,
+            vib_delay: 0.0,
+
+            env_attack: 0.0,
+            env_sustain: 0.3,
+            env_decay: 0.4,
+            env_punch: 0.0,
+
+            filter_on: false,
+            lpf_resonance: 0.0,
+            lpf_freq: 1.0,
+            lpf_ramp: 0.0,
+            hpf_freq: 0.0,
+            hpf_ramp: 0.0,
+
+            pha_offset: 0.0,
+            pha_ramp: 0.0,
+
+            repeat_speed: 0.0,
+
+            arp_speed: 0.0,
+            arp_mod: 0.0,
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], ParseError> {
+        if self.data.len() < count {
+            return Err(ParseError::UnexpectedEnd);
+        }
+
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ParseError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, ParseError> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn bool(&mut self) -> Result<bool, ParseError> {
+        Ok(self.bytes(1)?[0] != 0)
+    }
+}
+
+fn clamp(value: f32, min: f32, max: f32) -> f32 {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}
+
+impl Params {
+    /// Reads a `.sfs` file, all versions sfxr has written are accepted.
+    pub fn from_bytes(data: &[u8]) -> Result<Params, ParseError> {
+        let mut reader = Reader { data };
+        let mut params = Params::default();
+
+        // Little endian, as sfxr wrote its structs on x86.
+        let version = reader.u32()?;
+        if !(100..=FILE_VERSION).contains(&version) {
+            return Err(ParseError::UnknownVersion(version));
+        }
+
+        params.wave_type = match reader.u32()? {
+            0 => WaveType::Square,
+            1 => WaveType::Sawtooth,
+            2 => WaveType::Sine,
+            3 => WaveType::Noise,
+            other => return Err(ParseError::UnknownWaveType(other)),
+        };
+
+        if version >= 102 {
+            params.volume = reader.f32()?;
+        }
+
+        params.base_freq = reader.f32()?;
+        params.freq_limit = reader.f32()?;
+        params.freq_ramp = reader.f32()?;
+        if version >= 101 {
+            params.freq_dramp = reader.f32()?;
+        }
+        params.duty = reader.f32()?;
+        params.duty_ramp = reader.f32()?;
+
+        params.vib_strength = reader.f32()?;
+        params.vib_speed = reader.f32()?;
+        params.vib_delay = reader.f32()?;
+
+        params.env_attack = reader.f32()?;
+        params.env_sustain = reader.f32()?;
+        params.env_decay = reader.f32()?;
+        params.env_punch = reader.f32()?;
+
+        params.filter_on = reader.bool()?;
+        params.lpf_resonance = reader.f32()?;
+        params.lpf_freq = reader.f32()?;
+        params.lpf_ramp = reader.f32()?;
+        params.hpf_freq = reader.f32()?;
+        params.hpf_ramp = reader.f32()?;
+
+        params.pha_offset = reader.f32()?;
+        params.pha_ramp = reader.f32()?;
+
+        params.repeat_speed = reader.f32()?;
+
+        if version >= 101 {
+            params.arp_speed = reader.f32()?;
+            params.arp_mod = reader.f32()?;
+        }
+
+        Ok(params)
+    }
+
+    /// Writes a `.sfs` file sfxr can load.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        let wave_type: u32 = match self.wave_type {
+            WaveType::Square => 0,
+            WaveType::Sawtooth => 1,
+            WaveType::Sine => 2,
+            WaveType::Noise => 3,
+        };
+
+        res.extend_from_slice(&FILE_VERSION.to_le_bytes());
+        res.extend_from_slice(&wave_type.to_le_bytes());
+
+        let before_filter = [
+            self.volume,
+            self.base_freq,
+            self.freq_limit,
+            self.freq_ramp,
+            self.freq_dramp,
+            self.duty,
+            self.duty_ramp,
+            self.vib_strength,
+            self.vib_speed,
+            self.vib_delay,
+            self.env_attack,
+            self.env_sustain,
+            self.env_decay,
+            self.env_punch,
+        ];
+
+        let after_filter = [
+            self.lpf_resonance,
+            self.lpf_freq,
+            self.lpf_ramp,
+            self.hpf_freq,
+            self.hpf_ramp,
+            self.pha_offset,
+            self.pha_ramp,
+            self.repeat_speed,
+            self.arp_speed,
+            self.arp_mod,
+        ];
+
+        for value in before_filter.iter() {
+            res.extend_from_slice(&value.to_le_bytes());
+        }
+
+        res.push(self.filter_on as u8);
+
+        for value in after_filter.iter() {
+            res.extend_from_slice(&value.to_le_bytes());
+        }
+
+        res
+    }
+
+    /// Nudges about half of the sliders a little, like the Mutate button in
+    /// sfxr. Useful to make repeated sounds less monotonous.
+    pub fn mutate(&mut self, rng: &mut Rng) {
+        let mut unipolar = [
+            &mut self.base_freq,
+            &mut self.duty,
+            &mut self.vib_strength,
+            &mut self.vib_speed,
+            &mut self.vib_delay,
+            &mut self.env_attack,
+            &mut self.env_sustain,
+            &mut self.env_decay,
+            &mut self.env_punch,
+            &mut self.lpf_resonance,
+            &mut self.lpf_freq,
+            &mut self.hpf_freq,
+            &mut self.repeat_speed,
+            &mut self.arp_speed,
+        ];
+
+        for value in unipolar.iter_mut() {
+            if rng.next_u32() & 0x10000 != 0 {
+                **value = clamp(**value + rng.next_f32() * 0.1 - 0.05, 0.0, 1.0);
+            }
+        }
+
+        let mut bipolar = [
+            &mut self.freq_ramp,
+            &mut self.freq_dramp,
+            &mut self.duty_ramp,
+            &mut self.lpf_ramp,
+            &mut self.hpf_ramp,
+            &mut self.pha_offset,
+            &mut self.pha_ramp,
+            &mut self.arp_mod,
+        ];
+
+        for value in bipolar.iter_mut() {
+            if rng.next_u32() & 0x10000 != 0 {
+                **value = clamp(**value + rng.next_f32() * 0.1 - 0.05, -1.0, 1.0);
+            }
+        }
+    }
+}
+
+/// Generates the sound one 44100 Hz sample at a time.
+pub struct Synth {
+    params: Params,
+    rng: Rng,
+    playing: bool,
+
+    phase: i32,
+    period: f64,
+    max_period: f64,
+    slide: f64,
+    delta_slide: f64,
+    square_duty: f32,
+    square_slide: f32,
+    arp_mod: f64,
+    arp_time: i32,
+    arp_limit: i32,
+
+    lpf_pos: f32,
+    lpf_delta: f32,
+    lpf_w: f32,
+    lpf_w_delta: f32,
+    lpf_damping: f32,
+    hpf_pos: f32,
+    hpf: f32,
+    hpf_delta: f32,
+
+    vib_phase: f32,
+    vib_speed: f32,
+    vib_amp: f32,
+
+    env_volume: f32,
+    env_stage: usize,
+    env_time: i32,
+    env_length: [i32; 3],
+
+    phaser_phase: f32,
+    phaser_delta: f32,
+    phaser_offset: i32,
+    phaser_pos: usize,
+    phaser_buffer: [f32; PHASER_SIZE],
+
+    noise_buffer: [f32; NOISE_SIZE],
+
+    repeat_time: i32,
+    repeat_limit: i32,
+}
+
+impl Synth {
+    /// `seed` drives the noise, the same seed gives the same sound.
+    pub fn new(params: &Params, seed: u32) -> Synth {
+        let mut synth = Synth {
+            params: params.clone(),
+            rng: Rng::new(seed),
+            playing: true,
+
+            phase: 0,
+            period: 0.0,
+            max_period: 0.0,
+            slide: 0.0,
+            delta_slide: 0.0,
+            square_duty: 0.0,
+            square_slide: 0.0,
+            arp_mod: 0.0,
+            arp_time: 0,
+            arp_limit: 0,
+
+            lpf_pos: 0.0,
+            lpf_delta: 0.0,
+            lpf_w: 0.0,
+            lpf_w_delta: 0.0,
+            lpf_damping: 0.0,
+            hpf_pos: 0.0,
+            hpf: 0.0,
+            hpf_delta: 0.0,
+
+            vib_phase: 0.0,
+            vib_speed: 0.0,
+            vib_amp: 0.0,
+
+            env_volume: 0.0,
+            env_stage: 0,
+            env_time: 0,
+            env_length: [0; 3],
+
+            phaser_phase: 0.0,
+            phaser_delta: 0.0,
+            phaser_offset: 0,
+            phaser_pos: 0,
+            phaser_buffer: [0.0; PHASER_SIZE],
+
+            noise_buffer: [0.0; NOISE_SIZE],
+
+            repeat_time: 0,
+            repeat_limit: 0,
+        };
+
+        synth.reset(false);
+        synth
+    }
+
+    fn refill_noise(&mut self) {
+        for value in self.noise_buffer.iter_mut() {
+            *value = self.rng.next_f32() * 2.0 - 1.0;
+        }
+    }
+
+    // A restart only resets the pitch, which is how the repeat slider works.
+    fn reset(&mut self, restart: bool) {
+        let p = &self.params;
+
+        if !restart {
+            self.phase = 0;
+        }
+
+        self.period = 100.0 / (p.base_freq as f64 * p.base_freq as f64 + 0.001);
+        self.max_period = 100.0 / (p.freq_limit as f64 * p.freq_limit as f64 + 0.001);
+        self.slide = 1.0 - pow(p.freq_ramp as f64, 3.0) * 0.01;
+        self.delta_slide = -pow(p.freq_dramp as f64, 3.0) * 0.000001;
+        self.square_duty = 0.5 - p.duty * 0.5;
+        self.square_slide = -p.duty_ramp * 0.00005;
+
+        self.arp_mod = if p.arp_mod >= 0.0 {
+            1.0 - pow(p.arp_mod as f64, 2.0) * 0.9
+        } else {
+            1.0 + pow(p.arp_mod as f64, 2.0) * 10.0
+        };
+        self.arp_time = 0;
+        self.arp_limit = if p.arp_speed == 1.0 {
+            0
+        } else {
+            (powf(1.0 - p.arp_speed, 2.0) * 20000.0 + 32.0) as i32
+        };
+
+        if restart {
+            return;
+        }
+
+        self.lpf_pos = 0.0;
+        self.lpf_delta = 0.0;
+        self.lpf_w = powf(p.lpf_freq, 3.0) * 0.1;
+        self.lpf_w_delta = 1.0 + p.lpf_ramp * 0.0001;
+        self.lpf_damping =
+            (5.0 / (1.0 + powf(p.lpf_resonance, 2.0) * 20.0) * (0.01 + self.lpf_w)).min(0.8);
+        self.hpf_pos = 0.0;
+        self.hpf = powf(p.hpf_freq, 2.0) * 0.1;
+        self.hpf_delta = 1.0 + p.hpf_ramp * 0.0003;
+
+        self.vib_phase = 0.0;
+        self.vib_speed = powf(p.vib_speed, 2.0) * 0.01;
+        self.vib_amp = p.vib_strength * 0.5;
+
+        self.env_volume = 0.0;
+        self.env_stage = 0;
+        self.env_time = 0;
+        self.env_length = [
+            (p.env_attack * p.env_attack * 100000.0) as i32,
+            (p.env_sustain * p.env_sustain * 100000.0) as i32,
+            (p.env_decay * p.env_decay * 100000.0) as i32,
+        ];
+
+        self.phaser_phase = powf(p.pha_offset, 2.0) * 1020.0;
+        if p.pha_offset < 0.0 {
+            self.phaser_phase = -self.phaser_phase;
+        }
+        self.phaser_delta = powf(p.pha_ramp, 2.0);
+        if p.pha_ramp < 0.0 {
+            self.phaser_delta = -self.phaser_delta;
+        }
+        self.phaser_offset = (self.phaser_phase as i32).abs();
+        self.phaser_pos = 0;
+        self.phaser_buffer = [0.0; PHASER_SIZE];
+
+        self.repeat_time = 0;
+        self.repeat_limit = if p.repeat_speed == 0.0 {
+            0
+        } else {
+            (powf(1.0 - p.repeat_speed, 2.0) * 20000.0 + 32.0) as i32
+        };
+
+        self.refill_noise();
+    }
+
+    /// The next sample at 44100 Hz in -1.0..=1.0 before the export gain,
+    /// None once the sound has ended.
+    pub fn next_sample(&mut self) -> Option<f32> {
+        if !self.playing {
+            return None;
+        }
+
+        self.repeat_time += 1;
+        if self.repeat_limit != 0 && self.repeat_time >= self.repeat_limit {
+            self.repeat_time = 0;
+            self.reset(true);
+        }
+
+        self.arp_time += 1;
+        if self.arp_limit != 0 && self.arp_time >= self.arp_limit {
+            self.arp_limit = 0;
+            self.period *= self.arp_mod;
+        }
+
+        self.slide += self.delta_slide;
+        self.period *= self.slide;
+        if self.period > self.max_period {
+            self.period = self.max_period;
+
+            if self.params.freq_limit > 0.0 {
+                self.playing = false;
+            }
+        }
+
+        let mut period = self.period as f32;
+        if self.vib_amp > 0.0 {
+            self.vib_phase += self.vib_speed;
+            period =
+                (self.period * (1.0 + sinf(self.vib_phase) as f64 * self.vib_amp as f64)) as f32;
+        }
+        let period = (period as i32).max(8);
+
+        self.square_duty = clamp(self.square_duty + self.square_slide, 0.0, 0.5);
+
+        self.env_time += 1;
+        if self.env_time > self.env_length[self.env_stage] {
+            self.env_time = 0;
+            self.env_stage += 1;
+
+            if self.env_stage == 3 {
+                self.playing = false;
+                return None;
+            }
+        }
+
+        // sfxr divides by zero on empty stages, those only last one sample.
+        let env_fraction = self.env_time as f32 / self.env_length[self.env_stage].max(1) as f32;
+        self.env_volume = match self.env_stage {
+            0 => env_fraction,
+            1 => 1.0 + (1.0 - env_fraction) * 2.0 * self.params.env_punch,
+            _ => 1.0 - env_fraction,
+        };
+
+        self.phaser_phase += self.phaser_delta;
+        self.phaser_offset = (self.phaser_phase as i32).abs().min(PHASER_SIZE as i32 - 1);
+
+        if self.hpf_delta != 0.0 {
+            self.hpf = clamp(self.hpf * self.hpf_delta, 0.00001, 0.1);
+        }
+
+        let mut sum = 0.0;
+
+        for _ in 0..SUPERSAMPLING {
+            self.phase += 1;
+            if self.phase >= period {
+                self.phase %= period;
+
+                if self.params.wave_type == WaveType::Noise {
+                    self.refill_noise();
+                }
+            }
+
+            let fp = self.phase as f32 / period as f32;
+            let mut sample = match self.params.wave_type {
+                WaveType::Square => {
+                    if fp < self.square_duty {
+                        0.5
+                    } else {
+                        -0.5
+                    }
+                }
+                WaveType::Sawtooth => 1.0 - fp * 2.0,
+                WaveType::Sine => sinf(fp * 2.0 * core::f32::consts::PI),
+                WaveType::Noise => {
+                    self.noise_buffer[(self.phase * NOISE_SIZE as i32 / period) as usize]
+                }
+            };
+
+            let previous = self.lpf_pos;
+            self.lpf_w = clamp(self.lpf_w * self.lpf_w_delta, 0.0, 0.1);
+
+            if self.params.lpf_freq != 1.0 {
+                self.lpf_delta += (sample - self.lpf_pos) * self.lpf_w;
+                self.lpf_delta -= self.lpf_delta * self.lpf_damping;
+            } else {
+                self.lpf_pos = sample;
+                self.lpf_delta = 0.0;
+            }
+            self.lpf_pos += self.lpf_delta;
+
+            self.hpf_pos += self.lpf_pos - previous;
+            self.hpf_pos -= self.hpf_pos * self.hpf;
+            sample = self.hpf_pos;
+
+            self.phaser_buffer[self.phaser_pos] = sample;
+            sample += self.phaser_buffer
+                [(self.phaser_pos + PHASER_SIZE - self.phaser_offset as usize) % PHASER_SIZE];
+            self.phaser_pos = (self.phaser_pos + 1) % PHASER_SIZE;
+
+            sum += sample * self.env_volume;
+        }
+
+        let sample = sum / SUPERSAMPLING as f32 * MASTER_VOLUME * 2.0 * self.params.volume;
+        Some(clamp(sample, -1.0, 1.0))
+    }
+}
+
+/// Renders the whole sound as 16 bit samples at `sample_rate`, the same way
+/// sfxr exports WAV files.
+pub fn render(params: &Params, sample_rate: u32, seed: u32) -> Vec<i16> {
+    let mut synth = Synth::new(params, seed);
+    let mut res = Vec::new();
+
+    let mut sum = 0.0;
+    let mut count = 0;
+    let mut position = 0;
+
+    while let Some(sample) = synth.next_sample() {
+        sum += clamp(sample * EXPORT_GAIN, -1.0, 1.0);
+        count += 1;
+        position += sample_rate;
+
+        // Averages the samples of each output sample, or repeats the last
+        // one when rendering above the synth rate.
+        while position >= SYNTH_RATE {
+            position -= SYNTH_RATE;
+
+            if count > 0 {
+                res.push((sum / count as f32 * EXPORT_SCALE) as i16);
+                sum = 0.0;
+                count = 0;
+            } else if let Some(&last) = res.last() {
+                res.push(last);
+            }
+        }
+    }
+
+    res
+}
@@ -0,0 +1,476 @@
+//! Long sounds like music, played while they are read so they never have to
+//! fit in RAM.
+//!
+//! Two chunks of the file are kept, raw 16 or 8 bit PCM from a WAV or frames
+//! of `adpcm` data. The stream plays from one while the other holds what
+//! comes next, and a chunk is read again as soon as it is used up. So a read
+//! is always a chunk ahead of what is played. Loops are followed when
+//! reading, the chunk after the loop end starts at the loop start.
+
+use crate::adpcm::{self, AdpcmError, Decoder, FRAME_BYTES, FRAME_SAMPLES};
+use crate::wav::{self, WavError};
+use alloc::{vec, vec::Vec};
+use core::convert::TryInto;
+
+// 512 ADPCM frames, or 1152 frames of 16 bit stereo.
+const CHUNK_SIZE: usize = 4608;
+// The WAV header has to be within this, with any chunks before the samples.
+const MAX_HEADER_SIZE: usize = 512;
+
+const FRACTION_BITS: u32 = 16;
+const FRACTION_ONE: u32 = 1 << FRACTION_BITS;
+const VOLUME_ONE: i32 = 256;
+const MAX_VOLUME: f32 = 2.0;
+
+/// Where a `Stream` reads its file from.
+pub trait StreamReader {
+    /// Fills `buf` with the file from `offset`.
+    fn read(&mut self, offset: u32, buf: &mut [u8]);
+
+    fn len(&self) -> usize;
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// For files already in RAM.
+impl StreamReader for &[u8] {
+    fn read(&mut self, offset: u32, buf: &mut [u8]) {
+        let offset = offset as usize;
+        buf.copy_from_slice(&self[offset..offset + buf.len()]);
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StreamError {
+    /// Neither a WAV nor `adpcm` data.
+    UnknownFormat,
+    UnexpectedEnd,
+    /// Only mono and stereo PCM is streamed.
+    UnsupportedFormat,
+    Adpcm(AdpcmError),
+}
+
+enum Codec {
+    Pcm {
+        channels: usize,
+        bits: u16,
+    },
+    Adpcm {
+        decoder: Decoder,
+        frame: [i16; FRAME_SAMPLES],
+        // The frame in `frame`.
+        frame_index: Option<usize>,
+        // Where the decoder was at the frame with the loop start, for going
+        // back there.
+        loop_history: Option<[i16; 2]>,
+    },
+}
+
+impl Codec {
+    // Samples in each unit read, a PCM frame or an ADPCM frame.
+    #[inline]
+    fn unit_samples(&self) -> usize {
+        match self {
+            Codec::Pcm { .. } => 1,
+            Codec::Adpcm { .. } => FRAME_SAMPLES,
+        }
+    }
+
+    #[inline]
+    fn unit_size(&self) -> usize {
+        match self {
+            Codec::Pcm { channels, bits } => channels * (*bits as usize / 8),
+            Codec::Adpcm { .. } => FRAME_BYTES,
+        }
+    }
+}
+
+/// Plays a WAV or `adpcm` file as it is read, see the module documentation.
+pub struct Stream<R: StreamReader> {
+    reader: R,
+    codec: Codec,
+    sample_rate: u32,
+    // Where the samples start in the file.
+    data_offset: u32,
+    len: usize,
+    loop_range: Option<(usize, usize)>,
+    chunks: [Vec<u8>; 2],
+    chunk_lens: [usize; 2],
+    current: usize,
+    chunk_offset: usize,
+    // The next unit to read into a chunk, None when the end is read.
+    next_unit: Option<usize>,
+    // The next sample to play.
+    position: usize,
+    // Playback interpolates between these two frames.
+    previous: [i32; 2],
+    next: [i32; 2],
+    fraction: u32,
+    // Position advance per output frame, 16.16 fixed point.
+    step: u32,
+    volume: i32,
+    // The last frame is read, `finished` once it has played.
+    ended: bool,
+    finished: bool,
+}
+
+impl<R: StreamReader> Stream<R> {
+    /// Starts reading. Plays at `sample_rate`, the stream is resampled if
+    /// its own rate differs.
+    pub fn new(mut reader: R, sample_rate: u32) -> Result<Stream<R>, StreamError> {
+        let mut header = vec![0; reader.len().min(MAX_HEADER_SIZE)];
+        reader.read(0, &mut header);
+
+        let (codec, source_rate, data_offset, len) = match adpcm::Header::parse(&header) {
+            Ok(adpcm) => {
+                if reader.len() < adpcm.size + adpcm.frame_count() * FRAME_BYTES {
+                    return Err(StreamError::UnexpectedEnd);
+                }
+
+                (
+                    Codec::Adpcm {
+                        decoder: adpcm.decoder(),
+                        frame: [0; FRAME_SAMPLES],
+                        frame_index: None,
+                        loop_history: None,
+                    },
+                    adpcm.sample_rate,
+                    adpcm.size,
+                    adpcm.len,
+                )
+            }
+            Err(AdpcmError::BadMagic) => {
+                let wav = wav::Header::parse(&header).map_err(|e| match e {
+                    WavError::NotWav => StreamError::UnknownFormat,
+                    WavError::UnexpectedEnd => StreamError::UnexpectedEnd,
+                    WavError::UnsupportedFormat => StreamError::UnsupportedFormat,
+                })?;
+
+                if wav.channels == 0 || wav.channels > 2 {
+                    return Err(StreamError::UnsupportedFormat);
+                }
+
+                let channels = wav.channels as usize;
+                let frame_size = channels * (wav.bits as usize / 8);
+                let size = wav
+                    .data_size
+                    .min(reader.len().saturating_sub(wav.data_offset));
+
+                (
+                    Codec::Pcm {
+                        channels,
+                        bits: wav.bits,
+                    },
+                    wav.sample_rate,
+                    wav.data_offset,
+                    size / frame_size,
+                )
+            }
+            Err(e) => return Err(StreamError::Adpcm(e)),
+        };
+
+        let step = ((source_rate as u64) << FRACTION_BITS) / sample_rate as u64;
+
+        let mut stream = Stream {
+            reader,
+            codec,
+            sample_rate: source_rate,
+            data_offset: data_offset as u32,
+            len,
+            loop_range: None,
+            chunks: [vec![0; CHUNK_SIZE], vec![0; CHUNK_SIZE]],
+            chunk_lens: [0; 2],
+            current: 0,
+            chunk_offset: 0,
+            next_unit: None,
+            position: 0,
+            previous: [0; 2],
+            next: [0; 2],
+            fraction: 0,
+            step: step.try_into().unwrap_or(u32::MAX),
+            volume: VOLUME_ONE,
+            ended: false,
+            finished: false,
+        };
+
+        stream.restart();
+        Ok(stream)
+    }
+
+    /// Plays `start..end` over and over once it gets to `end`, in samples.
+    /// Starts over from the beginning.
+    pub fn with_loop(mut self, start: usize, end: usize) -> Stream<R> {
+        assert!(start < end && end <= self.len, "Loop out of stream bounds");
+
+        self.loop_range = Some((start, end));
+        self.restart();
+        self
+    }
+
+    /// Loops all of it.
+    #[inline]
+    pub fn looping(self) -> Stream<R> {
+        let len = self.len;
+        self.with_loop(0, len)
+    }
+
+    /// The rate of the file.
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// In samples.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn is_looping(&self) -> bool {
+        self.loop_range.is_some()
+    }
+
+    /// Only once the end is reached without a loop.
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// 0.0 is silent, 1.0 plays it as is, up to 2.0.
+    pub fn set_volume(&mut self, volume: f32) {
+        let volume = if volume > MAX_VOLUME {
+            MAX_VOLUME
+        } else if volume > 0.0 {
+            volume
+        } else {
+            0.0
+        };
+
+        self.volume = (volume * VOLUME_ONE as f32) as i32;
+    }
+
+    /// Reads both chunks again from the beginning.
+    pub fn restart(&mut self) {
+        self.next_unit = if self.len > 0 { Some(0) } else { None };
+        self.fill(0);
+        self.fill(1);
+        self.current = 0;
+        self.chunk_offset = 0;
+        self.position = 0;
+        self.fraction = 0;
+        self.ended = false;
+        self.finished = false;
+
+        if let Codec::Adpcm {
+            decoder,
+            frame_index,
+            loop_history,
+            ..
+        } = &mut self.codec
+        {
+            decoder.set_history([0; 2]);
+            *frame_index = None;
+            *loop_history = None;
+        }
+
+        self.next = [0; 2];
+        self.advance();
+        self.advance();
+    }
+
+    // Reads the units that come next into a chunk, following the loop.
+    fn fill(&mut self, chunk: usize) {
+        let unit_samples = self.codec.unit_samples();
+        let unit_size = self.codec.unit_size();
+        let unit_count = CHUNK_SIZE / unit_size;
+
+        // Where the stream ends or wraps around, never read when empty.
+        let end = self.loop_range.map_or(self.len, |(_, end)| end);
+
+        let mut filled = 0;
+
+        while filled < unit_count {
+            let unit = match self.next_unit {
+                Some(unit) => unit,
+                None => break,
+            };
+
+            let last = (end - 1) / unit_samples;
+
+            let count = (last + 1 - unit).min(unit_count - filled);
+            self.reader.read(
+                self.data_offset + (unit * unit_size) as u32,
+                &mut self.chunks[chunk][filled * unit_size..(filled + count) * unit_size],
+            );
+            filled += count;
+
+            self.next_unit = if unit + count > last {
+                self.loop_range.map(|(start, _)| start / unit_samples)
+            } else {
+                Some(unit + count)
+            };
+        }
+
+        self.chunk_lens[chunk] = filled * unit_size;
+    }
+
+    // The offset of the next unit in the current chunk. When a chunk is used
+    // up playback moves to the other one and the used one is read again.
+    fn next_unit(&mut self) -> Option<usize> {
+        if self.chunk_offset >= self.chunk_lens[self.current] {
+            let used = self.current;
+            self.current ^= 1;
+            self.chunk_offset = 0;
+            self.fill(used);
+
+            if self.chunk_lens[self.current] == 0 {
+                return None;
+            }
+        }
+
+        let offset = self.chunk_offset;
+        self.chunk_offset += self.codec.unit_size();
+        Some(offset)
+    }
+
+    fn next_frame(&mut self) -> Option<[i32; 2]> {
+        let mut jumped = false;
+
+        match self.loop_range {
+            Some((start, end)) if self.position >= end => {
+                self.position = start;
+                jumped = true;
+            }
+            None if self.position >= self.len => return None,
+            _ => (),
+        }
+
+        let position = self.position;
+        self.position += 1;
+
+        match self.codec {
+            Codec::Pcm { channels, bits } => {
+                let offset = self.next_unit()?;
+                let bytes = &self.chunks[self.current][offset..];
+                let sample = |index: usize| {
+                    if bits == 8 {
+                        (bytes[index] as i32 - 128) << 8
+                    } else {
+                        i16::from_le_bytes([bytes[2 * index], bytes[2 * index + 1]]) as i32
+                    }
+                };
+
+                if channels == 1 {
+                    let value = sample(0);
+                    Some([value, value])
+                } else {
+                    Some([sample(0), sample(1)])
+                }
+            }
+            Codec::Adpcm { .. } => {
+                let value = self.adpcm_sample(position, jumped)?;
+                Some([value, value])
+            }
+        }
+    }
+
+    fn adpcm_sample(&mut self, position: usize, jumped: bool) -> Option<i32> {
+        let index = position / FRAME_SAMPLES;
+        let loop_frame = self.loop_range.map(|(start, _)| start / FRAME_SAMPLES);
+        let decoded = matches!(
+            self.codec,
+            Codec::Adpcm { frame_index: Some(decoded), .. } if decoded == index
+        );
+
+        if jumped || !decoded {
+            let offset = self.next_unit()?;
+            let bytes = &self.chunks[self.current][offset..offset + FRAME_BYTES];
+
+            if let Codec::Adpcm {
+                decoder,
+                frame,
+                frame_index,
+                loop_history,
+            } = &mut self.codec
+            {
+                if Some(index) == loop_frame {
+                    match loop_history {
+                        Some(history) if jumped => decoder.set_history(*history),
+                        None => *loop_history = Some(decoder.history()),
+                        _ => (),
+                    }
+                }
+
+                // A bad frame plays as silence rather than stop the music.
+                if decoder.decode_frame(bytes, frame).is_err() {
+                    *frame = [0; FRAME_SAMPLES];
+                }
+                *frame_index = Some(index);
+            }
+        }
+
+        match &self.codec {
+            Codec::Adpcm { frame, .. } => Some(frame[position % FRAME_SAMPLES] as i32),
+            Codec::Pcm { .. } => None,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.next;
+
+        if self.ended {
+            self.finished = true;
+            return;
+        }
+
+        // The last frame fades to silence over one frame.
+        match self.next_frame() {
+            Some(frame) => self.next = frame,
+            None => {
+                self.ended = true;
+                self.next = [0; 2];
+            }
+        }
+    }
+
+    /// Adds the next interleaved stereo frames to `out`, reading more of the
+    /// file as needed.
+    pub fn render(&mut self, out: &mut [i16]) {
+        for out in out.chunks_exact_mut(2) {
+            if self.finished {
+                return;
+            }
+
+            let fraction = (self.fraction >> 1) as i32;
+
+            for (channel, out) in out.iter_mut().enumerate() {
+                let (a, b) = (self.previous[channel], self.next[channel]);
+                let value = a + (((b - a) * fraction) >> (FRACTION_BITS - 1));
+                let value = *out as i32 + value * self.volume / VOLUME_ONE;
+
+                *out = value.max(i16::MIN as i32).min(i16::MAX as i32) as i16;
+            }
+
+            self.fraction += self.step;
+
+            while self.fraction >= FRACTION_ONE && !self.finished {
+                self.fraction -= FRACTION_ONE;
+                self.advance();
+            }
+        }
+    }
+}
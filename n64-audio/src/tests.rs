@@ -0,0 +1,1124 @@
+extern crate std;
+
+use crate::adpcm::{self, Adpcm, AdpcmError, FRAME_BYTES, FRAME_SAMPLES};
+use crate::chiptune::{self, Event, Instrument, SequenceError, Synth, Waveform};
+use crate::effects::{self, Biquad, BiquadKind, Bus, Delay, Ducker, OnePole, Reverb};
+use crate::midi::{self, MidiError};
+use crate::mixer::{Mixer, Sample, Scheduler};
+use crate::sfxr::{self, Params, ParseError, WaveType};
+use crate::stream::{Stream, StreamError};
+use crate::tracker::{self, Module, ModuleError, NOTE_OFF};
+use crate::wav::{self, Wav};
+use alloc::vec;
+use n64_math::rand::Rng;
+use std::{f64::consts::PI, vec::Vec};
+
+static EXPLOSION: &[u8] = include_bytes!("../../game/sound/explosion_0.sfs");
+
+#[test]
+fn sfxr_reads_and_writes_files() {
+    let params = Params::from_bytes(EXPLOSION).unwrap();
+    assert_eq!(params.wave_type, WaveType::Noise);
+    assert_eq!(params.volume, 0.5);
+    assert_eq!(params.to_bytes(), EXPLOSION);
+
+    assert_eq!(
+        Params::from_bytes(&EXPLOSION[..50]),
+        Err(ParseError::UnexpectedEnd)
+    );
+    assert_eq!(
+        Params::from_bytes(&[99, 0, 0, 0]),
+        Err(ParseError::UnknownVersion(99))
+    );
+}
+
+#[test]
+fn sfxr_renders_at_any_rate() {
+    let params = Params::from_bytes(EXPLOSION).unwrap();
+
+    let full = sfxr::render(&params, 44100, 1);
+    let half = sfxr::render(&params, 22050, 1);
+    let other = sfxr::render(&params, 32000, 1);
+
+    assert!(!full.is_empty());
+    assert_eq!(half.len(), full.len() / 2);
+    assert_eq!(other.len(), full.len() * 32000 / 44100);
+
+    // 22050 Hz is the average of each pair, like sfxr's own export.
+    for (i, &sample) in half.iter().enumerate().take(1000) {
+        let average = (full[2 * i] as i32 + full[2 * i + 1] as i32) / 2;
+        assert!((sample as i32 - average).abs() <= 1);
+    }
+
+    assert!(half.iter().any(|&sample| sample.abs() > 8000));
+    assert_eq!(sfxr::render(&params, 22050, 1), half);
+    assert_ne!(sfxr::render(&params, 22050, 2), half);
+}
+
+#[test]
+fn sfxr_waveforms() {
+    for &wave_type in [
+        WaveType::Square,
+        WaveType::Sawtooth,
+        WaveType::Sine,
+        WaveType::Noise,
+    ]
+    .iter()
+    {
+        let params = Params {
+            wave_type,
+            ..Params::default()
+        };
+
+        // Sustain 0.3 and decay 0.4 in sfxr's squared units, each stage
+        // lasts one sample more than its length.
+        let samples = sfxr::render(&params, 44100, 1);
+        assert_eq!(samples.len(), 1 + 9001 + 16000);
+        assert!(samples.iter().any(|&sample| sample > 5000));
+        assert!(samples.iter().any(|&sample| sample < -5000));
+    }
+}
+
+#[test]
+fn sfxr_mutate_stays_in_range() {
+    let mut params = Params::from_bytes(EXPLOSION).unwrap();
+    let mut rng = Rng::new(3);
+
+    for _ in 0..1000 {
+        params.mutate(&mut rng);
+    }
+
+    assert!(params.base_freq >= 0.0 && params.base_freq <= 1.0);
+    assert!(params.freq_ramp >= -1.0 && params.freq_ramp <= 1.0);
+    sfxr::render(&params, 22050, 1);
+}
+
+// Signal to noise ratio in dB.
+fn snr(original: &[i16], decoded: &[i16]) -> f64 {
+    assert_eq!(original.len(), decoded.len());
+
+    let signal = original.iter().map(|&x| (x as f64).powi(2)).sum::<f64>();
+    let noise = original
+        .iter()
+        .zip(decoded.iter())
+        .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+        .sum::<f64>();
+
+    10.0 * (signal / noise).log10()
+}
+
+fn round_trip(samples: &[i16]) -> (Vec<u8>, f64) {
+    let encoded = adpcm::encode(samples, 22050);
+    let adpcm = Adpcm::parse(&encoded).unwrap();
+
+    assert_eq!(adpcm.sample_rate(), 22050);
+    assert_eq!(adpcm.len(), samples.len());
+
+    let decoded = adpcm.decode().unwrap();
+    let snr = snr(samples, &decoded);
+    (encoded, snr)
+}
+
+#[test]
+fn adpcm_round_trip_snr() {
+    let tone = (0..22050)
+        .map(|i| (12000.0 * (2.0 * PI * 440.0 * i as f64 / 22050.0).sin()) as i16)
+        .collect::<Vec<_>>();
+
+    let (encoded, tone_snr) = round_trip(&tone);
+    assert!(tone_snr > 60.0, "tone {}", tone_snr);
+    assert!(encoded.len() < tone.len() * 2 / 3);
+
+    // A chord with a decaying envelope, closer to real music.
+    let chord = (0..22050)
+        .map(|i| {
+            let t = i as f64 / 22050.0;
+            let envelope = (-3.0 * t).exp();
+            let value = [220.0, 277.2, 329.6, 1760.0]
+                .iter()
+                .map(|frequency| (2.0 * PI * frequency * t).sin())
+                .sum::<f64>();
+
+            (7000.0 * envelope * value) as i16
+        })
+        .collect::<Vec<_>>();
+
+    let (_, chord_snr) = round_trip(&chord);
+    assert!(chord_snr > 35.0, "chord {}", chord_snr);
+
+    let params = Params::from_bytes(EXPLOSION).unwrap();
+    let explosion = sfxr::render(&params, 22050, 1);
+    let (_, explosion_snr) = round_trip(&explosion);
+    assert!(explosion_snr > 30.0, "explosion {}", explosion_snr);
+}
+
+#[test]
+fn adpcm_edge_cases() {
+    for len in [0, 1, FRAME_SAMPLES - 1, FRAME_SAMPLES + 1].iter() {
+        let samples = (0..*len).map(|i| (i * 1000) as i16).collect::<Vec<_>>();
+        let encoded = adpcm::encode(&samples, 22050);
+        let decoded = Adpcm::parse(&encoded).unwrap().decode().unwrap();
+        assert_eq!(decoded.len(), *len);
+    }
+
+    // Full scale square waves must not wrap around.
+    let square = (0..1000)
+        .map(|i| if i / 50 % 2 == 0 { i16::MAX } else { i16::MIN })
+        .collect::<Vec<_>>();
+    let (_, square_snr) = round_trip(&square);
+    assert!(square_snr > 20.0, "square {}", square_snr);
+
+    let encoded = adpcm::encode(&square, 22050);
+    assert_eq!(
+        Adpcm::parse(&encoded[..encoded.len() - 1]).err(),
+        Some(AdpcmError::UnexpectedEnd)
+    );
+    assert_eq!(Adpcm::parse(&[0; 20]).err(), Some(AdpcmError::BadMagic));
+
+    // Decoding frame by frame matches decoding everything.
+    let adpcm = Adpcm::parse(&encoded).unwrap();
+    let mut decoder = adpcm.decoder();
+    let mut frame = [0; FRAME_SAMPLES];
+    decoder.decode_frame(adpcm.frame(0), &mut frame).unwrap();
+    assert_eq!(frame[..], adpcm.decode().unwrap()[..FRAME_SAMPLES]);
+    assert_eq!(adpcm.frame(1).len(), FRAME_BYTES);
+}
+
+#[test]
+fn wav_round_trip() {
+    let samples = [0, 1, -1, i16::MAX, i16::MIN, 1234];
+    let data = wav::write(&samples, 2, 22050);
+    assert_eq!(data.len(), wav::HEADER_SIZE + 12);
+
+    let wav = Wav::parse(&data).unwrap();
+    assert_eq!(wav.sample_rate, 22050);
+    assert_eq!(wav.channels, 2);
+    assert_eq!(wav.samples, samples);
+    assert_eq!(wav.to_mono(), [0, 16383, -15767]);
+}
+
+static RAMP: [i16; 8] = [0, 1000, 2000, 3000, 4000, 5000, 6000, 7000];
+static LOUD: [i16; 4] = [30000; 4];
+static BYTES: [i8; 2] = [64, -64];
+
+fn mix(mixer: &mut Mixer, frames: usize) -> Vec<i16> {
+    let mut out = vec![0; 2 * frames];
+    mixer.mix(&mut out);
+    out
+}
+
+#[test]
+fn mixer_one_shot_volume_and_pan() {
+    let mut mixer = Mixer::new(4, 22050);
+    let ramp = Sample::from_i16(&RAMP, 22050);
+
+    let voice = mixer.play(&ramp).unwrap();
+    let out = mix(&mut mixer, 4);
+    assert_eq!(out, [0, 0, 1000, 1000, 2000, 2000, 3000, 3000]);
+
+    mixer.set_volume(voice, 0.5);
+    mixer.set_pan(voice, -1.0);
+    let out = mix(&mut mixer, 6);
+    assert_eq!(out, [2000, 0, 2500, 0, 3000, 0, 3500, 0, 0, 0, 0, 0]);
+    assert!(!mixer.is_playing(voice));
+
+    // Stopped voices ignore their old handle.
+    mixer.set_volume(voice, 1.0);
+    assert_eq!(mix(&mut mixer, 1), [0, 0]);
+
+    let bytes = Sample::from_i8(&BYTES, 22050);
+    mixer.play(&bytes).unwrap();
+    assert_eq!(mix(&mut mixer, 2), [16384, 16384, -16384, -16384]);
+}
+
+#[test]
+fn mixer_pitch_and_loop() {
+    let mut mixer = Mixer::new(1, 22050);
+
+    // Half the output rate plays every sample twice, interpolated.
+    let voice = mixer.play(&Sample::from_i16(&RAMP, 11025)).unwrap();
+    assert_eq!(mix(&mut mixer, 4), [0, 0, 500, 500, 1000, 1000, 1500, 1500]);
+
+    mixer.set_pitch(voice, 4.0);
+    assert_eq!(mix(&mut mixer, 3), [2000, 2000, 4000, 4000, 6000, 6000]);
+    assert_eq!(mix(&mut mixer, 1), [0, 0]);
+
+    let looped = Sample::from_i16(&RAMP, 44100).with_loop(4, 8);
+    let voice = mixer.play(&looped).unwrap();
+    let out = mix(&mut mixer, 6);
+    assert_eq!(
+        out[..],
+        [0, 0, 2000, 2000, 4000, 4000, 6000, 6000, 4000, 4000, 6000, 6000][..]
+    );
+    assert!(mixer.is_playing(voice));
+
+    // Only one-shots are stolen.
+    assert_eq!(mixer.play(&Sample::from_i16(&RAMP, 22050)), None);
+    mixer.stop(voice);
+    assert!(mixer.play(&Sample::from_i16(&RAMP, 22050)).is_some());
+}
+
+#[test]
+fn mixer_steals_oldest_and_limits() {
+    let mut mixer = Mixer::new(2, 22050);
+    let loud = Sample::from_i16(&LOUD, 22050).looping();
+    let ramp = Sample::from_i16(&RAMP, 22050);
+
+    let first = mixer.play(&ramp).unwrap();
+    let second = mixer.play(&ramp).unwrap();
+    let third = mixer.play(&ramp).unwrap();
+    assert!(!mixer.is_playing(first));
+    assert!(mixer.is_playing(second) && mixer.is_playing(third));
+
+    mixer.stop_all();
+    mixer.play(&loud).unwrap();
+    mixer.play(&loud).unwrap();
+
+    let out = mix(&mut mixer, 100);
+    assert!(out.iter().all(|&sample| sample > 30000));
+
+    mixer.set_master_volume(0.25);
+    let out = mix(&mut mixer, 10000);
+    assert_eq!(out[out.len() - 2..], [15000, 15000]);
+}
+
+#[test]
+fn mixer_plays_adpcm() {
+    let tone = (0..1000)
+        .map(|i| if i / 25 % 2 == 0 { 8000 } else { -8000 })
+        .collect::<Vec<i16>>();
+    let sample = Sample::from_adpcm(&adpcm::encode(&tone, 11025)).unwrap();
+    assert_eq!(sample.len(), tone.len());
+    assert_eq!(sample.sample_rate(), 11025);
+
+    let mut mixer = Mixer::new(1, 22050);
+    mixer.play(&sample).unwrap();
+    let out = mix(&mut mixer, 2 * tone.len() + 1);
+    assert!(out[..out.len() - 2].iter().any(|&sample| sample > 7000));
+    assert_eq!(out[out.len() - 2..], [0, 0]);
+}
+
+#[test]
+fn mixer_plays_at_frame() {
+    let mut mixer = Mixer::new(2, 22050);
+    let ramp = Sample::from_i16(&RAMP, 22050);
+
+    // Three frames into the buffer after the next one.
+    mix(&mut mixer, 4);
+    assert_eq!(mixer.frame(), 4);
+    let voice = mixer.play_at(&ramp, 11).unwrap();
+    assert!(mixer.is_playing(voice));
+    assert_eq!(mix(&mut mixer, 4), [0; 8]);
+    assert_eq!(mix(&mut mixer, 4), [0, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(mix(&mut mixer, 2), [1000, 1000, 2000, 2000]);
+    assert_eq!(mixer.frame(), 14);
+
+    // Already mixed starts right away.
+    mixer.stop_all();
+    mixer.play_at(&ramp, 3).unwrap();
+    assert_eq!(mix(&mut mixer, 2), [0, 0, 1000, 1000]);
+}
+
+#[test]
+fn scheduler_keeps_spacing() {
+    const BUFFER_FRAMES: usize = 512;
+    const FRAME_US: i64 = 16_667;
+
+    let mut mixer = Mixer::new(4, 22050);
+    let mut scheduler = Scheduler::new(BUFFER_FRAMES);
+    let click = Sample::from_vec(vec![10000], 22050);
+    let mut out = Vec::new();
+    let mut scheduled = Vec::new();
+
+    // Like the console: a click every video frame, then as many buffers
+    // mixed as have played, four ahead.
+    for i in 0..200 {
+        let time_us = 1_000_000 + i * FRAME_US;
+        scheduled.push(scheduler.frame_at(&mixer, time_us));
+        scheduler.play_at(&mut mixer, &click, time_us).unwrap();
+
+        let played = (i * FRAME_US * 22050 / 1_000_000) as u64;
+        while mixer.frame() < played + 4 * BUFFER_FRAMES as u64 {
+            out.extend(mix(&mut mixer, BUFFER_FRAMES));
+        }
+    }
+    out.extend(mix(&mut mixer, 4 * BUFFER_FRAMES));
+
+    let clicks = out
+        .chunks_exact(2)
+        .enumerate()
+        .filter(|(_, frame)| frame[0] != 0)
+        .map(|(i, _)| i as u64)
+        .collect::<Vec<_>>();
+    assert_eq!(clicks, scheduled);
+
+    // Exactly a video frame apart, not held back to buffer boundaries, once
+    // the first frame has filled the buffers.
+    for (i, pair) in clicks[1..].windows(2).enumerate() {
+        let time = i as i64 * FRAME_US;
+        let expected = (time + FRAME_US) * 22050 / 1_000_000 - time * 22050 / 1_000_000;
+        assert_eq!(pair[1] - pair[0], expected as u64);
+    }
+    assert!(clicks.iter().any(|frame| frame % BUFFER_FRAMES as u64 != 0));
+
+    // After a stall sounds are a buffer after the next mixed frame again.
+    mix(&mut mixer, 100_000);
+    assert_eq!(
+        scheduler.frame_at(&mixer, 1_000_000 + 201 * FRAME_US),
+        mixer.frame() + BUFFER_FRAMES as u64
+    );
+}
+
+// Frames per tick at the default tempo of 125.
+const TICK: usize = 441;
+
+fn square_wave() -> Vec<i8> {
+    (0..32).map(|i| if i < 16 { 64 } else { -64 }).collect()
+}
+
+// A 4 channel MOD with one pattern and a looping square wave as sample 1.
+// Cells are row, channel, Amiga period, effect and parameter.
+fn protracker_module(cells: &[(usize, usize, u16, u8, u8)]) -> Vec<u8> {
+    let mut data = vec![0; 1084 + 1024];
+    data[..4].copy_from_slice(b"test");
+    data[20 + 22..20 + 24].copy_from_slice(&16u16.to_be_bytes());
+    data[20 + 25] = 64;
+    data[20 + 28..20 + 30].copy_from_slice(&16u16.to_be_bytes());
+    data[950] = 1;
+    data[1080..1084].copy_from_slice(b"M.K.");
+
+    for &(row, channel, period, effect, param) in cells {
+        let offset = 1084 + 4 * (4 * row + channel);
+        let sample = if period != 0 { 1 } else { 0 };
+        data[offset..offset + 4].copy_from_slice(&[
+            (period >> 8) as u8,
+            period as u8,
+            sample << 4 | effect,
+            param,
+        ]);
+    }
+
+    data.extend(square_wave().iter().map(|&sample| sample as u8));
+    data
+}
+
+// Left and right peaks of every `frames` long block.
+fn peaks(out: &[i16], frames: usize) -> Vec<(i16, i16)> {
+    out.chunks(2 * frames)
+        .map(|block| {
+            let left = block.iter().step_by(2).map(|sample| sample.abs()).max();
+            let right = block
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .map(|sample| sample.abs())
+                .max();
+            (left.unwrap(), right.unwrap())
+        })
+        .collect()
+}
+
+fn zero_crossings(out: &[i16]) -> usize {
+    out.chunks_exact(2)
+        .zip(out.chunks_exact(2).skip(1))
+        .filter(|(a, b)| (a[0] < 0) != (b[0] < 0))
+        .count()
+}
+
+#[test]
+fn tracker_plays_mod() {
+    let data = protracker_module(&[
+        (0, 0, 428, 0x0, 0),
+        (1, 0, 0, 0xc, 32),
+        (2, 0, 214, 0x0, 0),
+        (3, 0, 0, 0xd, 0),
+    ]);
+
+    let module = Module::parse(&data).unwrap();
+    assert_eq!(module.name(), "test");
+    assert_eq!(module.channel_count(), 4);
+    assert_eq!(module.order_count(), 1);
+
+    // The pattern break on row 3 ends the song after 4 rows of 6 ticks.
+    let out = tracker::render(module, 22050);
+    assert_eq!(out.len(), 2 * 4 * 6 * TICK);
+
+    // Channel 1 is panned left, volume 32 is half.
+    let rows = peaks(&out, 6 * TICK);
+    assert_eq!(rows[0], (16384, 8192));
+    assert_eq!(rows[1], (8192, 4096));
+
+    // Half the period is an octave up.
+    let row = |index: usize| &out[2 * 6 * TICK * index..2 * 6 * TICK * (index + 1)];
+    let low = zero_crossings(row(1));
+    let high = zero_crossings(row(2));
+    assert!(low > 50 && (2 * low as i32 - high as i32).abs() <= 2);
+}
+
+#[test]
+fn tracker_flow_effects() {
+    // Speed 3, and rows 0 and 1 loop twice more before the break.
+    let data = protracker_module(&[
+        (0, 0, 428, 0xf, 3),
+        (0, 1, 0, 0xe, 0x60),
+        (1, 1, 0, 0xe, 0x62),
+        (2, 0, 0, 0xd, 0),
+    ]);
+    let out = tracker::render(Module::parse(&data).unwrap(), 22050);
+    assert_eq!(out.len(), 2 * 7 * 3 * TICK);
+
+    // Tempo 250 halves the tick and the jump back to the start ends the song.
+    let data = protracker_module(&[(0, 0, 428, 0xf, 250), (1, 0, 0, 0xb, 0)]);
+    let out = tracker::render(Module::parse(&data).unwrap(), 22050);
+    assert_eq!(out.len(), 2 * 2 * 6 * TICK / 2);
+
+    // Volume slides down 8 a tick after the first.
+    let data = protracker_module(&[(0, 0, 428, 0xa, 0x08), (1, 0, 0, 0xd, 0)]);
+    let out = tracker::render(Module::parse(&data).unwrap(), 22050);
+    let ticks = peaks(&out, TICK);
+    assert_eq!(ticks[0].0, 16384);
+    assert_eq!(ticks[1].0, 56 * 256);
+    assert_eq!(ticks[5].0, 24 * 256);
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+// A one channel XM at speed 1 with an instrument that has a volume envelope
+// from 64 down to a sustained 32 and a fadeout of 8 ticks. Plays C-4 and
+// releases it on row 8.
+fn xm_module() -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"Extended Module: ");
+    data.extend_from_slice(b"test                ");
+    data.push(0x1a);
+    data.extend_from_slice(&[b' '; 20]);
+    push_u16(&mut data, 0x0104);
+    push_u32(&mut data, 276);
+    for &value in &[1, 0, 1, 1, 1, 1, 1, 125] {
+        push_u16(&mut data, value);
+    }
+    data.extend_from_slice(&[0; 256]);
+
+    let mut pattern = Vec::new();
+    for row in 0..20 {
+        match row {
+            0 => pattern.extend_from_slice(&[0x83, 49, 1]),
+            8 => pattern.extend_from_slice(&[0x81, NOTE_OFF]),
+            _ => pattern.push(0x80),
+        }
+    }
+    push_u32(&mut data, 9);
+    data.push(0);
+    push_u16(&mut data, 20);
+    push_u16(&mut data, pattern.len() as u16);
+    data.extend_from_slice(&pattern);
+
+    let instrument = data.len();
+    push_u32(&mut data, 263);
+    data.extend_from_slice(&[0; 22]);
+    data.push(0);
+    push_u16(&mut data, 1);
+    push_u32(&mut data, 40);
+    data.extend_from_slice(&[0; 96]);
+    for &value in &[0, 64, 4, 32] {
+        push_u16(&mut data, value);
+    }
+    data.extend_from_slice(&[0; 40 + 48]);
+    // Point counts, sustain and loops, then the envelope flags.
+    data.extend_from_slice(&[2, 0, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0]);
+    push_u16(&mut data, 4096);
+    data.resize(instrument + 263, 0);
+
+    push_u32(&mut data, 32);
+    push_u32(&mut data, 0);
+    push_u32(&mut data, 32);
+    data.extend_from_slice(&[64, 0, 1, 128, 0, 0]);
+    data.extend_from_slice(&[0; 22]);
+
+    let mut previous = 0i8;
+    for sample in square_wave() {
+        data.push(sample.wrapping_sub(previous) as u8);
+        previous = sample;
+    }
+
+    data
+}
+
+#[test]
+fn tracker_xm_envelopes() {
+    let module = Module::parse(&xm_module()).unwrap();
+    assert_eq!(module.name(), "test");
+    assert_eq!(module.channel_count(), 1);
+
+    let out = tracker::render(module, 22050);
+    assert_eq!(out.len(), 2 * 20 * TICK);
+
+    let volumes = peaks(&out, TICK)
+        .iter()
+        .map(|&(left, right)| {
+            assert_eq!(left, right);
+            left / 256
+        })
+        .collect::<Vec<_>>();
+
+    // Down the envelope, held at the sustain point until the release and
+    // then faded out.
+    assert_eq!(volumes[..5], [64, 56, 48, 40, 32]);
+    assert!(volumes[5..9].iter().all(|&volume| volume == 32));
+    assert_eq!(volumes[9..17], [28, 24, 20, 16, 12, 8, 4, 0]);
+    assert!(volumes[17..].iter().all(|&volume| volume == 0));
+}
+
+#[test]
+fn tracker_errors_and_wav() {
+    assert_eq!(
+        Module::parse(&[0; 2000]).err(),
+        Some(ModuleError::UnknownFormat)
+    );
+
+    let data = protracker_module(&[(0, 0, 428, 0, 0)]);
+    assert_eq!(
+        Module::parse(&data[..1500]).err(),
+        Some(ModuleError::UnexpectedEnd)
+    );
+
+    let mut data = protracker_module(&[(0, 0, 428, 0, 0)]);
+    data[1080..1084].copy_from_slice(b"99CH");
+    assert_eq!(
+        Module::parse(&data).err(),
+        Some(ModuleError::TooManyChannels)
+    );
+
+    let data = xm_module();
+    assert_eq!(
+        Module::parse(&data[..data.len() - 40]).err(),
+        Some(ModuleError::UnexpectedEnd)
+    );
+
+    let out = tracker::render(Module::parse(&data).unwrap(), 32000);
+    let file = Wav::parse(&tracker::render_wav(Module::parse(&data).unwrap(), 32000)).unwrap();
+    assert_eq!(file.channels, 2);
+    assert_eq!(file.sample_rate, 32000);
+    assert_eq!(file.samples, out);
+}
+
+// Format 1, 96 ticks per quarter note. The tempo track starts at 0.25 s per
+// quarter and slows to 0.5 s after two quarters.
+fn midi_file() -> Vec<u8> {
+    let tempo_track: &[u8] = &[
+        0x00, 0xff, 0x51, 0x03, 0x03, 0xd0, 0x90, //
+        0x81, 0x40, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20, //
+        0x60, 0xff, 0x2f, 0x00,
+    ];
+    let note_track: &[u8] = &[
+        0x00, 0xb0, 0x07, 0x5a, // Volume 90
+        0x00, 0x90, 0x3c, 0x64, //
+        0x60, 0x3c, 0x00, // Running status, velocity 0
+        0x00, 0x40, 0x50, //
+        0x00, 0xf0, 0x02, 0x01, 0xf7, // Sysex
+        0x00, 0xc0, 0x05, // Program change
+        0x81, 0x40, 0x80, 0x40, 0x00, //
+        0x30, 0xff, 0x2f, 0x00,
+    ];
+
+    let mut data = b"MThd".to_vec();
+    data.extend_from_slice(&[0, 0, 0, 6, 0, 1, 0, 2, 0, 96]);
+
+    for track in &[tempo_track, b"skip me", note_track] {
+        data.extend_from_slice(if track.len() == 7 { b"XFIH" } else { b"MTrk" });
+        data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        data.extend_from_slice(track);
+    }
+
+    data
+}
+
+#[test]
+fn midi_imports_to_sequence() {
+    let data = midi_file();
+    let sequence = midi::import(&data).unwrap();
+    let (events, length) = chiptune::decode(&sequence).unwrap();
+
+    assert_eq!(
+        events,
+        [
+            (
+                0,
+                Event::Volume {
+                    channel: 0,
+                    value: 90
+                }
+            ),
+            (
+                0,
+                Event::NoteOn {
+                    channel: 0,
+                    note: 60,
+                    velocity: 100
+                }
+            ),
+            (
+                250,
+                Event::NoteOff {
+                    channel: 0,
+                    note: 60
+                }
+            ),
+            (
+                250,
+                Event::NoteOn {
+                    channel: 0,
+                    note: 64,
+                    velocity: 80
+                }
+            ),
+            (
+                1000,
+                Event::NoteOff {
+                    channel: 0,
+                    note: 64
+                }
+            ),
+        ]
+    );
+    assert_eq!(length, 1250);
+    assert!(sequence.len() < 32);
+
+    assert_eq!(midi::import(b"RIFF0000000000"), Err(MidiError::NotMidi));
+    assert_eq!(
+        midi::import(&data[..data.len() - 3]),
+        Err(MidiError::UnexpectedEnd)
+    );
+
+    let mut smpte = data.clone();
+    smpte[12] = 0xe7;
+    assert_eq!(midi::import(&smpte), Err(MidiError::UnsupportedTiming));
+}
+
+#[test]
+fn chiptune_sequence_round_trip() {
+    let events = [
+        (
+            0,
+            Event::Pan {
+                channel: 3,
+                value: 0,
+            },
+        ),
+        (
+            70_000,
+            Event::NoteOn {
+                channel: 15,
+                note: 127,
+                velocity: 1,
+            },
+        ),
+        (
+            70_000,
+            Event::NoteOff {
+                channel: 15,
+                note: 127,
+            },
+        ),
+    ];
+
+    let sequence = chiptune::encode(&events, 3_000_000);
+    assert_eq!(
+        chiptune::decode(&sequence),
+        Ok((events.to_vec(), 3_000_000))
+    );
+
+    assert_eq!(chiptune::decode(b"MIDI"), Err(SequenceError::BadMagic));
+    assert_eq!(
+        chiptune::decode(&sequence[..sequence.len() - 1]),
+        Err(SequenceError::UnexpectedEnd)
+    );
+    assert_eq!(
+        chiptune::decode(b"CHIP\x00\x70"),
+        Err(SequenceError::UnknownEvent(0x70))
+    );
+    assert!(Synth::new(b"CHIP\x00".to_vec(), 22050).is_err());
+}
+
+fn note(channel: u8, note: u8, length: u32, song_length: u32) -> Vec<u8> {
+    chiptune::encode(
+        &[
+            (
+                0,
+                Event::Volume {
+                    channel,
+                    value: 127,
+                },
+            ),
+            (
+                0,
+                Event::NoteOn {
+                    channel,
+                    note,
+                    velocity: 127,
+                },
+            ),
+            (length, Event::NoteOff { channel, note }),
+        ],
+        song_length,
+    )
+}
+
+#[test]
+fn chiptune_adsr() {
+    // 10 ms blocks at 10 kHz.
+    let mut synth = Synth::new(note(0, 69, 500, 900), 10000).unwrap();
+    synth.set_looping(false);
+    synth.set_instrument(
+        0,
+        Instrument {
+            waveform: Waveform::Square,
+            attack: 0.1,
+            decay: 0.1,
+            sustain: 0.5,
+            release: 0.2,
+            volume: 1.0,
+        },
+    );
+
+    let mut out = vec![0; 2 * 10000];
+    synth.render(&mut out);
+    let levels = peaks(&out, 100)
+        .iter()
+        .map(|&(left, right)| {
+            assert_eq!(left, right);
+            (left as f32 / i16::MAX as f32 * 100.0 + 0.5) as i32
+        })
+        .collect::<Vec<_>>();
+
+    // Up in 100 ms, down to half in another 100 ms, held until the note ends
+    // at 500 ms and released over 200 ms.
+    assert_eq!(levels[..3], [10, 20, 30]);
+    assert_eq!(levels[9], 100);
+    assert_eq!(levels[14], 60);
+    assert!(levels[20..50].iter().all(|&level| level == 50));
+    assert_eq!(levels[60], 25);
+    assert!(levels[70..].iter().all(|&level| level == 0));
+    assert!(synth.is_finished());
+}
+
+#[test]
+fn chiptune_waveforms() {
+    for &waveform in &[
+        Waveform::Square,
+        Waveform::Pulse(32),
+        Waveform::Triangle,
+        Waveform::Sawtooth,
+    ] {
+        let mut synth = Synth::new(note(2, 69, 1000, 1000), 22050).unwrap();
+        synth.set_instrument(
+            2,
+            Instrument {
+                waveform,
+                attack: 0.0,
+                sustain: 1.0,
+                ..Instrument::default()
+            },
+        );
+
+        // A4 is 440 Hz, two zero crossings a cycle.
+        let mut out = vec![0; 2 * 22050];
+        synth.render(&mut out);
+        let crossings = zero_crossings(&out);
+        assert!(
+            (878..=882).contains(&crossings),
+            "{:?} {}",
+            waveform,
+            crossings
+        );
+    }
+
+    // Drums by default on channel 10, panned left and added to what is
+    // there.
+    let mut sequence = note(9, 40, 100, 1000);
+    sequence = chiptune::encode(
+        &[
+            &[(
+                0,
+                Event::Pan {
+                    channel: 9,
+                    value: 0,
+                },
+            )][..],
+            &chiptune::decode(&sequence).unwrap().0,
+        ]
+        .concat(),
+        1000,
+    );
+    let mut synth = Synth::new(sequence, 22050).unwrap();
+    assert_eq!(synth.instrument(9).waveform, Waveform::Noise);
+
+    let mut out = vec![1000; 2 * 2205];
+    synth.render(&mut out);
+    assert!(out.iter().step_by(2).any(|&sample| sample > 5000));
+    assert!(out.iter().step_by(2).any(|&sample| sample < -5000));
+    assert!(out.iter().skip(1).step_by(2).all(|&sample| sample == 1000));
+}
+
+#[test]
+fn stream_pcm_loops() {
+    let samples = (0..3000)
+        .flat_map(|i| vec![2 * i as i16, -2 * i as i16])
+        .collect::<Vec<_>>();
+    let data = wav::write(&samples, 2, 22050);
+
+    // Longer than a chunk so the loop is read across them.
+    let mut stream = Stream::new(&data[..], 22050).unwrap().with_loop(1000, 2500);
+    assert_eq!(stream.len(), 3000);
+
+    let mut out = vec![0; 2 * 6000];
+    stream.render(&mut out);
+
+    let expected = (0..2500).chain((1000..2500).cycle()).take(6000);
+    for (frame, i) in out.chunks_exact(2).zip(expected) {
+        assert_eq!(frame, [2 * i as i16, -2 * i as i16]);
+    }
+    assert!(!stream.is_finished());
+
+    // At half the rate every other frame is between two.
+    let mut stream = Stream::new(&data[..], 44100).unwrap();
+    let mut out = vec![0; 2 * 100];
+    stream.render(&mut out);
+    assert_eq!(out[..8], [0, 0, 1, -1, 2, -2, 3, -3]);
+
+    // 8 bit mono, added to what is there and ends after the last frame.
+    let mut data = wav::header(1, 22050, 100).to_vec();
+    data[32] = 1;
+    data[34] = 8;
+    data.extend((0..100).map(|i| 128 + i as u8));
+
+    let mut stream = Stream::new(&data[..], 22050).unwrap();
+    let mut out = vec![7; 2 * 150];
+    stream.render(&mut out);
+
+    assert!(stream.is_finished());
+    assert_eq!(out[2 * 99..2 * 100], [(99 << 8) + 7, (99 << 8) + 7]);
+    assert!(out[2 * 100..].iter().all(|&sample| sample == 7));
+}
+
+#[test]
+fn stream_adpcm_loops_seamlessly() {
+    let samples = (0..20000)
+        .map(|i| ((i as f64 * 0.05).sin() * 12000.0) as i16)
+        .collect::<Vec<_>>();
+    let data = adpcm::encode(&samples, 22050);
+    let decoded = Adpcm::parse(&data).unwrap().decode().unwrap();
+
+    // The loop starts inside a frame and is longer than a chunk.
+    let mut stream = Stream::new(&data[..], 22050)
+        .unwrap()
+        .with_loop(5003, 17011);
+    let mut out = vec![0; 2 * 40000];
+    stream.render(&mut out);
+
+    let expected = (0..17011).chain((5003..17011).cycle()).take(40000);
+    for (frame, i) in out.chunks_exact(2).zip(expected) {
+        assert_eq!(frame, [decoded[i], decoded[i]]);
+    }
+
+    stream.restart();
+    let mut again = vec![0; 2 * 40000];
+    stream.render(&mut again);
+    assert_eq!(again, out);
+
+    assert_eq!(
+        Stream::new(&data[..1000], 22050).err(),
+        Some(StreamError::UnexpectedEnd)
+    );
+    assert_eq!(
+        Stream::new(&[0u8; 64][..], 22050).err(),
+        Some(StreamError::UnknownFormat)
+    );
+}
+
+// The left channel of `bus` after an impulse there, the right one must stay
+// silent.
+fn impulse_response(bus: &mut Bus, frames: usize) -> Vec<f64> {
+    let mut out = vec![0; 2 * frames];
+    out[0] = 16384;
+    bus.process(&mut out);
+
+    assert!(out.iter().skip(1).step_by(2).all(|&sample| sample == 0));
+    out.iter().step_by(2).map(|&sample| sample as f64).collect()
+}
+
+fn assert_close(response: &[f64], expected: &[f64], tolerance: f64) {
+    for (i, (a, b)) in response.iter().zip(expected.iter()).enumerate() {
+        assert!((a - b).abs() <= tolerance, "{}: {} {}", i, a, b);
+    }
+}
+
+// The same filter in floating point.
+fn biquad_response(b: [f64; 3], a: [f64; 3], frames: usize) -> Vec<f64> {
+    let mut x = [0.0; 3];
+    let mut y = [0.0; 3];
+
+    (0..frames)
+        .map(|i| {
+            x = [if i == 0 { 16384.0 } else { 0.0 }, x[0], x[1]];
+            let out = (b[0] * x[0] + b[1] * x[1] + b[2] * x[2] - a[1] * y[0] - a[2] * y[1]) / a[0];
+            y = [out, y[0], y[1]];
+            out
+        })
+        .collect()
+}
+
+#[test]
+fn effects_filter_impulse_responses() {
+    let a = 1.0 - (-2.0 * PI * 1000.0 / 22050.0).exp();
+    let low = (0..200)
+        .map(|i| 16384.0 * a * (1.0 - a).powi(i))
+        .collect::<Vec<_>>();
+
+    let mut bus = Bus::new().with(OnePole::low_pass(1000.0, 22050));
+    assert_close(&impulse_response(&mut bus, 200), &low, 1.0);
+
+    let high = low
+        .iter()
+        .enumerate()
+        .map(|(i, low)| if i == 0 { 16384.0 } else { 0.0 } - low)
+        .collect::<Vec<_>>();
+    let mut bus = Bus::new().with(OnePole::high_pass(1000.0, 22050));
+    assert_close(&impulse_response(&mut bus, 200), &high, 1.0);
+
+    let w0 = 2.0 * PI * 2000.0 / 22050.0;
+    let alpha = w0.sin() / (2.0 * 2.0);
+    let cos = w0.cos();
+    let a = [1.0 + alpha, -2.0 * cos, 1.0 - alpha];
+
+    for &(kind, b) in &[
+        (
+            BiquadKind::LowPass,
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+        ),
+        (
+            BiquadKind::HighPass,
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+        ),
+        (BiquadKind::BandPass, [alpha, 0.0, -alpha]),
+        (BiquadKind::Notch, [1.0, -2.0 * cos, 1.0]),
+    ] {
+        let mut bus = Bus::new().with(Biquad::new(kind, 2000.0, 2.0, 22050));
+        let expected = biquad_response(b, a, 500);
+        assert_close(&impulse_response(&mut bus, 500), &expected, 2.0);
+
+        // Nothing left ringing after a reset.
+        bus.reset();
+        let mut out = vec![0; 2 * 100];
+        bus.process(&mut out);
+        assert!(out.iter().all(|&sample| sample == 0), "{:?}", kind);
+    }
+
+    // Chained effects run in order before the volume.
+    let mut chained = Bus::new()
+        .with(Biquad::new(BiquadKind::LowPass, 2000.0, 2.0, 22050))
+        .with(OnePole::low_pass(1000.0, 22050));
+    chained.set_volume(0.5);
+
+    let mut first = Bus::new().with(Biquad::new(BiquadKind::LowPass, 2000.0, 2.0, 22050));
+    let mut second = Bus::new().with(OnePole::low_pass(1000.0, 22050));
+    second.set_volume(0.5);
+    let mut out = vec![0; 2 * 200];
+    out[0] = 16384;
+    first.process(&mut out);
+    second.process(&mut out);
+
+    let response = impulse_response(&mut chained, 200);
+    assert!(response.iter().any(|&sample| sample != 0.0));
+    assert_eq!(
+        response,
+        out.iter()
+            .step_by(2)
+            .map(|&sample| sample as f64)
+            .collect::<Vec<_>>()
+    );
+
+    let mut bus = Bus::new();
+    bus.set_volume(2.0);
+    let mut out = vec![20000, -20000, 1000, -1000];
+    bus.process(&mut out);
+    assert_eq!(out, [i16::MAX, i16::MIN, 2000, -2000]);
+}
+
+#[test]
+fn effects_delay_and_reverb() {
+    // 100 ms echoes, each half as loud as the last.
+    let mut bus = Bus::new().with(Delay::new(100, 0.5, 1.0, 22050));
+    let response = impulse_response(&mut bus, 10000);
+
+    for (i, &sample) in response.iter().enumerate() {
+        let expected = match i {
+            0 => 16384.0,
+            _ if i % 2205 == 0 => 16384.0 / (1 << (i / 2205 - 1)) as f64,
+            _ => 0.0,
+        };
+        assert_close(&[sample], &[expected], 1.0);
+    }
+
+    // Nothing before the shortest comb, then a tail that dies out and
+    // differs between the sides.
+    let mut bus = Bus::new().with(Reverb::new(0.5, 0.5, 1.0, 22050));
+    let mut out = vec![0; 2 * 22050 * 4];
+    out[0] = 16384;
+    out[1] = 16384;
+    bus.process(&mut out);
+
+    let first = 1116 * 22050 / 44100;
+    assert!(out[2..2 * first].iter().all(|&sample| sample == 0));
+    assert!(out[2 * first] != 0);
+
+    let energy = |frames: &[i16]| {
+        frames
+            .iter()
+            .map(|&sample| (sample as f64).powi(2))
+            .sum::<f64>()
+    };
+    let second = energy(&out[2 * 22050..2 * 2 * 22050]);
+    assert!(energy(&out[2..2 * 22050]) > 10.0 * second);
+    assert!(energy(&out[2 * 3 * 22050..]) < second);
+    assert!(out
+        .chunks_exact(2)
+        .skip(1)
+        .any(|frame| frame[0] != frame[1]));
+
+    bus.reset();
+    let mut out = vec![0; 2 * 22050];
+    bus.process(&mut out);
+    assert!(out.iter().all(|&sample| sample == 0));
+}
+
+#[test]
+fn effects_ducking() {
+    let mut ducker = Ducker::new(0.1, 0.25, 10.0, 100.0, 22050);
+    let mut music = vec![8000; 2 * 22050];
+    let mut sfx = vec![0; 2 * 22050];
+    for sample in sfx[2 * 2205..2 * 4410].iter_mut() {
+        *sample = 16000;
+    }
+
+    ducker.process(&mut music, &sfx);
+
+    // Untouched until the sound effect, down to a quarter while it plays and
+    // back up after.
+    assert!(music[..2 * 2205].iter().all(|&sample| sample == 8000));
+    assert!(music[2 * 2205 + 2] < 8000);
+    assert!(music[2 * 4410 - 2] <= 2100);
+    assert!(music[2 * 4410 + 2 * 100] < 4000);
+    assert!(music[2 * 22050 - 2] >= 7950);
+    assert!(ducker.gain() > 0.99);
+
+    let mut out = vec![30000, -30000, 100];
+    effects::add(&mut out, &[10000, -10000, 100]);
+    assert_eq!(out, [i16::MAX, i16::MIN, 200]);
+}
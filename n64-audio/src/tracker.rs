@@ -0,0 +1,1034 @@
+//! Plays ProTracker MOD and FastTracker 2 XM modules through the `Mixer`,
+//! one mixer voice per channel.
+//!
+//! Both formats load into the same model, notes are XM style (1 is C-0 and
+//! 49 is C-4) and MOD effects are a subset of the XM ones. Pitch uses Amiga
+//! periods for MOD and XMs that ask for it, otherwise linear periods.
+//! Ping-pong loops are unrolled at load time since the mixer only loops
+//! forwards.
+
+use crate::mixer::{Mixer, Sample, Voice};
+use crate::wav;
+use alloc::{string::String, vec::Vec};
+use libm::exp2f;
+
+mod protracker;
+mod xm;
+
+pub const MAX_CHANNELS: usize = 32;
+pub const NOTE_OFF: u8 = 97;
+
+const MAX_NOTE: u8 = 96;
+const MAX_VOLUME: i32 = 64;
+const MAX_FADEOUT: i32 = 32768;
+const CENTER_PAN: i32 = 128;
+
+// Samples are stored at the rate C-4 plays at, so the pitch is the frequency
+// over this.
+const BASE_RATE: u32 = 8363;
+const BASE_PERIOD: f32 = 428.0;
+const BASE_NOTE: f32 = 48.0;
+const LINEAR_BASE_PERIOD: f32 = 4608.0;
+const LINEAR_SEMITONE: f32 = 64.0;
+
+const DEFAULT_SPEED: u8 = 6;
+const DEFAULT_TEMPO: u8 = 125;
+
+// Stops `render` from running forever on songs that never loop back.
+const MAX_RENDER_SECONDS: u32 = 30 * 60;
+
+static VIBRATO_TABLE: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253, 255, 253, 250, 244,
+    235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ModuleError {
+    /// Neither an XM nor a MOD with a known signature.
+    UnknownFormat,
+    UnexpectedEnd,
+    TooManyChannels,
+    BadPattern,
+}
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+struct Cell {
+    // 0 is no note, otherwise 1 to `MAX_NOTE` or `NOTE_OFF`.
+    note: u8,
+    // 1 based, 0 keeps the current instrument.
+    instrument: u8,
+    // The XM volume column, 0 is empty.
+    volume: u8,
+    effect: u8,
+    param: u8,
+}
+
+struct Pattern {
+    rows: usize,
+    cells: Vec<Cell>,
+}
+
+#[derive(Default)]
+struct Envelope {
+    // Tick and value from 0 to 64, empty when the envelope is off.
+    points: Vec<(u16, u8)>,
+    sustain: Option<usize>,
+    loop_range: Option<(usize, usize)>,
+}
+
+impl Envelope {
+    #[inline]
+    fn is_enabled(&self) -> bool {
+        !self.points.is_empty()
+    }
+
+    fn value(&self, tick: u16) -> i32 {
+        let index = self
+            .points
+            .iter()
+            .rposition(|&(point, _)| point <= tick)
+            .unwrap_or(0);
+
+        let (start, from) = self.points[index];
+        match self.points.get(index + 1) {
+            Some(&(end, to)) if end > start => {
+                let (from, to) = (from as i32, to as i32);
+                from + (to - from) * (tick - start) as i32 / (end - start) as i32
+            }
+            _ => from as i32,
+        }
+    }
+
+    fn advance(&self, tick: u16, key_on: bool) -> u16 {
+        if let Some(sustain) = self.sustain {
+            if key_on && tick == self.points[sustain].0 {
+                return tick;
+            }
+        }
+
+        if let Some((start, end)) = self.loop_range {
+            if tick >= self.points[end].0 {
+                return self.points[start].0;
+            }
+        }
+
+        match self.points.last() {
+            Some(&(last, _)) if tick >= last => tick,
+            _ => tick + 1,
+        }
+    }
+}
+
+struct InstrumentSample {
+    sample: Option<Sample>,
+    volume: u8,
+    // None keeps the channel panning, MODs pan by channel.
+    panning: Option<u8>,
+    relative_note: i8,
+    finetune: i8,
+}
+
+impl InstrumentSample {
+    // Takes signed 16 bit data, `loop_type` is 0 for none, 1 for forward and
+    // 2 for ping-pong.
+    fn new(mut data: Vec<i16>, loop_start: usize, loop_length: usize, loop_type: u8) -> Self {
+        let loop_end = loop_start.saturating_add(loop_length).min(data.len());
+        let loop_range = if loop_type != 0 && loop_start < loop_end {
+            if loop_type == 2 {
+                data.truncate(loop_end);
+                let reverse = data[loop_start..loop_end]
+                    .iter()
+                    .rev()
+                    .copied()
+                    .collect::<Vec<_>>();
+                data.extend_from_slice(&reverse);
+                Some((loop_start, data.len()))
+            } else {
+                Some((loop_start, loop_end))
+            }
+        } else {
+            None
+        };
+
+        let sample = if data.is_empty() {
+            None
+        } else {
+            let sample = Sample::from_vec(data, BASE_RATE);
+            Some(match loop_range {
+                Some((start, end)) => sample.with_loop(start, end),
+                None => sample,
+            })
+        };
+
+        InstrumentSample {
+            sample,
+            volume: 64,
+            panning: None,
+            relative_note: 0,
+            finetune: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Instrument {
+    samples: Vec<InstrumentSample>,
+    // Sample index for every note.
+    keymap: Vec<u8>,
+    volume_envelope: Envelope,
+    panning_envelope: Envelope,
+    // Taken off a 32768 volume every tick after the key is released.
+    fadeout: u16,
+}
+
+/// A parsed MOD or XM song with its samples ready to play.
+pub struct Module {
+    name: String,
+    channels: usize,
+    orders: Vec<u8>,
+    restart: usize,
+    patterns: Vec<Pattern>,
+    instruments: Vec<Instrument>,
+    // Initial panning of every channel.
+    panning: Vec<u8>,
+    speed: u8,
+    tempo: u8,
+    linear_periods: bool,
+}
+
+impl Module {
+    /// Reads XM modules and 4 to 32 channel MODs with a "M.K." style
+    /// signature.
+    pub fn parse(data: &[u8]) -> Result<Module, ModuleError> {
+        if data.starts_with(xm::MAGIC) {
+            xm::parse(data)
+        } else {
+            protracker::parse(data)
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn channel_count(&self) -> usize {
+        self.channels
+    }
+
+    #[inline]
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+
+    fn pattern(&self, order: usize) -> Option<&Pattern> {
+        self.patterns.get(*self.orders.get(order)? as usize)
+    }
+
+    // Missing patterns play as 64 empty rows.
+    fn row_count(&self, order: usize) -> usize {
+        self.pattern(order).map_or(64, |pattern| pattern.rows)
+    }
+
+    fn cell(&self, order: usize, row: usize, channel: usize) -> Cell {
+        self.pattern(order)
+            .and_then(|pattern| pattern.cells.get(row * self.channels + channel))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn period(&self, note: u8, finetune: i8) -> f32 {
+        let note = (note - 1) as f32 + finetune as f32 / 128.0;
+
+        if self.linear_periods {
+            LINEAR_BASE_PERIOD - (note - BASE_NOTE) * LINEAR_SEMITONE
+        } else {
+            BASE_PERIOD * exp2f((BASE_NOTE - note) / 12.0)
+        }
+    }
+
+    // Playback speed relative to `BASE_RATE`, `arpeggio` is in semitones.
+    fn pitch(&self, period: f32, arpeggio: u8) -> f32 {
+        if self.linear_periods {
+            exp2f((LINEAR_BASE_PERIOD - period) / (12.0 * LINEAR_SEMITONE) + arpeggio as f32 / 12.0)
+        } else {
+            BASE_PERIOD / period.max(1.0) * exp2f(arpeggio as f32 / 12.0)
+        }
+    }
+
+    // Period units per effect unit, linear periods are four times finer.
+    fn period_scale(&self) -> f32 {
+        if self.linear_periods {
+            4.0
+        } else {
+            1.0
+        }
+    }
+}
+
+#[derive(Default)]
+struct Channel {
+    voice: Option<Voice>,
+    // 1 based like in the patterns.
+    instrument: usize,
+    sample: Option<(usize, usize)>,
+    cell: Cell,
+    note: u8,
+    finetune: i8,
+    period: f32,
+    target_period: f32,
+    volume: i32,
+    panning: i32,
+    key_on: bool,
+    fadeout: i32,
+    volume_envelope_tick: u16,
+    panning_envelope_tick: u16,
+
+    // Recalculated every tick.
+    arpeggio: u8,
+    vibrato_offset: f32,
+    tremolo_offset: i32,
+
+    vibrato_position: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    tremolo_position: u8,
+    tremolo_speed: u8,
+    tremolo_depth: u8,
+    loop_row: usize,
+    loop_count: u8,
+    retrigger_ticks: u8,
+
+    // Parameters remembered for when an effect is given without one.
+    porta_speed: u8,
+    porta_up: u8,
+    porta_down: u8,
+    fine_porta_up: u8,
+    fine_porta_down: u8,
+    extra_fine_porta_up: u8,
+    extra_fine_porta_down: u8,
+    volume_slide: u8,
+    fine_volume_up: u8,
+    fine_volume_down: u8,
+    panning_slide: u8,
+    global_volume_slide: u8,
+    sample_offset: u8,
+    retrigger: u8,
+}
+
+impl Channel {
+    // The instrument of the sample playing, which can differ from the last
+    // instrument given.
+    fn playing_instrument<'a>(&self, module: &'a Module) -> Option<&'a Instrument> {
+        let (instrument, _) = self.sample?;
+        module.instruments.get(instrument)
+    }
+
+    fn slide_volume(&mut self, param: u8) {
+        self.volume = slide(self.volume, param, MAX_VOLUME);
+    }
+
+    fn key_off(&mut self, module: &Module) {
+        self.key_on = false;
+
+        // Without an envelope there is nothing to release.
+        let has_envelope = matches!(
+            self.playing_instrument(module),
+            Some(instrument) if instrument.volume_envelope.is_enabled()
+        );
+
+        if !has_envelope {
+            self.volume = 0;
+        }
+    }
+
+    fn vibrato(&mut self, module: &Module) {
+        let delta = wave(self.vibrato_position) * self.vibrato_depth as i32 / 128;
+        self.vibrato_offset = delta as f32 * module.period_scale();
+        self.vibrato_position = self.vibrato_position.wrapping_add(self.vibrato_speed) & 63;
+    }
+
+    fn tremolo(&mut self) {
+        self.tremolo_offset = wave(self.tremolo_position) * self.tremolo_depth as i32 / 64;
+        self.tremolo_position = self.tremolo_position.wrapping_add(self.tremolo_speed) & 63;
+    }
+
+    fn tone_portamento(&mut self, module: &Module) {
+        let speed = self.porta_speed as f32 * module.period_scale();
+
+        if self.period < self.target_period {
+            self.period = (self.period + speed).min(self.target_period);
+        } else {
+            self.period = (self.period - speed).max(self.target_period);
+        }
+    }
+
+    // True every `interval` ticks after the row is read.
+    fn count_retrigger(&mut self, interval: u8) -> bool {
+        self.retrigger_ticks += 1;
+        if interval == 0 || self.retrigger_ticks < interval {
+            return false;
+        }
+
+        self.retrigger_ticks = 0;
+        true
+    }
+
+    fn slide_period(&mut self, amount: f32) {
+        self.period = clamp(self.period + amount, 1.0, 2.0 * LINEAR_BASE_PERIOD);
+    }
+}
+
+#[inline]
+fn clamp<T: PartialOrd>(value: T, min: T, max: T) -> T {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}
+
+// The high nibble of `param` slides up, otherwise the low nibble slides down.
+fn slide(value: i32, param: u8, max: i32) -> i32 {
+    let up = (param >> 4) as i32;
+    let down = (param & 0xf) as i32;
+
+    if up != 0 {
+        (value + up).min(max)
+    } else {
+        (value - down).max(0)
+    }
+}
+
+// Sine from -255 to 255 over 64 steps.
+fn wave(position: u8) -> i32 {
+    let value = VIBRATO_TABLE[(position & 31) as usize] as i32;
+
+    if position & 32 == 0 {
+        value
+    } else {
+        -value
+    }
+}
+
+// Remembers `param` unless it is 0, and returns what is remembered.
+fn remember(memory: &mut u8, param: u8) -> u8 {
+    if param != 0 {
+        *memory = param;
+    }
+
+    *memory
+}
+
+/// Steps through a `Module` and drives one `Mixer` voice per channel. The
+/// mixer can play sound effects on its other voices at the same time.
+pub struct Player {
+    module: Module,
+    channels: Vec<Channel>,
+    order: usize,
+    row: usize,
+    tick: u8,
+    speed: u8,
+    tempo: u8,
+    global_volume: i32,
+    volume: f32,
+    pattern_delay: u8,
+    // Set while a delayed row repeats, its notes are not played again.
+    row_repeated: bool,
+    jump_order: Option<usize>,
+    break_row: Option<usize>,
+    loop_row: Option<usize>,
+    looped: bool,
+    // Output frames until the next tick, and the fraction of a frame carried
+    // over between ticks.
+    frames_left: usize,
+    frame_remainder: u32,
+}
+
+impl Player {
+    pub fn new(module: Module) -> Player {
+        let channels = module
+            .panning
+            .iter()
+            .map(|&panning| Channel {
+                panning: panning as i32,
+                ..Channel::default()
+            })
+            .collect();
+
+        Player {
+            speed: module.speed,
+            tempo: module.tempo,
+            module,
+            channels,
+            order: 0,
+            row: 0,
+            tick: 0,
+            global_volume: MAX_VOLUME,
+            volume: 1.0,
+            pattern_delay: 0,
+            row_repeated: false,
+            jump_order: None,
+            break_row: None,
+            loop_row: None,
+            looped: false,
+            frames_left: 0,
+            frame_remainder: 0,
+        }
+    }
+
+    #[inline]
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Music volume on top of the song's own, 0.0 to 2.0.
+    #[inline]
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    /// The order and row playing now.
+    #[inline]
+    pub fn position(&self) -> (usize, usize) {
+        (self.order, self.row)
+    }
+
+    /// True once the song has reached its end or jumped back, it keeps
+    /// playing from the restart position.
+    #[inline]
+    pub fn has_looped(&self) -> bool {
+        self.looped
+    }
+
+    /// Stops the channels' voices, `render` starts them again.
+    pub fn stop(&mut self, mixer: &mut Mixer) {
+        for channel in self.channels.iter_mut() {
+            if let Some(voice) = channel.voice.take() {
+                mixer.stop(voice);
+            }
+        }
+    }
+
+    /// Mixes the next interleaved stereo frames into `out`, advancing the
+    /// song at the mixer's sample rate. Use in place of `Mixer::mix`.
+    pub fn render(&mut self, mixer: &mut Mixer, out: &mut [i16]) {
+        let mut out = out;
+
+        while out.len() >= 2 {
+            if self.frames_left == 0 {
+                self.update(mixer);
+
+                // A tick is 2.5 / tempo seconds.
+                let frames = mixer.sample_rate() * 5 + self.frame_remainder;
+                let divisor = 2 * self.tempo.max(1) as u32;
+                self.frames_left = (frames / divisor) as usize;
+                self.frame_remainder = frames % divisor;
+            }
+
+            let frames = self.frames_left.min(out.len() / 2);
+            let (now, rest) = out.split_at_mut(2 * frames);
+            mixer.mix(now);
+            out = rest;
+            self.frames_left -= frames;
+        }
+    }
+
+    fn update(&mut self, mixer: &mut Mixer) {
+        if self.tick == 0 && !self.row_repeated {
+            self.play_row(mixer);
+        } else {
+            self.update_effects(mixer);
+        }
+
+        self.update_voices(mixer);
+
+        self.tick += 1;
+        if self.tick >= self.speed {
+            self.tick = 0;
+            self.next_row();
+        }
+    }
+
+    fn play_row(&mut self, mixer: &mut Mixer) {
+        for index in 0..self.channels.len() {
+            let cell = self.module.cell(self.order, self.row, index);
+            self.channels[index].cell = cell;
+            self.channels[index].arpeggio = 0;
+            self.channels[index].vibrato_offset = 0.0;
+            self.channels[index].tremolo_offset = 0;
+            self.channels[index].retrigger_ticks = 0;
+
+            let module = &self.module;
+            let channel = &mut self.channels[index];
+
+            if cell.instrument != 0
+                && module
+                    .instruments
+                    .get(cell.instrument as usize - 1)
+                    .is_some()
+            {
+                channel.instrument = cell.instrument as usize;
+            }
+
+            let tone_portamento = matches!(cell.effect, 0x3 | 0x5) || cell.volume >> 4 == 0xf;
+            let note_delay = cell.effect == 0xe && cell.param >> 4 == 0xd && cell.param & 0xf != 0;
+
+            if cell.note == NOTE_OFF {
+                channel.key_off(module);
+            } else if cell.note != 0 && tone_portamento {
+                if let Some((note, finetune)) = channel_note(module, channel, cell.note) {
+                    channel.target_period = module.period(note, finetune);
+                }
+            } else if cell.note != 0 && !note_delay {
+                trigger(module, channel, mixer, cell.note);
+            }
+
+            if cell.instrument != 0 && cell.note != NOTE_OFF && !note_delay {
+                reset_instrument(module, channel);
+            }
+
+            self.volume_column(index, true);
+            self.row_effect(index, mixer);
+        }
+    }
+
+    // The XM volume column, `first_tick` is when the row is read.
+    fn volume_column(&mut self, index: usize, first_tick: bool) {
+        let module = &self.module;
+        let channel = &mut self.channels[index];
+        let value = channel.cell.volume;
+        let param = value & 0xf;
+
+        match (value >> 4, first_tick) {
+            (0x1..=0x4, true) => channel.volume = (value - 0x10) as i32,
+            (0x5, true) => channel.volume = MAX_VOLUME,
+            (0x6, false) => channel.volume = (channel.volume - param as i32).max(0),
+            (0x7, false) => channel.volume = (channel.volume + param as i32).min(MAX_VOLUME),
+            (0x8, true) => channel.volume = (channel.volume - param as i32).max(0),
+            (0x9, true) => channel.volume = (channel.volume + param as i32).min(MAX_VOLUME),
+            (0xa, true) => channel.vibrato_speed = param,
+            (0xb, true) if param != 0 => channel.vibrato_depth = param,
+            (0xb, false) => channel.vibrato(module),
+            (0xc, true) => channel.panning = (param << 4) as i32,
+            (0xd, false) => channel.panning = (channel.panning - param as i32).max(0),
+            (0xe, false) => channel.panning = (channel.panning + param as i32).min(255),
+            (0xf, true) if param != 0 => channel.porta_speed = param << 4,
+            (0xf, false) => channel.tone_portamento(module),
+            _ => (),
+        }
+    }
+
+    // Effects that act when the row is read.
+    fn row_effect(&mut self, index: usize, mixer: &mut Mixer) {
+        let module = &self.module;
+        let channel = &mut self.channels[index];
+        let Cell { effect, param, .. } = channel.cell;
+        let (x, y) = (param >> 4, param & 0xf);
+        let scale = module.period_scale();
+
+        match effect {
+            0x1 => {
+                remember(&mut channel.porta_up, param);
+            }
+            0x2 => {
+                remember(&mut channel.porta_down, param);
+            }
+            0x3 => {
+                remember(&mut channel.porta_speed, param);
+            }
+            0x4 => {
+                if x != 0 {
+                    channel.vibrato_speed = x;
+                }
+                if y != 0 {
+                    channel.vibrato_depth = y;
+                }
+            }
+            0x5 | 0x6 | 0xa => {
+                remember(&mut channel.volume_slide, param);
+            }
+            0x7 => {
+                if x != 0 {
+                    channel.tremolo_speed = x;
+                }
+                if y != 0 {
+                    channel.tremolo_depth = y;
+                }
+            }
+            0x8 => channel.panning = param as i32,
+            0x9 => {
+                let offset = remember(&mut channel.sample_offset, param) as usize * 256;
+                if channel.cell.note != 0 && channel.cell.note != NOTE_OFF {
+                    if let Some(voice) = channel.voice {
+                        mixer.set_position(voice, offset);
+                    }
+                }
+            }
+            0xb => {
+                self.jump_order = Some(param as usize);
+            }
+            0xc => channel.volume = (param as i32).min(MAX_VOLUME),
+            0xd => {
+                // The row is in decimal.
+                self.break_row = Some(x as usize * 10 + y as usize);
+            }
+            0xe => match x {
+                0x1 => {
+                    let amount = remember(&mut channel.fine_porta_up, y) as f32;
+                    channel.slide_period(-amount * scale);
+                }
+                0x2 => {
+                    let amount = remember(&mut channel.fine_porta_down, y) as f32;
+                    channel.slide_period(amount * scale);
+                }
+                0x6 => {
+                    if y == 0 {
+                        channel.loop_row = self.row;
+                    } else {
+                        if channel.loop_count == 0 {
+                            channel.loop_count = y;
+                        } else {
+                            channel.loop_count -= 1;
+                        }
+
+                        if channel.loop_count != 0 {
+                            self.loop_row = Some(channel.loop_row);
+                        }
+                    }
+                }
+                0x8 => channel.panning = (y << 4) as i32,
+                0xa => {
+                    let amount = remember(&mut channel.fine_volume_up, y) as i32;
+                    channel.volume = (channel.volume + amount).min(MAX_VOLUME);
+                }
+                0xb => {
+                    let amount = remember(&mut channel.fine_volume_down, y) as i32;
+                    channel.volume = (channel.volume - amount).max(0);
+                }
+                0xc if y == 0 => channel.volume = 0,
+                0xe if self.pattern_delay == 0 => self.pattern_delay = y,
+                _ => (),
+            },
+            0xf if param != 0 => {
+                if param < 0x20 {
+                    self.speed = param;
+                } else {
+                    self.tempo = param;
+                }
+            }
+            // G, set global volume.
+            0x10 => self.global_volume = (param as i32).min(MAX_VOLUME),
+            // H, global volume slide.
+            0x11 => {
+                remember(&mut channel.global_volume_slide, param);
+            }
+            // K, key off.
+            0x14 if param == 0 => channel.key_off(module),
+            // L, set envelope position.
+            0x15 => {
+                channel.volume_envelope_tick = param as u16;
+                channel.panning_envelope_tick = param as u16;
+            }
+            // P, panning slide.
+            0x19 => {
+                remember(&mut channel.panning_slide, param);
+            }
+            // R, retrigger with volume change, only the retrigger is done.
+            0x1b => {
+                remember(&mut channel.retrigger, param);
+            }
+            // X1 and X2, extra fine portamento.
+            0x21 => match x {
+                1 => {
+                    let amount = remember(&mut channel.extra_fine_porta_up, y) as f32;
+                    channel.slide_period(-amount * scale / 4.0);
+                }
+                2 => {
+                    let amount = remember(&mut channel.extra_fine_porta_down, y) as f32;
+                    channel.slide_period(amount * scale / 4.0);
+                }
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    // Effects that act on the ticks after the row is read.
+    fn update_effects(&mut self, mixer: &mut Mixer) {
+        for index in 0..self.channels.len() {
+            self.channels[index].arpeggio = 0;
+            self.channels[index].vibrato_offset = 0.0;
+            self.channels[index].tremolo_offset = 0;
+
+            self.volume_column(index, false);
+
+            let tick = self.tick;
+            let module = &self.module;
+            let channel = &mut self.channels[index];
+            let Cell { effect, param, .. } = channel.cell;
+            let (x, y) = (param >> 4, param & 0xf);
+            let scale = module.period_scale();
+
+            match effect {
+                0x0 if param != 0 => channel.arpeggio = [0, x, y][tick as usize % 3],
+                0x1 => channel.slide_period(-(channel.porta_up as f32) * scale),
+                0x2 => channel.slide_period(channel.porta_down as f32 * scale),
+                0x3 => channel.tone_portamento(module),
+                0x4 => channel.vibrato(module),
+                0x5 => {
+                    channel.tone_portamento(module);
+                    channel.slide_volume(channel.volume_slide);
+                }
+                0x6 => {
+                    channel.vibrato(module);
+                    channel.slide_volume(channel.volume_slide);
+                }
+                0x7 => channel.tremolo(),
+                0xa => channel.slide_volume(channel.volume_slide),
+                0xe => match x {
+                    0x9 if channel.count_retrigger(y) => {
+                        let note = channel.cell.note;
+                        if note != 0 && note != NOTE_OFF {
+                            trigger(module, channel, mixer, note);
+                        }
+                    }
+                    0xc if tick == y => channel.volume = 0,
+                    0xd if tick == y && !self.row_repeated => {
+                        let note = channel.cell.note;
+                        if note != 0 && note != NOTE_OFF {
+                            trigger(module, channel, mixer, note);
+                            if channel.cell.instrument != 0 {
+                                reset_instrument(module, channel);
+                            }
+                        }
+                    }
+                    _ => (),
+                },
+                0x11 => {
+                    self.global_volume =
+                        slide(self.global_volume, channel.global_volume_slide, MAX_VOLUME);
+                }
+                0x14 if tick == param => channel.key_off(module),
+                0x19 => channel.panning = slide(channel.panning, channel.panning_slide, 255),
+                0x1b if channel.count_retrigger(channel.retrigger & 0xf) => {
+                    retrigger(module, channel, mixer);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn update_voices(&mut self, mixer: &mut Mixer) {
+        let module = &self.module;
+        let global_volume = self.global_volume as f32 / MAX_VOLUME as f32;
+
+        for channel in self.channels.iter_mut() {
+            let voice = match channel.voice {
+                Some(voice) if mixer.is_playing(voice) => voice,
+                _ => continue,
+            };
+
+            let instrument = match channel.playing_instrument(module) {
+                Some(instrument) => instrument,
+                None => continue,
+            };
+
+            if !channel.key_on && channel.fadeout == 0 {
+                mixer.stop(voice);
+                channel.voice = None;
+                continue;
+            }
+
+            let mut volume = clamp(channel.volume + channel.tremolo_offset, 0, MAX_VOLUME);
+            let mut panning = channel.panning;
+
+            if instrument.volume_envelope.is_enabled() {
+                let envelope = instrument
+                    .volume_envelope
+                    .value(channel.volume_envelope_tick);
+                volume = volume * envelope / MAX_VOLUME * channel.fadeout / MAX_FADEOUT;
+
+                channel.volume_envelope_tick = instrument
+                    .volume_envelope
+                    .advance(channel.volume_envelope_tick, channel.key_on);
+
+                if !channel.key_on {
+                    channel.fadeout = (channel.fadeout - instrument.fadeout as i32).max(0);
+                }
+            }
+
+            if instrument.panning_envelope.is_enabled() {
+                // Swings up to the nearest edge, the envelope is centered at 32.
+                let envelope = instrument
+                    .panning_envelope
+                    .value(channel.panning_envelope_tick);
+                let range = CENTER_PAN - (panning - CENTER_PAN).abs();
+                panning += (envelope - 32) * range / 32;
+
+                channel.panning_envelope_tick = instrument
+                    .panning_envelope
+                    .advance(channel.panning_envelope_tick, channel.key_on);
+            }
+
+            let period = channel.period + channel.vibrato_offset;
+            mixer.set_pitch(voice, module.pitch(period, channel.arpeggio));
+            mixer.set_volume(
+                voice,
+                volume as f32 / MAX_VOLUME as f32 * global_volume * self.volume,
+            );
+            mixer.set_pan(voice, (panning - CENTER_PAN) as f32 / CENTER_PAN as f32);
+        }
+    }
+
+    fn next_row(&mut self) {
+        if self.pattern_delay > 0 {
+            self.pattern_delay -= 1;
+            self.row_repeated = true;
+            return;
+        }
+
+        self.row_repeated = false;
+
+        if let Some(row) = self.loop_row.take() {
+            self.row = row;
+            self.jump_order = None;
+            self.break_row = None;
+            return;
+        }
+
+        let jump_order = self.jump_order.take();
+        let break_row = self.break_row.take();
+
+        if jump_order.is_some() || break_row.is_some() {
+            let order = jump_order.unwrap_or(self.order + 1);
+            if order <= self.order {
+                self.looped = true;
+            }
+
+            self.order = order;
+            self.row = break_row.unwrap_or(0);
+        } else {
+            self.row += 1;
+            if self.row >= self.module.row_count(self.order) {
+                self.row = 0;
+                self.order += 1;
+            }
+        }
+
+        if self.order >= self.module.orders.len() {
+            self.order = self.module.restart;
+            self.looped = true;
+        }
+
+        if self.row >= self.module.row_count(self.order) {
+            self.row = 0;
+        }
+
+        // Pattern loops start over in every pattern.
+        if self.row == 0 {
+            for channel in self.channels.iter_mut() {
+                channel.loop_row = 0;
+            }
+        }
+    }
+}
+
+// The note with the sample's relative note and its finetune.
+fn channel_note(module: &Module, channel: &Channel, note: u8) -> Option<(u8, i8)> {
+    let instrument = module.instruments.get(channel.instrument.wrapping_sub(1))?;
+    let sample_index = *instrument.keymap.get(note as usize - 1).unwrap_or(&0) as usize;
+    let sample = instrument.samples.get(sample_index)?;
+    let note = clamp(note as i32 + sample.relative_note as i32, 1, 119) as u8;
+    Some((note, sample.finetune))
+}
+
+fn trigger(module: &Module, channel: &mut Channel, mixer: &mut Mixer, note: u8) {
+    let instrument = match module.instruments.get(channel.instrument.wrapping_sub(1)) {
+        Some(instrument) => instrument,
+        None => return,
+    };
+
+    let sample_index = *instrument.keymap.get(note as usize - 1).unwrap_or(&0) as usize;
+    if instrument.samples.get(sample_index).is_none() {
+        return;
+    }
+
+    let (real_note, finetune) = channel_note(module, channel, note).unwrap();
+    channel.sample = Some((channel.instrument - 1, sample_index));
+    channel.note = real_note;
+    channel.finetune = finetune;
+    channel.period = module.period(real_note, finetune);
+    channel.target_period = channel.period;
+    channel.vibrato_position = 0;
+    channel.tremolo_position = 0;
+    channel.key_on = true;
+    channel.fadeout = MAX_FADEOUT;
+    channel.volume_envelope_tick = 0;
+    channel.panning_envelope_tick = 0;
+
+    retrigger(module, channel, mixer);
+}
+
+// Starts the channel's sample again from the beginning.
+fn retrigger(module: &Module, channel: &mut Channel, mixer: &mut Mixer) {
+    if let Some(voice) = channel.voice.take() {
+        mixer.stop(voice);
+    }
+
+    let (instrument, sample) = match channel.sample {
+        Some(sample) => sample,
+        None => return,
+    };
+
+    if let Some(sample) = &module.instruments[instrument].samples[sample].sample {
+        channel.voice = mixer.play(sample);
+
+        // Silent until `update_voices` sets it up at the end of the tick.
+        if let Some(voice) = channel.voice {
+            mixer.set_volume(voice, 0.0);
+        }
+    }
+}
+
+// An instrument number resets the volume and panning to the sample's.
+fn reset_instrument(module: &Module, channel: &mut Channel) {
+    if let Some((instrument, sample)) = channel.sample {
+        let sample = &module.instruments[instrument].samples[sample];
+        channel.volume = sample.volume as i32;
+        if let Some(panning) = sample.panning {
+            channel.panning = panning as i32;
+        }
+    }
+}
+
+/// Plays `module` once from the start into interleaved stereo, stopping when
+/// the song loops or after 30 minutes.
+pub fn render(module: Module, sample_rate: u32) -> Vec<i16> {
+    let mut mixer = Mixer::new(module.channel_count(), sample_rate);
+    let mut player = Player::new(module);
+    let mut res = Vec::new();
+    let mut buffer = Vec::new();
+
+    // A tick at a time so the song stops exactly at its end.
+    while res.len() < 2 * (MAX_RENDER_SECONDS * sample_rate) as usize {
+        if player.frames_left == 0 && player.has_looped() {
+            break;
+        }
+
+        buffer.clear();
+        buffer.resize(2 * player.frames_left.max(1), 0);
+        player.render(&mut mixer, &mut buffer);
+        res.extend_from_slice(&buffer);
+    }
+
+    res
+}
+
+/// `render` as a 16 bit stereo WAV file.
+pub fn render_wav(module: Module, sample_rate: u32) -> Vec<u8> {
+    wav::write(&render(module, sample_rate), 2, sample_rate)
+}
@@ -0,0 +1,164 @@
+//! ProTracker MODs: 31 samples of 8 bit data, 64 row patterns and a four
+//! letter signature that gives the channel count.
+
+use super::{
+    clamp, Cell, Instrument, InstrumentSample, Module, ModuleError, Pattern, BASE_NOTE,
+    BASE_PERIOD, DEFAULT_SPEED, DEFAULT_TEMPO, MAX_CHANNELS, MAX_NOTE,
+};
+use alloc::{string::String, vec, vec::Vec};
+use libm::{log2f, roundf};
+
+const SAMPLE_COUNT: usize = 31;
+const SAMPLE_HEADER_SIZE: usize = 30;
+const SONG_LENGTH_OFFSET: usize = 950;
+const ORDERS_OFFSET: usize = 952;
+const MAX_ORDERS: usize = 128;
+const SIGNATURE_OFFSET: usize = 1080;
+const PATTERNS_OFFSET: usize = 1084;
+const ROWS: usize = 64;
+
+// Amiga style, left and right channels alternate. Not all the way to the side
+// so headphones don't sound odd.
+const LEFT: u8 = 0x40;
+const RIGHT: u8 = 0xc0;
+
+fn channel_count(signature: &[u8]) -> Option<usize> {
+    match signature {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => Some(4),
+        b"6CHN" => Some(6),
+        b"8CHN" | b"FLT8" | b"OCTA" | b"CD81" => Some(8),
+        [a, b, b'C', b'H'] | [a, b, b'C', b'N'] if a.is_ascii_digit() && b.is_ascii_digit() => {
+            Some(((a - b'0') * 10 + (b - b'0')) as usize)
+        }
+        [a, b'C', b'H', b'N'] if a.is_ascii_digit() => Some((a - b'0') as usize),
+        _ => None,
+    }
+}
+
+#[inline]
+fn u16_at(data: &[u8], offset: usize) -> usize {
+    u16::from_be_bytes([data[offset], data[offset + 1]]) as usize
+}
+
+fn name(data: &[u8]) -> String {
+    data.iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect()
+}
+
+// The note an Amiga period plays, 428 is C-4.
+fn period_to_note(period: usize) -> u8 {
+    if period == 0 {
+        return 0;
+    }
+
+    let note = roundf(12.0 * log2f(BASE_PERIOD / period as f32)) + BASE_NOTE + 1.0;
+    if note < 1.0 {
+        1
+    } else if note < MAX_NOTE as f32 {
+        note as u8
+    } else {
+        MAX_NOTE
+    }
+}
+
+pub(super) fn parse(data: &[u8]) -> Result<Module, ModuleError> {
+    let signature = data
+        .get(SIGNATURE_OFFSET..PATTERNS_OFFSET)
+        .ok_or(ModuleError::UnknownFormat)?;
+    let channels = channel_count(signature).ok_or(ModuleError::UnknownFormat)?;
+
+    if channels == 0 || channels > MAX_CHANNELS {
+        return Err(ModuleError::TooManyChannels);
+    }
+
+    let song_length = clamp(data[SONG_LENGTH_OFFSET] as usize, 1, MAX_ORDERS);
+    let restart = data[SONG_LENGTH_OFFSET + 1] as usize;
+    let orders = data[ORDERS_OFFSET..ORDERS_OFFSET + song_length].to_vec();
+
+    // Patterns that are in the order table but not played are stored too.
+    let pattern_count = data[ORDERS_OFFSET..ORDERS_OFFSET + MAX_ORDERS]
+        .iter()
+        .max()
+        .map_or(0, |&max| max as usize + 1);
+
+    let pattern_size = ROWS * channels * 4;
+    let mut patterns = Vec::with_capacity(pattern_count);
+
+    for index in 0..pattern_count {
+        let start = PATTERNS_OFFSET + index * pattern_size;
+        let bytes = data
+            .get(start..start + pattern_size)
+            .ok_or(ModuleError::UnexpectedEnd)?;
+
+        let cells = bytes
+            .chunks_exact(4)
+            .map(|cell| Cell {
+                note: period_to_note(((cell[0] as usize & 0xf) << 8) | cell[1] as usize),
+                instrument: (cell[0] & 0xf0) | (cell[2] >> 4),
+                volume: 0,
+                effect: cell[2] & 0xf,
+                param: cell[3],
+            })
+            .collect();
+
+        patterns.push(Pattern { rows: ROWS, cells });
+    }
+
+    // Sample data follows the patterns. Files cut short are common, what is
+    // missing is left out.
+    let mut offset = PATTERNS_OFFSET + pattern_count * pattern_size;
+    let mut instruments = Vec::with_capacity(SAMPLE_COUNT);
+
+    for index in 0..SAMPLE_COUNT {
+        let header = &data[20 + index * SAMPLE_HEADER_SIZE..20 + (index + 1) * SAMPLE_HEADER_SIZE];
+        let length = 2 * u16_at(header, 22);
+        let loop_start = 2 * u16_at(header, 26);
+        let loop_length = 2 * u16_at(header, 28);
+
+        let end = (offset + length).min(data.len());
+        let samples = data
+            .get(offset..end)
+            .unwrap_or(&[])
+            .iter()
+            .map(|&byte| (byte as i8 as i16) << 8)
+            .collect();
+        offset += length;
+
+        // A loop of one word is how ProTracker says there is no loop.
+        let loop_type = if loop_length > 2 { 1 } else { 0 };
+        let mut sample = InstrumentSample::new(samples, loop_start, loop_length, loop_type);
+
+        // The finetune is a signed nibble of 1/8 semitones.
+        sample.finetune = ((header[24] & 0xf) << 4) as i8;
+        sample.volume = header[25].min(64);
+
+        instruments.push(Instrument {
+            samples: vec![sample],
+            keymap: vec![0; MAX_NOTE as usize],
+            ..Instrument::default()
+        });
+    }
+
+    Ok(Module {
+        name: name(&data[..20]),
+        channels,
+        restart: if restart < orders.len() { restart } else { 0 },
+        orders,
+        patterns,
+        instruments,
+        panning: (0..channels)
+            .map(|channel| {
+                if channel % 4 == 0 || channel % 4 == 3 {
+                    LEFT
+                } else {
+                    RIGHT
+                }
+            })
+            .collect(),
+        speed: DEFAULT_SPEED,
+        tempo: DEFAULT_TEMPO,
+        linear_periods: false,
+    })
+}
@@ -0,0 +1,276 @@
+//! FastTracker 2 XMs: packed patterns, instruments with several samples and
+//! envelopes, and delta coded 8 or 16 bit sample data. All little endian.
+
+use super::{
+    clamp, Cell, Envelope, Instrument, InstrumentSample, Module, ModuleError, Pattern,
+    MAX_CHANNELS, MAX_NOTE, NOTE_OFF,
+};
+use alloc::{string::String, vec::Vec};
+use core::convert::TryInto;
+
+pub(super) const MAGIC: &[u8] = b"Extended Module: ";
+
+const HEADER_SIZE_OFFSET: usize = 60;
+const MAX_ORDERS: usize = 256;
+const MAX_ENVELOPE_POINTS: usize = 12;
+
+// Instrument header offsets, the sample part is only there when the
+// instrument has samples.
+const INSTRUMENT_SAMPLE_COUNT: usize = 27;
+const INSTRUMENT_SAMPLE_HEADER_SIZE: usize = 29;
+const INSTRUMENT_KEYMAP: usize = 33;
+const INSTRUMENT_VOLUME_POINTS: usize = 129;
+const INSTRUMENT_PANNING_POINTS: usize = 177;
+const INSTRUMENT_ENVELOPES: usize = 225;
+const INSTRUMENT_FADEOUT: usize = 239;
+
+const FLAG_LINEAR_PERIODS: u16 = 1;
+
+const ENVELOPE_ON: u8 = 1;
+const ENVELOPE_SUSTAIN: u8 = 2;
+const ENVELOPE_LOOP: u8 = 4;
+
+const SAMPLE_LOOP_MASK: u8 = 3;
+const SAMPLE_16_BIT: u8 = 16;
+
+fn bytes(data: &[u8], offset: usize, len: usize) -> Result<&[u8], ModuleError> {
+    data.get(offset..offset.saturating_add(len))
+        .ok_or(ModuleError::UnexpectedEnd)
+}
+
+#[inline]
+fn u8_at(data: &[u8], offset: usize) -> Result<u8, ModuleError> {
+    Ok(bytes(data, offset, 1)?[0])
+}
+
+#[inline]
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, ModuleError> {
+    Ok(u16::from_le_bytes(
+        bytes(data, offset, 2)?.try_into().unwrap(),
+    ))
+}
+
+#[inline]
+fn u32_at(data: &[u8], offset: usize) -> Result<usize, ModuleError> {
+    Ok(u32::from_le_bytes(bytes(data, offset, 4)?.try_into().unwrap()) as usize)
+}
+
+fn name(data: &[u8]) -> String {
+    let name = data
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect::<String>();
+
+    String::from(name.trim_end())
+}
+
+fn parse_pattern(
+    data: &[u8],
+    offset: usize,
+    channels: usize,
+) -> Result<(Pattern, usize), ModuleError> {
+    let header_size = u32_at(data, offset)?;
+    let rows = u16_at(data, offset + 5)? as usize;
+    let packed_size = u16_at(data, offset + 7)? as usize;
+    let packed = bytes(data, offset + header_size, packed_size)?;
+
+    let mut cells = Vec::with_capacity(rows * channels);
+    let mut packed = packed.iter().copied();
+
+    // Empty patterns have no data at all.
+    while packed_size != 0 && cells.len() < rows * channels {
+        let first = packed.next().ok_or(ModuleError::BadPattern)?;
+
+        // The high bit says which fields follow, otherwise all five do and
+        // the first is the note.
+        let mask = if first & 0x80 != 0 { first } else { 0x1f };
+        let mut field = |bit: u8, first_field: bool| -> Result<u8, ModuleError> {
+            if first_field && first & 0x80 == 0 {
+                Ok(first)
+            } else if mask & bit != 0 {
+                packed.next().ok_or(ModuleError::BadPattern)
+            } else {
+                Ok(0)
+            }
+        };
+
+        let note = field(0x01, true)?;
+        cells.push(Cell {
+            note: if note <= MAX_NOTE || note == NOTE_OFF {
+                note
+            } else {
+                0
+            },
+            instrument: field(0x02, false)?,
+            volume: field(0x04, false)?,
+            effect: field(0x08, false)?,
+            param: field(0x10, false)?,
+        });
+    }
+
+    cells.resize(rows * channels, Cell::default());
+
+    Ok((Pattern { rows, cells }, offset + header_size + packed_size))
+}
+
+fn parse_envelope(header: &[u8], points: usize, index: usize) -> Result<Envelope, ModuleError> {
+    let count = u8_at(header, INSTRUMENT_ENVELOPES + index)? as usize;
+    let sustain = u8_at(header, INSTRUMENT_ENVELOPES + 2 + 3 * index)? as usize;
+    let loop_start = u8_at(header, INSTRUMENT_ENVELOPES + 3 + 3 * index)? as usize;
+    let loop_end = u8_at(header, INSTRUMENT_ENVELOPES + 4 + 3 * index)? as usize;
+    let flags = u8_at(header, INSTRUMENT_ENVELOPES + 8 + index)?;
+
+    if flags & ENVELOPE_ON == 0 || count == 0 || count > MAX_ENVELOPE_POINTS {
+        return Ok(Envelope::default());
+    }
+
+    let mut envelope = Envelope::default();
+
+    for point in 0..count {
+        let tick = u16_at(header, points + 4 * point)?;
+        let value = u16_at(header, points + 4 * point + 2)?.min(64) as u8;
+        envelope.points.push((tick, value));
+    }
+
+    if flags & ENVELOPE_SUSTAIN != 0 && sustain < count {
+        envelope.sustain = Some(sustain);
+    }
+
+    if flags & ENVELOPE_LOOP != 0 && loop_start <= loop_end && loop_end < count {
+        envelope.loop_range = Some((loop_start, loop_end));
+    }
+
+    Ok(envelope)
+}
+
+// Returns the instrument and the offset after its sample data.
+fn parse_instrument(data: &[u8], offset: usize) -> Result<(Instrument, usize), ModuleError> {
+    let header_size = u32_at(data, offset)?;
+    let header = bytes(data, offset, header_size)?;
+    let sample_count = if header_size > INSTRUMENT_SAMPLE_COUNT + 1 {
+        u16_at(header, INSTRUMENT_SAMPLE_COUNT)? as usize
+    } else {
+        0
+    };
+
+    let mut offset = offset + header_size;
+
+    if sample_count == 0 {
+        return Ok((Instrument::default(), offset));
+    }
+
+    let sample_header_size = u32_at(header, INSTRUMENT_SAMPLE_HEADER_SIZE)?;
+    let keymap = bytes(header, INSTRUMENT_KEYMAP, MAX_NOTE as usize)?.to_vec();
+    let volume_envelope = parse_envelope(header, INSTRUMENT_VOLUME_POINTS, 0)?;
+    let panning_envelope = parse_envelope(header, INSTRUMENT_PANNING_POINTS, 1)?;
+    let fadeout = u16_at(header, INSTRUMENT_FADEOUT)?;
+
+    // All the sample headers come first, then all the data.
+    let mut headers = Vec::with_capacity(sample_count);
+    for _ in 0..sample_count {
+        headers.push(bytes(data, offset, sample_header_size.max(18))?);
+        offset += sample_header_size;
+    }
+
+    let mut samples = Vec::with_capacity(sample_count);
+
+    for header in headers {
+        let length = u32_at(header, 0)?;
+        let mut loop_start = u32_at(header, 4)?;
+        let mut loop_length = u32_at(header, 8)?;
+        let flags = header[14];
+
+        let bytes = bytes(data, offset, length)?;
+        offset += length;
+
+        let data = if flags & SAMPLE_16_BIT != 0 {
+            loop_start /= 2;
+            loop_length /= 2;
+
+            let mut value = 0i16;
+            bytes
+                .chunks_exact(2)
+                .map(|delta| {
+                    value = value.wrapping_add(i16::from_le_bytes([delta[0], delta[1]]));
+                    value
+                })
+                .collect()
+        } else {
+            let mut value = 0i8;
+            bytes
+                .iter()
+                .map(|&delta| {
+                    value = value.wrapping_add(delta as i8);
+                    (value as i16) << 8
+                })
+                .collect()
+        };
+
+        let mut sample =
+            InstrumentSample::new(data, loop_start, loop_length, flags & SAMPLE_LOOP_MASK);
+        sample.volume = header[12].min(64);
+        sample.finetune = header[13] as i8;
+        sample.panning = Some(header[15]);
+        sample.relative_note = header[16] as i8;
+
+        samples.push(sample);
+    }
+
+    Ok((
+        Instrument {
+            samples,
+            keymap,
+            volume_envelope,
+            panning_envelope,
+            fadeout,
+        },
+        offset,
+    ))
+}
+
+pub(super) fn parse(data: &[u8]) -> Result<Module, ModuleError> {
+    let header_size = u32_at(data, HEADER_SIZE_OFFSET)?;
+    let song_length = (u16_at(data, 64)? as usize).min(MAX_ORDERS);
+    let restart = u16_at(data, 66)? as usize;
+    let channels = u16_at(data, 68)? as usize;
+    let pattern_count = u16_at(data, 70)? as usize;
+    let instrument_count = u16_at(data, 72)? as usize;
+    let flags = u16_at(data, 74)?;
+    let speed = u16_at(data, 76)?;
+    let tempo = u16_at(data, 78)?;
+    let orders = bytes(data, 80, song_length)?.to_vec();
+
+    if channels == 0 || channels > MAX_CHANNELS {
+        return Err(ModuleError::TooManyChannels);
+    }
+
+    let mut offset = HEADER_SIZE_OFFSET + header_size;
+
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        let (pattern, next) = parse_pattern(data, offset, channels)?;
+        patterns.push(pattern);
+        offset = next;
+    }
+
+    let mut instruments = Vec::with_capacity(instrument_count);
+    for _ in 0..instrument_count {
+        let (instrument, next) = parse_instrument(data, offset)?;
+        instruments.push(instrument);
+        offset = next;
+    }
+
+    Ok(Module {
+        name: name(bytes(data, 17, 20)?),
+        channels,
+        restart: if restart < orders.len() { restart } else { 0 },
+        orders,
+        patterns,
+        instruments,
+        panning: (0..channels).map(|_| 128).collect(),
+        speed: clamp(speed, 1, 31) as u8,
+        tempo: clamp(tempo, 32, 255) as u8,
+        linear_periods: flags & FLAG_LINEAR_PERIODS != 0,
+    })
+}
@@ -0,0 +1,167 @@
+//! Reading and writing uncompressed PCM WAV files.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+pub const HEADER_SIZE: usize = 44;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WavError {
+    NotWav,
+    UnexpectedEnd,
+    /// Only 8 and 16 bit PCM is read.
+    UnsupportedFormat,
+}
+
+pub struct Wav {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Interleaved if there is more than one channel.
+    pub samples: Vec<i16>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// The format and where the samples are. Only needs the file up to the
+/// start of the samples, like when streaming.
+#[derive(Copy, Clone, Debug)]
+pub struct Header {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits: u16,
+    pub data_offset: usize,
+    pub data_size: usize,
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Header, WavError> {
+        if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            return Err(WavError::NotWav);
+        }
+
+        let mut format = None;
+        let mut offset = 12;
+
+        while offset + 8 <= data.len() {
+            let id = &data[offset..offset + 4];
+            let size = u32_at(data, offset + 4) as usize;
+
+            if id == b"data" {
+                let (tag, channels, sample_rate, bits) = format.ok_or(WavError::NotWav)?;
+
+                if tag != 1 || (bits != 8 && bits != 16) {
+                    return Err(WavError::UnsupportedFormat);
+                }
+
+                return Ok(Header {
+                    sample_rate,
+                    channels,
+                    bits,
+                    data_offset: offset + 8,
+                    data_size: size,
+                });
+            }
+
+            let body = data
+                .get(offset + 8..offset + 8 + size)
+                .ok_or(WavError::UnexpectedEnd)?;
+
+            if id == b"fmt " {
+                if size < 16 {
+                    return Err(WavError::UnexpectedEnd);
+                }
+
+                // Format, channels, sample rate and bits per sample.
+                format = Some((
+                    u16_at(body, 0),
+                    u16_at(body, 2),
+                    u32_at(body, 4),
+                    u16_at(body, 14),
+                ));
+            }
+
+            // Chunks are padded to an even size.
+            offset += 8 + size + (size & 1);
+        }
+
+        Err(WavError::UnexpectedEnd)
+    }
+}
+
+impl Wav {
+    pub fn parse(data: &[u8]) -> Result<Wav, WavError> {
+        let header = Header::parse(data)?;
+        let body = data
+            .get(header.data_offset..header.data_offset + header.data_size)
+            .ok_or(WavError::UnexpectedEnd)?;
+
+        let samples = if header.bits == 8 {
+            body.iter().map(|&byte| (byte as i16 - 128) << 8).collect()
+        } else {
+            body.chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                .collect()
+        };
+
+        Ok(Wav {
+            sample_rate: header.sample_rate,
+            channels: header.channels,
+            samples,
+        })
+    }
+
+    /// The average of all channels.
+    pub fn to_mono(&self) -> Vec<i16> {
+        let channels = self.channels.max(1) as usize;
+
+        self.samples
+            .chunks_exact(channels)
+            .map(|frame| {
+                let sum = frame.iter().map(|&sample| sample as i32).sum::<i32>();
+                (sum / channels as i32) as i16
+            })
+            .collect()
+    }
+}
+
+/// The header of a 16 bit PCM file with `data_size` bytes of samples. Lets a
+/// file be written as it goes and the header rewritten at the end.
+pub fn header(channels: u16, sample_rate: u32, data_size: u32) -> [u8; HEADER_SIZE] {
+    let block_align = channels * 2;
+    let mut res = [0; HEADER_SIZE];
+
+    res[0..4].copy_from_slice(b"RIFF");
+    res[4..8].copy_from_slice(&(data_size + HEADER_SIZE as u32 - 8).to_le_bytes());
+    res[8..12].copy_from_slice(b"WAVE");
+    res[12..16].copy_from_slice(b"fmt ");
+    res[16..20].copy_from_slice(&16u32.to_le_bytes());
+    res[20..22].copy_from_slice(&1u16.to_le_bytes());
+    res[22..24].copy_from_slice(&channels.to_le_bytes());
+    res[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+    res[28..32].copy_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    res[32..34].copy_from_slice(&block_align.to_le_bytes());
+    res[34..36].copy_from_slice(&16u16.to_le_bytes());
+    res[36..40].copy_from_slice(b"data");
+    res[40..44].copy_from_slice(&data_size.to_le_bytes());
+
+    res
+}
+
+/// A whole 16 bit PCM file, `samples` interleaved if there is more than one
+/// channel.
+pub fn write(samples: &[i16], channels: u16, sample_rate: u32) -> Vec<u8> {
+    let mut res = Vec::with_capacity(HEADER_SIZE + 2 * samples.len());
+    res.extend_from_slice(&header(channels, sample_rate, 2 * samples.len() as u32));
+
+    for sample in samples {
+        res.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    res
+}
@@ -10,5 +10,5 @@ pub mod rand;
 pub use aabb2::Aabb2;
 pub use color::Color;
 pub use hash::{BuildFnvHasher, FnvHasher};
-pub use rand::{random_f32, random_f64, random_u32, random_u64};
+pub use rand::{random_f32, random_f64, random_u32, random_u64, seed_random};
 pub use vec2::Vec2;
@@ -2,6 +2,9 @@ use core::mem;
 use core::num::Wrapping;
 use spin::Mutex;
 
+/// Seed of `Rng::new_unseeded` and of the global generator at startup.
+pub const DEFAULT_SEED: u32 = 0x66126c8d;
+
 static GLOBAL_RNG: Mutex<Rng> = Mutex::new(Rng::new_unseeded());
 
 pub struct Rng {
@@ -11,8 +14,13 @@ pub struct Rng {
 impl Rng {
     #[inline]
     pub const fn new_unseeded() -> Rng {
+        Rng::new(DEFAULT_SEED)
+    }
+
+    #[inline]
+    pub const fn new(seed: u32) -> Rng {
         Rng {
-            seed: Wrapping(0x66126c8d),
+            seed: Wrapping(seed),
         }
     }
 
@@ -46,6 +54,12 @@ impl Rng {
     }
 }
 
+/// Restarts the global generator, the same seed gives the same sequence.
+#[inline]
+pub fn seed_random(seed: u32) {
+    *GLOBAL_RNG.lock() = Rng::new(seed);
+}
+
 #[inline]
 pub fn random_u32() -> u32 {
     GLOBAL_RNG.lock().next_u32()
@@ -0,0 +1,567 @@
+#![no_std]
+
+//! The filesystem on a Controller Pak, in the layout the console's own
+//! libraries use so notes can be managed by other games and tools.
+//!
+//! Page 0 holds the ID sector, page 1 the inode table and page 2 its backup.
+//! Pages 3 and 4 hold the note table. The remaining 123 pages store note data,
+//! each note is a chain of pages linked through the inode table.
+
+extern crate alloc;
+
+mod name;
+
+#[cfg(test)]
+mod tests;
+
+use alloc::{string::String, vec::Vec};
+
+/// Bytes moved by one accessory read or write.
+pub const BLOCK_SIZE: usize = 32;
+pub const PAGE_SIZE: usize = 256;
+pub const PAGES: usize = 128;
+pub const PAK_SIZE: usize = PAGES * PAGE_SIZE;
+pub const FIRST_DATA_PAGE: usize = 5;
+pub const DATA_PAGES: usize = PAGES - FIRST_DATA_PAGE;
+pub const MAX_NOTES: usize = 16;
+pub const NAME_LENGTH: usize = 16;
+pub const EXTENSION_LENGTH: usize = 4;
+
+const ID_BLOCK_ADDRESSES: [usize; 4] = [0x20, 0x60, 0x80, 0xc0];
+const ID_CHECKSUM_OFFSET: usize = 28;
+const ID_DEVICE_OFFSET: usize = 24;
+const ID_BANKS_OFFSET: usize = 26;
+
+const INODE_PAGE: usize = 1;
+const INODE_BACKUP_PAGE: usize = 2;
+const NOTE_TABLE_PAGE: usize = 3;
+
+// Inode values other than these are the next page of the note.
+const INODE_LAST: u16 = 0x0001;
+const INODE_FREE: u16 = 0x0003;
+
+const NOTE_SIZE: usize = 32;
+const NOTE_OCCUPIED: u8 = 0x02;
+
+/// Raw access to a pak, see `Controllers::read_pak` and
+/// `Controllers::write_pak`.
+pub trait PakDevice {
+    type Error;
+
+    fn read_block(&mut self, address: u16, block: &mut [u8; BLOCK_SIZE])
+        -> Result<(), Self::Error>;
+
+    fn write_block(&mut self, address: u16, block: &[u8; BLOCK_SIZE]) -> Result<(), Self::Error>;
+}
+
+/// A problem found by `ControllerPak::check`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    /// The note in this slot starts outside the data pages.
+    Note(usize),
+    /// The page chain of the note in this slot runs into a free or invalid
+    /// page, or loops.
+    Chain(usize),
+    /// The page is in the chains of two notes.
+    CrossLinked(usize),
+    /// The page is in use but belongs to no note.
+    Lost(usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FsError<E> {
+    Device(E),
+    /// No valid ID block, the pak needs formatting.
+    NotFormatted,
+    /// Both copies of the inode table are damaged.
+    BadInodeTable,
+    Corrupt(Corruption),
+    NotFound,
+    Exists,
+    NoSpace,
+    NoFreeNote,
+    /// The access goes past the end of the note.
+    OutOfBounds,
+}
+
+/// Identifies a note. Game and publisher codes come from the ROM header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NoteKey {
+    pub game_code: u32,
+    pub publisher_code: u16,
+    name: [u8; NAME_LENGTH],
+    extension: [u8; EXTENSION_LENGTH],
+}
+
+impl NoteKey {
+    /// Names hold up to 16 digits, letters, spaces and `!"#'*+,-./:=?@`. Lower
+    /// case letters are stored as upper case. Returns None for names that can
+    /// not be stored and for zero codes, which mark free slots.
+    pub fn new(game_code: u32, publisher_code: u16, name: &str) -> Option<NoteKey> {
+        if game_code == 0 || publisher_code == 0 || name.is_empty() {
+            return None;
+        }
+
+        let mut key = NoteKey {
+            game_code,
+            publisher_code,
+            name: [0; NAME_LENGTH],
+            extension: [0; EXTENSION_LENGTH],
+        };
+
+        name::encode(name, &mut key.name)?;
+
+        Some(key)
+    }
+
+    /// Up to 4 characters from the same set as the name.
+    pub fn with_extension(mut self, extension: &str) -> Option<NoteKey> {
+        name::encode(extension, &mut self.extension)?;
+        Some(self)
+    }
+
+    pub fn name(&self) -> String {
+        name::decode(&self.name)
+    }
+
+    pub fn extension(&self) -> String {
+        name::decode(&self.extension)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Note {
+    pub key: NoteKey,
+    pub size: usize,
+}
+
+#[derive(Copy, Clone)]
+struct Entry([u8; NOTE_SIZE]);
+
+impl Entry {
+    const EMPTY: Entry = Entry([0; NOTE_SIZE]);
+
+    fn new(key: &NoteKey, start_page: usize) -> Entry {
+        let mut entry = Entry::EMPTY;
+        entry.0[0..4].copy_from_slice(&key.game_code.to_be_bytes());
+        entry.0[4..6].copy_from_slice(&key.publisher_code.to_be_bytes());
+        entry.0[6..8].copy_from_slice(&(start_page as u16).to_be_bytes());
+        entry.0[8] = NOTE_OCCUPIED;
+        entry.0[12..16].copy_from_slice(&key.extension);
+        entry.0[16..32].copy_from_slice(&key.name);
+        entry
+    }
+
+    fn key(&self) -> NoteKey {
+        let mut key = NoteKey {
+            game_code: u32::from_be_bytes([self.0[0], self.0[1], self.0[2], self.0[3]]),
+            publisher_code: u16::from_be_bytes([self.0[4], self.0[5]]),
+            name: [0; NAME_LENGTH],
+            extension: [0; EXTENSION_LENGTH],
+        };
+
+        key.extension.copy_from_slice(&self.0[12..16]);
+        key.name.copy_from_slice(&self.0[16..32]);
+        key
+    }
+
+    fn start_page(&self) -> usize {
+        u16::from_be_bytes([self.0[6], self.0[7]]) as usize
+    }
+
+    // Same test as the console's libraries.
+    fn in_use(&self) -> bool {
+        self.0[0..4] != [0; 4] && self.0[4..6] != [0; 2]
+    }
+}
+
+fn id_checksums(block: &[u8]) -> (u16, u16) {
+    let mut sum = 0u16;
+    let mut inverted_sum = 0u16;
+
+    for word in block[..ID_CHECKSUM_OFFSET].chunks(2) {
+        let word = u16::from_be_bytes([word[0], word[1]]);
+        sum = sum.wrapping_add(word);
+        inverted_sum = inverted_sum.wrapping_add(!word);
+    }
+
+    (sum, inverted_sum)
+}
+
+fn id_block_valid(block: &[u8]) -> bool {
+    let (sum, inverted_sum) = id_checksums(block);
+    let stored = u16::from_be_bytes([block[28], block[29]]);
+    let stored_inverted = u16::from_be_bytes([block[30], block[31]]);
+
+    sum == stored && inverted_sum == stored_inverted && block[ID_DEVICE_OFFSET + 1] & 0x01 != 0
+}
+
+// The checksum, in the low byte of the first reserved entry, is the byte sum
+// of the data page entries.
+fn inode_checksum(page: &[u8; PAGE_SIZE]) -> u8 {
+    page[FIRST_DATA_PAGE * 2..]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn address(page: usize, offset: usize) -> u16 {
+    (page * PAGE_SIZE + offset) as u16
+}
+
+/// A mounted Controller Pak. The inode and note tables are kept in memory and
+/// written through on every change.
+pub struct ControllerPak<D> {
+    device: D,
+    inodes: [u16; PAGES],
+    entries: [Entry; MAX_NOTES],
+}
+
+impl<D: PakDevice> ControllerPak<D> {
+    /// Reads the tables. A damaged inode table is restored from its backup.
+    pub fn mount(device: D) -> Result<ControllerPak<D>, FsError<D::Error>> {
+        let mut pak = ControllerPak {
+            device,
+            inodes: [INODE_FREE; PAGES],
+            entries: [Entry::EMPTY; MAX_NOTES],
+        };
+
+        let mut page = [0; PAGE_SIZE];
+        pak.read_page(0, &mut page)?;
+
+        if !ID_BLOCK_ADDRESSES
+            .iter()
+            .any(|&address| id_block_valid(&page[address..address + BLOCK_SIZE]))
+        {
+            return Err(FsError::NotFormatted);
+        }
+
+        pak.read_page(INODE_PAGE, &mut page)?;
+        if inode_checksum(&page) != page[1] {
+            pak.read_page(INODE_BACKUP_PAGE, &mut page)?;
+
+            if inode_checksum(&page) != page[1] {
+                return Err(FsError::BadInodeTable);
+            }
+
+            pak.write_page(INODE_PAGE, &page)?;
+        }
+
+        for (inode, bytes) in pak.inodes.iter_mut().zip(page.chunks(2)) {
+            *inode = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+
+        for (i, entry) in pak.entries.iter_mut().enumerate() {
+            let address = address(NOTE_TABLE_PAGE, i * NOTE_SIZE);
+            pak.device
+                .read_block(address, &mut entry.0)
+                .map_err(FsError::Device)?;
+        }
+
+        Ok(pak)
+    }
+
+    /// Erases the pak and writes empty tables.
+    pub fn format(device: D) -> Result<ControllerPak<D>, FsError<D::Error>> {
+        let mut pak = ControllerPak {
+            device,
+            inodes: [INODE_FREE; PAGES],
+            entries: [Entry::EMPTY; MAX_NOTES],
+        };
+
+        let mut id_block = [0; BLOCK_SIZE];
+        id_block[ID_DEVICE_OFFSET + 1] = 0x01;
+        id_block[ID_BANKS_OFFSET] = 1;
+
+        let (sum, inverted_sum) = id_checksums(&id_block);
+        id_block[28..30].copy_from_slice(&sum.to_be_bytes());
+        id_block[30..32].copy_from_slice(&inverted_sum.to_be_bytes());
+
+        let mut page = [0; PAGE_SIZE];
+        for &address in ID_BLOCK_ADDRESSES.iter() {
+            page[address..address + BLOCK_SIZE].copy_from_slice(&id_block);
+        }
+        pak.write_page(0, &page)?;
+
+        for inode in pak.inodes[..FIRST_DATA_PAGE].iter_mut() {
+            *inode = 0;
+        }
+        pak.write_inodes()?;
+
+        for i in 0..MAX_NOTES {
+            pak.write_entry(i)?;
+        }
+
+        Ok(pak)
+    }
+
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    pub fn free_pages(&self) -> usize {
+        self.inodes[FIRST_DATA_PAGE..]
+            .iter()
+            .filter(|&&inode| inode == INODE_FREE)
+            .count()
+    }
+
+    pub fn free_bytes(&self) -> usize {
+        self.free_pages() * PAGE_SIZE
+    }
+
+    /// The notes on the pak, by slot.
+    pub fn notes(&self) -> Vec<Note> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.in_use())
+            .map(|(i, entry)| Note {
+                key: entry.key(),
+                size: self.pages(i).map(|pages| pages.len()).unwrap_or(0) * PAGE_SIZE,
+            })
+            .collect()
+    }
+
+    pub fn find(&self, key: &NoteKey) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.in_use() && entry.key() == *key)
+    }
+
+    /// Size in bytes, always a whole number of pages.
+    pub fn size(&self, key: &NoteKey) -> Result<usize, FsError<D::Error>> {
+        Ok(self.note_pages(key)?.len() * PAGE_SIZE)
+    }
+
+    /// Creates a note of at least `size` bytes. The contents are whatever the
+    /// pages held before.
+    pub fn create(&mut self, key: &NoteKey, size: usize) -> Result<(), FsError<D::Error>> {
+        if self.find(key).is_some() {
+            return Err(FsError::Exists);
+        }
+
+        let slot = self
+            .entries
+            .iter()
+            .position(|entry| !entry.in_use())
+            .ok_or(FsError::NoFreeNote)?;
+
+        let page_count = size.saturating_sub(1) / PAGE_SIZE + 1;
+        if page_count > self.free_pages() {
+            return Err(FsError::NoSpace);
+        }
+
+        let pages = (FIRST_DATA_PAGE..PAGES)
+            .filter(|&page| self.inodes[page] == INODE_FREE)
+            .take(page_count)
+            .collect::<Vec<_>>();
+
+        for (i, &page) in pages.iter().enumerate() {
+            self.inodes[page] = match pages.get(i + 1) {
+                Some(&next) => next as u16,
+                None => INODE_LAST,
+            };
+        }
+
+        // Tables first, a note is never left pointing at free pages.
+        self.write_inodes()?;
+
+        self.entries[slot] = Entry::new(key, pages[0]);
+        self.write_entry(slot)
+    }
+
+    pub fn delete(&mut self, key: &NoteKey) -> Result<(), FsError<D::Error>> {
+        let slot = self.find(key).ok_or(FsError::NotFound)?;
+        let pages = self.pages(slot).map_err(FsError::Corrupt)?;
+
+        self.entries[slot] = Entry::EMPTY;
+        self.write_entry(slot)?;
+
+        for page in pages {
+            self.inodes[page] = INODE_FREE;
+        }
+
+        self.write_inodes()
+    }
+
+    pub fn read(
+        &mut self,
+        key: &NoteKey,
+        offset: usize,
+        data: &mut [u8],
+    ) -> Result<(), FsError<D::Error>> {
+        let pages = self.note_pages(key)?;
+        let mut block = [0; BLOCK_SIZE];
+
+        self.for_each_block(&pages, offset, data.len(), |device, address, range, at| {
+            device.read_block(address, &mut block)?;
+            data[at..at + range.len()].copy_from_slice(&block[range]);
+            Ok(())
+        })
+    }
+
+    pub fn write(
+        &mut self,
+        key: &NoteKey,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), FsError<D::Error>> {
+        let pages = self.note_pages(key)?;
+        let mut block = [0; BLOCK_SIZE];
+
+        self.for_each_block(&pages, offset, data.len(), |device, address, range, at| {
+            if range.len() < BLOCK_SIZE {
+                device.read_block(address, &mut block)?;
+            }
+
+            let len = range.len();
+            block[range].copy_from_slice(&data[at..at + len]);
+            device.write_block(address, &block)
+        })
+    }
+
+    /// Checks that every note has a valid chain of its own pages and that no
+    /// used page is left without a note.
+    pub fn check(&self) -> Result<(), Corruption> {
+        let mut owners = [None; PAGES];
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            if !entry.in_use() {
+                continue;
+            }
+
+            for page in self.pages(i)? {
+                if let Some(owner) = owners[page] {
+                    return Err(if owner == i {
+                        Corruption::Chain(i)
+                    } else {
+                        Corruption::CrossLinked(page)
+                    });
+                }
+
+                owners[page] = Some(i);
+            }
+        }
+
+        for (page, (owner, &inode)) in owners.iter().zip(self.inodes.iter()).enumerate() {
+            if page >= FIRST_DATA_PAGE && owner.is_none() && inode != INODE_FREE {
+                return Err(Corruption::Lost(page));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn note_pages(&self, key: &NoteKey) -> Result<Vec<usize>, FsError<D::Error>> {
+        let slot = self.find(key).ok_or(FsError::NotFound)?;
+        self.pages(slot).map_err(FsError::Corrupt)
+    }
+
+    // The chain of a note, stops at the first loop found.
+    fn pages(&self, slot: usize) -> Result<Vec<usize>, Corruption> {
+        let mut page = self.entries[slot].start_page();
+        if !(FIRST_DATA_PAGE..PAGES).contains(&page) {
+            return Err(Corruption::Note(slot));
+        }
+
+        let mut pages = Vec::new();
+        loop {
+            if pages.contains(&page) {
+                return Err(Corruption::Chain(slot));
+            }
+
+            pages.push(page);
+
+            match self.inodes[page] {
+                INODE_LAST => return Ok(pages),
+                next if (FIRST_DATA_PAGE..PAGES).contains(&(next as usize)) => page = next as usize,
+                _ => return Err(Corruption::Chain(slot)),
+            }
+        }
+    }
+
+    // Calls `f` with the device address, the range within the block and the
+    // offset into the caller's data for every block `offset..offset + len`
+    // of the note touches.
+    fn for_each_block(
+        &mut self,
+        pages: &[usize],
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(&mut D, u16, core::ops::Range<usize>, usize) -> Result<(), D::Error>,
+    ) -> Result<(), FsError<D::Error>> {
+        if offset + len > pages.len() * PAGE_SIZE {
+            return Err(FsError::OutOfBounds);
+        }
+
+        let mut position = offset;
+        while position < offset + len {
+            let page = pages[position / PAGE_SIZE];
+            let in_page = position % PAGE_SIZE;
+            let start = in_page % BLOCK_SIZE;
+            let end = (start + offset + len - position).min(BLOCK_SIZE);
+
+            f(
+                &mut self.device,
+                address(page, in_page - start),
+                start..end,
+                position - offset,
+            )
+            .map_err(FsError::Device)?;
+
+            position += end - start;
+        }
+
+        Ok(())
+    }
+
+    fn read_page(
+        &mut self,
+        page: usize,
+        data: &mut [u8; PAGE_SIZE],
+    ) -> Result<(), FsError<D::Error>> {
+        let mut block = [0; BLOCK_SIZE];
+
+        for (i, chunk) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+            self.device
+                .read_block(address(page, i * BLOCK_SIZE), &mut block)
+                .map_err(FsError::Device)?;
+            chunk.copy_from_slice(&block);
+        }
+
+        Ok(())
+    }
+
+    fn write_page(&mut self, page: usize, data: &[u8; PAGE_SIZE]) -> Result<(), FsError<D::Error>> {
+        let mut block = [0; BLOCK_SIZE];
+
+        for (i, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+            block.copy_from_slice(chunk);
+            self.device
+                .write_block(address(page, i * BLOCK_SIZE), &block)
+                .map_err(FsError::Device)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_inodes(&mut self) -> Result<(), FsError<D::Error>> {
+        let mut page = [0; PAGE_SIZE];
+        for (bytes, inode) in page.chunks_mut(2).zip(self.inodes.iter()) {
+            bytes.copy_from_slice(&inode.to_be_bytes());
+        }
+
+        page[0] = 0;
+        page[1] = inode_checksum(&page);
+
+        self.write_page(INODE_PAGE, &page)?;
+        self.write_page(INODE_BACKUP_PAGE, &page)
+    }
+
+    fn write_entry(&mut self, slot: usize) -> Result<(), FsError<D::Error>> {
+        let entry = self.entries[slot];
+        self.device
+            .write_block(address(NOTE_TABLE_PAGE, slot * NOTE_SIZE), &entry.0)
+            .map_err(FsError::Device)
+    }
+}
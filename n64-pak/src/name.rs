@@ -0,0 +1,56 @@
+use alloc::string::String;
+
+// Note names use the character set of the console's font instead of ASCII.
+// 0x00 pads the end of a name, codes past 0x41 are kana.
+const SPACE: u8 = 0x0f;
+const DIGITS: u8 = 0x10;
+const LETTERS: u8 = 0x1a;
+const SYMBOLS: u8 = 0x34;
+const SYMBOL_CHARS: &[u8] = b"!\"#'*+,-./:=?@";
+
+fn encode_char(c: char) -> Option<u8> {
+    let c = c.to_ascii_uppercase();
+
+    match c {
+        ' ' => Some(SPACE),
+        '0'..='9' => Some(DIGITS + (c as u8 - b'0')),
+        'A'..='Z' => Some(LETTERS + (c as u8 - b'A')),
+        _ => SYMBOL_CHARS
+            .iter()
+            .position(|&symbol| symbol as char == c)
+            .map(|i| SYMBOLS + i as u8),
+    }
+}
+
+fn decode_char(code: u8) -> char {
+    match code {
+        SPACE => ' ',
+        DIGITS..=0x19 => (b'0' + code - DIGITS) as char,
+        LETTERS..=0x33 => (b'A' + code - LETTERS) as char,
+        SYMBOLS..=0x41 => SYMBOL_CHARS[(code - SYMBOLS) as usize] as char,
+        _ => '?',
+    }
+}
+
+/// Encodes `text` into `out`, padded with zeroes. Lower case letters are
+/// stored as upper case. Returns None if `text` is too long or has characters
+/// the pak can not store.
+pub(crate) fn encode(text: &str, out: &mut [u8]) -> Option<()> {
+    for byte in out.iter_mut() {
+        *byte = 0;
+    }
+
+    for (i, c) in text.chars().enumerate() {
+        *out.get_mut(i)? = encode_char(c)?;
+    }
+
+    Some(())
+}
+
+pub(crate) fn decode(codes: &[u8]) -> String {
+    codes
+        .iter()
+        .take_while(|&&code| code != 0)
+        .map(|&code| decode_char(code))
+        .collect()
+}
@@ -0,0 +1,229 @@
+extern crate std;
+
+use super::*;
+use std::{vec, vec::Vec};
+
+const GAME_CODE: u32 = 0x4e4c_4b45;
+const PUBLISHER_CODE: u16 = 0x3031;
+
+// A 32 KB pak image in memory.
+struct MemoryPak {
+    data: Vec<u8>,
+}
+
+impl MemoryPak {
+    fn new() -> MemoryPak {
+        MemoryPak {
+            data: vec![0; PAK_SIZE],
+        }
+    }
+}
+
+impl PakDevice for MemoryPak {
+    type Error = ();
+
+    fn read_block(&mut self, address: u16, block: &mut [u8; BLOCK_SIZE]) -> Result<(), ()> {
+        let address = address as usize;
+        block.copy_from_slice(&self.data[address..address + BLOCK_SIZE]);
+        Ok(())
+    }
+
+    fn write_block(&mut self, address: u16, block: &[u8; BLOCK_SIZE]) -> Result<(), ()> {
+        let address = address as usize;
+        self.data[address..address + BLOCK_SIZE].copy_from_slice(block);
+        Ok(())
+    }
+}
+
+fn key(name: &str) -> NoteKey {
+    NoteKey::new(GAME_CODE, PUBLISHER_CODE, name).unwrap()
+}
+
+fn formatted() -> ControllerPak<MemoryPak> {
+    ControllerPak::format(MemoryPak::new()).unwrap()
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+#[test]
+fn blank_pak_is_not_formatted() {
+    assert_eq!(
+        ControllerPak::mount(MemoryPak::new()).err(),
+        Some(FsError::NotFormatted)
+    );
+}
+
+#[test]
+fn format_and_mount() {
+    let pak = formatted();
+    assert_eq!(pak.free_pages(), DATA_PAGES);
+    assert!(pak.notes().is_empty());
+    assert_eq!(pak.check(), Ok(()));
+
+    let pak = ControllerPak::mount(pak.into_device()).unwrap();
+    assert_eq!(pak.free_pages(), DATA_PAGES);
+    assert_eq!(pak.check(), Ok(()));
+}
+
+#[test]
+fn names() {
+    let key = NoteKey::new(GAME_CODE, PUBLISHER_CODE, "Loka hi-score!")
+        .unwrap()
+        .with_extension("A")
+        .unwrap();
+
+    assert_eq!(key.name(), "LOKA HI-SCORE!");
+    assert_eq!(key.extension(), "A");
+
+    assert!(NoteKey::new(GAME_CODE, PUBLISHER_CODE, "").is_none());
+    assert!(NoteKey::new(GAME_CODE, PUBLISHER_CODE, "NAME LONGER THAN 16").is_none());
+    assert!(NoteKey::new(GAME_CODE, PUBLISHER_CODE, "TAB\t").is_none());
+    assert!(NoteKey::new(0, PUBLISHER_CODE, "ZERO").is_none());
+    assert!(key.with_extension("TOO LONG").is_none());
+}
+
+#[test]
+fn create_write_read_delete() {
+    let mut pak = formatted();
+    let save = key("SAVE");
+    let other = key("OTHER");
+
+    pak.create(&save, 600).unwrap();
+    pak.create(&other, 1).unwrap();
+    assert_eq!(pak.size(&save), Ok(3 * PAGE_SIZE));
+    assert_eq!(pak.size(&other), Ok(PAGE_SIZE));
+    assert_eq!(pak.free_pages(), DATA_PAGES - 4);
+    assert_eq!(pak.create(&save, 1), Err(FsError::Exists));
+
+    // Unaligned and spanning pages.
+    let data = pattern(500);
+    pak.write(&save, 13, &data).unwrap();
+    pak.write(&other, 0, &[0xaa; PAGE_SIZE]).unwrap();
+
+    let mut pak = ControllerPak::mount(pak.into_device()).unwrap();
+    assert_eq!(pak.check(), Ok(()));
+
+    let notes = pak.notes();
+    assert_eq!(notes.len(), 2);
+    assert_eq!(notes[0].key, save);
+    assert_eq!(notes[0].size, 3 * PAGE_SIZE);
+
+    let mut read = vec![0; 500];
+    pak.read(&save, 13, &mut read).unwrap();
+    assert_eq!(read, data);
+
+    let mut read = [0; 3];
+    pak.read(&save, 12, &mut read).unwrap();
+    assert_eq!(read, [0, data[0], data[1]]);
+
+    assert_eq!(
+        pak.read(&save, 3 * PAGE_SIZE - 2, &mut read),
+        Err(FsError::OutOfBounds)
+    );
+    assert_eq!(pak.write(&key("MISSING"), 0, &[1]), Err(FsError::NotFound));
+
+    pak.delete(&save).unwrap();
+    assert_eq!(pak.find(&save), None);
+    assert_eq!(pak.free_pages(), DATA_PAGES - 1);
+    assert_eq!(pak.check(), Ok(()));
+
+    let mut read = [0; PAGE_SIZE];
+    pak.read(&other, 0, &mut read).unwrap();
+    assert_eq!(read[..], [0xaa; PAGE_SIZE][..]);
+}
+
+#[test]
+fn fills_up() {
+    let mut pak = formatted();
+
+    assert_eq!(pak.create(&key("HUGE"), PAK_SIZE), Err(FsError::NoSpace));
+
+    for i in 0..MAX_NOTES {
+        pak.create(&key(&std::format!("NOTE {}", i)), PAGE_SIZE)
+            .unwrap();
+    }
+
+    assert_eq!(pak.create(&key("ONE MORE"), 1), Err(FsError::NoFreeNote));
+    assert_eq!(pak.free_pages(), DATA_PAGES - MAX_NOTES);
+    assert_eq!(pak.check(), Ok(()));
+}
+
+#[test]
+fn restores_inode_table_from_backup() {
+    let mut pak = formatted();
+    let save = key("SAVE");
+    pak.create(&save, 1000).unwrap();
+
+    let mut device = pak.into_device();
+    device.data[INODE_PAGE * PAGE_SIZE + 20] ^= 0xff;
+
+    let pak = ControllerPak::mount(device).unwrap();
+    assert_eq!(pak.size(&save), Ok(4 * PAGE_SIZE));
+    assert_eq!(pak.check(), Ok(()));
+
+    let mut device = pak.into_device();
+    assert_eq!(
+        device.data[INODE_PAGE * PAGE_SIZE..][..PAGE_SIZE],
+        device.data[INODE_BACKUP_PAGE * PAGE_SIZE..][..PAGE_SIZE]
+    );
+
+    device.data[INODE_PAGE * PAGE_SIZE + 20] ^= 0xff;
+    device.data[INODE_BACKUP_PAGE * PAGE_SIZE + 20] ^= 0xff;
+    assert_eq!(
+        ControllerPak::mount(device).err(),
+        Some(FsError::BadInodeTable)
+    );
+}
+
+// Rewrites an inode and its checksum in both copies of the table.
+fn set_inode(device: &mut MemoryPak, page: usize, value: u16) {
+    for &table in [INODE_PAGE, INODE_BACKUP_PAGE].iter() {
+        let start = table * PAGE_SIZE;
+        device.data[start + page * 2..start + page * 2 + 2].copy_from_slice(&value.to_be_bytes());
+
+        let mut table_page = [0; PAGE_SIZE];
+        table_page.copy_from_slice(&device.data[start..start + PAGE_SIZE]);
+        device.data[start + 1] = inode_checksum(&table_page);
+    }
+}
+
+#[test]
+fn check_finds_corruption() {
+    let mut pak = formatted();
+    pak.create(&key("FIRST"), 2 * PAGE_SIZE).unwrap();
+    pak.create(&key("SECOND"), 2 * PAGE_SIZE).unwrap();
+    let device = pak.into_device();
+
+    // FIRST is on pages 5 and 6, SECOND on 7 and 8.
+    let mut cross_linked = MemoryPak {
+        data: device.data.clone(),
+    };
+    set_inode(&mut cross_linked, 6, 7);
+    let pak = ControllerPak::mount(cross_linked).unwrap();
+    assert_eq!(pak.check(), Err(Corruption::CrossLinked(7)));
+
+    let mut looped = MemoryPak {
+        data: device.data.clone(),
+    };
+    set_inode(&mut looped, 6, 5);
+    let mut pak = ControllerPak::mount(looped).unwrap();
+    assert_eq!(pak.check(), Err(Corruption::Chain(0)));
+    assert_eq!(
+        pak.read(&key("FIRST"), 0, &mut [0; 4]),
+        Err(FsError::Corrupt(Corruption::Chain(0)))
+    );
+
+    let mut broken = MemoryPak {
+        data: device.data.clone(),
+    };
+    set_inode(&mut broken, 8, INODE_FREE);
+    let pak = ControllerPak::mount(broken).unwrap();
+    assert_eq!(pak.check(), Err(Corruption::Chain(1)));
+
+    let mut lost = MemoryPak { data: device.data };
+    set_inode(&mut lost, 100, INODE_LAST);
+    let pak = ControllerPak::mount(lost).unwrap();
+    assert_eq!(pak.check(), Err(Corruption::Lost(100)));
+}
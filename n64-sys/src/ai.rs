@@ -1,7 +1,10 @@
 #![allow(dead_code)]
 
 use crate::sys::{memory_barrier, uncached_addr, virtual_to_physical};
+use alloc::{vec, vec::Vec};
 use core::ptr::{read_volatile, write_volatile};
+use core::slice;
+use n64_types::{AudioConfig, MAX_BUFFER_FRAMES};
 
 const AI_BASE: usize = 0xA4500000;
 
@@ -21,14 +24,14 @@ const AI_STATUS_FULL: usize = 1 << 31;
 
 const TV_TYPE_LOC: usize = 0x80000300;
 
-const FREQUENCY: usize = 22050;
 const BUFFER_COUNT: usize = 4;
 
-pub const BUFFER_NO_SAMPLES: usize = 2 * 512;
-
+static mut FREQUENCY: usize = 0;
 static mut REAL_FREQUENCY: usize = 0;
-static mut BUFFERS: [[i16; BUFFER_NO_SAMPLES]; BUFFER_COUNT] =
-    [[0; BUFFER_NO_SAMPLES]; BUFFER_COUNT];
+static mut BUFFER_NO_SAMPLES: usize = 0;
+// All the buffers back to back, allocated by `init` for the configured size.
+// Words since the AI DMAs from 8 byte aligned addresses.
+static mut BUFFERS: Vec<u64> = Vec::new();
 
 static mut NOW_PLAYING: usize = 0;
 static mut NOW_WRITING: usize = 0;
@@ -45,8 +48,20 @@ fn ai_full() -> bool {
 }
 
 #[inline]
-pub fn init() {
+pub fn init(config: AudioConfig) {
+    // DMA lengths are in multiples of 8 bytes, two stereo frames.
+    assert!(
+        config.buffer_frames > 0
+            && config.buffer_frames <= MAX_BUFFER_FRAMES
+            && config.buffer_frames & 1 == 0,
+        "Bad audio buffer size"
+    );
+
     unsafe {
+        FREQUENCY = config.sample_rate as usize;
+        BUFFER_NO_SAMPLES = config.buffer_samples();
+        BUFFERS = vec![0; BUFFER_COUNT * BUFFER_NO_SAMPLES / 4];
+
         let clockrate = match read_volatile(TV_TYPE_LOC as *const usize) {
             0 => AI_PAL_DACRATE,
             2 => AI_MPAL_DACRATE,
@@ -60,6 +75,26 @@ pub fn init() {
     }
 }
 
+/// The rate the DAC really plays at, the closest the clock gets to the
+/// configured one.
+#[inline]
+pub fn real_frequency() -> usize {
+    unsafe { REAL_FREQUENCY }
+}
+
+#[inline]
+pub fn buffer_no_samples() -> usize {
+    unsafe { BUFFER_NO_SAMPLES }
+}
+
+#[inline]
+unsafe fn buffer(index: usize) -> &'static mut [i16] {
+    slice::from_raw_parts_mut(
+        (BUFFERS.as_mut_ptr() as *mut i16).add(index * BUFFER_NO_SAMPLES),
+        BUFFER_NO_SAMPLES,
+    )
+}
+
 #[inline]
 pub fn write_audio_blocking(f: &mut impl FnMut(&mut [i16])) {
     unsafe {
@@ -71,7 +106,7 @@ pub fn write_audio_blocking(f: &mut impl FnMut(&mut [i16])) {
         BUFFERS_FULL_BITMASK |= 1 << next;
         NOW_WRITING = next;
 
-        f(&mut BUFFERS[NOW_WRITING]);
+        f(buffer(NOW_WRITING));
     }
 }
 
@@ -101,7 +136,7 @@ pub fn submit_audio_data_to_dac() {
 
             write_volatile(
                 AI_ADDR,
-                virtual_to_physical(uncached_addr(BUFFERS[NOW_PLAYING].as_ptr())),
+                virtual_to_physical(uncached_addr(buffer(NOW_PLAYING).as_ptr())),
             );
             memory_barrier();
             write_volatile(AI_LENGTH, (BUFFER_NO_SAMPLES * 2) & !7);
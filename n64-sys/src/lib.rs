@@ -5,6 +5,7 @@
 extern crate alloc;
 
 pub mod ai;
+pub mod pi;
 pub mod rdp;
 pub mod si;
 pub mod sys;
@@ -0,0 +1,306 @@
+#![allow(dead_code)]
+
+use crate::sys::{
+    current_time_us, data_cache_hit_writeback, data_cache_hit_writeback_invalidate, memory_barrier,
+    uncached_addr, virtual_to_physical,
+};
+use core::intrinsics::volatile_copy_nonoverlapping_memory;
+use core::ptr::{read_volatile, write_volatile};
+
+const PI_BASE: usize = 0xA460_0000;
+
+const PI_DRAM_ADDR: *mut usize = (PI_BASE + 0x00) as _;
+const PI_CART_ADDR: *mut usize = (PI_BASE + 0x04) as _;
+const PI_RD_LEN: *mut usize = (PI_BASE + 0x08) as _;
+const PI_WR_LEN: *mut usize = (PI_BASE + 0x0C) as _;
+const PI_STATUS: *mut usize = (PI_BASE + 0x10) as _;
+const PI_BSD_DOM2_LAT: *mut usize = (PI_BASE + 0x24) as _;
+const PI_BSD_DOM2_PWD: *mut usize = (PI_BASE + 0x28) as _;
+const PI_BSD_DOM2_PGS: *mut usize = (PI_BASE + 0x2C) as _;
+const PI_BSD_DOM2_RLS: *mut usize = (PI_BASE + 0x30) as _;
+
+const PI_STATUS_DMA_BUSY: usize = 0x0001;
+const PI_STATUS_IO_BUSY: usize = 0x0002;
+const PI_STATUS_ERROR: usize = 0x0004;
+
+const PI_STATUS_RESET: usize = 0x0001;
+const PI_STATUS_CLEAR_INTERRUPT: usize = 0x0002;
+
+pub const CART_DOM1_ADDR2: usize = 0x1000_0000;
+pub const CART_DOM2_ADDR2: usize = 0x0800_0000;
+
+const UNCACHED_BASE: usize = 0xA000_0000;
+
+const BOUNCE_BUFFER_LEN: usize = 256;
+
+#[repr(align(16))]
+struct BounceBuffer([u64; BOUNCE_BUFFER_LEN]);
+
+/// The most one DMA through the bounce buffer moves.
+pub const DMA_CHUNK_LEN: usize = BOUNCE_BUFFER_LEN * 8;
+
+static mut BOUNCE_BUFFER: BounceBuffer = BounceBuffer([0; BOUNCE_BUFFER_LEN]);
+
+#[inline]
+fn dma_wait() {
+    while unsafe { read_volatile(PI_STATUS) } & (PI_STATUS_DMA_BUSY | PI_STATUS_IO_BUSY) > 0 {}
+}
+
+// Reads a single word from the cartridge bus without going through DMA.
+// `cart_addr` is a physical address and must be 4 byte aligned.
+#[inline]
+pub fn io_read(cart_addr: usize) -> u32 {
+    dma_wait();
+    unsafe { read_volatile((UNCACHED_BASE | cart_addr) as *const u32) }
+}
+
+#[inline]
+pub fn io_write(cart_addr: usize, value: u32) {
+    dma_wait();
+    unsafe { write_volatile((UNCACHED_BASE | cart_addr) as *mut u32, value) };
+    dma_wait();
+}
+
+// Raw PI DMA from the cartridge bus into RDRAM. `dram` must be 8 byte
+// aligned, `cart_addr` 2 byte aligned and `len` a multiple of 2.
+#[inline]
+unsafe fn dma_read_raw(dram: *mut u64, cart_addr: usize, len: usize) {
+    dma_wait();
+
+    write_volatile(PI_STATUS, PI_STATUS_RESET | PI_STATUS_CLEAR_INTERRUPT);
+    memory_barrier();
+    write_volatile(PI_DRAM_ADDR, virtual_to_physical(dram));
+    memory_barrier();
+    write_volatile(PI_CART_ADDR, cart_addr);
+    memory_barrier();
+    write_volatile(PI_WR_LEN, len - 1);
+    memory_barrier();
+
+    dma_wait();
+}
+
+// Raw PI DMA from RDRAM to the cartridge bus, same alignment rules as
+// `dma_read_raw`.
+#[inline]
+unsafe fn dma_write_raw(dram: *const u64, cart_addr: usize, len: usize) {
+    dma_wait();
+
+    write_volatile(PI_STATUS, PI_STATUS_RESET | PI_STATUS_CLEAR_INTERRUPT);
+    memory_barrier();
+    write_volatile(PI_DRAM_ADDR, virtual_to_physical(dram));
+    memory_barrier();
+    write_volatile(PI_CART_ADDR, cart_addr);
+    memory_barrier();
+    write_volatile(PI_RD_LEN, len - 1);
+    memory_barrier();
+
+    dma_wait();
+}
+
+// Reads `out.len()` bytes starting at the physical cartridge address
+// `cart_addr`. Neither the address nor the output buffer need any particular
+// alignment, the transfer goes through an aligned bounce buffer.
+#[inline]
+pub fn read(cart_addr: usize, out: &mut [u8]) {
+    let mut done = 0;
+
+    while done < out.len() {
+        let addr = cart_addr + done;
+        let aligned_addr = addr & !1;
+        let skip = addr - aligned_addr;
+        let chunk = usize::min(out.len() - done, DMA_CHUNK_LEN - skip);
+        let dma_len = (skip + chunk + 1) & !1;
+
+        unsafe {
+            let bounce = &mut BOUNCE_BUFFER.0;
+
+            data_cache_hit_writeback_invalidate(&bounce[..]);
+            dma_read_raw(bounce.as_mut_ptr(), aligned_addr, dma_len);
+
+            volatile_copy_nonoverlapping_memory(
+                out[done..].as_mut_ptr(),
+                uncached_addr((bounce.as_ptr() as *const u8).add(skip)),
+                chunk,
+            );
+        }
+
+        done += chunk;
+    }
+}
+
+// Writes `data` to the physical cartridge address `cart_addr`. The address
+// and the length must be multiples of 2, the buffer needs no alignment.
+#[inline]
+pub fn write(cart_addr: usize, data: &[u8]) {
+    let mut done = 0;
+
+    while done < data.len() {
+        let chunk = usize::min(data.len() - done, DMA_CHUNK_LEN);
+
+        unsafe {
+            let bounce = &mut BOUNCE_BUFFER.0;
+            let bytes = core::slice::from_raw_parts_mut(bounce.as_mut_ptr() as *mut u8, chunk);
+            bytes.copy_from_slice(&data[done..done + chunk]);
+
+            data_cache_hit_writeback(&bounce[..]);
+            dma_write_raw(bounce.as_ptr(), cart_addr + done, chunk);
+        }
+
+        done += chunk;
+    }
+}
+
+// Domain 2 timings for the save chips, from the console's libraries.
+fn set_domain2_timing(page_size: usize) {
+    dma_wait();
+
+    unsafe {
+        write_volatile(PI_BSD_DOM2_LAT, 0x05);
+        write_volatile(PI_BSD_DOM2_PWD, 0x0c);
+        write_volatile(PI_BSD_DOM2_PGS, page_size);
+        write_volatile(PI_BSD_DOM2_RLS, 0x02);
+    }
+}
+
+pub const SRAM_SIZE: usize = 32 * 1024;
+
+const SRAM_PROBE: [u8; 4] = [0x5a, 0xa5, 0x3c, 0xc3];
+
+// The last word of the chip, away from the save header at the start of each
+// copy.
+const SRAM_PROBE_ADDR: usize = CART_DOM2_ADDR2 + SRAM_SIZE - SRAM_PROBE.len();
+
+/// Looks for SRAM by writing to its last word. The probed bytes are restored.
+pub fn sram_detect() -> bool {
+    set_domain2_timing(0x0d);
+
+    let mut saved = [0; 4];
+    read(SRAM_PROBE_ADDR, &mut saved);
+
+    write(SRAM_PROBE_ADDR, &SRAM_PROBE);
+
+    let mut probe = [0; 4];
+    read(SRAM_PROBE_ADDR, &mut probe);
+
+    write(SRAM_PROBE_ADDR, &saved);
+
+    probe == SRAM_PROBE
+}
+
+pub fn sram_read(offset: usize, out: &mut [u8]) {
+    read(CART_DOM2_ADDR2 + offset, out);
+}
+
+// `offset` and the length must be multiples of 2.
+pub fn sram_write(offset: usize, data: &[u8]) {
+    write(CART_DOM2_ADDR2 + offset, data);
+}
+
+pub const FLASHRAM_SIZE: usize = 128 * 1024;
+pub const FLASHRAM_PAGE_SIZE: usize = 128;
+pub const FLASHRAM_SECTOR_SIZE: usize = 16 * 1024;
+
+const FLASHRAM_COMMAND: usize = CART_DOM2_ADDR2 + 0x1_0000;
+const FLASHRAM_IDENTIFY: u32 = 0xe100_0000;
+const FLASHRAM_STATUS: u32 = 0xd200_0000;
+const FLASHRAM_SET_ERASE_PAGE: u32 = 0x4b00_0000;
+const FLASHRAM_ERASE: u32 = 0x7800_0000;
+const FLASHRAM_LOAD_PAGE: u32 = 0xb400_0000;
+const FLASHRAM_PROGRAM_PAGE: u32 = 0xa500_0000;
+const FLASHRAM_READ: u32 = 0xf000_0000;
+
+const FLASHRAM_ID: u32 = 0x1111_8001;
+
+pub const FLASHRAM_STATUS_PROGRAM_BUSY: u8 = 0x01;
+pub const FLASHRAM_STATUS_ERASE_BUSY: u8 = 0x02;
+pub const FLASHRAM_STATUS_PROGRAM_OK: u8 = 0x04;
+pub const FLASHRAM_STATUS_ERASE_OK: u8 = 0x08;
+
+// Erasing a sector takes up to a few hundred ms.
+const FLASHRAM_TIMEOUT_US: i64 = 1_000_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlashError {
+    Timeout,
+    Failed,
+}
+
+/// Sends the identify command. SRAM does not decode the command address and
+/// can take the write at offset 0, that word is put back when no FlashRAM
+/// answers.
+pub fn flashram_detect() -> bool {
+    set_domain2_timing(0x0f);
+
+    let saved = io_read(CART_DOM2_ADDR2);
+
+    io_write(FLASHRAM_COMMAND, FLASHRAM_IDENTIFY);
+    let mut id = [0; 8];
+    read(CART_DOM2_ADDR2, &mut id);
+
+    if u32::from_be_bytes([id[0], id[1], id[2], id[3]]) == FLASHRAM_ID {
+        true
+    } else {
+        io_write(CART_DOM2_ADDR2, saved);
+        false
+    }
+}
+
+pub fn flashram_status() -> u8 {
+    io_write(FLASHRAM_COMMAND, FLASHRAM_STATUS);
+    io_read(CART_DOM2_ADDR2) as u8
+}
+
+fn flashram_wait(busy: u8, ok: u8) -> Result<(), FlashError> {
+    let start = current_time_us();
+
+    loop {
+        let status = flashram_status();
+
+        if status & busy == 0 {
+            return if status & ok != 0 {
+                Ok(())
+            } else {
+                Err(FlashError::Failed)
+            };
+        }
+
+        if current_time_us() - start > FLASHRAM_TIMEOUT_US {
+            return Err(FlashError::Timeout);
+        }
+    }
+}
+
+// The chip is on a 16 bit bus and reads are addressed in halfwords, byte
+// `offset` of the chip is at `bus_offset = offset / 2`. That has to be even,
+// and `out` at most `DMA_CHUNK_LEN` so it is a single DMA.
+pub fn flashram_read(bus_offset: usize, out: &mut [u8]) {
+    assert!(
+        bus_offset & 1 == 0 && out.len() <= DMA_CHUNK_LEN,
+        "Bad FlashRAM read"
+    );
+
+    io_write(FLASHRAM_COMMAND, FLASHRAM_READ);
+    read(CART_DOM2_ADDR2 + bus_offset, out);
+}
+
+/// Sets the 16 KB sector to 0xff.
+pub fn flashram_erase_sector(sector: usize) -> Result<(), FlashError> {
+    let page = sector * FLASHRAM_SECTOR_SIZE / FLASHRAM_PAGE_SIZE;
+
+    io_write(FLASHRAM_COMMAND, FLASHRAM_SET_ERASE_PAGE | page as u32);
+    io_write(FLASHRAM_COMMAND, FLASHRAM_ERASE);
+
+    flashram_wait(FLASHRAM_STATUS_ERASE_BUSY, FLASHRAM_STATUS_ERASE_OK)
+}
+
+/// Programming only clears bits, the page has to be erased first.
+pub fn flashram_program_page(
+    page: usize,
+    data: &[u8; FLASHRAM_PAGE_SIZE],
+) -> Result<(), FlashError> {
+    io_write(FLASHRAM_COMMAND, FLASHRAM_LOAD_PAGE);
+    write(CART_DOM2_ADDR2, data);
+    io_write(FLASHRAM_COMMAND, FLASHRAM_PROGRAM_PAGE | page as u32);
+
+    flashram_wait(FLASHRAM_STATUS_PROGRAM_BUSY, FLASHRAM_STATUS_PROGRAM_OK)
+}
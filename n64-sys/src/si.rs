@@ -1,7 +1,8 @@
 use crate::sys::{
-    data_cache_hit_writeback_invalidate, memory_barrier, uncached_addr, uncached_addr_mut,
-    virtual_to_physical, virtual_to_physical_mut,
+    current_time_us, data_cache_hit_writeback_invalidate, memory_barrier, uncached_addr,
+    uncached_addr_mut, virtual_to_physical, virtual_to_physical_mut,
 };
+use core::convert::TryInto;
 use core::intrinsics::volatile_copy_nonoverlapping_memory;
 use core::ptr::{read_volatile, write_volatile};
 
@@ -76,3 +77,230 @@ pub fn read_controllers(outblock: &mut [u64; 8]) {
 
     dma_pif_block(&READ_CON_BLOCK, outblock);
 }
+
+#[inline]
+pub fn read_controller_status(outblock: &mut [u64; 8]) {
+    static CON_STATUS_BLOCK: [u64; 8] = [
+        0xff010300ffffffff,
+        0xff010300ffffffff,
+        0xff010300ffffffff,
+        0xff010300ffffffff,
+        0xfe00000000000000,
+        0,
+        0,
+        1,
+    ];
+
+    dma_pif_block(&CON_STATUS_BLOCK, outblock);
+}
+
+pub const ACCESSORY_BLOCK_SIZE: usize = 32;
+
+const JOYBUS_STATUS: u8 = 0x00;
+const JOYBUS_READ_ACCESSORY: u8 = 0x02;
+const JOYBUS_WRITE_ACCESSORY: u8 = 0x03;
+const JOYBUS_READ_EEPROM: u8 = 0x04;
+const JOYBUS_WRITE_EEPROM: u8 = 0x05;
+
+// The cartridge is the channel after the four controller ports.
+const EEPROM_CHANNEL: usize = 4;
+const EEPROM_4K: u16 = 0x0080;
+const EEPROM_16K: u16 = 0x00c0;
+const EEPROM_STATUS_BUSY: u8 = 0x80;
+// A write takes up to 15 ms.
+const EEPROM_WRITE_TIMEOUT_US: i64 = 30_000;
+
+const PIF_BLOCK_END: u8 = 0xfe;
+const PIF_SKIP_CHANNEL: u8 = 0x00;
+const PIF_ERROR_MASK: u8 = 0xc0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JoybusError {
+    NoDevice,
+    // The data did not arrive intact, or there is no accessory to answer.
+    Crc,
+    // The EEPROM did not finish a write in time.
+    Timeout,
+}
+
+// Runs one joybus command on `port`, the response is written to `rx`.
+fn joybus_command(port: usize, tx: &[u8], rx: &mut [u8]) -> Result<(), JoybusError> {
+    let mut block = [0u8; 64];
+
+    let tx_start = port + 2;
+    let rx_start = tx_start + tx.len();
+
+    for channel in block[..port].iter_mut() {
+        *channel = PIF_SKIP_CHANNEL;
+    }
+    block[port] = tx.len() as u8;
+    block[port + 1] = rx.len() as u8;
+    block[tx_start..rx_start].copy_from_slice(tx);
+    for byte in block[rx_start..rx_start + rx.len()].iter_mut() {
+        *byte = 0xff;
+    }
+    block[rx_start + rx.len()] = PIF_BLOCK_END;
+    block[63] = 1;
+
+    let mut inblock = [0u64; 8];
+    for (word, bytes) in inblock.iter_mut().zip(block.chunks_exact(8)) {
+        *word = u64::from_be_bytes(bytes.try_into().unwrap());
+    }
+
+    let mut outblock = [0u64; 8];
+    dma_pif_block(&inblock, &mut outblock);
+
+    for (bytes, word) in block.chunks_exact_mut(8).zip(outblock.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+
+    if block[port + 1] & PIF_ERROR_MASK != 0 {
+        return Err(JoybusError::NoDevice);
+    }
+
+    rx.copy_from_slice(&block[rx_start..rx_start + rx.len()]);
+
+    Ok(())
+}
+
+/// The accessory address with its 5 bit CRC in the low bits. Addresses are
+/// 32 byte aligned.
+pub fn accessory_address_crc(address: u16) -> u16 {
+    const XOR_TABLE: [u16; 16] = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x15, 0x1f, 0x0b, 0x16, 0x19, 0x07, 0x0e, 0x1c, 0x0d, 0x1a,
+        0x01,
+    ];
+
+    let address = address & !0x1f;
+    let mut crc = 0;
+
+    for bit in 5..16 {
+        if address & (1 << bit) != 0 {
+            crc ^= XOR_TABLE[bit];
+        }
+    }
+
+    address | crc
+}
+
+/// CRC-8 with polynomial 0x85 that the accessory sends after data.
+pub fn accessory_data_crc(data: &[u8; ACCESSORY_BLOCK_SIZE]) -> u8 {
+    let mut crc: u8 = 0;
+
+    for i in 0..=ACCESSORY_BLOCK_SIZE {
+        for bit in (0..8).rev() {
+            let xor = if crc & 0x80 != 0 { 0x85 } else { 0x00 };
+
+            crc <<= 1;
+
+            if i < ACCESSORY_BLOCK_SIZE && data[i] & (1 << bit) != 0 {
+                crc |= 1;
+            }
+
+            crc ^= xor;
+        }
+    }
+
+    crc
+}
+
+pub fn read_accessory(
+    port: usize,
+    address: u16,
+    data: &mut [u8; ACCESSORY_BLOCK_SIZE],
+) -> Result<(), JoybusError> {
+    let address = accessory_address_crc(address).to_be_bytes();
+    let tx = [JOYBUS_READ_ACCESSORY, address[0], address[1]];
+    let mut rx = [0; ACCESSORY_BLOCK_SIZE + 1];
+
+    joybus_command(port, &tx, &mut rx)?;
+
+    data.copy_from_slice(&rx[..ACCESSORY_BLOCK_SIZE]);
+
+    if rx[ACCESSORY_BLOCK_SIZE] != accessory_data_crc(data) {
+        return Err(JoybusError::Crc);
+    }
+
+    Ok(())
+}
+
+pub fn write_accessory(
+    port: usize,
+    address: u16,
+    data: &[u8; ACCESSORY_BLOCK_SIZE],
+) -> Result<(), JoybusError> {
+    let address = accessory_address_crc(address).to_be_bytes();
+    let mut tx = [0; ACCESSORY_BLOCK_SIZE + 3];
+    tx[0] = JOYBUS_WRITE_ACCESSORY;
+    tx[1] = address[0];
+    tx[2] = address[1];
+    tx[3..].copy_from_slice(data);
+    let mut rx = [0; 1];
+
+    joybus_command(port, &tx, &mut rx)?;
+
+    if rx[0] != accessory_data_crc(data) {
+        return Err(JoybusError::Crc);
+    }
+
+    Ok(())
+}
+
+pub const EEPROM_BLOCK_SIZE: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EepromType {
+    Eeprom4k,
+    Eeprom16k,
+}
+
+impl EepromType {
+    #[inline]
+    pub fn blocks(self) -> usize {
+        match self {
+            EepromType::Eeprom4k => 64,
+            EepromType::Eeprom16k => 256,
+        }
+    }
+}
+
+fn eeprom_status() -> Result<[u8; 3], JoybusError> {
+    let mut rx = [0; 3];
+    joybus_command(EEPROM_CHANNEL, &[JOYBUS_STATUS], &mut rx)?;
+    Ok(rx)
+}
+
+/// The EEPROM on the cartridge, if there is one.
+pub fn eeprom_type() -> Option<EepromType> {
+    let status = eeprom_status().ok()?;
+
+    match u16::from_be_bytes([status[0], status[1]]) {
+        EEPROM_4K => Some(EepromType::Eeprom4k),
+        EEPROM_16K => Some(EepromType::Eeprom16k),
+        _ => None,
+    }
+}
+
+pub fn read_eeprom(block: u8, data: &mut [u8; EEPROM_BLOCK_SIZE]) -> Result<(), JoybusError> {
+    joybus_command(EEPROM_CHANNEL, &[JOYBUS_READ_EEPROM, block], data)
+}
+
+/// Writes a block and waits for the EEPROM to finish.
+pub fn write_eeprom(block: u8, data: &[u8; EEPROM_BLOCK_SIZE]) -> Result<(), JoybusError> {
+    let mut tx = [0; EEPROM_BLOCK_SIZE + 2];
+    tx[0] = JOYBUS_WRITE_EEPROM;
+    tx[1] = block;
+    tx[2..].copy_from_slice(data);
+    let mut rx = [0; 1];
+
+    joybus_command(EEPROM_CHANNEL, &tx, &mut rx)?;
+
+    let start = current_time_us();
+    while eeprom_status()?[2] & EEPROM_STATUS_BUSY != 0 {
+        if current_time_us() - start > EEPROM_WRITE_TIMEOUT_US {
+            return Err(JoybusError::Timeout);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+/// Most sound is made at this rate, see `AudioConfig::default`.
+pub const DEFAULT_SAMPLE_RATE: u32 = 22050;
+
+/// About 23 ms at the default rate, see `AudioConfig::default`.
+pub const DEFAULT_BUFFER_FRAMES: usize = 512;
+
+/// The largest `buffer_frames`. The console allocates its buffers for the
+/// configured size when audio starts.
+pub const MAX_BUFFER_FRAMES: usize = 2048;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AudioConfig {
+    /// Usually 22050, 32000 or 44100. The console can only get close to it,
+    /// what it really runs at is `Audio::real_frequency`.
+    pub sample_rate: u32,
+    /// Stereo frames in every buffer `Audio::update` hands out. Even, and at
+    /// most `MAX_BUFFER_FRAMES`.
+    pub buffer_frames: usize,
+}
+
+impl AudioConfig {
+    #[inline]
+    pub fn buffer_samples(self) -> usize {
+        2 * self.buffer_frames
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        AudioConfig {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            buffer_frames: DEFAULT_BUFFER_FRAMES,
+        }
+    }
+}
@@ -1,7 +1,11 @@
 #![no_std]
 
+pub use audio_config::{
+    AudioConfig, DEFAULT_BUFFER_FRAMES, DEFAULT_SAMPLE_RATE, MAX_BUFFER_FRAMES,
+};
 pub use rdp_command::RdpCommand;
 pub use video_mode::VideoMode;
 
+mod audio_config;
 mod rdp_command;
 mod video_mode;
@@ -1,12 +1,27 @@
 use n64_sys::ai;
+use n64_types::AudioConfig;
 
-pub struct Audio {}
+pub struct Audio {
+    config: AudioConfig,
+}
 
 impl Audio {
     #[inline]
-    pub(crate) fn new() -> Self {
-        ai::init();
-        Self {}
+    pub(crate) fn new(config: AudioConfig) -> Self {
+        ai::init(config);
+        Self { config }
+    }
+
+    #[inline]
+    pub fn config(&self) -> AudioConfig {
+        self.config
+    }
+
+    /// The rate buffers are played at, only close to `config().sample_rate`
+    /// on the console. Mix and time music at this rate.
+    #[inline]
+    pub fn real_frequency(&self) -> u32 {
+        ai::real_frequency() as u32
     }
 
     #[inline]
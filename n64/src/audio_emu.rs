@@ -1,16 +1,21 @@
 use cpal::{
     self,
     traits::{DeviceTrait, EventLoopTrait, HostTrait},
-    StreamData, UnknownTypeOutputBuffer,
+    EventLoop, StreamData, UnknownTypeOutputBuffer,
 };
+use n64_audio::wav;
 use std::error::Error;
 use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
     sync::mpsc::{channel, Receiver, Sender},
     thread,
+    time::{Duration, Instant},
 };
 use rubato::{Resampler, SincFixedIn, InterpolationType, InterpolationParameters, WindowFunction};
+use n64_types::AudioConfig;
 
-const BUFFER_NO_SAMPLES: usize = 2 * 512;
 const BUFFER_COUNT: usize = 4;
 
 fn get_sample<T>(
@@ -25,8 +30,8 @@ fn get_sample<T>(
         if let Ok(buffer) = to_audio_receiver.try_recv() {
 
             let mut channels = Vec::new();    
-            channels.push(Vec::with_capacity(BUFFER_NO_SAMPLES / 2));
-            channels.push(Vec::with_capacity(BUFFER_NO_SAMPLES / 2));
+            channels.push(Vec::with_capacity(buffer.samples.len() / 2));
+            channels.push(Vec::with_capacity(buffer.samples.len() / 2));
 
             for frame in buffer.samples.chunks_exact(2) {
                 channels[0].push(frame[0] as f32 / (i16::MAX as f32));
@@ -64,10 +69,7 @@ fn get_sample<T>(
     })
 }
 
-fn audio_thread(
-    to_audio_receiver: Receiver<Buffer>,
-    from_audio_sender: Sender<Buffer>,
-) -> Result<(), Box<dyn Error>> {
+fn open_device(config: AudioConfig) -> Result<(EventLoop, SincFixedIn<f32>), Box<dyn Error>> {
     let host = cpal::default_host();
     let device = host
         .default_output_device()
@@ -88,13 +90,22 @@ fn audio_thread(
         window: WindowFunction::BlackmanHarris2,
     };
 
-    let mut resampler = SincFixedIn::<f32>::new(
-        format.sample_rate.0 as f64 / 22050 as f64,
+    let resampler = SincFixedIn::<f32>::new(
+        format.sample_rate.0 as f64 / config.sample_rate as f64,
         params,
-        BUFFER_NO_SAMPLES / 2,
+        config.buffer_frames,
         2,
     );
 
+    Ok((event_loop, resampler))
+}
+
+fn audio_thread(
+    event_loop: EventLoop,
+    mut resampler: SincFixedIn<f32>,
+    to_audio_receiver: Receiver<Buffer>,
+    from_audio_sender: Sender<Buffer>,
+) -> ! {
     let mut current_buffer = None;
     let mut current_index = 0;
 
@@ -155,14 +166,134 @@ fn audio_thread(
     });
 }
 
+// Hands the buffers back at the rate they would have played, or as soon as
+// they are consumed.
+fn consume_buffers(
+    config: AudioConfig,
+    real_time: bool,
+    to_audio_receiver: &Receiver<Buffer>,
+    from_audio_sender: &Sender<Buffer>,
+    mut f: impl FnMut(&[i16]) -> io::Result<()>,
+) -> io::Result<()> {
+    let start = Instant::now();
+    let mut frames = 0;
+
+    for buffer in to_audio_receiver.iter() {
+        f(&buffer.samples)?;
+        frames += (buffer.samples.len() / 2) as u64;
+
+        if real_time {
+            let due = start + Duration::from_micros(frames * 1_000_000 / config.sample_rate as u64);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+
+        from_audio_sender.send(buffer).ok();
+    }
+
+    Ok(())
+}
+
+fn write_wav(
+    config: AudioConfig,
+    path: &Path,
+    real_time: bool,
+    to_audio_receiver: &Receiver<Buffer>,
+    from_audio_sender: &Sender<Buffer>,
+) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&wav::header(2, config.sample_rate, 0))?;
+
+    let mut data_size = 0;
+    let mut bytes = Vec::new();
+
+    consume_buffers(
+        config,
+        real_time,
+        to_audio_receiver,
+        from_audio_sender,
+        |samples| {
+            bytes.clear();
+            for sample in samples {
+                bytes.extend_from_slice(&sample.to_le_bytes());
+            }
+
+            file.write_all(&bytes)?;
+            data_size += bytes.len() as u32;
+
+            // Kept up to date so the file is whole however the game exits.
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&wav::header(2, config.sample_rate, data_size))?;
+            file.seek(SeekFrom::End(0))?;
+
+            Ok(())
+        },
+    )
+}
+
+fn sink_thread(
+    config: AudioConfig,
+    sink: AudioSink,
+    to_audio_receiver: Receiver<Buffer>,
+    from_audio_sender: Sender<Buffer>,
+) {
+    let result: Result<(), Box<dyn Error>> = match sink {
+        AudioSink::Device => match open_device(config) {
+            Ok((event_loop, resampler)) => {
+                audio_thread(event_loop, resampler, to_audio_receiver, from_audio_sender)
+            }
+            Err(e) => Err(e),
+        },
+        AudioSink::Null { real_time } => consume_buffers(
+            config,
+            real_time,
+            &to_audio_receiver,
+            &from_audio_sender,
+            |_| Ok(()),
+        )
+        .map_err(|e| e.into()),
+        AudioSink::Wav { path, real_time } => write_wav(
+            config,
+            &path,
+            real_time,
+            &to_audio_receiver,
+            &from_audio_sender,
+        )
+        .map_err(|e| e.into()),
+    };
+
+    // The game still needs its buffers back as if they were played.
+    if let Err(e) = result {
+        println!("Audio Error: {}, audio is thrown away", e);
+        consume_buffers(config, true, &to_audio_receiver, &from_audio_sender, |_| {
+            Ok(())
+        })
+        .ok();
+    }
+}
+
+/// Where the PC build sends its audio.
+#[derive(Clone, Debug)]
+pub enum AudioSink {
+    /// The default output device. Falls back to `Null` in real time if there
+    /// is none.
+    Device,
+    /// Throws the audio away, either at the rate it would have played or as
+    /// fast as the game makes it.
+    Null { real_time: bool },
+    /// Writes the audio to a 16 bit stereo WAV file.
+    Wav { path: PathBuf, real_time: bool },
+}
+
 struct Buffer {
     samples: Box<[i16]>,
 }
 
 impl Buffer {
-    fn new() -> Self {
+    fn new(len: usize) -> Self {
         let mut samples = Vec::new();
-        samples.resize_with(BUFFER_NO_SAMPLES, Default::default);
+        samples.resize_with(len, Default::default);
         Self {
             samples: samples.into_boxed_slice(),
         }
@@ -170,48 +301,81 @@ impl Buffer {
 }
 
 pub struct Audio {
-    to_audio_sender: Sender<Buffer>,
-    from_audio_receiver: Receiver<Buffer>,
+    config: AudioConfig,
+    sink: AudioSink,
+    to_audio_sender: Option<Sender<Buffer>>,
+    from_audio_receiver: Option<Receiver<Buffer>>,
     buffers: Vec<Buffer>,
 }
 
 impl Audio {
     #[inline]
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(config: AudioConfig) -> Self {
         let mut buffers = Vec::new();
 
         for _ in 0..BUFFER_COUNT {
-            buffers.push(Buffer::new());
+            buffers.push(Buffer::new(config.buffer_samples()));
         }
 
-        let (to_audio_sender, to_audio_receiver) = channel();
-        let (from_audio_sender, from_audio_receiver) = channel();
-
-        thread::spawn(
-            move || match audio_thread(to_audio_receiver, from_audio_sender) {
-                Ok(()) => (),
-                Err(e) => {
-                    println!("Audio Error: {}", e);
-                }
-            },
-        );
-
         Self {
-            to_audio_sender,
-            from_audio_receiver,
+            config,
+            sink: AudioSink::Device,
+            to_audio_sender: None,
+            from_audio_receiver: None,
             buffers,
         }
     }
 
+    /// Only before the first `update`, that is when the sink is opened.
+    pub fn set_sink(&mut self, sink: AudioSink) {
+        assert!(
+            self.to_audio_sender.is_none(),
+            "The audio sink is already open"
+        );
+        self.sink = sink;
+    }
+
+    #[inline]
+    pub fn sink(&self) -> &AudioSink {
+        &self.sink
+    }
+
+    #[inline]
+    pub fn config(&self) -> AudioConfig {
+        self.config
+    }
+
+    /// The rate buffers are played at. The resampler turns it into whatever
+    /// the sound card wants, so it is exactly `config().sample_rate` here.
+    #[inline]
+    pub fn real_frequency(&self) -> u32 {
+        self.config.sample_rate
+    }
+
     #[inline]
     pub fn update(&mut self, mut f: impl FnMut(&mut [i16])) {
-        while let Ok(buffer) = self.from_audio_receiver.try_recv() {
+        if self.to_audio_sender.is_none() {
+            let (to_audio_sender, to_audio_receiver) = channel();
+            let (from_audio_sender, from_audio_receiver) = channel();
+            let config = self.config;
+            let sink = self.sink.clone();
+
+            thread::spawn(move || sink_thread(config, sink, to_audio_receiver, from_audio_sender));
+
+            self.to_audio_sender = Some(to_audio_sender);
+            self.from_audio_receiver = Some(from_audio_receiver);
+        }
+
+        let to_audio_sender = self.to_audio_sender.as_ref().unwrap();
+        let from_audio_receiver = self.from_audio_receiver.as_ref().unwrap();
+
+        while let Ok(buffer) = from_audio_receiver.try_recv() {
             self.buffers.push(buffer);
         }
 
         for mut buffer in self.buffers.drain(..) {
             f(&mut buffer.samples);
-            self.to_audio_sender
+            to_audio_sender
                 .send(buffer)
                 .map_err(|_| println!("Failed to send buffer to audio system"))
                 .ok();
@@ -0,0 +1,299 @@
+use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not};
+use n64_math::Vec2;
+
+pub const MAX_CONTROLLERS: usize = 4;
+
+/// Full deflection of the stick on a real controller, about ±80. Raw values
+/// past it are clamped by `ControllerState::stick`.
+pub const STICK_RANGE: f32 = 80.0;
+
+/// A set of controller buttons. The bits match the button word returned by
+/// the PIF.
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct Buttons(u16);
+
+impl Buttons {
+    pub const A: Buttons = Buttons(0x8000);
+    pub const B: Buttons = Buttons(0x4000);
+    pub const Z: Buttons = Buttons(0x2000);
+    pub const START: Buttons = Buttons(0x1000);
+    pub const UP: Buttons = Buttons(0x0800);
+    pub const DOWN: Buttons = Buttons(0x0400);
+    pub const LEFT: Buttons = Buttons(0x0200);
+    pub const RIGHT: Buttons = Buttons(0x0100);
+    pub const L: Buttons = Buttons(0x0020);
+    pub const R: Buttons = Buttons(0x0010);
+    pub const C_UP: Buttons = Buttons(0x0008);
+    pub const C_DOWN: Buttons = Buttons(0x0004);
+    pub const C_LEFT: Buttons = Buttons(0x0002);
+    pub const C_RIGHT: Buttons = Buttons(0x0001);
+
+    /// The N64 Mouse reports its buttons as A and B.
+    pub const MOUSE_LEFT: Buttons = Buttons::A;
+    pub const MOUSE_RIGHT: Buttons = Buttons::B;
+
+    pub const DPAD: Buttons = Buttons(0x0f00);
+    pub const C: Buttons = Buttons(0x000f);
+    // Bits 6 and 7 are the reset flag and unused.
+    pub const ALL: Buttons = Buttons(0xff3f);
+
+    #[inline]
+    pub const fn empty() -> Buttons {
+        Buttons(0)
+    }
+
+    #[inline]
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn from_bits_truncate(bits: u16) -> Buttons {
+        Buttons(bits & Buttons::ALL.0)
+    }
+
+    #[inline]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// True if all buttons in `other` are in `self`.
+    #[inline]
+    pub const fn contains(self, other: Buttons) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// True if any button in `other` is in `self`.
+    #[inline]
+    pub const fn intersects(self, other: Buttons) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for Buttons {
+    type Output = Buttons;
+
+    #[inline]
+    fn bitor(self, other: Buttons) -> Buttons {
+        Buttons(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Buttons {
+    #[inline]
+    fn bitor_assign(&mut self, other: Buttons) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for Buttons {
+    type Output = Buttons;
+
+    #[inline]
+    fn bitand(self, other: Buttons) -> Buttons {
+        Buttons(self.0 & other.0)
+    }
+}
+
+impl BitAndAssign for Buttons {
+    #[inline]
+    fn bitand_assign(&mut self, other: Buttons) {
+        self.0 &= other.0;
+    }
+}
+
+impl Not for Buttons {
+    type Output = Buttons;
+
+    #[inline]
+    fn not(self) -> Buttons {
+        Buttons(!self.0 & Buttons::ALL.0)
+    }
+}
+
+/// What is plugged into a controller port, as reported by the PIF status
+/// command.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Device {
+    None,
+    Controller,
+    Mouse,
+    Other(u16),
+}
+
+/// What is plugged into the accessory slot of a controller.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Accessory {
+    None,
+    /// Something that could not be identified, see `Controllers::identify_pak`.
+    Pak,
+    ControllerPak,
+    RumblePak,
+}
+
+/// Input from one controller port, updated once per frame by `Controllers`.
+/// Disconnected ports read as no buttons held and a centered stick.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ControllerState {
+    pub(crate) device: Device,
+    pub(crate) accessory: Accessory,
+    buttons: Buttons,
+    previous: Buttons,
+    // Frames each button has been held, indexed by bit.
+    held: [u32; 16],
+    x: i8,
+    y: i8,
+}
+
+impl ControllerState {
+    pub(crate) const DISCONNECTED: ControllerState = ControllerState {
+        device: Device::None,
+        accessory: Accessory::None,
+        buttons: Buttons::empty(),
+        previous: Buttons::empty(),
+        held: [0; 16],
+        x: 0,
+        y: 0,
+    };
+
+    pub(crate) fn update(&mut self, buttons: Buttons, x: i8, y: i8) {
+        self.previous = self.buttons;
+        self.buttons = buttons;
+
+        for (bit, held) in self.held.iter_mut().enumerate() {
+            if buttons.bits() & (1 << bit) != 0 {
+                *held = held.saturating_add(1);
+            } else {
+                *held = 0;
+            }
+        }
+
+        self.x = x;
+        self.y = y;
+    }
+
+    // Held buttons are released on the frame the controller goes away.
+    pub(crate) fn disconnect(&mut self) {
+        self.update(Buttons::empty(), 0, 0);
+        self.device = Device::None;
+        self.accessory = Accessory::None;
+    }
+
+    #[inline]
+    pub fn connected(&self) -> bool {
+        self.device != Device::None
+    }
+
+    #[inline]
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    #[inline]
+    pub fn accessory(&self) -> Accessory {
+        self.accessory
+    }
+
+    /// All buttons held this frame.
+    #[inline]
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    /// True if all of `buttons` are held.
+    #[inline]
+    pub fn pressed(&self, buttons: Buttons) -> bool {
+        self.buttons.contains(buttons)
+    }
+
+    /// True on the first frame all of `buttons` are held.
+    #[inline]
+    pub fn just_pressed(&self, buttons: Buttons) -> bool {
+        self.pressed(buttons) && !self.previous.contains(buttons)
+    }
+
+    /// True on the first frame any of `buttons` is no longer held after all of
+    /// them were.
+    #[inline]
+    pub fn just_released(&self, buttons: Buttons) -> bool {
+        !self.pressed(buttons) && self.previous.contains(buttons)
+    }
+
+    /// Frames all of `buttons` have been held, 1 on the frame they were
+    /// pressed and 0 if any of them is up.
+    pub fn held_frames(&self, buttons: Buttons) -> u32 {
+        if buttons.is_empty() {
+            return 0;
+        }
+
+        self.held
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| buttons.bits() & (1 << bit) != 0)
+            .map(|(_, &held)| held)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Raw stick x, positive to the right. On a mouse it is the movement, see
+    /// `mouse_delta`.
+    #[inline]
+    pub fn x(&self) -> i8 {
+        self.x
+    }
+
+    /// Raw stick y, positive up.
+    #[inline]
+    pub fn y(&self) -> i8 {
+        self.y
+    }
+
+    /// Mouse movement since the last frame in mouse counts, x to the right
+    /// and y up. Zero if the device is not a mouse.
+    #[inline]
+    pub fn mouse_delta(&self) -> (i8, i8) {
+        if self.device == Device::Mouse {
+            (self.x, self.y)
+        } else {
+            (0, 0)
+        }
+    }
+
+    /// Stick x in -1.0..=1.0 with a deadzone on the axis, see `stick`.
+    #[inline]
+    pub fn stick_x(&self, deadzone: f32) -> f32 {
+        apply_deadzone(self.x as f32 / STICK_RANGE, deadzone)
+    }
+
+    /// Stick y in -1.0..=1.0 with a deadzone on the axis, see `stick`.
+    #[inline]
+    pub fn stick_y(&self, deadzone: f32) -> f32 {
+        apply_deadzone(self.y as f32 / STICK_RANGE, deadzone)
+    }
+
+    /// Stick position with y up and a length of at most 1.0. `deadzone` is a
+    /// fraction of the full range, the stick reads as zero inside it and the
+    /// travel outside it is rescaled to start from zero.
+    pub fn stick(&self, deadzone: f32) -> Vec2 {
+        let raw = Vec2::new(self.x as f32 / STICK_RANGE, self.y as f32 / STICK_RANGE);
+        let length = raw.length();
+
+        if length <= deadzone {
+            return Vec2::zero();
+        }
+
+        let scaled = ((length - deadzone) / (1.0 - deadzone)).min(1.0);
+
+        raw * (scaled / length)
+    }
+}
+
+fn apply_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value > deadzone {
+        ((value - deadzone) / (1.0 - deadzone)).min(1.0)
+    } else if value < -deadzone {
+        ((value + deadzone) / (1.0 - deadzone)).max(-1.0)
+    } else {
+        0.0
+    }
+}
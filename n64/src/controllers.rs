@@ -1,98 +1,195 @@
+use crate::controller_state::{Accessory, Buttons, ControllerState, Device, MAX_CONTROLLERS};
 use crate::graphics::Graphics;
+use crate::pak::{PakError, PAK_BLOCK_SIZE};
+use crate::replay::{Recording, Replay};
 use n64_sys::si;
 
+// The status command is slower than reading buttons, plugging things in is
+// picked up within half a second.
+const STATUS_INTERVAL: u32 = 30;
+
+// Top bits of the rx byte in a PIF command block.
+const PIF_ERROR_MASK: u64 = 0xc0;
+
+const DEVICE_TYPE_CONTROLLER: u16 = 0x0500;
+const DEVICE_TYPE_MOUSE: u16 = 0x0200;
+
+const STATUS_PAK_INSERTED: u64 = 0x01;
+
+fn pak_error(error: si::JoybusError) -> PakError {
+    match error {
+        si::JoybusError::NoDevice => PakError::NoController,
+        si::JoybusError::Crc | si::JoybusError::Timeout => PakError::Crc,
+    }
+}
+
+#[inline]
+fn pif_error(block: u64) -> bool {
+    (block >> 40) & PIF_ERROR_MASK != 0
+}
+
 pub struct Controllers {
     data: [u64; 8],
+    status: [u64; 8],
+    ports: [ControllerState; MAX_CONTROLLERS],
+    replay: Replay,
+    until_status: u32,
 }
 
 impl Controllers {
     #[inline]
     pub fn new() -> Controllers {
-        Controllers { data: [0; 8] }
-    }
+        Controllers {
+            data: [0; 8],
+            status: [0; 8],
+            ports: [ControllerState::DISCONNECTED; MAX_CONTROLLERS],
+            replay: Replay::default(),
+            until_status: 0,
+        }
+    }
+
+    /// Reads all ports. `frame_time_us` is the measured time since the last
+    /// frame, the returned frame time is the one to simulate with. During
+    /// playback both come from the recording.
+    pub fn update(&mut self, _graphics: &Graphics, frame_time_us: i64) -> i64 {
+        if let Some(frame_time_us) = self.replay.play(&mut self.ports) {
+            return frame_time_us;
+        }
+
+        if self.until_status == 0 {
+            self.update_status();
+            self.until_status = STATUS_INTERVAL;
+        }
+        self.until_status -= 1;
 
-    #[inline]
-    pub fn update(&mut self, _graphics: &Graphics) {
         si::read_controllers(&mut self.data);
-    }
 
-    #[inline]
-    pub fn x(&self) -> i8 {
-        ((self.data[0] >> 8) & 0xff) as i8
-    }
+        for (port, &data) in self.ports.iter_mut().zip(self.data.iter()) {
+            if pif_error(data) {
+                port.disconnect();
+                continue;
+            }
 
-    #[inline]
-    pub fn y(&self) -> i8 {
-        (self.data[0] & 0xff) as i8
-    }
+            if port.device == Device::None {
+                // Plugged in since the last status command. Taken as a
+                // controller until the status on the next frame tells if it
+                // is a mouse.
+                port.device = Device::Controller;
+                self.until_status = 0;
+            }
 
-    #[inline]
-    pub fn a(&self) -> bool {
-        self.data[0] & 0x8000_0000 > 0
-    }
+            port.update(
+                Buttons::from_bits_truncate((data >> 16) as u16),
+                ((data >> 8) & 0xff) as i8,
+                (data & 0xff) as i8,
+            );
+        }
 
-    #[inline]
-    pub fn b(&self) -> bool {
-        self.data[0] & 0x4000_0000 > 0
+        self.replay.record(&self.ports, frame_time_us)
     }
 
-    #[inline]
-    pub fn z(&self) -> bool {
-        self.data[0] & 0x2000_0000 > 0
+    fn update_status(&mut self) {
+        si::read_controller_status(&mut self.status);
+
+        for i in 0..MAX_CONTROLLERS {
+            let status = self.status[i];
+            let port = &mut self.ports[i];
+
+            if pif_error(status) {
+                port.disconnect();
+                continue;
+            }
+
+            port.device = match ((status >> 16) & 0xffff) as u16 {
+                DEVICE_TYPE_CONTROLLER => Device::Controller,
+                DEVICE_TYPE_MOUSE => Device::Mouse,
+                other => Device::Other(other),
+            };
+
+            // Only controllers have an accessory slot.
+            if port.device != Device::Controller || (status >> 8) & STATUS_PAK_INSERTED == 0 {
+                port.accessory = Accessory::None;
+            } else if port.accessory == Accessory::None {
+                // Newly inserted.
+                port.accessory = Accessory::Pak;
+                self.identify_pak(i);
+            }
+        }
     }
 
-    #[inline]
-    pub fn start(&self) -> bool {
-        self.data[0] & 0x1000_0000 > 0
+    pub fn read_pak(
+        &mut self,
+        port: usize,
+        address: u16,
+        data: &mut [u8; PAK_BLOCK_SIZE],
+    ) -> Result<(), PakError> {
+        self.check_pak(port)?;
+        si::read_accessory(port, address, data).map_err(pak_error)
     }
 
-    #[inline]
-    pub fn up(&self) -> bool {
-        self.data[0] & 0x0800_0000 > 0
+    pub fn write_pak(
+        &mut self,
+        port: usize,
+        address: u16,
+        data: &[u8; PAK_BLOCK_SIZE],
+    ) -> Result<(), PakError> {
+        self.check_pak(port)?;
+        si::write_accessory(port, address, data).map_err(pak_error)
     }
 
-    #[inline]
-    pub fn down(&self) -> bool {
-        self.data[0] & 0x0400_0000 > 0
+    fn check_pak(&self, port: usize) -> Result<(), PakError> {
+        let port = &self.ports[port];
+
+        if !port.connected() {
+            Err(PakError::NoController)
+        } else if port.accessory() == Accessory::None {
+            Err(PakError::NoPak)
+        } else {
+            Ok(())
+        }
     }
 
     #[inline]
-    pub fn left(&self) -> bool {
-        self.data[0] & 0x0200_0000 > 0
+    pub fn port(&self, port: usize) -> &ControllerState {
+        &self.ports[port]
     }
 
     #[inline]
-    pub fn right(&self) -> bool {
-        self.data[0] & 0x0100_0000 > 0
+    pub fn ports(&self) -> &[ControllerState; MAX_CONTROLLERS] {
+        &self.ports
     }
 
     #[inline]
-    pub fn l(&self) -> bool {
-        self.data[0] & 0x0020_0000 > 0
+    pub(crate) fn port_mut(&mut self, port: usize) -> &mut ControllerState {
+        &mut self.ports[port]
     }
 
+    /// Records the input of every following frame, `seed` is stored for the
+    /// replay to seed the random generator with.
     #[inline]
-    pub fn r(&self) -> bool {
-        self.data[0] & 0x0010_0000 > 0
+    pub fn start_recording(&mut self, seed: u32) {
+        self.replay.start_recording(seed);
     }
 
+    /// The recording so far.
     #[inline]
-    pub fn c_up(&self) -> bool {
-        self.data[0] & 0x0008_0000 > 0
+    pub fn recording(&self) -> Option<&Recording> {
+        self.replay.recording()
     }
 
     #[inline]
-    pub fn c_down(&self) -> bool {
-        self.data[0] & 0x0004_0000 > 0
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.replay.stop_recording()
     }
 
+    /// Input comes from `recording` instead of the controllers until it ends.
     #[inline]
-    pub fn c_left(&self) -> bool {
-        self.data[0] & 0x0002_0000 > 0
+    pub fn start_playback(&mut self, recording: Recording) {
+        self.replay.start_playback(recording);
     }
 
     #[inline]
-    pub fn c_right(&self) -> bool {
-        self.data[0] & 0x0001_0000 > 0
+    pub fn is_playing_back(&self) -> bool {
+        self.replay.is_playing_back()
     }
 }
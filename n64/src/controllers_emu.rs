@@ -1,121 +1,227 @@
-use std::collections::HashSet;
-use winit::event::VirtualKeyCode;
+use crate::controller_state::{
+    Accessory, Buttons, ControllerState, Device, MAX_CONTROLLERS, STICK_RANGE,
+};
 use crate::graphics::Graphics;
+use crate::pak::{PakError, PAK_BLOCK_SIZE};
+use crate::replay::{Recording, Replay};
+use bindings::{Bindings, Input, Target, BINDINGS_PATH};
+use pak::EmuPak;
+
+pub(crate) mod bindings;
+pub(crate) mod pak;
+
+fn move_towards(current: f32, target: f32, max_step: f32) -> f32 {
+    if (target - current).abs() <= max_step {
+        target
+    } else if target > current {
+        current + max_step
+    } else {
+        current - max_step
+    }
+}
+
+fn to_raw_stick(value: f32) -> i8 {
+    if value > STICK_RANGE {
+        STICK_RANGE as i8
+    } else if value < -STICK_RANGE {
+        -STICK_RANGE as i8
+    } else {
+        value as i8
+    }
+}
+
+// Motion past what one frame can report is carried over to the next.
+fn to_mouse_delta(value: f32) -> i8 {
+    if value > i8::MAX as f32 {
+        i8::MAX
+    } else if value < i8::MIN as f32 {
+        i8::MIN
+    } else {
+        value as i8
+    }
+}
 
 pub struct Controllers {
-    data: HashSet<VirtualKeyCode>,
+    bindings: Bindings,
+    paks: [EmuPak; MAX_CONTROLLERS],
+    ports: [ControllerState; MAX_CONTROLLERS],
+    replay: Replay,
+    // Key driven stick position per port, in raw stick units.
+    sticks: [(f32, f32); MAX_CONTROLLERS],
+    // Mouse motion not yet reported, in N64 Mouse counts.
+    mouse_remainders: [(f32, f32); MAX_CONTROLLERS],
 }
 
 impl Controllers {
     #[inline]
     pub fn new() -> Controllers {
+        let bindings = Bindings::load(BINDINGS_PATH);
+        let paks = [
+            EmuPak::new(&bindings.ports[0].pak),
+            EmuPak::new(&bindings.ports[1].pak),
+            EmuPak::new(&bindings.ports[2].pak),
+            EmuPak::new(&bindings.ports[3].pak),
+        ];
+
         Controllers {
-            data: HashSet::new(),
+            bindings,
+            paks,
+            ports: [ControllerState::DISCONNECTED; MAX_CONTROLLERS],
+            replay: Replay::default(),
+            sticks: [(0.0, 0.0); MAX_CONTROLLERS],
+            mouse_remainders: [(0.0, 0.0); MAX_CONTROLLERS],
         }
     }
 
-    #[inline]
-    pub fn update(&mut self, graphics: &Graphics) {
-        self.data = graphics.keys_down.clone();
-    }
-
-    #[inline]
-    pub fn x(&self) -> i8 {
-        let mut res = 0;
-
-        if self.data.contains(&VirtualKeyCode::Right) {
-            res += 127;
-        }
-
-        if self.data.contains(&VirtualKeyCode::Left) {
-            res -= 127;
+    /// Reads all ports and saves controller paks written since the last frame.
+    /// `frame_time_us` is the measured time since the last frame, the returned
+    /// frame time is the one to simulate with. During playback both come from
+    /// the recording.
+    pub fn update(&mut self, graphics: &Graphics, frame_time_us: i64) -> i64 {
+        for pak in self.paks.iter_mut() {
+            pak.flush();
         }
 
-        res
-    }
-
-    #[inline]
-    pub fn y(&self) -> i8 {
-        let mut res = 0;
-
-        if self.data.contains(&VirtualKeyCode::Up) {
-            res += 127;
+        if let Some(frame_time_us) = self.replay.play(&mut self.ports) {
+            return frame_time_us;
         }
 
-        if self.data.contains(&VirtualKeyCode::Down) {
-            res -= 127;
+        let dt = frame_time_us as f32 / 1e6;
+
+        for ((((port, port_bindings), stick), remainder), pak) in self
+            .ports
+            .iter_mut()
+            .zip(self.bindings.ports.iter())
+            .zip(self.sticks.iter_mut())
+            .zip(self.mouse_remainders.iter_mut())
+            .zip(self.paks.iter())
+        {
+            if port_bindings.is_empty() {
+                continue;
+            }
+
+            let mut buttons = Buttons::empty();
+            let mut target = (0.0, 0.0);
+
+            for &(input, binding_target) in port_bindings.inputs.iter() {
+                let down = match input {
+                    Input::Key(key) => graphics.keys_down.contains(&key),
+                    Input::Mouse(button) => graphics.mouse_buttons_down.contains(&button),
+                };
+
+                if !down {
+                    continue;
+                }
+
+                match binding_target {
+                    Target::Button(button) => buttons |= button,
+                    Target::StickUp => target.1 = STICK_RANGE,
+                    Target::StickDown => target.1 = -STICK_RANGE,
+                    Target::StickLeft => target.0 = -STICK_RANGE,
+                    Target::StickRight => target.0 = STICK_RANGE,
+                }
+            }
+
+            if port_bindings.mouse > 0.0 {
+                // Screen y is down, mouse y is up.
+                let x = remainder.0 + graphics.mouse_delta.0 as f32 * port_bindings.mouse;
+                let y = remainder.1 - graphics.mouse_delta.1 as f32 * port_bindings.mouse;
+                let (dx, dy) = (to_mouse_delta(x), to_mouse_delta(y));
+                *remainder = (x - dx as f32, y - dy as f32);
+
+                port.device = Device::Mouse;
+                port.accessory = Accessory::None;
+                port.update(buttons, dx, dy);
+                continue;
+            }
+
+            if port_bindings.stick_ramp > 0.0 {
+                let max_step = STICK_RANGE * dt / port_bindings.stick_ramp;
+                stick.0 = move_towards(stick.0, target.0, max_step);
+                stick.1 = move_towards(stick.1, target.1, max_step);
+            } else {
+                *stick = target;
+            }
+
+            // Screen y is down, stick y is up.
+            let x = stick.0 + graphics.mouse_delta.0 as f32 * port_bindings.mouse_stick;
+            let y = stick.1 - graphics.mouse_delta.1 as f32 * port_bindings.mouse_stick;
+
+            port.device = Device::Controller;
+            port.accessory = pak.accessory();
+            port.update(buttons, to_raw_stick(x), to_raw_stick(y));
         }
 
-        res
-    }
-
-    #[inline]
-    pub fn a(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::X)
-    }
-
-    #[inline]
-    pub fn b(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::C)
+        self.replay.record(&self.ports, frame_time_us)
     }
 
     #[inline]
-    pub fn z(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::Space)
+    pub fn port(&self, port: usize) -> &ControllerState {
+        &self.ports[port]
     }
 
     #[inline]
-    pub fn start(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::Return)
+    pub fn ports(&self) -> &[ControllerState; MAX_CONTROLLERS] {
+        &self.ports
     }
 
     #[inline]
-    pub fn up(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::W)
+    pub(crate) fn port_mut(&mut self, port: usize) -> &mut ControllerState {
+        &mut self.ports[port]
     }
 
-    #[inline]
-    pub fn down(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::S)
-    }
+    pub fn read_pak(
+        &mut self,
+        port: usize,
+        address: u16,
+        data: &mut [u8; PAK_BLOCK_SIZE],
+    ) -> Result<(), PakError> {
+        if !self.ports[port].connected() {
+            return Err(PakError::NoController);
+        }
 
-    #[inline]
-    pub fn left(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::A)
+        self.paks[port].read(address, data)
     }
 
-    #[inline]
-    pub fn right(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::D)
-    }
+    pub fn write_pak(
+        &mut self,
+        port: usize,
+        address: u16,
+        data: &[u8; PAK_BLOCK_SIZE],
+    ) -> Result<(), PakError> {
+        if !self.ports[port].connected() {
+            return Err(PakError::NoController);
+        }
 
-    #[inline]
-    pub fn l(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::Q)
+        self.paks[port].write(port, address, data)
     }
 
+    /// Records the input of every following frame, `seed` is stored for the
+    /// replay to seed the random generator with.
     #[inline]
-    pub fn r(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::E)
+    pub fn start_recording(&mut self, seed: u32) {
+        self.replay.start_recording(seed);
     }
 
+    /// The recording so far.
     #[inline]
-    pub fn c_up(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::I)
+    pub fn recording(&self) -> Option<&Recording> {
+        self.replay.recording()
     }
 
     #[inline]
-    pub fn c_down(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::K)
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.replay.stop_recording()
     }
 
+    /// Input comes from `recording` instead of the controllers until it ends.
     #[inline]
-    pub fn c_left(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::J)
+    pub fn start_playback(&mut self, recording: Recording) {
+        self.replay.start_playback(recording);
     }
 
     #[inline]
-    pub fn c_right(&self) -> bool {
-        self.data.contains(&VirtualKeyCode::L)
+    pub fn is_playing_back(&self) -> bool {
+        self.replay.is_playing_back()
     }
 }
@@ -0,0 +1,256 @@
+use super::pak::PakConfig;
+use crate::controller_state::{Buttons, MAX_CONTROLLERS};
+use std::fs;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+pub(crate) const BINDINGS_PATH: &str = "bindings.cfg";
+
+// Seconds for a key to move the stick from center to full deflection.
+const DEFAULT_STICK_RAMP: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Target {
+    Button(Buttons),
+    StickUp,
+    StickDown,
+    StickLeft,
+    StickRight,
+}
+
+pub(crate) struct PortBindings {
+    pub(crate) inputs: Vec<(Input, Target)>,
+    pub(crate) stick_ramp: f32,
+    // Stick units per mouse count, 0 when the mouse is not mapped.
+    pub(crate) mouse_stick: f32,
+    // N64 Mouse counts per host mouse count, 0 when the port is a controller.
+    pub(crate) mouse: f32,
+    pub(crate) pak: PakConfig,
+}
+
+impl PortBindings {
+    fn empty() -> PortBindings {
+        PortBindings {
+            inputs: Vec::new(),
+            stick_ramp: DEFAULT_STICK_RAMP,
+            mouse_stick: 0.0,
+            mouse: 0.0,
+            pak: PakConfig::None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.inputs.is_empty() && self.mouse_stick == 0.0 && self.mouse == 0.0
+    }
+}
+
+pub(crate) struct Bindings {
+    pub(crate) ports: [PortBindings; MAX_CONTROLLERS],
+}
+
+impl Default for Bindings {
+    /// Port 1 and 2 share the keyboard, see the README.
+    fn default() -> Bindings {
+        Bindings::parse(DEFAULT_BINDINGS).unwrap()
+    }
+}
+
+impl Bindings {
+    /// Reads `path`, falls back to the default bindings if it is missing or
+    /// broken.
+    pub(crate) fn load(path: &str) -> Bindings {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return Bindings::default(),
+        };
+
+        match Bindings::parse(&text) {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                println!("Unable to load bindings {}: {}", path, e);
+                Bindings::default()
+            }
+        }
+    }
+
+    /// One binding per line, `<port> <target> <value>`. Ports are numbered 1
+    /// to 4. `#` starts a comment.
+    pub(crate) fn parse(text: &str) -> Result<Bindings, String> {
+        let mut bindings = Bindings {
+            ports: [
+                PortBindings::empty(),
+                PortBindings::empty(),
+                PortBindings::empty(),
+                PortBindings::empty(),
+            ],
+        };
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words = line.split_whitespace().collect::<Vec<_>>();
+
+            if words.is_empty() {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {}", line_index + 1, message);
+
+            if words.len() != 3 {
+                return Err(error("expected <port> <target> <value>"));
+            }
+
+            let port = match words[0].parse::<usize>() {
+                Ok(port) if (1..=MAX_CONTROLLERS).contains(&port) => &mut bindings.ports[port - 1],
+                _ => return Err(error("port must be 1 to 4")),
+            };
+
+            match words[1] {
+                "stick_ramp" => {
+                    port.stick_ramp = parse_positive(words[2]).ok_or_else(|| error("bad ramp"))?;
+                }
+                "pak" => {
+                    port.pak = match words[2] {
+                        "none" => PakConfig::None,
+                        "rumble" => PakConfig::Rumble,
+                        path => PakConfig::Controller(path.into()),
+                    };
+                }
+                "mouse_stick" => {
+                    port.mouse_stick =
+                        parse_positive(words[2]).ok_or_else(|| error("bad sensitivity"))?;
+                }
+                "mouse" => {
+                    port.mouse =
+                        parse_positive(words[2]).ok_or_else(|| error("bad sensitivity"))?;
+                }
+                target => {
+                    let target = target_from_name(target).ok_or_else(|| error("unknown target"))?;
+                    let input = input_from_name(words[2]).ok_or_else(|| error("unknown key"))?;
+                    port.inputs.push((input, target));
+                }
+            }
+        }
+
+        Ok(bindings)
+    }
+}
+
+fn parse_positive(value: &str) -> Option<f32> {
+    value.parse::<f32>().ok().filter(|v| *v >= 0.0)
+}
+
+fn target_from_name(name: &str) -> Option<Target> {
+    Some(match name {
+        "a" => Target::Button(Buttons::A),
+        "b" => Target::Button(Buttons::B),
+        "z" => Target::Button(Buttons::Z),
+        "start" => Target::Button(Buttons::START),
+        "up" => Target::Button(Buttons::UP),
+        "down" => Target::Button(Buttons::DOWN),
+        "left" => Target::Button(Buttons::LEFT),
+        "right" => Target::Button(Buttons::RIGHT),
+        "l" => Target::Button(Buttons::L),
+        "r" => Target::Button(Buttons::R),
+        "c_up" => Target::Button(Buttons::C_UP),
+        "c_down" => Target::Button(Buttons::C_DOWN),
+        "c_left" => Target::Button(Buttons::C_LEFT),
+        "c_right" => Target::Button(Buttons::C_RIGHT),
+        "stick_up" => Target::StickUp,
+        "stick_down" => Target::StickDown,
+        "stick_left" => Target::StickLeft,
+        "stick_right" => Target::StickRight,
+        _ => return None,
+    })
+}
+
+fn input_from_name(name: &str) -> Option<Input> {
+    MOUSE_NAMES
+        .iter()
+        .find(|(mouse_name, _)| mouse_name.eq_ignore_ascii_case(name))
+        .map(|(_, button)| Input::Mouse(*button))
+        .or_else(|| {
+            KEY_NAMES
+                .iter()
+                .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+                .map(|(_, key)| Input::Key(*key))
+        })
+}
+
+static MOUSE_NAMES: &[(&str, MouseButton)] = &[
+    ("MouseLeft", MouseButton::Left),
+    ("MouseRight", MouseButton::Right),
+    ("MouseMiddle", MouseButton::Middle),
+];
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        &[$((stringify!($key), VirtualKeyCode::$key)),*]
+    };
+}
+
+// Names are the `VirtualKeyCode` variants, matched ignoring case.
+#[rustfmt::skip]
+static KEY_NAMES: &[(&str, VirtualKeyCode)] = key_names![
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Up, Down, Left, Right,
+    Insert, Delete, Home, End, PageUp, PageDown,
+    Back, Return, Space, Tab,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    Add, Subtract, Multiply, Divide, Decimal, NumpadEnter, NumpadComma, NumpadEquals,
+    Apostrophe, Backslash, Comma, Equals, Grave, LBracket, RBracket, Minus, Period, Semicolon,
+    Slash,
+    LAlt, RAlt, LControl, RControl, LShift, RShift, Capital,
+];
+
+const DEFAULT_BINDINGS: &str = "
+1 pak rumble
+1 stick_up Up
+1 stick_down Down
+1 stick_left Left
+1 stick_right Right
+1 a X
+1 b C
+1 z Space
+1 start Return
+1 up W
+1 down S
+1 left A
+1 right D
+1 l Q
+1 r E
+1 c_up I
+1 c_down K
+1 c_left J
+1 c_right L
+
+2 stick_up Numpad8
+2 stick_down Numpad2
+2 stick_left Numpad4
+2 stick_right Numpad6
+2 a Numpad0
+2 b Decimal
+2 z NumpadEnter
+2 start Add
+2 up T
+2 down G
+2 left F
+2 right H
+2 l R
+2 r Y
+2 c_up Numpad9
+2 c_down Numpad3
+2 c_left Numpad7
+2 c_right Numpad1
+
+3 mouse 1
+3 a MouseLeft
+3 b MouseRight
+";
@@ -0,0 +1,144 @@
+use crate::controller_state::Accessory;
+use crate::pak::{PakError, CONTROLLER_PAK_SIZE, PAK_BLOCK_SIZE};
+use std::fs;
+
+const RUMBLE_MOTOR_ADDRESS: u16 = 0xc000;
+const RUMBLE_PROBE_ADDRESS: u16 = 0x8000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum PakConfig {
+    None,
+    Rumble,
+    // Contents are kept in the file.
+    Controller(String),
+}
+
+// Stands in for the pak in a controller. Behaves like the real ones seen
+// through the joybus accessory commands.
+pub(crate) enum EmuPak {
+    None,
+    Rumble {
+        probe: u8,
+        motor: bool,
+    },
+    Controller {
+        path: String,
+        data: Vec<u8>,
+        dirty: bool,
+    },
+}
+
+impl EmuPak {
+    pub(crate) fn new(config: &PakConfig) -> EmuPak {
+        match config {
+            PakConfig::None => EmuPak::None,
+            PakConfig::Rumble => EmuPak::Rumble {
+                probe: 0,
+                motor: false,
+            },
+            PakConfig::Controller(path) => {
+                let mut data = fs::read(path).unwrap_or_default();
+                data.resize(CONTROLLER_PAK_SIZE, 0);
+
+                EmuPak::Controller {
+                    path: path.clone(),
+                    data,
+                    dirty: false,
+                }
+            }
+        }
+    }
+
+    pub(crate) fn accessory(&self) -> Accessory {
+        match self {
+            EmuPak::None => Accessory::None,
+            EmuPak::Rumble { .. } => Accessory::RumblePak,
+            EmuPak::Controller { .. } => Accessory::ControllerPak,
+        }
+    }
+
+    pub(crate) fn read(
+        &mut self,
+        address: u16,
+        block: &mut [u8; PAK_BLOCK_SIZE],
+    ) -> Result<(), PakError> {
+        let address = (address & !0x1f) as usize;
+
+        match self {
+            EmuPak::None => return Err(PakError::NoPak),
+            EmuPak::Rumble { probe, .. } => {
+                let value = if address == RUMBLE_PROBE_ADDRESS as usize {
+                    *probe
+                } else {
+                    0
+                };
+
+                *block = [value; PAK_BLOCK_SIZE];
+            }
+            EmuPak::Controller { data, .. } => {
+                if address < CONTROLLER_PAK_SIZE {
+                    block.copy_from_slice(&data[address..address + PAK_BLOCK_SIZE]);
+                } else {
+                    *block = [0; PAK_BLOCK_SIZE];
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write(
+        &mut self,
+        port: usize,
+        address: u16,
+        block: &[u8; PAK_BLOCK_SIZE],
+    ) -> Result<(), PakError> {
+        let address = address & !0x1f;
+
+        match self {
+            EmuPak::None => return Err(PakError::NoPak),
+            EmuPak::Rumble { probe, motor } => {
+                if address == RUMBLE_PROBE_ADDRESS {
+                    *probe = block[0];
+                } else if address == RUMBLE_MOTOR_ADDRESS {
+                    let on = block[0] != 0;
+
+                    if on != *motor {
+                        println!("Rumble port {} {}", port + 1, if on { "on" } else { "off" });
+                        *motor = on;
+                    }
+                }
+            }
+            EmuPak::Controller { data, dirty, .. } => {
+                let address = address as usize;
+
+                if address < CONTROLLER_PAK_SIZE {
+                    data[address..address + PAK_BLOCK_SIZE].copy_from_slice(block);
+                    *dirty = true;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes come a block at a time, the file is only rewritten once per
+    // frame.
+    pub(crate) fn flush(&mut self) {
+        if let EmuPak::Controller { path, data, dirty } = self {
+            if *dirty {
+                if let Err(e) = fs::write(&path, &data) {
+                    println!("Unable to save controller pak {}: {}", path, e);
+                }
+
+                *dirty = false;
+            }
+        }
+    }
+}
+
+impl Drop for EmuPak {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
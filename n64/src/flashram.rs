@@ -0,0 +1,128 @@
+use crate::save_data::SaveError;
+use alloc::vec;
+use core::{convert::TryInto, ops::Range};
+
+pub(crate) const FLASHRAM_SIZE: usize = 128 * 1024;
+pub(crate) const PAGE_SIZE: usize = 128;
+pub(crate) const SECTOR_SIZE: usize = 16 * 1024;
+
+// Reads are addressed in halfwords, byte `offset` of the chip is at
+// `offset / 2` on the bus. PI DMA needs an even bus address, so reads start
+// on multiples of 4 bytes.
+pub(crate) const READ_ALIGN: usize = 4;
+
+/// The operations of a FlashRAM chip. Erasing sets a sector to 0xff,
+/// programming a page can only clear bits.
+pub(crate) trait FlashChip {
+    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), SaveError>;
+
+    fn erase_sector(&mut self, sector: usize) -> Result<(), SaveError>;
+
+    fn program_page(&mut self, page: usize, data: &[u8; PAGE_SIZE]) -> Result<(), SaveError>;
+}
+
+/// Reads any range of bytes, widened to whole `READ_ALIGN` units for the
+/// chip.
+pub(crate) fn read(
+    chip: &mut impl FlashChip,
+    offset: usize,
+    data: &mut [u8],
+) -> Result<(), SaveError> {
+    if offset + data.len() > FLASHRAM_SIZE {
+        return Err(SaveError::OutOfBounds);
+    }
+
+    let start = offset & !(READ_ALIGN - 1);
+    let end = (offset + data.len() + READ_ALIGN - 1) & !(READ_ALIGN - 1);
+
+    if start == offset && end == offset + data.len() {
+        return chip.read(offset, data);
+    }
+
+    let mut buffer = vec![0; end - start];
+    chip.read(start, &mut buffer)?;
+    data.copy_from_slice(&buffer[offset - start..][..data.len()]);
+
+    Ok(())
+}
+
+/// Splits a read of `len` bytes at `offset` into DMAs of at most `max_len`
+/// bytes. Gives the bus offset of each, from the start of domain 2, and
+/// where its bytes go.
+pub(crate) fn dma_chunks(
+    offset: usize,
+    len: usize,
+    max_len: usize,
+) -> impl Iterator<Item = (usize, Range<usize>)> {
+    assert!(
+        offset & (READ_ALIGN - 1) == 0 && max_len & (READ_ALIGN - 1) == 0,
+        "Unaligned FlashRAM read"
+    );
+
+    (0..len)
+        .step_by(max_len)
+        .map(move |start| ((offset + start) / 2, start..(start + max_len).min(len)))
+}
+
+/// Writes any range of bytes. Sectors are only erased when a bit has to be
+/// set, and only pages that change are programmed, both wear the chip.
+pub(crate) fn write(
+    chip: &mut impl FlashChip,
+    offset: usize,
+    data: &[u8],
+) -> Result<(), SaveError> {
+    if offset + data.len() > FLASHRAM_SIZE {
+        return Err(SaveError::OutOfBounds);
+    }
+
+    let mut sector_data = vec![0; SECTOR_SIZE];
+    let mut position = offset;
+    let end = offset + data.len();
+
+    while position < end {
+        let sector = position / SECTOR_SIZE;
+        let sector_start = sector * SECTOR_SIZE;
+        let start = position - sector_start;
+        let len = (SECTOR_SIZE - start).min(end - position);
+        let new = &data[position - offset..][..len];
+
+        chip.read(sector_start, &mut sector_data)?;
+
+        let old = &sector_data[start..start + len];
+        if old != new {
+            let erase = old
+                .iter()
+                .zip(new.iter())
+                .any(|(&old, &new)| old & new != new);
+
+            sector_data[start..start + len].copy_from_slice(new);
+
+            if erase {
+                chip.erase_sector(sector)?;
+            }
+
+            for (i, page) in sector_data.chunks(PAGE_SIZE).enumerate() {
+                let page_start = i * PAGE_SIZE;
+                let changed = page_start < start + len && start < page_start + PAGE_SIZE;
+
+                // After an erase every page that is not blank is written back.
+                let program = if erase {
+                    page.iter().any(|&byte| byte != 0xff)
+                } else {
+                    changed
+                };
+
+                if program {
+                    chip.program_page(
+                        (sector_start + page_start) / PAGE_SIZE,
+                        page.try_into().unwrap(),
+                    )?;
+                }
+            }
+        }
+
+        position += len;
+    }
+
+    Ok(())
+}
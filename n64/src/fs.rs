@@ -0,0 +1,200 @@
+use crate::rom::Rom;
+use alloc::{string::String, vec, vec::Vec};
+use core::convert::TryInto;
+use core::str;
+use n64_audio::stream::StreamReader;
+
+// Asset pack layout, all values big endian:
+//
+// Header:  magic: [u8; 4], version: u32, entry_count: u32, reserved: u32
+// Entries: name: [u8; 56] (nul padded), offset: u32, size: u32
+// Data:    file contents, each file 8 byte aligned
+//
+// Entries are sorted by name and offsets are relative to the start of the
+// pack. The pack is written by `game/build.rs`.
+
+pub const PACK_MAGIC: [u8; 4] = *b"LPAK";
+pub const PACK_VERSION: u32 = 1;
+pub const PACK_HEADER_SIZE: usize = 16;
+pub const PACK_ENTRY_SIZE: usize = 64;
+pub const PACK_NAME_SIZE: usize = 56;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FsError {
+    PackNotFound,
+    BadPack,
+    NotFound,
+    InvalidSeek,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SeekFrom {
+    Start(u32),
+    Current(i32),
+    End(i32),
+}
+
+struct Entry {
+    name: String,
+    offset: u32,
+    size: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct File {
+    offset: u32,
+    size: u32,
+    pos: u32,
+}
+
+impl File {
+    #[inline]
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    #[inline]
+    pub fn position(&self) -> u32 {
+        self.pos
+    }
+
+    #[inline]
+    pub fn eof(&self) -> bool {
+        self.pos >= self.size
+    }
+}
+
+/// A file with its own handle on the pack, for reading it on the side like
+/// a `Stream` does.
+pub struct FileReader {
+    rom: Rom,
+    file: File,
+}
+
+impl StreamReader for FileReader {
+    #[inline]
+    fn read(&mut self, offset: u32, buf: &mut [u8]) {
+        self.rom.read(self.file.offset + offset, buf);
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.file.size as usize
+    }
+}
+
+pub struct Fs {
+    rom: Rom,
+    entries: Vec<Entry>,
+}
+
+impl Fs {
+    // On the console the pack is located after the program image in ROM and
+    // `pack_path` is ignored. On PC a pack with the same file name next to
+    // the executable or in the current directory is used before `pack_path`.
+    pub fn new(pack_path: &str) -> Result<Fs, FsError> {
+        let mut rom = Rom::open(pack_path)?;
+
+        let mut header = [0; PACK_HEADER_SIZE];
+        rom.read(0, &mut header);
+
+        if header[0..4] != PACK_MAGIC || read_u32(&header[4..8]) != PACK_VERSION {
+            return Err(FsError::BadPack);
+        }
+
+        let entry_count = read_u32(&header[8..12]) as usize;
+
+        let mut directory = vec![0; entry_count * PACK_ENTRY_SIZE];
+        rom.read(PACK_HEADER_SIZE as u32, &mut directory);
+
+        let mut entries = Vec::with_capacity(entry_count);
+
+        for raw in directory.chunks_exact(PACK_ENTRY_SIZE) {
+            let name = &raw[..PACK_NAME_SIZE];
+            let name_len = name.iter().position(|c| *c == 0).unwrap_or(PACK_NAME_SIZE);
+            let name = str::from_utf8(&name[..name_len]).map_err(|_| FsError::BadPack)?;
+
+            entries.push(Entry {
+                name: String::from(name),
+                offset: read_u32(&raw[PACK_NAME_SIZE..(PACK_NAME_SIZE + 4)]),
+                size: read_u32(&raw[(PACK_NAME_SIZE + 4)..PACK_ENTRY_SIZE]),
+            });
+        }
+
+        Ok(Fs { rom, entries })
+    }
+
+    pub fn open(&self, name: &str) -> Result<File, FsError> {
+        let index = self
+            .entries
+            .binary_search_by(|e| e.name.as_str().cmp(name))
+            .map_err(|_| FsError::NotFound)?;
+
+        let entry = &self.entries[index];
+
+        Ok(File {
+            offset: entry.offset,
+            size: entry.size,
+            pos: 0,
+        })
+    }
+
+    pub fn reader(&self, name: &str) -> Result<FileReader, FsError> {
+        Ok(FileReader {
+            file: self.open(name)?,
+            rom: self.rom.try_clone()?,
+        })
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.open(name).is_ok()
+    }
+
+    // Reads up to `buf.len()` bytes from the current position and returns the
+    // number of bytes read, 0 at the end of the file.
+    pub fn read(&mut self, file: &mut File, buf: &mut [u8]) -> usize {
+        let left = (file.size - u32::min(file.pos, file.size)) as usize;
+        let len = usize::min(left, buf.len());
+
+        if len > 0 {
+            self.rom.read(file.offset + file.pos, &mut buf[..len]);
+            file.pos += len as u32;
+        }
+
+        len
+    }
+
+    pub fn seek(&self, file: &mut File, pos: SeekFrom) -> Result<u32, FsError> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(offset) => file.pos as i64 + offset as i64,
+            SeekFrom::End(offset) => file.size as i64 + offset as i64,
+        };
+
+        if new_pos < 0 || new_pos > file.size as i64 {
+            return Err(FsError::InvalidSeek);
+        }
+
+        file.pos = new_pos as u32;
+
+        Ok(file.pos)
+    }
+
+    pub fn read_to_vec(&mut self, name: &str) -> Result<Vec<u8>, FsError> {
+        let mut file = self.open(name)?;
+
+        let mut data = vec![0; file.size() as usize];
+        self.read(&mut file, &mut data);
+
+        Ok(data)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.name.as_str())
+    }
+}
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes.try_into().unwrap())
+}
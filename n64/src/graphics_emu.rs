@@ -8,7 +8,7 @@ use std::thread;
 use std::thread_local;
 use textured_rect::TexturedRect;
 use winit::{
-    event::{self, VirtualKeyCode, WindowEvent},
+    event::{self, DeviceEvent, MouseButton, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     platform::desktop::EventLoopExtDesktop,
     window::Window,
@@ -58,6 +58,9 @@ thread_local! {
 pub struct Graphics {
     pub(crate) video_mode: VideoMode,
     pub(crate) keys_down: HashSet<VirtualKeyCode>,
+    pub(crate) mouse_buttons_down: HashSet<MouseButton>,
+    // Mouse motion since the previous frame, in raw device counts.
+    pub(crate) mouse_delta: (f64, f64),
 
     _window: Window,
     _adapter: wgpu::Adapter,
@@ -91,6 +94,7 @@ impl Graphics {
         };
 
         let keys_down = HashSet::new();
+        let mouse_buttons_down = HashSet::new();
 
         let size = window.inner_size();
 
@@ -155,6 +159,8 @@ impl Graphics {
         Self {
             video_mode,
             keys_down,
+            mouse_buttons_down,
+            mouse_delta: (0.0, 0.0),
 
             _window: window,
             _adapter: adapter,
@@ -178,6 +184,8 @@ impl Graphics {
     }
 
     pub(crate) fn poll_events(&mut self, framebuffer: &mut Framebuffer) {
+        self.mouse_delta = (0.0, 0.0);
+
         EVENT_LOOP.with(|event_loop| {
             event_loop
                 .lock()
@@ -230,8 +238,29 @@ impl Graphics {
                             } => {
                                 self.keys_down.remove(&keycode);
                             }
+                            WindowEvent::MouseInput {
+                                state: event::ElementState::Pressed,
+                                button,
+                                ..
+                            } => {
+                                self.mouse_buttons_down.insert(button);
+                            }
+                            WindowEvent::MouseInput {
+                                state: event::ElementState::Released,
+                                button,
+                                ..
+                            } => {
+                                self.mouse_buttons_down.remove(&button);
+                            }
                             _ => {}
                         },
+                        event::Event::DeviceEvent {
+                            event: DeviceEvent::MouseMotion { delta },
+                            ..
+                        } => {
+                            self.mouse_delta.0 += delta.0;
+                            self.mouse_delta.1 += delta.1;
+                        }
                         event::Event::RedrawRequested(_) => {
                             self.render_cpu_buffer(framebuffer);
                         }
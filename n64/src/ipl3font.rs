@@ -1,4 +1,5 @@
 use crate::gfx::TextureMut;
+use core::fmt;
 use n64_math::Color;
 
 pub const GLYPH_WIDTH: i32 = 13;
@@ -173,3 +174,36 @@ pub fn draw_char(out_tex: &mut TextureMut, x: i32, y: i32, color: Color, ch: u8)
         }
     }
 }
+
+/// Text for `draw_str` formatted into a fixed buffer instead of a `String`.
+/// What does not fit is cut off.
+pub struct TextBuffer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> TextBuffer<'a> {
+    #[inline]
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<'a> fmt::Write for TextBuffer<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+
+        if len < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -3,52 +3,84 @@
 extern crate alloc;
 
 pub use audio::Audio;
+pub use controller_state::{
+    Accessory, Buttons, ControllerState, Device, MAX_CONTROLLERS, STICK_RANGE,
+};
 pub use controllers::Controllers;
 pub use framebuffer::{slow_cpu_clear, Framebuffer};
 pub use graphics::Graphics;
-pub use n64_types::VideoMode;
+pub use n64_audio::mixer::{Mixer, Sample, Scheduler, Voice};
+pub use n64_audio::stream::StreamError;
+pub use n64_pak::{ControllerPak, Corruption, FsError, Note, NoteKey};
+pub use n64_types::{AudioConfig, VideoMode, DEFAULT_BUFFER_FRAMES, DEFAULT_SAMPLE_RATE};
+pub use pak::{PakError, PakPort, CONTROLLER_PAK_SIZE, PAK_BLOCK_SIZE};
+pub use replay::{Recording, ReplayError};
+pub use save_data::{SaveData, SaveError, SaveType};
+pub use saves::Saves;
 
+pub mod fs;
 pub mod gfx;
 pub mod ipl3font;
 
+mod controller_state;
+mod flashram;
 mod framebuffer;
+mod pak;
+mod replay;
+mod save_data;
+
+#[cfg(test)]
+mod tests;
 
 cfg_if::cfg_if! {
     if #[cfg(target_vendor = "nintendo64")] {
         mod audio;
         mod graphics;
         mod controllers;
+        mod rom;
+        mod saves;
     } else {
         pub mod audio_emu;
         pub mod graphics_emu;
         pub mod controllers_emu;
+        pub mod rom_emu;
+        pub mod saves_emu;
 
         use audio_emu as audio;
         use graphics_emu as graphics;
         use controllers_emu as controllers;
+        use rom_emu as rom;
+        use saves_emu as saves;
     }
 }
 
+/// Music and other long sounds read from the asset pack as they play, see
+/// `Fs::reader`.
+pub type Stream = n64_audio::stream::Stream<fs::FileReader>;
+
 pub struct N64 {
     pub audio: Audio,
     pub framebuffer: Framebuffer,
     pub graphics: Graphics,
     pub controllers: Controllers,
+    pub saves: Saves,
 }
 
 impl N64 {
     #[inline]
-    pub fn new(video_mode: VideoMode) -> N64 {
-        let audio = Audio::new();
+    pub fn new(video_mode: VideoMode, audio_config: AudioConfig) -> N64 {
+        let audio = Audio::new(audio_config);
         let mut framebuffer = Framebuffer::new(video_mode);
         let graphics = Graphics::new(video_mode, &mut framebuffer);
         let controllers = Controllers::new();
+        let saves = Saves::new();
 
         N64 {
             audio,
             framebuffer,
             graphics,
             controllers,
+            saves,
         }
     }
 }
@@ -0,0 +1,112 @@
+use crate::controller_state::Accessory;
+use crate::controllers::Controllers;
+use n64_pak::{ControllerPak, FsError, PakDevice};
+
+/// Accessories are read and written in blocks of this many bytes, at 32 byte
+/// aligned addresses.
+pub const PAK_BLOCK_SIZE: usize = n64_pak::BLOCK_SIZE;
+
+pub const CONTROLLER_PAK_SIZE: usize = n64_pak::PAK_SIZE;
+
+// Unmapped on a Controller Pak, reads back what was written on a Rumble Pak.
+const PROBE_ADDRESS: u16 = 0x8000;
+const RUMBLE_PROBE: u8 = 0x80;
+const RUMBLE_MOTOR_ADDRESS: u16 = 0xc000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PakError {
+    NoController,
+    NoPak,
+    // The data did not survive the transfer.
+    Crc,
+    WrongPak,
+}
+
+impl Controllers {
+    /// Finds out what kind of pak is in `port` and stores it in the port's
+    /// `ControllerState`. Writes to the pak.
+    pub fn identify_pak(&mut self, port: usize) -> Accessory {
+        let accessory = match self.probe_pak(port) {
+            Ok(accessory) => accessory,
+            Err(PakError::NoController) | Err(PakError::NoPak) => Accessory::None,
+            Err(_) => Accessory::Pak,
+        };
+
+        self.port_mut(port).accessory = accessory;
+
+        accessory
+    }
+
+    fn probe_pak(&mut self, port: usize) -> Result<Accessory, PakError> {
+        let mut block = [RUMBLE_PROBE; PAK_BLOCK_SIZE];
+        self.write_pak(port, PROBE_ADDRESS, &block)?;
+        self.read_pak(port, PROBE_ADDRESS, &mut block)?;
+
+        if block[0] == RUMBLE_PROBE {
+            Ok(Accessory::RumblePak)
+        } else {
+            Ok(Accessory::ControllerPak)
+        }
+    }
+
+    pub fn set_rumble(&mut self, port: usize, on: bool) -> Result<(), PakError> {
+        if self.port(port).accessory() != Accessory::RumblePak {
+            return Err(PakError::WrongPak);
+        }
+
+        let block = [on as u8; PAK_BLOCK_SIZE];
+        self.write_pak(port, RUMBLE_MOTOR_ADDRESS, &block)
+    }
+
+    /// Mounts the filesystem on the Controller Pak in `port`. Each call reads
+    /// the tables again, so keep the result while working with notes.
+    pub fn mount_controller_pak(
+        &mut self,
+        port: usize,
+    ) -> Result<ControllerPak<PakPort<'_>>, FsError<PakError>> {
+        ControllerPak::mount(self.pak_port(port)?)
+    }
+
+    /// Erases the Controller Pak in `port`.
+    pub fn format_controller_pak(
+        &mut self,
+        port: usize,
+    ) -> Result<ControllerPak<PakPort<'_>>, FsError<PakError>> {
+        ControllerPak::format(self.pak_port(port)?)
+    }
+
+    fn pak_port(&mut self, port: usize) -> Result<PakPort<'_>, FsError<PakError>> {
+        if self.port(port).accessory() != Accessory::ControllerPak {
+            return Err(FsError::Device(PakError::WrongPak));
+        }
+
+        Ok(PakPort {
+            controllers: self,
+            port,
+        })
+    }
+}
+
+/// The Controller Pak in one port, as seen by the filesystem.
+pub struct PakPort<'a> {
+    controllers: &'a mut Controllers,
+    port: usize,
+}
+
+impl<'a> PakDevice for PakPort<'a> {
+    type Error = PakError;
+
+    #[inline]
+    fn read_block(
+        &mut self,
+        address: u16,
+        block: &mut [u8; PAK_BLOCK_SIZE],
+    ) -> Result<(), PakError> {
+        self.controllers.read_pak(self.port, address, block)
+    }
+
+    #[inline]
+    fn write_block(&mut self, address: u16, block: &[u8; PAK_BLOCK_SIZE]) -> Result<(), PakError> {
+        self.controllers.write_pak(self.port, address, block)
+    }
+}
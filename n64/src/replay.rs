@@ -0,0 +1,290 @@
+use crate::controller_state::{Buttons, ControllerState, Device, MAX_CONTROLLERS};
+use alloc::vec::Vec;
+
+// Layout:
+//
+// | "LREC" | version: u8 | seed: u32 be | frame* |
+//
+// frame:
+//
+// | changed << 4 | connected: u8 | frame time us: varint | port* |
+//
+// One port entry, `| flags: u8 | buttons: u16 be | x: i8 | y: i8 |`, for each
+// connected port whose input changed since the previous frame, in port order.
+// Version 1 entries have no flags.
+const MAGIC: &[u8; 4] = b"LREC";
+const VERSION: u8 = 2;
+const FLAG_MOUSE: u8 = 0x01;
+const HEADER_SIZE: usize = 9;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    BadHeader,
+    UnsupportedVersion,
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+struct PortInput {
+    connected: bool,
+    mouse: bool,
+    buttons: Buttons,
+    x: i8,
+    y: i8,
+}
+
+impl PortInput {
+    fn from_state(state: &ControllerState) -> PortInput {
+        PortInput {
+            connected: state.connected(),
+            mouse: state.device() == Device::Mouse,
+            buttons: state.buttons(),
+            x: state.x(),
+            y: state.y(),
+        }
+    }
+}
+
+/// Controller input and frame times of a play session, in a compact binary
+/// format. Together with the random seed it replays the session exactly.
+#[derive(Clone)]
+pub struct Recording {
+    data: Vec<u8>,
+}
+
+impl Recording {
+    pub fn new(seed: u32) -> Recording {
+        let mut data = Vec::with_capacity(64 * 1024);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&seed.to_be_bytes());
+
+        Recording { data }
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Recording, ReplayError> {
+        if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+            return Err(ReplayError::BadHeader);
+        }
+
+        if data[4] == 0 || data[4] > VERSION {
+            return Err(ReplayError::UnsupportedVersion);
+        }
+
+        Ok(Recording { data })
+    }
+
+    #[inline]
+    fn version(&self) -> u8 {
+        self.data[4]
+    }
+
+    #[inline]
+    pub fn seed(&self) -> u32 {
+        u32::from_be_bytes([self.data[5], self.data[6], self.data[7], self.data[8]])
+    }
+
+    /// The file contents. Frames are only ever appended, so a recording that is
+    /// still running can be written out incrementally.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn push_frame(
+        &mut self,
+        previous: &[PortInput; MAX_CONTROLLERS],
+        ports: &[PortInput; MAX_CONTROLLERS],
+        frame_time_us: u32,
+    ) {
+        let mut connected = 0;
+        let mut changed = 0;
+
+        for (i, (previous, port)) in previous.iter().zip(ports.iter()).enumerate() {
+            if port.connected {
+                connected |= 1 << i;
+
+                if port != previous {
+                    changed |= 1 << i;
+                }
+            }
+        }
+
+        self.data.push(changed << 4 | connected);
+
+        let mut value = frame_time_us;
+        loop {
+            if value < 0x80 {
+                self.data.push(value as u8);
+                break;
+            }
+
+            self.data.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+
+        for (i, port) in ports.iter().enumerate() {
+            if changed & (1 << i) != 0 {
+                self.data.push(if port.mouse { FLAG_MOUSE } else { 0 });
+                self.data
+                    .extend_from_slice(&port.buttons.bits().to_be_bytes());
+                self.data.push(port.x as u8);
+                self.data.push(port.y as u8);
+            }
+        }
+    }
+}
+
+struct Playback {
+    recording: Recording,
+    offset: usize,
+    ports: [PortInput; MAX_CONTROLLERS],
+}
+
+impl Playback {
+    fn read_u8(&mut self) -> Option<u8> {
+        let value = *self.recording.data.get(self.offset)?;
+        self.offset += 1;
+        Some(value)
+    }
+
+    // None at the end of the recording, a truncated last frame also ends it.
+    fn next_frame(&mut self) -> Option<u32> {
+        let mask = self.read_u8()?;
+        let connected = mask & 0xf;
+        let changed = mask >> 4;
+
+        let mut frame_time_us = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            frame_time_us |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        for i in 0..MAX_CONTROLLERS {
+            if connected & (1 << i) == 0 {
+                self.ports[i] = PortInput::default();
+            } else if changed & (1 << i) != 0 {
+                let flags = if self.recording.version() >= 2 {
+                    self.read_u8()?
+                } else {
+                    0
+                };
+                let buttons = u16::from_be_bytes([self.read_u8()?, self.read_u8()?]);
+
+                self.ports[i] = PortInput {
+                    connected: true,
+                    mouse: flags & FLAG_MOUSE != 0,
+                    buttons: Buttons::from_bits_truncate(buttons),
+                    x: self.read_u8()? as i8,
+                    y: self.read_u8()? as i8,
+                };
+            }
+        }
+
+        Some(frame_time_us)
+    }
+}
+
+struct Recorder {
+    recording: Recording,
+    previous: [PortInput; MAX_CONTROLLERS],
+}
+
+/// Recording and playback state shared by both `Controllers` backends.
+#[derive(Default)]
+pub(crate) struct Replay {
+    recorder: Option<Recorder>,
+    playback: Option<Playback>,
+}
+
+impl Replay {
+    pub(crate) fn start_recording(&mut self, seed: u32) {
+        self.recorder = Some(Recorder {
+            recording: Recording::new(seed),
+            previous: [PortInput::default(); MAX_CONTROLLERS],
+        });
+    }
+
+    #[inline]
+    pub(crate) fn recording(&self) -> Option<&Recording> {
+        self.recorder.as_ref().map(|recorder| &recorder.recording)
+    }
+
+    #[inline]
+    pub(crate) fn stop_recording(&mut self) -> Option<Recording> {
+        self.recorder.take().map(|recorder| recorder.recording)
+    }
+
+    pub(crate) fn start_playback(&mut self, recording: Recording) {
+        self.playback = Some(Playback {
+            recording,
+            offset: HEADER_SIZE,
+            ports: [PortInput::default(); MAX_CONTROLLERS],
+        });
+    }
+
+    #[inline]
+    pub(crate) fn is_playing_back(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /// Replaces `ports` with the next recorded frame and returns its frame
+    /// time. Returns None, and goes back to live input, when the recording
+    /// has ended.
+    pub(crate) fn play(&mut self, ports: &mut [ControllerState; MAX_CONTROLLERS]) -> Option<i64> {
+        let playback = self.playback.as_mut()?;
+
+        let frame_time_us = match playback.next_frame() {
+            Some(frame_time_us) => frame_time_us,
+            None => {
+                self.playback = None;
+                return None;
+            }
+        };
+
+        for (port, input) in ports.iter_mut().zip(playback.ports.iter()) {
+            if input.connected {
+                // Paks are left as they are, they still work during playback.
+                port.device = if input.mouse {
+                    Device::Mouse
+                } else {
+                    Device::Controller
+                };
+                port.update(input.buttons, input.x, input.y);
+            } else {
+                port.disconnect();
+            }
+        }
+
+        Some(frame_time_us as i64)
+    }
+
+    /// Appends `ports` to the recording, if there is one. Returns the frame
+    /// time to simulate with, the same one a replay will return.
+    pub(crate) fn record(
+        &mut self,
+        ports: &[ControllerState; MAX_CONTROLLERS],
+        frame_time_us: i64,
+    ) -> i64 {
+        let frame_time_us = frame_time_us.max(0).min(u32::MAX as i64) as u32;
+
+        if let Some(recorder) = &mut self.recorder {
+            let mut inputs = [PortInput::default(); MAX_CONTROLLERS];
+            for (input, port) in inputs.iter_mut().zip(ports.iter()) {
+                *input = PortInput::from_state(port);
+            }
+
+            recorder
+                .recording
+                .push_frame(&recorder.previous, &inputs, frame_time_us);
+            recorder.previous = inputs;
+        }
+
+        frame_time_us as i64
+    }
+}
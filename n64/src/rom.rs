@@ -0,0 +1,51 @@
+use crate::fs::{FsError, PACK_MAGIC};
+use n64_sys::pi;
+
+// Written by the entrypoint, the RAM address of the end of the program image.
+const FS_START_LOC: usize = 0x8000_031C;
+
+// The program image is loaded from ROM offset 0x1000 to this address.
+const PROGRAM_LOAD_ADDR: usize = 0x8000_0400;
+const PROGRAM_ROM_OFFSET: usize = 0x1000;
+
+// The pack is appended to the ROM on this alignment by the deploy tool. The
+// ROM may have been padded after the program image so we scan for the magic.
+pub const PACK_ALIGN: usize = 0x1000;
+const MAX_ROM_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct Rom {
+    base: usize,
+}
+
+impl Rom {
+    pub fn open(_pack_path: &str) -> Result<Rom, FsError> {
+        let rom_end = unsafe { core::ptr::read_volatile(FS_START_LOC as *const usize) };
+        let rom_end = rom_end - PROGRAM_LOAD_ADDR + PROGRAM_ROM_OFFSET;
+
+        let magic = u32::from_be_bytes(PACK_MAGIC);
+        let mut offset = (rom_end + PACK_ALIGN - 1) & !(PACK_ALIGN - 1);
+
+        while offset < MAX_ROM_SIZE {
+            if pi::io_read(pi::CART_DOM1_ADDR2 + offset) == magic {
+                return Ok(Rom {
+                    base: pi::CART_DOM1_ADDR2 + offset,
+                });
+            }
+
+            offset += PACK_ALIGN;
+        }
+
+        Err(FsError::PackNotFound)
+    }
+
+    #[inline]
+    pub fn try_clone(&self) -> Result<Rom, FsError> {
+        Ok(self.clone())
+    }
+
+    #[inline]
+    pub fn read(&mut self, offset: u32, buf: &mut [u8]) {
+        pi::read(self.base + offset as usize, buf);
+    }
+}
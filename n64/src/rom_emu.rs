@@ -0,0 +1,70 @@
+use crate::fs::FsError;
+use std::env;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+pub struct Rom {
+    file: File,
+}
+
+impl Rom {
+    // A copied build finds the pack next to the executable or in the
+    // directory it runs from, `pack_path` from the build machine comes last
+    // for `cargo run`.
+    pub fn open(pack_path: &str) -> Result<Rom, FsError> {
+        let pack_path = Path::new(pack_path);
+        let mut candidates = Vec::new();
+
+        if let Some(name) = pack_path.file_name() {
+            if let Some(exe_dir) = env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(PathBuf::from))
+            {
+                candidates.push(exe_dir.join(name));
+            }
+
+            candidates.push(PathBuf::from(name));
+        }
+
+        candidates.push(pack_path.to_path_buf());
+
+        for path in candidates.iter() {
+            if let Ok(file) = File::open(path) {
+                return Ok(Rom { file });
+            }
+        }
+
+        println!("Unable to find asset pack, looked for:");
+        for path in candidates.iter() {
+            println!("    {}", path.to_string_lossy());
+        }
+
+        Err(FsError::PackNotFound)
+    }
+
+    // Shares the file position, every read seeks first anyway.
+    pub fn try_clone(&self) -> Result<Rom, FsError> {
+        let file = self.file.try_clone().map_err(|e| {
+            println!("Unable to open asset pack again: {}", e);
+            FsError::PackNotFound
+        })?;
+
+        Ok(Rom { file })
+    }
+
+    #[inline]
+    pub fn read(&mut self, offset: u32, buf: &mut [u8]) {
+        let res = self
+            .file
+            .seek(SeekFrom::Start(offset as u64))
+            .and_then(|_| self.file.read_exact(buf));
+
+        if let Err(e) = res {
+            println!("Asset pack read error at {}: {}", offset, e);
+            for b in buf.iter_mut() {
+                *b = 0;
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use crate::saves::Saves;
+use alloc::{vec, vec::Vec};
+
+// The storage is split in two copies that are written in turn, so a save cut
+// short by power-off leaves the previous one intact. Each copy is:
+//
+// | "LS" | version: u16 | sequence: u16 | length: u16 | crc: u16 | data |
+//
+// All big endian. The CRC covers everything after the magic except itself,
+// and the copy with the later sequence number wins.
+const MAGIC: &[u8; 2] = b"LS";
+const HEADER_SIZE: usize = 10;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SaveType {
+    None,
+    Eeprom4k,
+    Eeprom16k,
+    Sram,
+    FlashRam,
+}
+
+impl SaveType {
+    /// Size in bytes.
+    #[inline]
+    pub fn size(self) -> usize {
+        match self {
+            SaveType::None => 0,
+            SaveType::Eeprom4k => 512,
+            SaveType::Eeprom16k => 2048,
+            SaveType::Sram => 32 * 1024,
+            SaveType::FlashRam => 128 * 1024,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SaveError {
+    NoStorage,
+    /// Nothing has been saved, or every copy is damaged.
+    Empty,
+    /// The data does not fit in the storage.
+    TooLarge,
+    /// `SaveData::read` did not accept the data.
+    Invalid,
+    OutOfBounds,
+    Io,
+}
+
+/// Data that survives power-off, see `Saves::load` and `Saves::store`.
+pub trait SaveData: Sized {
+    /// Stored with the data. Change it when the layout changes, `read` gets
+    /// the version the data was stored with.
+    const VERSION: u16;
+
+    fn write(&self, out: &mut Vec<u8>);
+
+    /// Returns None if the data can not be read.
+    fn read(version: u16, data: &[u8]) -> Option<Self>;
+}
+
+struct Header {
+    version: u16,
+    sequence: u16,
+    length: usize,
+    crc: u16,
+}
+
+fn crc16(data: &[u8], mut crc: u16) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+fn copy_crc(header: &[u8], data: &[u8]) -> u16 {
+    crc16(data, crc16(&header[2..8], 0xffff))
+}
+
+impl Saves {
+    #[inline]
+    fn copy_size(&self) -> usize {
+        self.save_type().size() / 2
+    }
+
+    fn read_copy(&mut self, copy: usize, data: &mut Vec<u8>) -> Result<Header, SaveError> {
+        let offset = copy * self.copy_size();
+        let mut bytes = [0; HEADER_SIZE];
+        self.read(offset, &mut bytes)?;
+
+        let header = Header {
+            version: u16::from_be_bytes([bytes[2], bytes[3]]),
+            sequence: u16::from_be_bytes([bytes[4], bytes[5]]),
+            length: u16::from_be_bytes([bytes[6], bytes[7]]) as usize,
+            crc: u16::from_be_bytes([bytes[8], bytes[9]]),
+        };
+
+        if &bytes[..2] != MAGIC || HEADER_SIZE + header.length > self.copy_size() {
+            return Err(SaveError::Empty);
+        }
+
+        data.clear();
+        data.resize(header.length, 0);
+        self.read(offset + HEADER_SIZE, data)?;
+
+        if copy_crc(&bytes, data) != header.crc {
+            return Err(SaveError::Empty);
+        }
+
+        Ok(header)
+    }
+
+    // The copy holding the latest save, with its header, if any is intact.
+    fn latest_copy(&mut self, data: &mut Vec<u8>) -> Result<Option<(usize, Header)>, SaveError> {
+        let mut latest: Option<(usize, Header)> = None;
+        let mut copy_data = Vec::new();
+
+        for copy in 0..2 {
+            let header = match self.read_copy(copy, &mut copy_data) {
+                Ok(header) => header,
+                Err(SaveError::Empty) => continue,
+                Err(e) => return Err(e),
+            };
+
+            let newer = match &latest {
+                Some((_, latest)) => header.sequence.wrapping_sub(latest.sequence) as i16 > 0,
+                None => true,
+            };
+
+            if newer {
+                latest = Some((copy, header));
+                core::mem::swap(data, &mut copy_data);
+            }
+        }
+
+        Ok(latest)
+    }
+
+    /// Loads what was last stored. Falls back to the older copy if the latest
+    /// one is damaged.
+    pub fn load<T: SaveData>(&mut self) -> Result<T, SaveError> {
+        if self.save_type() == SaveType::None {
+            return Err(SaveError::NoStorage);
+        }
+
+        let mut data = Vec::new();
+        let (_, header) = self.latest_copy(&mut data)?.ok_or(SaveError::Empty)?;
+
+        T::read(header.version, &data).ok_or(SaveError::Invalid)
+    }
+
+    pub fn store<T: SaveData>(&mut self, value: &T) -> Result<(), SaveError> {
+        if self.save_type() == SaveType::None {
+            return Err(SaveError::NoStorage);
+        }
+
+        let mut data = vec![0; HEADER_SIZE];
+        value.write(&mut data);
+
+        if data.len() > self.copy_size() {
+            return Err(SaveError::TooLarge);
+        }
+
+        let (copy, sequence) = match self.latest_copy(&mut Vec::new())? {
+            Some((copy, header)) => (1 - copy, header.sequence.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        let length = (data.len() - HEADER_SIZE) as u16;
+
+        data[..2].copy_from_slice(MAGIC);
+        data[2..4].copy_from_slice(&T::VERSION.to_be_bytes());
+        data[4..6].copy_from_slice(&sequence.to_be_bytes());
+        data[6..8].copy_from_slice(&length.to_be_bytes());
+
+        let crc = copy_crc(&data[..HEADER_SIZE], &data[HEADER_SIZE..]);
+        data[8..10].copy_from_slice(&crc.to_be_bytes());
+
+        let offset = copy * self.copy_size();
+        self.write(offset, &data)
+    }
+}
@@ -0,0 +1,161 @@
+use crate::flashram::{self, FlashChip, PAGE_SIZE};
+use crate::save_data::{SaveError, SaveType};
+use n64_sys::pi;
+use n64_sys::si::{self, EepromType, EEPROM_BLOCK_SIZE};
+
+#[derive(Copy, Clone)]
+enum Chip {
+    None,
+    Eeprom(EepromType),
+    Sram,
+    FlashRam,
+}
+
+struct CartFlash;
+
+impl FlashChip for CartFlash {
+    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), SaveError> {
+        for (bus_offset, range) in flashram::dma_chunks(offset, data.len(), pi::DMA_CHUNK_LEN) {
+            pi::flashram_read(bus_offset, &mut data[range]);
+        }
+
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, sector: usize) -> Result<(), SaveError> {
+        pi::flashram_erase_sector(sector).map_err(|_| SaveError::Io)
+    }
+
+    fn program_page(&mut self, page: usize, data: &[u8; PAGE_SIZE]) -> Result<(), SaveError> {
+        pi::flashram_program_page(page, data).map_err(|_| SaveError::Io)
+    }
+}
+
+/// The save chip on the cartridge.
+pub struct Saves {
+    chip: Chip,
+}
+
+impl Saves {
+    /// Probes for EEPROM, then FlashRAM, then SRAM. Both cartridge bus probes
+    /// write to the chip and restore what they overwrote.
+    #[inline]
+    pub fn new() -> Saves {
+        let chip = if let Some(eeprom) = si::eeprom_type() {
+            Chip::Eeprom(eeprom)
+        } else if pi::flashram_detect() {
+            Chip::FlashRam
+        } else if pi::sram_detect() {
+            Chip::Sram
+        } else {
+            Chip::None
+        };
+
+        Saves { chip }
+    }
+
+    #[inline]
+    pub fn save_type(&self) -> SaveType {
+        match self.chip {
+            Chip::None => SaveType::None,
+            Chip::Eeprom(EepromType::Eeprom4k) => SaveType::Eeprom4k,
+            Chip::Eeprom(EepromType::Eeprom16k) => SaveType::Eeprom16k,
+            Chip::Sram => SaveType::Sram,
+            Chip::FlashRam => SaveType::FlashRam,
+        }
+    }
+
+    pub fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), SaveError> {
+        self.check_bounds(offset, data.len())?;
+
+        match self.chip {
+            Chip::None => Err(SaveError::NoStorage),
+            Chip::Eeprom(_) => read_eeprom(offset, data),
+            Chip::Sram => {
+                pi::sram_read(offset, data);
+                Ok(())
+            }
+            Chip::FlashRam => flashram::read(&mut CartFlash, offset, data),
+        }
+    }
+
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SaveError> {
+        self.check_bounds(offset, data.len())?;
+
+        match self.chip {
+            Chip::None => Err(SaveError::NoStorage),
+            Chip::Eeprom(_) => write_eeprom(offset, data),
+            Chip::Sram => write_sram(offset, data),
+            Chip::FlashRam => flashram::write(&mut CartFlash, offset, data),
+        }
+    }
+
+    fn check_bounds(&self, offset: usize, len: usize) -> Result<(), SaveError> {
+        match self.save_type() {
+            SaveType::None => Err(SaveError::NoStorage),
+            save_type if offset + len > save_type.size() => Err(SaveError::OutOfBounds),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn read_eeprom(offset: usize, data: &mut [u8]) -> Result<(), SaveError> {
+    let mut block = [0; EEPROM_BLOCK_SIZE];
+    let mut position = offset;
+
+    while position < offset + data.len() {
+        let start = position % EEPROM_BLOCK_SIZE;
+        let len = (EEPROM_BLOCK_SIZE - start).min(offset + data.len() - position);
+
+        si::read_eeprom((position / EEPROM_BLOCK_SIZE) as u8, &mut block)
+            .map_err(|_| SaveError::Io)?;
+
+        data[position - offset..][..len].copy_from_slice(&block[start..start + len]);
+        position += len;
+    }
+
+    Ok(())
+}
+
+// Blocks that already hold the data are not rewritten, the EEPROM wears out
+// with writes.
+fn write_eeprom(offset: usize, data: &[u8]) -> Result<(), SaveError> {
+    let mut block = [0; EEPROM_BLOCK_SIZE];
+    let mut position = offset;
+
+    while position < offset + data.len() {
+        let index = (position / EEPROM_BLOCK_SIZE) as u8;
+        let start = position % EEPROM_BLOCK_SIZE;
+        let len = (EEPROM_BLOCK_SIZE - start).min(offset + data.len() - position);
+
+        si::read_eeprom(index, &mut block).map_err(|_| SaveError::Io)?;
+
+        let new = &data[position - offset..][..len];
+        if block[start..start + len] != *new {
+            block[start..start + len].copy_from_slice(new);
+            si::write_eeprom(index, &block).map_err(|_| SaveError::Io)?;
+        }
+
+        position += len;
+    }
+
+    Ok(())
+}
+
+// SRAM DMA moves whole halfwords, odd edges keep the byte next to them.
+fn write_sram(offset: usize, data: &[u8]) -> Result<(), SaveError> {
+    let start = offset & !1;
+    let end = (offset + data.len() + 1) & !1;
+
+    if start == offset && end == offset + data.len() {
+        pi::sram_write(offset, data);
+        return Ok(());
+    }
+
+    let mut buffer = alloc::vec![0; end - start];
+    pi::sram_read(start, &mut buffer);
+    buffer[offset - start..][..data.len()].copy_from_slice(data);
+    pi::sram_write(start, &buffer);
+
+    Ok(())
+}
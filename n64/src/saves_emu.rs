@@ -0,0 +1,148 @@
+use crate::flashram::{self, FlashChip, PAGE_SIZE, SECTOR_SIZE};
+use crate::save_data::{SaveError, SaveType};
+use std::fs;
+
+// Looked for in this order in the directory the game runs from, the first one
+// found is the cartridge's save chip. Without any, a 4k EEPROM is saved to the
+// first file. The files are raw images, the same as other emulators use.
+const SAVE_FILES: [(&str, SaveType); 3] = [
+    ("save.eep", SaveType::Eeprom4k),
+    ("save.sra", SaveType::Sram),
+    ("save.fla", SaveType::FlashRam),
+];
+
+/// Stands in for the save chip with a file. A 2 KB `.eep` file is a 16k
+/// EEPROM.
+pub struct Saves {
+    save_type: SaveType,
+    path: Option<&'static str>,
+    data: Vec<u8>,
+}
+
+impl Saves {
+    pub fn new() -> Saves {
+        let (path, save_type, mut data) = SAVE_FILES
+            .iter()
+            .find_map(|&(path, save_type)| fs::read(path).ok().map(|data| (path, save_type, data)))
+            .unwrap_or((SAVE_FILES[0].0, SAVE_FILES[0].1, Vec::new()));
+
+        let save_type =
+            if save_type == SaveType::Eeprom4k && data.len() == SaveType::Eeprom16k.size() {
+                SaveType::Eeprom16k
+            } else {
+                save_type
+            };
+
+        // Erased chips read back as 0xff.
+        data.resize(save_type.size(), 0xff);
+
+        Saves {
+            save_type,
+            path: Some(path),
+            data,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn in_memory(save_type: SaveType) -> Saves {
+        Saves {
+            save_type,
+            path: None,
+            data: vec![0xff; save_type.size()],
+        }
+    }
+
+    #[inline]
+    pub fn save_type(&self) -> SaveType {
+        self.save_type
+    }
+
+    pub fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), SaveError> {
+        self.check_bounds(offset, data.len())?;
+
+        if self.save_type == SaveType::FlashRam {
+            flashram::read(&mut FlashModel::new(&mut self.data), offset, data)
+        } else {
+            data.copy_from_slice(&self.data[offset..offset + data.len()]);
+            Ok(())
+        }
+    }
+
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), SaveError> {
+        self.check_bounds(offset, data.len())?;
+
+        if self.save_type == SaveType::FlashRam {
+            flashram::write(&mut FlashModel::new(&mut self.data), offset, data)?;
+        } else {
+            self.data[offset..offset + data.len()].copy_from_slice(data);
+        }
+
+        if let Some(path) = self.path {
+            fs::write(path, &self.data).map_err(|e| {
+                println!("Unable to write {}: {}", path, e);
+                SaveError::Io
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn check_bounds(&self, offset: usize, len: usize) -> Result<(), SaveError> {
+        if offset + len > self.data.len() {
+            Err(SaveError::OutOfBounds)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// FlashRAM in memory. Like the chip, programming can only clear bits.
+pub(crate) struct FlashModel<'a> {
+    data: &'a mut [u8],
+    pub(crate) erases: usize,
+    pub(crate) programs: usize,
+}
+
+impl<'a> FlashModel<'a> {
+    pub(crate) fn new(data: &'a mut [u8]) -> FlashModel<'a> {
+        FlashModel {
+            data,
+            erases: 0,
+            programs: 0,
+        }
+    }
+}
+
+impl<'a> FlashChip for FlashModel<'a> {
+    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), SaveError> {
+        // Addressed in halfwords like the chip, a page per DMA.
+        for (bus_offset, range) in flashram::dma_chunks(offset, data.len(), PAGE_SIZE) {
+            let start = 2 * bus_offset;
+            let len = range.len();
+            data[range].copy_from_slice(&self.data[start..start + len]);
+        }
+
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, sector: usize) -> Result<(), SaveError> {
+        for byte in self.data[sector * SECTOR_SIZE..][..SECTOR_SIZE].iter_mut() {
+            *byte = 0xff;
+        }
+
+        self.erases += 1;
+        Ok(())
+    }
+
+    fn program_page(&mut self, page: usize, data: &[u8; PAGE_SIZE]) -> Result<(), SaveError> {
+        for (byte, new) in self.data[page * PAGE_SIZE..][..PAGE_SIZE]
+            .iter_mut()
+            .zip(data.iter())
+        {
+            *byte &= new;
+        }
+
+        self.programs += 1;
+        Ok(())
+    }
+}
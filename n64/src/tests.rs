@@ -0,0 +1,162 @@
+use crate::flashram::{self, FLASHRAM_SIZE, PAGE_SIZE, SECTOR_SIZE};
+use crate::saves_emu::FlashModel;
+use crate::{SaveData, SaveError, SaveType, Saves};
+use n64_math::rand::Rng;
+
+#[test]
+fn flashram_writes_read_back() {
+    let mut flash = vec![0xff; FLASHRAM_SIZE];
+    let mut expected = flash.clone();
+    let mut rng = Rng::new(1);
+
+    for _ in 0..200 {
+        let offset = rng.next_u32() as usize % FLASHRAM_SIZE;
+        let len = (rng.next_u32() as usize % (2 * SECTOR_SIZE)).min(FLASHRAM_SIZE - offset);
+        let data = (0..len).map(|_| rng.next_u32() as u8).collect::<Vec<_>>();
+
+        flashram::write(&mut FlashModel::new(&mut flash), offset, &data).unwrap();
+        expected[offset..offset + len].copy_from_slice(&data);
+
+        assert!(flash == expected);
+    }
+
+    assert_eq!(
+        flashram::write(&mut FlashModel::new(&mut flash), FLASHRAM_SIZE - 1, &[0, 0]),
+        Err(SaveError::OutOfBounds)
+    );
+}
+
+#[test]
+fn flashram_reads_halfword_addresses() {
+    // A sector in 2 KiB DMAs, each at half its offset on the bus.
+    let chunks = flashram::dma_chunks(SECTOR_SIZE, SECTOR_SIZE, 2048).collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 8);
+    for (i, (bus_offset, range)) in chunks.into_iter().enumerate() {
+        assert_eq!(bus_offset, (SECTOR_SIZE + i * 2048) / 2);
+        assert_eq!(range, i * 2048..(i + 1) * 2048);
+    }
+
+    assert_eq!(
+        flashram::dma_chunks(12, 3000, 2048).collect::<Vec<_>>(),
+        [(6, 0..2048), (1030, 2048..3000)]
+    );
+
+    // Reads are widened to 4 bytes, the model reads through `dma_chunks`.
+    let mut flash = (0..FLASHRAM_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+    let mut data = [0; 7];
+    flashram::read(&mut FlashModel::new(&mut flash), 10, &mut data).unwrap();
+    assert_eq!(data, [10, 11, 12, 13, 14, 15, 16]);
+
+    assert_eq!(
+        flashram::read(
+            &mut FlashModel::new(&mut flash),
+            FLASHRAM_SIZE - 2,
+            &mut data
+        ),
+        Err(SaveError::OutOfBounds)
+    );
+}
+
+#[test]
+#[should_panic(expected = "Unaligned FlashRAM read")]
+fn flashram_rejects_odd_bus_addresses() {
+    flashram::dma_chunks(10, 100, 2048).count();
+}
+
+#[test]
+fn flashram_erases_only_when_needed() {
+    let mut flash = vec![0xff; FLASHRAM_SIZE];
+    let offset = SECTOR_SIZE - PAGE_SIZE / 2;
+    let data = [0xf0; PAGE_SIZE];
+
+    // Blank pages are programmed directly, across a sector boundary.
+    let mut model = FlashModel::new(&mut flash);
+    flashram::write(&mut model, offset, &data).unwrap();
+    assert_eq!((model.erases, model.programs), (0, 2));
+
+    // Clearing more bits needs no erase either.
+    let mut model = FlashModel::new(&mut flash);
+    flashram::write(&mut model, offset, &[0x30; 4]).unwrap();
+    assert_eq!((model.erases, model.programs), (0, 1));
+
+    // Nothing changes.
+    let mut model = FlashModel::new(&mut flash);
+    flashram::write(&mut model, offset, &[0x30; 4]).unwrap();
+    assert_eq!((model.erases, model.programs), (0, 0));
+
+    // Setting a bit erases the sector and writes back the pages in use.
+    let mut model = FlashModel::new(&mut flash);
+    flashram::write(&mut model, offset, &[0x31]).unwrap();
+    assert_eq!((model.erases, model.programs), (1, 1));
+
+    assert_eq!(flash[offset..offset + 4], [0x31, 0x30, 0x30, 0x30]);
+    assert_eq!(
+        flash[offset + 4..offset + PAGE_SIZE],
+        [0xf0; PAGE_SIZE - 4][..]
+    );
+}
+
+#[derive(Debug, PartialEq)]
+struct Scores(Vec<u32>);
+
+impl SaveData for Scores {
+    const VERSION: u16 = 2;
+
+    fn write(&self, out: &mut Vec<u8>) {
+        for score in &self.0 {
+            out.extend_from_slice(&score.to_be_bytes());
+        }
+    }
+
+    fn read(version: u16, data: &[u8]) -> Option<Scores> {
+        if version != Self::VERSION {
+            return None;
+        }
+
+        Some(Scores(
+            data.chunks_exact(4)
+                .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect(),
+        ))
+    }
+}
+
+#[test]
+fn save_data_on_every_chip() {
+    for &save_type in [
+        SaveType::Eeprom4k,
+        SaveType::Eeprom16k,
+        SaveType::Sram,
+        SaveType::FlashRam,
+    ]
+    .iter()
+    {
+        let mut saves = Saves::in_memory(save_type);
+        assert_eq!(saves.load::<Scores>(), Err(SaveError::Empty));
+
+        for i in 0..5 {
+            let scores = Scores(vec![i, i * 100, 7]);
+            saves.store(&scores).unwrap();
+            assert_eq!(saves.load::<Scores>(), Ok(scores));
+        }
+
+        let too_large = Scores(vec![0; save_type.size() / 8]);
+        assert_eq!(saves.store(&too_large), Err(SaveError::TooLarge));
+    }
+}
+
+#[test]
+fn save_data_survives_a_damaged_copy() {
+    let mut saves = Saves::in_memory(SaveType::FlashRam);
+    saves.store(&Scores(vec![1])).unwrap();
+    saves.store(&Scores(vec![2])).unwrap();
+
+    // The second store went to the second copy, damage its data.
+    let offset = SaveType::FlashRam.size() / 2 + 13;
+    saves.write(offset, &[5]).unwrap();
+    assert_eq!(saves.load::<Scores>(), Ok(Scores(vec![1])));
+
+    // The next store replaces the damaged copy.
+    saves.store(&Scores(vec![3])).unwrap();
+    assert_eq!(saves.load::<Scores>(), Ok(Scores(vec![3])));
+}